@@ -0,0 +1,75 @@
+use csync::{
+    clargs::LogOpts,
+    config,
+    daemon::{self, Daemon, RepoSpec},
+    logger, CsyncErr, CsyncResult,
+};
+use std::{path::PathBuf, sync::Arc};
+use structopt::StructOpt;
+use termion::color;
+
+/// Keep `csync` directories in sync while running, each with a key that it only holds, in locked
+/// memory, between `csync daemon unlock` and `csync daemon lock`.
+///
+/// Each table under `[csyncd]` in the config file is a repository, like `[csyncd.laptop]`, which
+/// encrypts like `csync encrypt --profile <profile>` does. Its keys are `profile`, `every` for the
+/// number of seconds between syncs, and `watch` to also sync whatever changes under the source as
+/// it changes. Repositories are locked until they are unlocked with `csync daemon unlock`.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "csyncd")]
+struct DaemonOpts {
+    /// Read the repositories from this file rather than from `csync/config.toml` under
+    /// `$XDG_CONFIG_HOME` or under `~/.config`.
+    #[structopt(long = "config", parse(from_os_str))]
+    config_opt: Option<PathBuf>,
+
+    /// Listen on this socket rather than on `$XDG_RUNTIME_DIR/csyncd.sock`. Its directory is created
+    /// if it doesn't exist, and has to be one that only you can get into.
+    #[structopt(long = "socket", parse(from_os_str))]
+    socket_opt: Option<PathBuf>,
+
+    // what gets logged and where, like with `csync`
+    #[structopt(flatten)]
+    log_opts: LogOpts,
+
+    /// Only log errors.
+    #[structopt(short, long)]
+    quiet: bool,
+}
+
+fn main() {
+    let opts = DaemonOpts::from_args();
+    if let Err(err) = run(&opts) {
+        eprintln!("{}[csyncd error]{}: {}", color::Fg(color::Red), color::Fg(color::Reset), err);
+        std::process::exit(err.exit_code());
+    }
+}
+
+// which only returns if something goes wrong
+fn run(opts: &DaemonOpts) -> CsyncResult<()> {
+    logger::init(
+        logger::level_of(opts.quiet, opts.log_opts.verbose),
+        opts.log_opts.log_file_opt.as_deref(),
+        opts.log_opts.log_paths,
+    )?;
+
+    let config_path = match &opts.config_opt {
+        Some(config_path) => config_path.clone(),
+        None => config::default_config_path()
+            .ok_or_else(|| CsyncErr::ConfigInvalid(String::from("there is no `$HOME` to find the config file under")))?,
+    };
+    let repo_specs = RepoSpec::from_config(&config_path)?;
+    if repo_specs.is_empty() {
+        Err(CsyncErr::ConfigInvalid(format!(
+            "{:?} has no repositories for `csyncd`, like `[csyncd.laptop]`",
+            config_path
+        )))?;
+    }
+
+    let socket = opts.socket_opt.clone().unwrap_or_else(daemon::default_socket_path);
+    let listener = daemon::bind(&socket)?;
+    let daemon = Arc::new(Daemon::new(repo_specs));
+    daemon.start_triggers()?;
+    log::info!("listening on {:?}", socket);
+    daemon.serve(listener)
+}
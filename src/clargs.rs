@@ -1,6 +1,9 @@
-use crate::prelude::DEFAULT_ZSTD_LEVEL_STR;
-use std::path::PathBuf;
-use structopt::StructOpt;
+use crate::prelude::{DEFAULT_DEBOUNCE_MS_STR, DEFAULT_ZSTD_LEVEL_STR};
+use std::{ffi::OsString, path::PathBuf};
+use structopt::{
+    clap::{self, ErrorKind},
+    StructOpt,
+};
 
 /// CryptSync (`csync`) efficiently compresses and encrypts a set of files and directories.
 ///
@@ -13,132 +16,758 @@ use structopt::StructOpt;
 pub enum Opts {
     /// Encrypt a file/directory to a compressed/encrypted `csync` directory.
     Encrypt {
-        /// Authentication algorithm to use; supported algorithms are [`hmac-sha512`].
-        #[structopt(long, default_value = "hmac-sha512")]
-        auth: String,
+        /// When encrypting to an existing `csync` directory, decide which files have changed by
+        /// comparing their contents against the keyed hashes recorded in the `csync` directory,
+        /// rather than comparing their sizes and modified times.
+        ///
+        /// This reads every file in the source, but isn't fooled by restored or copied files.
+        #[structopt(long)]
+        checksum: bool,
+
+        /// Split the contents of files into chunks at boundaries that depend on the contents, and
+        /// store each distinct chunk once. Only the chunks that changed are written again, and
+        /// identical files share their chunks.
+        ///
+        /// Once a `csync` directory has chunks, files are stored this way even without this flag.
+        #[structopt(long)]
+        chunked: bool,
+
+        /// Print what would be created, updated, skipped or deleted in the `csync` directory, and how
+        /// many bytes that involves, without encrypting or writing anything. The key is only derived
+        /// if it has to be checked against an existing `csync` directory.
+        #[structopt(long)]
+        dry_run: bool,
+
+        // algorithms and their parameters, shared with `import-tar`
+        #[structopt(flatten)]
+        encryption_opts: EncryptionOpts,
+
+        /// Leave out whatever matches this pattern, which follows the syntax of `.gitignore` files
+        /// and is relative to the source, like `target/` or `*.log`. Can be given more than once.
+        ///
+        /// `.csyncignore` files anywhere under the source always leave out whatever they match, the
+        /// same way `.gitignore` files do. Directories that are left out aren't walked at all.
+        #[structopt(long = "exclude", number_of_values = 1)]
+        excludes: Vec<String>,
+
+        /// Leave out whatever matches the patterns in this file, one per line, like `--exclude`.
+        /// Can be given more than once.
+        #[structopt(long = "exclude-from", number_of_values = 1, parse(from_os_str))]
+        exclude_from_files: Vec<PathBuf>,
+
+        /// Also leave out whatever `.gitignore` files under the source match.
+        #[structopt(long)]
+        gitignore: bool,
 
-        /// Encryption algorithm to use; supported algorithms are [`aes256cbc`, `chacha20`].
-        #[structopt(long, default_value = "chacha20")]
-        cipher: String,
+        /// Encrypt whatever matches this pattern even if `--exclude` or `--exclude-from` match it, like
+        /// `--exclude '*.log' --include keep.log`. Nothing can be included from under a directory
+        /// that is left out. Can be given more than once.
+        #[structopt(long = "include", number_of_values = 1)]
+        includes: Vec<String>,
 
-        /// Compression algorithm to use; supported algorithms are [`zstd`]. `gzip` to come soon!
-        #[structopt(long, default_value = "zstd")]
-        compressor: String,
+        /// Keep an encrypted index of every file in the `csync` directory, so that incremental
+        /// runs don't have to read every ciphertext.
+        ///
+        /// Once a `csync` directory has an index, it is kept up to date even without this flag.
+        /// `csync` directories on S3 or over SFTP always have one.
+        #[structopt(long)]
+        index: bool,
 
         /// Number of threads to use; defaults to the number of cores available on the machine.
         #[structopt(long = "num-threads")]
         num_threads_opt: Option<usize>,
 
+        /// Name to store the stream under when the source is `-`, which is also the name of the
+        /// file that `csync decrypt` writes it to.
+        #[structopt(long = "name")]
+        name_opt: Option<String>,
+
+        /// Don't descend into directories that are on other file systems than the source, like
+        /// mount points.
+        #[structopt(long)]
+        one_file_system: bool,
+
         /// `csync` directory in which compressed/encrypted files will be stored. This directory
         /// must be empty or be another `csync` directory that accepts the password from this
         /// session.
+        ///
+        /// `s3://bucket/prefix` stores the files in an S3-compatible bucket instead, with the
+        /// credentials, region and endpoint read from `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`,
+        /// `AWS_SESSION_TOKEN`, `AWS_REGION` and `AWS_ENDPOINT_URL`.
+        ///
+        /// `sftp://[user@]host[:port]/path` stores the files on an SSH server instead, logging in
+        /// with `ssh-agent` or a key file in `~/.ssh` or `CSYNC_SSH_KEY`. The host key must already be
+        /// in `~/.ssh/known_hosts`, and paths starting with `/~/` are relative to the home directory.
+        ///
+        /// A path ending in `.csar` packs the files into a single archive instead, which can be split
+        /// with `--volume-size`.
         #[structopt(short, long, parse(from_os_str))]
         out_dir: PathBuf,
 
-        /// Key-derivation algorithm to use; supported options are `scrypt`, `pbkdf2`.
-        #[structopt(long, default_value = "scrypt")]
-        key_deriv_alg: String,
+        /// How to report what was done: `human` for text on stderr, `json` for a single JSON object
+        /// on stdout once everything is done, or `ndjson` for a JSON object per line on stdout as
+        /// it happens. Neither of the JSON formats is colored.
+        #[structopt(long, default_value = "human", parse(try_from_str = parse_output_format))]
+        output: OutputFormat,
 
-        /// Number of seconds the key derivation process should take on this machine. `csync` will
-        /// approximate the parameters for the key derivation algorithm of your choosing, in order
-        /// to meet this requirement.
+        /// Read the password from the first line of this file rather than prompting for it, which
+        /// is needed when the source is `-` and there is no terminal to prompt on.
+        #[structopt(long = "password-file", parse(from_os_str))]
+        password_file_opt: Option<PathBuf>,
+
+        /// Also encrypt the directory at `PATH`, given as `NAME=PATH`, as if it were next to the
+        /// source and named `NAME`; `csync decrypt` writes it to `<out-dir>/NAME`. Can be given more
+        /// than once, like `--root projects=$HOME/projects --root etc=/etc`.
+        #[structopt(long = "root", number_of_values = 1, parse(try_from_str = parse_root))]
+        roots: Vec<(String, PathBuf)>,
+
+        /// After encrypting, record a snapshot of every file in the source, which `csync decrypt
+        /// --snapshot` can restore even after the files change. Implies `--chunked`.
         ///
-        /// Note that this *approximates*: if your key derivation algorithm is `pbkdf2`, `csync`
-        /// can approximate the parameters almost exactly. `scrypt` however, is tricky, and the
-        /// key derivation may take up to, but no more than twice as long as the requested timing.
-        #[structopt(long, default_value = "2")]
-        key_deriv_time: u16,
-
-        /// Indicates that key derivation algorithms should use their explicit parameters rather
-        /// than approximating them based on `--key-deriv-time`.
+        /// Once a `csync` directory has snapshots, one is recorded on every run even without this
+        /// flag. Use `csync clean` to prune old snapshots.
+        #[structopt(long)]
+        snapshot: bool,
+
+        /// The source directory to csync.
         ///
-        /// For example, `csync encrypt src -o out` uses `scrypt` with parameters that obey the
-        /// default value of `--key-deriv-time`. Running `csync encrypt src -o out
-        /// --key-deriv-by-params` runs `scrypt` using `--scrypt-log-n`, `--scrypt-r, `--scrypt-p`.
+        /// `-` reads a single stream from stdin instead, like the output of `tar c` or a database
+        /// dump, and stores it under `--name`, which defaults to `stdin`. A `csync` directory that
+        /// holds a stream can't hold anything else.
+        #[structopt(parse(from_os_str))]
+        source: PathBuf,
+
+        // which config file and profile the defaults of the options come from, shared with every
+        // subcommand
+        #[structopt(flatten)]
+        config_opts: ConfigOpts,
+
+        // what gets logged and where, shared with every subcommand
+        #[structopt(flatten)]
+        log_opts: LogOpts,
+
+        /// Suppress the printing of information like step-by-step reporting and timing informations.
+        #[structopt(short, long)]
+        quiet: bool,
+
+        /// When encrypting to a new `.csar` archive, split it into volumes of this many bytes, named
+        /// `<archive>`, `<archive>.1`, `<archive>.2` and so on. Accepts suffixes like `650M` or `4G`,
+        /// and has to be at least `1M`.
+        #[structopt(long = "volume-size", parse(try_from_str = parse_volume_size))]
+        volume_size_opt: Option<u64>,
+    },
+
+    /// Encrypt a directory to a `csync` directory, then keep encrypting whatever changes in it until
+    /// interrupted, like a live encrypted mirror of it.
+    ///
+    /// Changes are noticed with `inotify`, and only the paths that changed are synced. Files and
+    /// directories that are removed or renamed away have their ciphertexts removed. The key is only
+    /// derived once, when `csync watch` starts. The `csync` directory always gets an index, which
+    /// is what finds the ciphertexts of removed files without decrypting every cipherpath.
+    Watch {
+        /// Like `csync encrypt --checksum`.
         #[structopt(long)]
-        key_deriv_by_params: bool,
+        checksum: bool,
 
-        /// Use this algorithm within `pbkdf2`; supported options are `hmac-sha512`.
-        #[structopt(long = "pbkdf2-alg", default_value = "hmac-sha512")]
-        pbkdf2_alg: String,
+        /// Wait until nothing has changed for this many milliseconds before syncing what did, so
+        /// that files that are being written are synced once they are done.
+        #[structopt(long = "debounce", default_value = DEFAULT_DEBOUNCE_MS_STR)]
+        debounce_ms: u64,
 
-        /// Number of iterations for `pbkdf2`; ignored unless `--key-deriv-by-params` is specified.
-        #[structopt(long = "pbkdf2-num-iter", default_value = "131072")]
-        pbkdf2_num_iter: u32,
+        // algorithms and their parameters, shared with `encrypt`
+        #[structopt(flatten)]
+        encryption_opts: EncryptionOpts,
 
-        /// `log_2(n)` parameter for `scrypt`; ignored unless `--key-deriv-by-params` is specified.
-        #[structopt(long, default_value = "15")]
-        scrypt_log_n: u8,
+        /// Like `csync encrypt --exclude`.
+        #[structopt(long = "exclude", number_of_values = 1)]
+        excludes: Vec<String>,
 
-        /// `r` parameter for `scrypt`; ignored unless `--key-deriv-by-params` is specified.
-        #[structopt(long, default_value = "8")]
-        scrypt_r: u32,
+        /// Like `csync encrypt --exclude-from`.
+        #[structopt(long = "exclude-from", number_of_values = 1, parse(from_os_str))]
+        exclude_from_files: Vec<PathBuf>,
 
-        /// `p` parameter for `scrypt`; ignored unless `--key-deriv-by-params` is specified.
-        #[structopt(long, default_value = "1")]
-        scrypt_p: u32,
+        /// Like `csync encrypt --gitignore`.
+        #[structopt(long)]
+        gitignore: bool,
 
-        /// Length of the output of `scrypt`, in bytes.
-        #[structopt(long, default_value = "512")]
-        scrypt_output_len: usize,
+        /// Like `csync encrypt --include`.
+        #[structopt(long = "include", number_of_values = 1)]
+        includes: Vec<String>,
 
-        /// Generate cryptographically secure pseudorandom salts that are this many bytes long.
-        ///
-        /// New salts are generated each time salts are needed.
-        #[structopt(long, default_value = "512")]
-        salt_len: u16,
+        /// Number of threads to use; defaults to the number of cores available on the machine.
+        #[structopt(long = "num-threads")]
+        num_threads_opt: Option<usize>,
 
-        /// The source directory to csync.
+        /// Like `csync encrypt --one-file-system`.
+        #[structopt(long)]
+        one_file_system: bool,
+
+        /// `csync` directory in which compressed/encrypted files will be stored, which can be
+        /// anything `csync encrypt --out-dir` accepts except for a `.csar` archive.
+        #[structopt(short, long, parse(from_os_str))]
+        out_dir: PathBuf,
+
+        /// Read the password from the first line of this file rather than prompting for it.
+        #[structopt(long = "password-file", parse(from_os_str))]
+        password_file_opt: Option<PathBuf>,
+
+        /// The source directory to watch.
         #[structopt(parse(from_os_str))]
         source: PathBuf,
 
-        /// Evenly distribute the compressed/encrypted files into `64^(spread_depth)` different
-        /// directories.
-        #[structopt(long, default_value = "3")]
-        spread_depth: u8,
+        // which config file and profile the defaults of the options come from, shared with every
+        // subcommand
+        #[structopt(flatten)]
+        config_opts: ConfigOpts,
+
+        // what gets logged and where, shared with every subcommand
+        #[structopt(flatten)]
+        log_opts: LogOpts,
 
         /// Suppress the printing of information like step-by-step reporting and timing informations.
         #[structopt(short, long)]
         quiet: bool,
-
-        /// Compression level for `zstd`, allowed range is 1-19.
-        #[structopt(long, default_value = DEFAULT_ZSTD_LEVEL_STR)]
-        zstd_level: u8,
     },
 
     /// Decrypt a `csync` directory back to its plaintext form.
     Decrypt {
+        /// Before overwriting an existing file, rename it to its path with this suffix appended.
+        /// Implies `--overwrite` unless `--newer-wins` or `--incremental` is specified.
+        #[structopt(long = "backup-suffix")]
+        backup_suffix_opt: Option<String>,
+
+        /// Remove files and directories under the decrypted root in `--out-dir` that no longer
+        /// exist in the `csync` directory.
+        #[structopt(long)]
+        delete: bool,
+
+        /// Print what would be created, updated, skipped or deleted under `--out-dir`, and how many
+        /// bytes that involves, without decrypting or writing anything.
+        #[structopt(long)]
+        dry_run: bool,
+
+        /// Allow decrypting to a non-empty `--out-dir`, and only overwrite existing files whose
+        /// size or modified time differ from their plaintexts at the time of encryption.
+        #[structopt(long)]
+        incremental: bool,
+
+        /// Allow decrypting to a non-empty `--out-dir`, and only overwrite existing files that
+        /// were modified before their encrypted counterparts.
+        #[structopt(long)]
+        newer_wins: bool,
+
         /// Use this many threads; defaults to the number of cores available on the machine.
         #[structopt(long = "num-threads")]
         num_threads_opt: Option<usize>,
 
-        /// `csync` directory in which compressed/encrypted files will be stored. This directory
-        /// must be empty or be another `csync` directory that accepts the password from this
-        /// session.
+        /// Directory to write the plaintexts to.
+        ///
+        /// `-` writes the plaintext to stdout instead, if the `csync` directory holds a single file
+        /// or a stream from `csync encrypt -`, which can then be piped to something like `tar x`.
         #[structopt(short, long, parse(from_os_str))]
         out_dir: PathBuf,
 
-        /// The source directory to csync.
+        /// How to report what was done: `human` for text on stderr, `json` for a single JSON object
+        /// on stdout once everything is done, or `ndjson` for a JSON object per line on stdout as
+        /// it happens. Neither of the JSON formats is colored.
+        #[structopt(long, default_value = "human", parse(try_from_str = parse_output_format))]
+        output: OutputFormat,
+
+        /// Allow decrypting to a non-empty `--out-dir`, overwriting any existing files.
+        #[structopt(long)]
+        overwrite: bool,
+
+        /// Only decrypt the root with this name, which is the name of the source or of a `--root`
+        /// that was encrypted. Can be given more than once; every root is decrypted if it isn't.
+        #[structopt(long = "root", number_of_values = 1)]
+        roots: Vec<String>,
+
+        /// Allow decrypting to a non-empty `--out-dir`, leaving any existing files untouched.
+        #[structopt(long)]
+        skip_existing: bool,
+
+        /// Restore the files as they were in the snapshot with this id, rather than as they were
+        /// last encrypted.
+        #[structopt(long = "snapshot")]
+        snapshot_opt: Option<u64>,
+
+        /// The `csync` directory to read from, which can also be an `s3://bucket/prefix` or an
+        /// `sftp://[user@]host[:port]/path` or a `.csar` archive.
+        #[structopt(parse(from_os_str))]
+        source: PathBuf,
+
+        // which config file and profile the defaults of the options come from, shared with every
+        // subcommand
+        #[structopt(flatten)]
+        config_opts: ConfigOpts,
+
+        // what gets logged and where, shared with every subcommand
+        #[structopt(flatten)]
+        log_opts: LogOpts,
+
+        /// Suppress the printing of information like step-by-step reporting and timing informations.
+        #[structopt(short, long)]
+        quiet: bool,
+    },
+
+    /// Decrypt a single file from a `csync` directory and write its plaintext to stdout.
+    Cat {
+        /// The `csync` directory to read from, which can also be an `s3://bucket/prefix` or an
+        /// `sftp://[user@]host[:port]/path` or a `.csar` archive.
+        #[structopt(parse(from_os_str))]
+        source: PathBuf,
+
+        /// Path of the file to decrypt, as it would appear under the output directory of
+        /// `csync decrypt`; for example `src/main.rs` if `src/` was encrypted.
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        // which config file and profile the defaults of the options come from, shared with every
+        // subcommand
+        #[structopt(flatten)]
+        config_opts: ConfigOpts,
+
+        // what gets logged and where, shared with every subcommand
+        #[structopt(flatten)]
+        log_opts: LogOpts,
+
+        /// Suppress the printing of information like step-by-step reporting and timing informations.
+        #[structopt(short, long)]
+        quiet: bool,
+    },
+
+    /// Encrypt the entries of a tar archive to a new `csync` directory, without extracting them
+    /// first. Files, directories and symlinks are stored along with their modes and modified times.
+    ImportTar {
+        /// The tar archive to read, or `-` to read it from stdin, like the output of `tar c`.
+        #[structopt(parse(from_os_str))]
+        archive: PathBuf,
+
+        /// Store each file as chunks, like `csync encrypt --chunked`.
+        #[structopt(long)]
+        chunked: bool,
+
+        // algorithms and their parameters, shared with `encrypt`
+        #[structopt(flatten)]
+        encryption_opts: EncryptionOpts,
+
+        /// Name of the directory that the entries are stored under, which is also where `csync
+        /// decrypt` writes them to. Defaults to the name of the archive without its extension, or
+        /// `stdin` if the archive is `-`.
+        #[structopt(long = "name")]
+        name_opt: Option<String>,
+
+        /// New `csync` directory in which compressed/encrypted files will be stored, which can be
+        /// anything `csync encrypt --out-dir` accepts.
+        #[structopt(short, long, parse(from_os_str))]
+        out_dir: PathBuf,
+
+        /// Read the password from the first line of this file rather than prompting for it, which
+        /// is needed when the archive is `-` and there is no terminal to prompt on.
+        #[structopt(long = "password-file", parse(from_os_str))]
+        password_file_opt: Option<PathBuf>,
+
+        // which config file and profile the defaults of the options come from, shared with every
+        // subcommand
+        #[structopt(flatten)]
+        config_opts: ConfigOpts,
+
+        // what gets logged and where, shared with every subcommand
+        #[structopt(flatten)]
+        log_opts: LogOpts,
+
+        /// Suppress the printing of information like step-by-step reporting and timing informations.
+        #[structopt(short, long)]
+        quiet: bool,
+    },
+
+    /// Decrypt a `csync` directory straight into a tar archive, without writing the plaintexts to
+    /// disk. The contents of the encrypted directory are at the top of the archive, which means
+    /// that archives from `csync import-tar` come back out as they went in.
+    ExportTar {
+        /// Write the archive to this file rather than to stdout.
+        #[structopt(short, long = "out", parse(from_os_str))]
+        out_opt: Option<PathBuf>,
+
+        /// The `csync` directory to read from, which can also be an `s3://bucket/prefix` or an
+        /// `sftp://[user@]host[:port]/path` or a `.csar` archive.
         #[structopt(parse(from_os_str))]
         source: PathBuf,
 
+        // which config file and profile the defaults of the options come from, shared with every
+        // subcommand
+        #[structopt(flatten)]
+        config_opts: ConfigOpts,
+
+        // what gets logged and where, shared with every subcommand
+        #[structopt(flatten)]
+        log_opts: LogOpts,
+
         /// Suppress the printing of information like step-by-step reporting and timing informations.
         #[structopt(short, long)]
         quiet: bool,
     },
 
     /// Clean a `csync` directory by making it as compact as possible.
+    ///
+    /// Snapshots that none of the `--keep-*` options keep are removed, along with every chunk that
+    /// is no longer referenced. Every snapshot is kept if none of them are specified.
     Clean {
+        /// Print which snapshots and chunks would be deleted, and how many bytes they take up,
+        /// without deleting anything.
+        #[structopt(long)]
+        dry_run: bool,
+
+        /// Keep the latest snapshot of each of the last this many days that have one.
+        #[structopt(long = "keep-daily")]
+        keep_daily_opt: Option<usize>,
+
+        /// Keep this many of the latest snapshots.
+        #[structopt(long = "keep-last")]
+        keep_last_opt: Option<usize>,
+
+        /// Keep the latest snapshot of each of the last this many weeks that have one.
+        #[structopt(long = "keep-weekly")]
+        keep_weekly_opt: Option<usize>,
+
         /// Use this many threads; defaults to the number of cores available on the machine.
         #[structopt(long = "num-threads")]
         num_threads_opt: Option<usize>,
 
-        /// The source directory to csync.
+        /// How to report what was done: `human` for text on stderr, `json` for a single JSON object
+        /// on stdout once everything is done, or `ndjson` for a JSON object per line on stdout as
+        /// it happens. Neither of the JSON formats is colored.
+        #[structopt(long, default_value = "human", parse(try_from_str = parse_output_format))]
+        output: OutputFormat,
+
+        /// The `csync` directory to read from, which can also be an `s3://bucket/prefix` or an
+        /// `sftp://[user@]host[:port]/path` or a `.csar` archive.
         #[structopt(parse(from_os_str))]
         source: PathBuf,
 
+        // which config file and profile the defaults of the options come from, shared with every
+        // subcommand
+        #[structopt(flatten)]
+        config_opts: ConfigOpts,
+
+        // what gets logged and where, shared with every subcommand
+        #[structopt(flatten)]
+        log_opts: LogOpts,
+
         /// Suppress the printing of information like step-by-step reporting and timing informations.
         #[structopt(short, long)]
         quiet: bool,
     },
+
+    /// Ask a running `csyncd` about the `csync` directories it keeps in sync, or tell it what to do
+    /// with them. See `csyncd --help` for how to run it.
+    Daemon {
+        // what to ask for
+        #[structopt(subcommand)]
+        command: DaemonCommand,
+
+        /// The socket that `csyncd` listens on, which defaults to `$XDG_RUNTIME_DIR/csyncd.sock`. Its
+        /// directory has to be one that only you can get into.
+        #[structopt(long = "socket", parse(from_os_str))]
+        socket_opt: Option<PathBuf>,
+
+        // which config file and profile the defaults of the options come from, shared with every
+        // subcommand
+        #[structopt(flatten)]
+        config_opts: ConfigOpts,
+
+        // what gets logged and where, shared with every subcommand
+        #[structopt(flatten)]
+        log_opts: LogOpts,
+
+        /// Suppress the printing of information like step-by-step reporting and timing informations.
+        #[structopt(short, long)]
+        quiet: bool,
+    },
+}
+
+/// What `csync daemon` asks `csyncd` for. Repositories are named like the tables that `csyncd`
+/// reads them from, like `laptop` for `[csyncd.laptop]`.
+#[derive(Clone, Debug, StructOpt)]
+pub enum DaemonCommand {
+    /// Print whether each repository is unlocked, and how its last sync went.
+    Status,
+
+    /// Sync a repository now, and wait for it to be done.
+    SyncNow {
+        /// Name of the repository.
+        repo: String,
+    },
+
+    /// Make `csyncd` forget the key of a repository, which isn't synced again until it is unlocked.
+    Lock {
+        /// Name of the repository.
+        repo: String,
+    },
+
+    /// Give `csyncd` a hash of the password of a repository, which it derives the key from and keeps
+    /// in locked memory until the repository is locked again. The repository is synced right away.
+    Unlock {
+        /// Name of the repository.
+        repo: String,
+
+        /// Read the password from the first line of this file rather than prompting for it.
+        #[structopt(long = "password-file", parse(from_os_str))]
+        password_file_opt: Option<PathBuf>,
+    },
+}
+
+/// Algorithms used to encrypt new `csync` directories, and their parameters.
+#[derive(Clone, Debug, StructOpt)]
+pub struct EncryptionOpts {
+    /// Authentication algorithm to use; supported algorithms are [`hmac-sha512`].
+    #[structopt(long, default_value = "hmac-sha512")]
+    pub auth: String,
+
+    /// Encryption algorithm to use; supported algorithms are [`aes256cbc`, `chacha20`].
+    #[structopt(long, default_value = "chacha20")]
+    pub cipher: String,
+
+    /// Compression algorithm to use; supported algorithms are [`zstd`]. `gzip` to come soon!
+    #[structopt(long, default_value = "zstd")]
+    pub compressor: String,
+
+    /// Key-derivation algorithm to use; supported options are `scrypt`, `pbkdf2`.
+    #[structopt(long, default_value = "scrypt")]
+    pub key_deriv_alg: String,
+
+    /// Number of seconds the key derivation process should take on this machine. `csync` will
+    /// approximate the parameters for the key derivation algorithm of your choosing, in order
+    /// to meet this requirement.
+    ///
+    /// Note that this *approximates*: if your key derivation algorithm is `pbkdf2`, `csync`
+    /// can approximate the parameters almost exactly. `scrypt` however, is tricky, and the
+    /// key derivation may take up to, but no more than twice as long as the requested timing.
+    #[structopt(long, default_value = "2")]
+    pub key_deriv_time: u16,
+
+    /// Indicates that key derivation algorithms should use their explicit parameters rather
+    /// than approximating them based on `--key-deriv-time`.
+    ///
+    /// For example, `csync encrypt src -o out` uses `scrypt` with parameters that obey the
+    /// default value of `--key-deriv-time`. Running `csync encrypt src -o out
+    /// --key-deriv-by-params` runs `scrypt` using `--scrypt-log-n`, `--scrypt-r, `--scrypt-p`.
+    #[structopt(long)]
+    pub key_deriv_by_params: bool,
+
+    /// Use this algorithm within `pbkdf2`; supported options are `hmac-sha512`.
+    #[structopt(long = "pbkdf2-alg", default_value = "hmac-sha512")]
+    pub pbkdf2_alg: String,
+
+    /// Number of iterations for `pbkdf2`; ignored unless `--key-deriv-by-params` is specified.
+    #[structopt(long = "pbkdf2-num-iter", default_value = "131072")]
+    pub pbkdf2_num_iter: u32,
+
+    /// `log_2(n)` parameter for `scrypt`; ignored unless `--key-deriv-by-params` is specified.
+    #[structopt(long, default_value = "15")]
+    pub scrypt_log_n: u8,
+
+    /// `r` parameter for `scrypt`; ignored unless `--key-deriv-by-params` is specified.
+    #[structopt(long, default_value = "8")]
+    pub scrypt_r: u32,
+
+    /// `p` parameter for `scrypt`; ignored unless `--key-deriv-by-params` is specified.
+    #[structopt(long, default_value = "1")]
+    pub scrypt_p: u32,
+
+    /// Length of the output of `scrypt`, in bytes; at least 32.
+    #[structopt(long, default_value = "512")]
+    pub scrypt_output_len: usize,
+
+    /// Generate cryptographically secure pseudorandom salts that are this many bytes long.
+    ///
+    /// New salts are generated each time salts are needed. Salts have to be at least 16 bytes long.
+    #[structopt(long, default_value = "512")]
+    pub salt_len: u16,
+
+    /// Evenly distribute the compressed/encrypted files into `64^(spread_depth)` different
+    /// directories; at most 64.
+    #[structopt(long, default_value = "3")]
+    pub spread_depth: u8,
+
+    /// Compression level for `zstd`, allowed range is 1-19.
+    #[structopt(long, default_value = DEFAULT_ZSTD_LEVEL_STR)]
+    pub zstd_level: u8,
+}
+
+/// Where the options that aren't on the command line are read from. They are read before the
+/// command line is parsed, with `config::with_config`; these are only here to document them.
+#[derive(Clone, Debug, StructOpt)]
+pub struct ConfigOpts {
+    /// Read options from this config file rather than from `~/.config/csync/config.toml`, which is
+    /// only read if it exists. Options under `[encrypt]` apply to every `csync encrypt`, and so on.
+    #[structopt(long = "config", parse(from_os_str))]
+    pub config_opt: Option<PathBuf>,
+
+    /// Also apply the options of this profile in the config file, like `nightly` for the options
+    /// under `[encrypt.nightly]`. Options on the command line override the ones in the config file,
+    /// and `--no-<flag>` turns off a flag that the config file turns on, like `--no-checksum`.
+    #[structopt(long = "profile")]
+    pub profile_opt: Option<String>,
+}
+
+/// What `csync` logs about what it is doing, and where.
+#[derive(Clone, Debug, StructOpt)]
+pub struct LogOpts {
+    /// Log more of what is going on: `-v` for each step, `-vv` for why each file is synced or
+    /// skipped, and `-vvv` for everything. Only errors are logged with `--quiet`.
+    #[structopt(short, long, parse(from_occurrences))]
+    pub verbose: u8,
+
+    /// Append the log to this file rather than writing it to stderr.
+    #[structopt(long = "log-file", parse(from_os_str))]
+    pub log_file_opt: Option<PathBuf>,
+
+    /// Log the paths of plaintexts as they are, rather than as `<redacted>`. Keys and passwords are
+    /// never logged.
+    #[structopt(long)]
+    pub log_paths: bool,
+}
+
+/// How `csync` reports what it did; `--output`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+    Ndjson,
+}
+
+impl Opts {
+    /// Parse the command line, along with the options from the config file in `args` and the
+    /// positional arguments from it, like `config::with_config` returns them. The positional
+    /// arguments are only used if the command line is missing them.
+    ///
+    /// Exits with a usage message if the arguments aren't valid, like `StructOpt::from_args` does.
+    pub fn from_config_args(args: Vec<OsString>, positionals: Vec<OsString>) -> Self {
+        Opts::try_from_config_args(args, positionals).unwrap_or_else(|err| err.exit())
+    }
+
+    /// Like `from_config_args`, but returns the usage message rather than exiting with it.
+    pub fn try_from_config_args(args: Vec<OsString>, positionals: Vec<OsString>) -> Result<Self, clap::Error> {
+        let app = Opts::clap();
+        match app.clone().get_matches_from_safe(&args) {
+            Ok(matches) => Ok(Opts::from_clap(&matches)),
+            Err(err) if err.kind == ErrorKind::MissingRequiredArgument && !positionals.is_empty() => Ok(Opts::from_clap(
+                &app.get_matches_from_safe(args.into_iter().chain(positionals))?,
+            )),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// # Returns
+    ///
+    /// What `--output` is, which is `human` for subcommands that don't have it.
+    pub fn output_format(&self) -> OutputFormat {
+        match self {
+            Opts::Encrypt { output, .. } | Opts::Decrypt { output, .. } | Opts::Clean { output, .. } => *output,
+            Opts::Watch { .. } | Opts::Cat { .. } | Opts::ImportTar { .. } | Opts::ExportTar { .. } | Opts::Daemon { .. } => {
+                OutputFormat::Human
+            }
+        }
+    }
+
+    /// # Returns
+    ///
+    /// What gets logged and where, along with whether it is `--quiet`.
+    pub fn log_opts(&self) -> (&LogOpts, bool) {
+        match self {
+            Opts::Encrypt { log_opts, quiet, .. }
+            | Opts::Watch { log_opts, quiet, .. }
+            | Opts::Decrypt { log_opts, quiet, .. }
+            | Opts::Cat { log_opts, quiet, .. }
+            | Opts::ImportTar { log_opts, quiet, .. }
+            | Opts::ExportTar { log_opts, quiet, .. }
+            | Opts::Clean { log_opts, quiet, .. }
+            | Opts::Daemon { log_opts, quiet, .. } => (log_opts, *quiet),
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Whether this is only a `--dry-run`, which subcommands that don't have it never are.
+    pub fn dry_run(&self) -> bool {
+        match self {
+            Opts::Encrypt { dry_run, .. } | Opts::Decrypt { dry_run, .. } | Opts::Clean { dry_run, .. } => *dry_run,
+            Opts::Watch { .. } | Opts::Cat { .. } | Opts::ImportTar { .. } | Opts::ExportTar { .. } | Opts::Daemon { .. } => {
+                false
+            }
+        }
+    }
+}
+
+// smallest volume that an archive can be split into
+const MIN_VOLUME_SIZE: u64 = 1 << 20;
+
+// a number of bytes, with an optional binary suffix like `K`, `M`, `G` or `T`
+fn parse_volume_size(s: &str) -> Result<u64, String> {
+    let (digits, shift) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 10),
+        Some('M') => (&s[..s.len() - 1], 20),
+        Some('G') => (&s[..s.len() - 1], 30),
+        Some('T') => (&s[..s.len() - 1], 40),
+        _ => (s, 0),
+    };
+    match digits.parse::<u64>().ok().and_then(|num| num.checked_mul(1 << shift)) {
+        Some(size) if size >= MIN_VOLUME_SIZE => Ok(size),
+        Some(_) => Err(format!("volumes have to be at least {} bytes", MIN_VOLUME_SIZE)),
+        None => Err(format!("{:?} is not a size like `650M`", s)),
+    }
+}
+
+// one of the formats of `--output`
+fn parse_output_format(s: &str) -> Result<OutputFormat, String> {
+    match s {
+        "human" => Ok(OutputFormat::Human),
+        "json" => Ok(OutputFormat::Json),
+        "ndjson" => Ok(OutputFormat::Ndjson),
+        _ => Err(format!("{:?} is not one of `human`, `json` or `ndjson`", s)),
+    }
+}
+
+// a named root like `etc=/etc`, split at the first `=`
+fn parse_root(s: &str) -> Result<(String, PathBuf), String> {
+    match s.find('=') {
+        Some(i) if 0 < i && i + 1 < s.len() => Ok((s[..i].to_string(), PathBuf::from(&s[i + 1..]))),
+        _ => Err(format!("{:?} is not a root like `etc=/etc`", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volume_sizes() {
+        assert_eq!(parse_volume_size("1048576"), Ok(1 << 20));
+        assert_eq!(parse_volume_size("650M"), Ok(650 << 20));
+        assert_eq!(parse_volume_size("4g"), Ok(4 << 30));
+        assert_eq!(parse_volume_size("1T"), Ok(1 << 40));
+        ["", "M", "1.5G", "-1M", "1K", "99999999999T"]
+            .iter()
+            .for_each(|s| assert!(parse_volume_size(s).is_err(), "{:?}", s));
+    }
+
+    #[test]
+    fn roots() {
+        assert_eq!(parse_root("etc=/etc"), Ok((String::from("etc"), PathBuf::from("/etc"))));
+        assert_eq!(parse_root("a=b=c"), Ok((String::from("a"), PathBuf::from("b=c"))));
+        ["", "etc", "=/etc", "etc="]
+            .iter()
+            .for_each(|s| assert!(parse_root(s).is_err(), "{:?}", s));
+    }
+
+    #[test]
+    fn output_formats() {
+        assert_eq!(parse_output_format("human"), Ok(OutputFormat::Human));
+        assert_eq!(parse_output_format("json"), Ok(OutputFormat::Json));
+        assert_eq!(parse_output_format("ndjson"), Ok(OutputFormat::Ndjson));
+        ["", "JSON", "text"]
+            .iter()
+            .for_each(|s| assert!(parse_output_format(s).is_err(), "{:?}", s));
+    }
 }
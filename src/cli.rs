@@ -1,5 +1,5 @@
-use crate::{hasher::deterministic_hash, prelude::*, secure_vec::*};
-use std::io::Read;
+use csync::{csync_err, hasher::deterministic_hash, CryptoSecureBytes, CsyncResult, SecureBytes};
+use std::{io::Read, path::Path};
 use termion::{
     color::{self, *},
     cursor,
@@ -327,7 +327,10 @@ where
         }
     }
 
-    pub fn handle_keys(mut self) -> SecureBytes {
+    pub fn handle_keys<R>(mut self, input: R) -> SecureBytes
+    where
+        R: Read,
+    {
         if 7 <= self.term_height {
             draw_border(&mut self.screen, self.border_l, self.border_r, self.border_t, self.border_b);
         }
@@ -341,7 +344,7 @@ where
             self.prompt_goto,
             self.pw_portion_width,
         );
-        for key_down in std::io::stdin().keys() {
+        for key_down in input.keys() {
             //
             match key_down.unwrap() {
                 //
@@ -400,10 +403,22 @@ where
     }
 }
 
-pub fn get_password(confirm_password: bool) -> CsyncResult<CryptoSecureBytes> {
+/// # Parameters
+///
+/// 1. `confirm_password`: whether to have the password entered twice
+/// 1. `stdin_is_plaintext`: whether stdin is being read for something else than the password, in
+///    which case the password has to be typed into the terminal
+pub fn get_password(confirm_password: bool, stdin_is_plaintext: bool) -> CsyncResult<CryptoSecureBytes> {
     let isatty = isatty::stderr_isatty();
+    if stdin_is_plaintext && !isatty {
+        csync_err!(
+            CommandLineArgumentConflict,
+            "the password has to be given with `--password-file` when the source is `-` and there is no terminal".to_string()
+        )?;
+    }
+
     let initial = deterministic_hash(match isatty {
-        true => run(false, |_| None),
+        true => run(false, stdin_is_plaintext, |_| None),
         false => {
             let mut buffer = Vec::new();
             std::io::stdin().read_to_end(&mut buffer).unwrap();
@@ -444,7 +459,7 @@ pub fn get_password(confirm_password: bool) -> CsyncResult<CryptoSecureBytes> {
 
     match confirm_password && isatty {
         true => {
-            let confirm = deterministic_hash(run(true, |k| Some(deterministic_hash(k) == initial)));
+            let confirm = deterministic_hash(run(true, stdin_is_plaintext, |k| Some(deterministic_hash(k) == initial)));
             // constant time comparison
             match initial == confirm {
                 true => Ok(initial),
@@ -455,10 +470,18 @@ pub fn get_password(confirm_password: bool) -> CsyncResult<CryptoSecureBytes> {
     }
 }
 
+/// # Returns
+///
+/// The password on the first line of the file at `path`, which isn't confirmed.
+pub fn read_password_file(path: &Path) -> CsyncResult<CryptoSecureBytes> {
+    let password: Vec<_> = std::fs::read(path)?.into_iter().take_while(|byte| *byte != 10u8).collect();
+    Ok(deterministic_hash(password))
+}
+
 // TODO refactor
 //
 // too long
-fn run<F>(confirm: bool, key_matches: F) -> SecureBytes
+fn run<F>(confirm: bool, from_tty: bool, key_matches: F) -> SecureBytes
 where
     F: Fn(SecureBytes) -> Option<bool>,
 {
@@ -466,7 +489,12 @@ where
     let stderr = std::io::stderr().into_raw_mode().unwrap();
     let screen = AlternateScreen::from(stderr);
 
-    CliFrontEnd::new(confirm, term_width, term_height, screen, key_matches).handle_keys()
+    // keys are read from the terminal itself when stdin is taken
+    let input: Box<dyn Read> = match from_tty {
+        true => Box::new(termion::get_tty().unwrap()),
+        false => Box::new(std::io::stdin()),
+    };
+    CliFrontEnd::new(confirm, term_width, term_height, screen, key_matches).handle_keys(input)
 }
 
 fn draw_border<W>(screen: &mut W, border_l: u16, border_r: u16, border_t: u16, border_b: u16)
@@ -0,0 +1,417 @@
+use crate::prelude::*;
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+
+/// A value in a config file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    Array(Vec<Value>),
+}
+
+/// The tables of a config file, which is written in TOML with strings, integers, booleans or arrays
+/// of them as the values of its keys.
+///
+/// Options of a subcommand are under the table with its name, like `[encrypt]`, and apply every
+/// time it runs. Each profile is a table under that, like `[encrypt.nightly]`, whose options apply
+/// on top of those with `--profile nightly`. Keys are the long names of the options, like `out-dir`
+/// or `zstd-level`, along with the names of the positional arguments like `source`. A boolean that
+/// the config file turns on is turned back off with `--no-<name>` on the command line, like
+/// `--no-checksum`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Config {
+    // tables by their dotted names, like `encrypt.nightly`; keys that come before every table are
+    // under ``
+    tables: BTreeMap<String, BTreeMap<String, Value>>,
+}
+
+// positional arguments that can be given in a config file, in the order that they are passed in
+const POSITIONALS: [&str; 3] = ["archive", "source", "path"];
+
+// options with a short form, which also have to be looked for when deciding whether an option was
+// given on the command line
+const SHORTS: [(&str, char); 4] = [("out", 'o'), ("out-dir", 'o'), ("quiet", 'q'), ("verbose", 'v')];
+
+impl Config {
+    /// # Returns
+    ///
+    /// The config in `text`, or a description of where it isn't valid.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let root: toml::value::Table = toml::from_str(text).map_err(|err| err.to_string())?;
+        let mut tables = BTreeMap::new();
+        flatten(String::new(), root, &mut tables)?;
+        Ok(Self { tables })
+    }
+
+    /// # Returns
+    ///
+    /// The config in the file at `path`.
+    pub fn load(path: &Path) -> CsyncResult<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text).map_err(|message| CsyncErr::ConfigInvalid(format!("{:?}: {}", path, message))),
+            Err(err) => csync_err!(ConfigInvalid, format!("{:?}: {}", path, err)),
+        }
+    }
+
+    /// # Returns
+    ///
+    /// The keys in the table named `name`, like `encrypt.nightly`, if there is one.
+    #[inline]
+    pub fn table(&self, name: &str) -> Option<&BTreeMap<String, Value>> {
+        self.tables.get(name)
+    }
+
+    /// # Returns
+    ///
+    /// The profiles of `subcommand` by their names, like `nightly` for `[encrypt.nightly]`.
+    pub fn profiles<'a>(&'a self, subcommand: &str) -> impl Iterator<Item = (&'a str, &'a BTreeMap<String, Value>)> + 'a {
+        let prefix = format!("{}.", subcommand);
+        self.tables
+            .iter()
+            .filter_map(move |(name, values)| name.strip_prefix(&prefix).map(|profile| (profile, values)))
+    }
+
+    /// # Parameters
+    ///
+    /// 1. `subcommand`: like `encrypt`
+    /// 1. `profile_opt`: name of the profile that was asked for, which has to exist if it is given
+    /// 1. `cli_args`: arguments given to the subcommand on the command line
+    ///
+    /// # Returns
+    ///
+    /// Options from the table of `subcommand` and from its profile, that aren't in `cli_args`, and
+    /// the positional arguments from them, which only apply if none are in `cli_args`.
+    pub fn args_of(
+        &self,
+        subcommand: &str,
+        profile_opt: Option<&str>,
+        cli_args: &[OsString],
+    ) -> CsyncResult<(Vec<OsString>, Vec<OsString>)> {
+        let values = self.values_of(subcommand, profile_opt)?;
+
+        let mut options = Vec::new();
+        for (key, value) in values.iter() {
+            let name = key.replace('_', "-");
+            if POSITIONALS.contains(&name.as_str()) || is_given(&name, cli_args) {
+                continue;
+            }
+            if let Value::Bool(_) = value {
+                if is_given(&format!("no-{}", name), cli_args) {
+                    continue;
+                }
+            }
+            let flag = OsString::from(format!("--{}", name));
+            match value {
+                Value::Bool(true) => options.push(flag),
+                Value::Bool(false) => (),
+                Value::Array(elems) => {
+                    for elem in elems {
+                        options.push(flag.clone());
+                        options.push(scalar_arg(key, elem)?);
+                    }
+                }
+                scalar => {
+                    options.push(flag);
+                    options.push(scalar_arg(key, scalar)?);
+                }
+            }
+        }
+
+        let positionals = POSITIONALS
+            .iter()
+            .filter_map(|name| values.get(*name).map(|value| scalar_arg(name, value)))
+            .collect::<CsyncResult<_>>()?;
+        Ok((options, positionals))
+    }
+
+    /// # Returns
+    ///
+    /// `--no-<name>` for each boolean in the table of `subcommand` and in its profile, which only
+    /// keeps the config file from turning them on and means nothing to the argument parser.
+    pub fn negations(&self, subcommand: &str, profile_opt: Option<&str>) -> CsyncResult<Vec<OsString>> {
+        Ok(self
+            .values_of(subcommand, profile_opt)?
+            .iter()
+            .filter(|(_, value)| matches!(value, Value::Bool(_)))
+            .map(|(key, _)| OsString::from(format!("--no-{}", key.replace('_', "-"))))
+            .collect())
+    }
+
+    // the keys in the table of `subcommand`, with those of its profile over them
+    fn values_of(&self, subcommand: &str, profile_opt: Option<&str>) -> CsyncResult<BTreeMap<String, Value>> {
+        let mut values = self.table(subcommand).cloned().unwrap_or_default();
+        if let Some(profile) = profile_opt {
+            match self.table(&format!("{}.{}", subcommand, profile)) {
+                Some(profile_values) => values.extend(profile_values.clone()),
+                None => csync_err!(ConfigInvalid, format!("there is no `[{}.{}]` profile", subcommand, profile))?,
+            }
+        }
+        Ok(values)
+    }
+}
+
+// the values in `table` under the dotted name `name`, and each table in it under its own
+fn flatten(
+    name: String,
+    table: toml::value::Table,
+    tables: &mut BTreeMap<String, BTreeMap<String, Value>>,
+) -> Result<(), String> {
+    let mut values = BTreeMap::new();
+    for (key, item) in table {
+        let dotted_key = match name.is_empty() {
+            true => key.clone(),
+            false => format!("{}.{}", name, key),
+        };
+        match item {
+            toml::Value::Table(table) => flatten(dotted_key, table, tables)?,
+            value => {
+                values.insert(key, from_toml(&dotted_key, value)?);
+            }
+        }
+    }
+    tables.insert(name, values);
+    Ok(())
+}
+
+// the options that `value` can be given as
+fn from_toml(dotted_key: &str, value: toml::Value) -> Result<Value, String> {
+    match value {
+        toml::Value::Boolean(b) => Ok(Value::Bool(b)),
+        toml::Value::Integer(n) => Ok(Value::Int(n)),
+        toml::Value::String(s) => Ok(Value::Str(s)),
+        toml::Value::Array(elems) => elems
+            .into_iter()
+            .map(|elem| from_toml(dotted_key, elem))
+            .collect::<Result<_, _>>()
+            .map(Value::Array),
+        _ => Err(format!(
+            "`{}` has to be a string, an integer, a boolean or an array of them",
+            dotted_key
+        )),
+    }
+}
+
+/// # Returns
+///
+/// Where the config file is read from without `--config`, which is `csync/config.toml` under
+/// `$XDG_CONFIG_HOME` or under `~/.config`.
+pub fn default_config_path() -> Option<PathBuf> {
+    match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(config_home) if !config_home.is_empty() => Some(PathBuf::from(config_home)),
+        _ => std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")),
+    }
+    .map(|config_home| config_home.join("csync").join("config.toml"))
+}
+
+/// # Parameters
+///
+/// 1. `args`: every argument on the command line, including the name of the executable
+///
+/// # Returns
+///
+/// `args` with the options from the config file added, and the positional arguments from it, which
+/// only apply if none are in `args`. Options that are in `args` are left out of the config file, so
+/// a command line like `csync encrypt --profile nightly --zstd-level 19` overrides the profile, and
+/// so does `--no-checksum` for a profile with `checksum = true`.
+pub fn with_config(args: Vec<OsString>) -> CsyncResult<(Vec<OsString>, Vec<OsString>)> {
+    // everything but a subcommand, like `--help`, is left to the argument parser
+    let subcommand = match args.get(1).and_then(|arg| arg.to_str()) {
+        Some(subcommand) if !subcommand.starts_with('-') => subcommand.to_string(),
+        _ => return Ok((args, Vec::new())),
+    };
+    let cli_args = &args[2..];
+
+    let profile_opt = option_value("profile", cli_args);
+    let config = match option_value("config", cli_args) {
+        Some(path) => Config::load(Path::new(&path))?,
+        None => match default_config_path() {
+            Some(path) if path.exists() => Config::load(&path)?,
+            _ => Config::default(),
+        },
+    };
+
+    let (options, positionals) = config.args_of(&subcommand, profile_opt.as_deref(), cli_args)?;
+    let negations = config.negations(&subcommand, profile_opt.as_deref())?;
+    let end_of_options = cli_args.iter().position(|arg| arg == "--").unwrap_or(cli_args.len());
+    let with_options = args[..2]
+        .iter()
+        .cloned()
+        .chain(options)
+        .chain(
+            cli_args
+                .iter()
+                .enumerate()
+                .filter(|(i, arg)| *i >= end_of_options || !negations.contains(arg))
+                .map(|(_, arg)| arg.clone()),
+        )
+        .collect();
+    Ok((with_options, positionals))
+}
+
+// the value of `--name` in `args`, given as either `--name value` or `--name=value`
+fn option_value(name: &str, args: &[OsString]) -> Option<String> {
+    let flag = format!("--{}", name);
+    let prefix = format!("{}=", flag);
+    let mut args = args.iter().filter_map(|arg| arg.to_str()).take_while(|arg| *arg != "--");
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next().map(String::from);
+        } else if arg.starts_with(&prefix) {
+            return Some(arg[prefix.len()..].to_string());
+        }
+    }
+    None
+}
+
+// whether the option with the long name `name` is in `args`, in any of the forms it can be given in
+fn is_given(name: &str, args: &[OsString]) -> bool {
+    let flag = format!("--{}", name);
+    let prefix = format!("{}=", flag);
+    let short_opt = SHORTS.iter().find(|(long, _)| *long == name).map(|(_, short)| *short);
+    args.iter()
+        .filter_map(|arg| arg.to_str())
+        .take_while(|arg| *arg != "--")
+        .any(|arg| match (arg.strip_prefix("--"), arg.strip_prefix('-'), short_opt) {
+            (Some(_), _, _) => arg == flag || arg.starts_with(&prefix),
+            // shorts can be grouped like `-qv`, and the last one can have its value attached like `-oout`
+            (None, Some(shorts), Some(short)) => {
+                shorts.starts_with(short) || (shorts.chars().all(char::is_alphabetic) && shorts.contains(short))
+            }
+            _ => false,
+        })
+}
+
+// `value` as a single command line argument, with a leading `~/` expanded to the home directory
+fn scalar_arg(key: &str, value: &Value) -> CsyncResult<OsString> {
+    match value {
+        Value::Str(s) => match (s.strip_prefix("~/"), std::env::var_os("HOME")) {
+            (Some(rest), Some(home)) => Ok(PathBuf::from(home).join(rest).into_os_string()),
+            _ => Ok(OsString::from(s)),
+        },
+        Value::Int(n) => Ok(OsStr::new(&n.to_string()).to_os_string()),
+        Value::Bool(_) | Value::Array(_) => csync_err!(ConfigInvalid, format!("`{}` has to be a string or an integer", key)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    const CONFIG: &str = r#"
+# applies to every `encrypt`
+[encrypt]
+zstd-level = 9
+exclude = ["target/", '*.log'] # comments can follow
+
+[encrypt.nightly]
+source = "/Ux8e"
+out-dir = "s3://Jb3q/nightly"
+cipher = "aes256cbc"
+checksum = true
+one_file_system = false
+exclude = [
+    "*.tmp",
+]
+"#;
+
+    #[test]
+    fn parses_tables_and_values() {
+        let config = Config::parse(CONFIG).unwrap();
+        let encrypt = config.table("encrypt").unwrap();
+        assert_eq!(encrypt["zstd-level"], Value::Int(9));
+        assert_eq!(
+            encrypt["exclude"],
+            Value::Array(vec![Value::Str(String::from("target/")), Value::Str(String::from("*.log"))])
+        );
+        let nightly = config.table("encrypt.nightly").unwrap();
+        assert_eq!(nightly["checksum"], Value::Bool(true));
+        assert_eq!(nightly["exclude"], Value::Array(vec![Value::Str(String::from("*.tmp"))]));
+        assert_eq!(config.table("decrypt"), None);
+    }
+
+    #[test]
+    fn invalid_configs_say_where() {
+        [
+            ("[encrypt\n", "line 1"),
+            ("a = 1\na = 2\n", "line 2"),
+            ("[a]\n[a]\n", "line 2"),
+            ("a = \"b\n", "line 1"),
+            ("\n\na = b\n", "line 3"),
+            ("a = 1 b = 2\n", "line 1"),
+        ]
+        .iter()
+        .for_each(|(text, line)| match Config::parse(text) {
+            Err(message) => assert!(message.contains(line), "{:?}: {}", text, message),
+            Ok(config) => panic!("{:?} parsed as {:?}", text, config),
+        });
+    }
+
+    #[test]
+    fn profile_is_applied_under_cli_args() {
+        let config = Config::parse(CONFIG).unwrap();
+
+        let (options, positionals) = config
+            .args_of("encrypt", Some("nightly"), &args(&["--zstd-level=3", "-qo", "Vd2n"]))
+            .unwrap();
+        assert_eq!(options, args(&["--checksum", "--cipher", "aes256cbc", "--exclude", "*.tmp"]));
+        assert_eq!(positionals, args(&["/Ux8e"]));
+
+        // without the profile, only what applies to every `encrypt` is used
+        let (options, positionals) = config.args_of("encrypt", None, &[]).unwrap();
+        assert_eq!(
+            options,
+            args(&["--exclude", "target/", "--exclude", "*.log", "--zstd-level", "9"])
+        );
+        assert!(positionals.is_empty());
+
+        assert_eq!(
+            config.args_of("encrypt", Some("Wy7f"), &[]).unwrap_err().exit_code(),
+            CsyncErr::ConfigInvalid(String::new()).exit_code()
+        );
+    }
+
+    #[test]
+    fn booleans_are_turned_off_on_the_command_line() {
+        let config = Config::parse(CONFIG).unwrap();
+
+        let (options, _) = config.args_of("encrypt", Some("nightly"), &args(&["--no-checksum"])).unwrap();
+        assert!(!options.contains(&OsString::from("--checksum")));
+        assert_eq!(
+            config.negations("encrypt", Some("nightly")).unwrap(),
+            args(&["--no-checksum", "--no-one-file-system"])
+        );
+        assert!(config.negations("encrypt", None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn only_values_that_are_options_parse() {
+        ["a = 1.5\n", "a = 1979-05-27\n", "[[a]]\nb = 1\n"]
+            .iter()
+            .for_each(|text| assert!(Config::parse(text).is_err(), "{:?}", text));
+    }
+
+    #[test]
+    fn profiles_are_listed_by_name() {
+        let config = Config::parse(CONFIG).unwrap();
+        let profiles: Vec<_> = config.profiles("encrypt").map(|(name, _)| name).collect();
+        assert_eq!(profiles, vec!["nightly"]);
+        assert_eq!(config.profiles("decrypt").count(), 0);
+    }
+
+    #[test]
+    fn option_values() {
+        let cli_args = args(&["src", "--profile", "Kc4s", "--config=Rn6t", "--", "--profile"]);
+        assert_eq!(option_value("profile", &cli_args), Some(String::from("Kc4s")));
+        assert_eq!(option_value("config", &cli_args), Some(String::from("Rn6t")));
+        assert_eq!(option_value("out-dir", &cli_args), None);
+    }
+}
@@ -1,21 +1,29 @@
 use crate::{
-    crypt::util::*,
+    crypt::{chunk::*, snapshot::*, storage::*, util::*},
     fs_util::*,
     prelude::*,
     primitives::*,
     secure_vec::*,
-    specs::{action_spec::*, syncer_spec::*},
+    specs::{action_spec::*, conflict_spec::*, file_meta_spec::*, syncer_spec::*},
+    util::*,
 };
+use filetime::{set_file_mtime, set_symlink_file_times, FileTime};
 use std::{
+    ffi::OsString,
     fmt::Debug,
-    fs::{metadata, rename, File, Permissions},
-    os::unix::fs::PermissionsExt,
+    fs::{metadata, read_link, rename, symlink_metadata, File, Permissions},
+    io::Read,
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::{symlink, PermissionsExt},
+    },
     path::{Path, PathBuf},
 };
 
 /// Conceptually a mapping from some path `src` to a different path `dest`.
 ///
-/// `src` and `dest` are guarantede to be unique
+/// `src` and `dest` are guarantede to be unique. Whichever of them is in the `csync` dir is a key
+/// in its `Storage`, rather than a path on the filesystem.
 #[derive(Debug)]
 pub struct Action<'a> {
     pub dest: PathBuf,
@@ -23,6 +31,12 @@ pub struct Action<'a> {
     action_spec: ActionSpec,
     syncer_spec: &'a SyncerSpec,
     file_type: FileType,
+    // sizes of `src` and `dest` in bytes, measured while manifesting
+    lens_opt: Option<(u64, u64)>,
+    // size of the plaintext as of when it was scanned, if it was
+    recorded_len_opt: Option<u64>,
+    // present if the plaintext is restored from a snapshot rather than decrypted from `src`
+    snapshot_entry_opt: Option<SnapshotEntry>,
 }
 
 ///
@@ -30,6 +44,7 @@ impl<'a> Action<'a> {
     /// # Parameters
     ///
     /// SALTS IN ENCRYPTION CONFIG WILL BE OVERWRTTEN
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         syncer_spec: &'a SyncerSpec,
         salt_len: u16,
@@ -37,6 +52,7 @@ impl<'a> Action<'a> {
         dest: &Path,
         file_type: FileType,
         unix_mode_opt: Option<u32>,
+        file_meta_opt: Option<&FileMetaSpec>,
         key_hash: &DerivedKey,
     ) -> CsyncResult<Action<'a>> {
         macro_rules! get_unix_mode {
@@ -46,48 +62,140 @@ impl<'a> Action<'a> {
         };
 
         macro_rules! action {
-            ( $cipher_spec:expr, $unix_mode:expr, $key_hash:expr ) => {
+            ( $cipher_spec:expr, $unix_mode:expr, $chunked:expr, $key_hash:expr ) => {
                 Ok(Action {
-                    action_spec: ActionSpec::new(&$cipher_spec.resalt(salt_len), salt_len, $unix_mode, $key_hash)?,
+                    action_spec: ActionSpec::new(
+                        &$cipher_spec.resalt(salt_len),
+                        salt_len,
+                        $unix_mode,
+                        file_meta_opt,
+                        $chunked,
+                        $key_hash,
+                    )?,
                     dest: dest.to_path_buf(),
                     file_type,
+                    lens_opt: None,
+                    recorded_len_opt: file_meta_opt.map(FileMetaSpec::get_len),
+                    snapshot_entry_opt: None,
                     src: src.to_path_buf(),
                     syncer_spec,
                 })
             };
         };
         match syncer_spec {
-            SyncerSpec::Encrypt { cipher_spec, .. } => action!(cipher_spec, unix_mode_opt.or(get_unix_mode!()), key_hash),
-            SyncerSpec::Decrypt { cipher_spec, .. } => action!(cipher_spec, unix_mode_opt.or(get_unix_mode!()), key_hash),
+            SyncerSpec::Encrypt {
+                chunked, cipher_spec, ..
+            } => action!(
+                cipher_spec,
+                // `src` is only read if it has to be, as it might not exist
+                match unix_mode_opt {
+                    Some(unix_mode) => Some(unix_mode),
+                    None => get_unix_mode!(),
+                },
+                *chunked && file_type == FileType::File,
+                key_hash
+            ),
+            // the mode of the plaintext is recorded in `src`
+            SyncerSpec::Decrypt { cipher_spec, .. } => action!(cipher_spec, unix_mode_opt, false, key_hash),
             SyncerSpec::Clean { .. } => todo!(),
         }
     }
 
+    /// # Returns
+    ///
+    /// `self`, but restoring the plaintext from `snapshot_entry` when manifested, rather than
+    /// decrypting it from `src`.
+    #[inline]
+    pub fn with_snapshot_entry(self, snapshot_entry: &SnapshotEntry) -> Self {
+        Self {
+            snapshot_entry_opt: Some(snapshot_entry.clone()),
+            ..self
+        }
+    }
+
+    #[inline]
+    pub fn get_file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    /// # Returns
+    ///
+    /// Sizes of `src` and `dest` in bytes, if this action has been manifested.
+    #[inline]
+    pub fn get_lens(&self) -> Option<(u64, u64)> {
+        self.lens_opt
+    }
+
+    /// # Returns
+    ///
+    /// Size of the plaintext in bytes as of when it was scanned, if it was.
+    #[inline]
+    pub fn get_recorded_len(&self) -> Option<u64> {
+        self.recorded_len_opt
+    }
+
+    /// # Returns
+    ///
+    /// Metadata of the plaintext recorded in this action, if any.
+    #[inline]
+    pub fn get_file_meta(&self, key_hash: &DerivedKey) -> CsyncResult<Option<FileMetaSpec>> {
+        self.action_spec.get_file_meta(key_hash)
+    }
+
     /// # Parameters
     ///
     /// 1. `arena`: some directory such that  `Action`
-    pub fn manifest(self, arena: &Path, key_hash: &DerivedKey) -> CsyncResult<Self> {
+    /// 1. `storage`: where the `csync` dir is stored
+    pub fn manifest(self, arena: &Path, storage: &dyn Storage, key_hash: &DerivedKey) -> CsyncResult<Self> {
         //
-        let tid = thread_id::get().to_string();
-        let action_arena = arena.join(tid);
-
-        //
-        create_dir_all_if_nexists(&action_arena)?;
+        let action_arena = action_arena(arena)?;
 
         //
         match &self.syncer_spec {
-            SyncerSpec::Encrypt { .. } => self.encrypt(&action_arena, key_hash),
-            SyncerSpec::Decrypt { .. } => self.decrypt(&action_arena, key_hash),
+            SyncerSpec::Encrypt { .. } => self.encrypt(&action_arena, storage, key_hash, Option::<File>::None),
+            SyncerSpec::Decrypt { .. } => self.decrypt(&action_arena, storage, key_hash),
             _ => todo!(),
         }
     }
 
+    /// Like `manifest`, but encrypts the plaintext read from `src` rather than the file at
+    /// `self.src`, which doesn't have to exist.
+    ///
+    /// # Parameters
     ///
-    fn encrypt(self, action_arena: &Path, key_hash: &DerivedKey) -> CsyncResult<Self> {
+    /// 1. `src`: the plaintext of a file or the target of a symlink, read until its end
+    pub fn manifest_from<R>(self, arena: &Path, storage: &dyn Storage, key_hash: &DerivedKey, src: R) -> CsyncResult<Self>
+    where
+        R: Read,
+    {
+        match (&self.syncer_spec, self.file_type) {
+            (SyncerSpec::Encrypt { .. }, FileType::File) | (SyncerSpec::Encrypt { .. }, FileType::Symlink) => {
+                self.encrypt(&action_arena(arena)?, storage, key_hash, Some(src))
+            }
+            _ => csync_err!(
+                UnsupportedOperation,
+                String::from("only files and symlinks can be encrypted from a reader")
+            ),
+        }
+    }
+
+    // `src_opt` is read instead of `self.src`, if present
+    fn encrypt<R>(
+        mut self,
+        action_arena: &Path,
+        storage: &dyn Storage,
+        key_hash: &DerivedKey,
+        src_opt: Option<R>,
+    ) -> CsyncResult<Self>
+    where
+        R: Read,
+    {
         let tmp_dest = action_arena.join("Action_encrypt");
 
         remove(&tmp_dest)?;
-        {
+        let mut src_opt = src_opt.map(CountingReader::new);
+        let dest_len = {
+            let mut dest = CountingWriter::new(fopen_w(&tmp_dest)?);
             // use a macro to circumvent the type system
             macro_rules! csync {
                 ( $get_src:expr ) => {
@@ -96,46 +204,81 @@ impl<'a> Action<'a> {
                         &self.action_spec,
                         action_arena,
                         $get_src,
-                        &mut fopen_w(&tmp_dest)?,
+                        &mut dest,
                         key_hash,
                     )?
                 };
             };
-            match self.file_type {
-                FileType::File => csync!(fopen_r(&self.src)?),
-                FileType::Dir => {
+            match (self.file_type, self.syncer_spec) {
+                // the ciphertext holds references to the chunks, rather than the contents
+                (FileType::File, _) if self.action_spec.is_chunked() => {
+                    let chunk_refs = match &mut src_opt {
+                        Some(src) => store_chunks(self.syncer_spec, action_arena, src, storage, key_hash)?,
+                        None => store_chunks(self.syncer_spec, action_arena, fopen_r(&self.src)?, storage, key_hash)?,
+                    };
+                    let chunk_refs_ser = serialize(&chunk_refs)?;
+                    csync!(chunk_refs_ser.as_ref());
+                }
+                (FileType::File, _) => match &mut src_opt {
+                    Some(src) => csync!(src),
+                    None => csync!(fopen_r(&self.src)?),
+                },
+                // the plaintext of a symlink is its target
+                (FileType::Symlink, _) => match &mut src_opt {
+                    Some(src) => csync!(src),
+                    None => csync!(read_link(&self.src)?.as_os_str().as_bytes()),
+                },
+                (FileType::Dir, _) => {
                     let rand_bytes = rng!(MIN_DIR_RAND_DATA_LEN, MAX_DIR_RAND_DATA_LEN);
                     csync!(rand_bytes.0.unsecure())
                 }
             };
-        }
-
-        // eqivalent to `mkdir --parents "$(dirname $dest)"`
-        match self.dest.parent() {
-            Some(parent) => create_dir_all_if_nexists(parent)?,
-            None => (),
+            dest.count
         };
 
         // swap
-        rename(tmp_dest, &self.dest)?;
+        storage.put(&self.dest, &tmp_dest)?;
 
+        // the plaintext isn't looked at again, so its size is either counted or the one recorded
+        let src_len = match (&src_opt, self.recorded_len_opt) {
+            (Some(src), _) => src.count,
+            (None, recorded_len_opt) => recorded_len_opt.unwrap_or(0),
+        };
+        self.lens_opt = Some((src_len, dest_len));
         Ok(self)
     }
 
     ///
-    fn decrypt(self, action_arena: &Path, key_hash: &DerivedKey) -> CsyncResult<Self> {
+    fn decrypt(mut self, action_arena: &Path, storage: &dyn Storage, key_hash: &DerivedKey) -> CsyncResult<Self> {
         let tmp_dest = action_arena.join("Action_decrypt");
 
         remove(&tmp_dest)?;
-        let (_, action_spec) = csync_decrypt(
-            fopen_r(&self.src)?,
-            match self.file_type {
-                FileType::File => Some(fopen_w(&tmp_dest)?),
-                FileType::Dir => None,
-            },
-            key_hash,
-        )?;
+        let (action_spec, dest_len) = match (&self.snapshot_entry_opt, self.file_type, self.syncer_spec) {
+            // the metadata from the snapshot was recorded in `self.action_spec`
+            (Some(snapshot_entry), _, SyncerSpec::Decrypt { .. }) => {
+                let dest_len = match &snapshot_entry.chunk_refs {
+                    Some(chunk_refs) => restore_chunks(chunk_refs, storage, fopen_w(&tmp_dest)?, key_hash)?,
+                    None => 0,
+                };
+                (self.action_spec.clone(), dest_len)
+            }
+            (None, FileType::File, SyncerSpec::Decrypt { .. }) | (None, FileType::Symlink, SyncerSpec::Decrypt { .. }) => {
+                let mut dest = CountingWriter::new(fopen_w(&tmp_dest)?);
+                let action_spec = csync_decrypt_file(storage, &self.src, &mut dest, key_hash)?;
+                (action_spec, dest.count)
+            }
+            _ => (csync_decrypt(storage.get(&self.src)?, Option::<File>::None, key_hash)?.1, 0),
+        };
+        // a snapshot holds every file it restores, so the size of the file is the one it recorded
+        let src_len = match (&self.snapshot_entry_opt, self.recorded_len_opt) {
+            (Some(_), Some(recorded_len)) => recorded_len,
+            _ => storage.stat(&self.src)?.map_or(0, |object_meta| object_meta.len),
+        };
+        self.lens_opt = Some((src_len, dest_len));
 
+        if let SyncerSpec::Decrypt { out_dir, .. } = self.syncer_spec {
+            check_no_symlink_parents(out_dir, &self.dest)?;
+        }
         match self.dest.parent() {
             Some(parent) => create_dir_all_if_nexists(parent)?,
             None => (),
@@ -143,14 +286,54 @@ impl<'a> Action<'a> {
         match self.file_type {
             FileType::File => (),
             FileType::Dir => create_dir_all_if_nexists(&tmp_dest)?,
+            // `tmp_dest` holds the target, which the symlink takes the place of
+            FileType::Symlink => {
+                let target = OsString::from_vec(std::fs::read(&tmp_dest)?);
+                remove(&tmp_dest)?;
+                symlink(target, &tmp_dest)?;
+            }
         };
 
-        // set permission bits of `tmp_dest`
-        {
+        // set permission bits of `tmp_dest`; symlinks have none of their own, and setting them
+        // would change whatever they point to
+        if self.file_type != FileType::Symlink {
             let permission = Permissions::from_mode(action_spec.get_unix_mode().unwrap());
             File::open(&tmp_dest)?.set_permissions(permission)?;
         }
 
+        // restore the modified time of files, so that they can be compared against later; not
+        // done for directories, as their modified times change as their contents get decrypted
+        match (self.file_type, action_spec.get_file_meta(key_hash)?) {
+            (FileType::File, Some(file_meta)) => {
+                set_file_mtime(&tmp_dest, FileTime::from_system_time(file_meta.get_modified()))?
+            }
+            (FileType::Symlink, Some(file_meta)) => {
+                let mtime = FileTime::from_system_time(file_meta.get_modified());
+                set_symlink_file_times(&tmp_dest, mtime, mtime)?
+            }
+            _ => (),
+        };
+
+        // make room for `tmp_dest` if something is already at `self.dest`; directories get merged
+        match (self.syncer_spec, symlink_metadata(&self.dest)) {
+            (SyncerSpec::Decrypt { conflict_spec, .. }, Ok(meta)) if !(self.file_type == FileType::Dir && meta.is_dir()) => {
+                match conflict_spec {
+                    ConflictSpec::Fail => (),
+                    // something appeared at `self.dest` after it was checked
+                    ConflictSpec::SkipExisting => return Ok(self),
+                    ConflictSpec::Overwrite { .. } | ConflictSpec::NewerWins { .. } | ConflictSpec::Incremental { .. } => {
+                        match conflict_spec.backup_suffix() {
+                            Some(suffix) => rename(&self.dest, with_suffix(&self.dest, suffix))?,
+                            None if meta.is_dir() => std::fs::remove_dir_all(&self.dest)?,
+                            // `rename` replaces existing files
+                            None => (),
+                        }
+                    }
+                }
+            }
+            _ => (),
+        };
+
         match rename(&tmp_dest, &self.dest) {
             Ok(_) => Ok(self),
             Err(_) if self.file_type == FileType::Dir && self.dest.is_dir() => Ok(self),
@@ -159,6 +342,35 @@ impl<'a> Action<'a> {
     }
 }
 
+// `path` with `suffix` appended to its basename
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path_os_string = path.as_os_str().to_os_string();
+    path_os_string.push(suffix);
+    PathBuf::from(path_os_string)
+}
+
+// a directory under `arena` that only the current thread uses
+fn action_arena(arena: &Path) -> CsyncResult<PathBuf> {
+    let action_arena = arena.join(thread_id::get().to_string());
+    create_dir_all_if_nexists(&action_arena)?;
+    Ok(action_arena)
+}
+
+// none of the directories between `out_dir` and `dest` can be a symlink, as whatever is decrypted
+// to `dest` would be written wherever it points to, which can be outside of `out_dir`
+fn check_no_symlink_parents(out_dir: &Path, dest: &Path) -> CsyncResult<()> {
+    let parents = dest
+        .ancestors()
+        .skip(1)
+        .take_while(|parent| parent.starts_with(out_dir) && *parent != out_dir);
+    for parent in parents {
+        if symlink_metadata(parent).is_ok_and(|meta| meta.file_type().is_symlink()) {
+            csync_err!(DestinationThroughSymlink, parent.to_path_buf())?;
+        }
+    }
+    Ok(())
+}
+
 fn create_dir_all_if_nexists<P>(path: P) -> std::io::Result<()>
 where
     P: AsRef<Path>,
@@ -168,3 +380,79 @@ where
         false => std::fs::create_dir_all(&path),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crypt::storage::mem::MemStorage,
+        specs::{key_deriv_spec_ext::KeyDerivSpecExt, prelude::*},
+    };
+    use std::convert::TryFrom;
+
+    #[test]
+    fn manifested_actions_round_trip_through_a_storage() {
+        let dir = tmpdir!().unwrap();
+        let storage = MemStorage::default();
+        let key_hash = DerivedKey(sha512!(&b"Rk2pW9vMx4TqZe7n".to_vec().into()));
+        let src = dir.path().join("Hd6sJ1cV");
+        std::fs::write(&src, "Yb8nQ3fL").unwrap();
+
+        for chunked in [false, true].iter() {
+            let syncer_spec = SyncerSpec::try_from(&SyncerSpecExt::Encrypt {
+                auth_spec: AuthenticatorSpec::HmacSha512,
+                checksum: false,
+                chunked: *chunked,
+                cipher_spec: CipherSpec::ChaCha20 {
+                    init_vec: CryptoSecureBytes(rng!(16).0),
+                },
+                compressor_spec: CompressorSpec::Zstd { level: 3 },
+                filter_spec: FilterSpec::default(),
+                index: false,
+                kd_spec_ext: KeyDerivSpecExt::Pbkdf2ByParams {
+                    alg_opt: None,
+                    num_iter: 1,
+                    salt_len: 16,
+                },
+                out_dir: dir.path().to_path_buf(),
+                roots: Vec::new(),
+                snapshot: false,
+                source: dir.path().to_path_buf(),
+                spread_depth: 3,
+                source_kind: SourceKind::Path,
+                verbose: false,
+                volume_size_opt: None,
+                salt_len: 16,
+            })
+            .unwrap();
+
+            let cipherpath = PathBuf::from("a/b/c/Mw5tG0kX.csync");
+            let action = Action::new(&syncer_spec, 16, &src, &cipherpath, FileType::File, None, None, &key_hash).unwrap();
+            action.manifest(dir.path(), &storage, &key_hash).unwrap();
+            assert!(storage.exists(&cipherpath).unwrap());
+
+            let mut plaintext = Vec::new();
+            let action_spec = csync_decrypt_file(&storage, &cipherpath, &mut plaintext, &key_hash).unwrap();
+            assert_eq!(plaintext, b"Yb8nQ3fL");
+            assert_eq!(action_spec.is_chunked(), *chunked);
+        }
+    }
+
+    #[test]
+    fn nothing_is_decrypted_through_a_symlink() {
+        let out_dir = tmpdir!().unwrap();
+        let elsewhere = tmpdir!().unwrap();
+        symlink(elsewhere.path(), out_dir.path().join("Qn3b")).unwrap();
+        std::fs::create_dir(out_dir.path().join("Wc8f")).unwrap();
+
+        let exit_code = CsyncErr::DestinationThroughSymlink(PathBuf::new()).exit_code();
+        for dest in ["Qn3b/Lx5k", "Qn3b/Lx5k/Dm1r"].iter() {
+            let err = check_no_symlink_parents(out_dir.path(), &out_dir.path().join(dest)).unwrap_err();
+            assert_eq!(err.exit_code(), exit_code);
+        }
+        // the symlink itself can be replaced, and nothing else is one
+        for dest in ["Qn3b", "Wc8f/Lx5k", "Tz6p/Lx5k"].iter() {
+            check_no_symlink_parents(out_dir.path(), &out_dir.path().join(dest)).unwrap();
+        }
+    }
+}
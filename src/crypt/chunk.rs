@@ -0,0 +1,374 @@
+use crate::{
+    crypt::{storage::*, util::*},
+    encoder::{crypt_encoder::*, hmac::*, text::*},
+    fs_util::*,
+    hasher::KeyPurpose,
+    prelude::*,
+    secure_vec::*,
+    specs::prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::TryInto,
+    ffi::OsStr,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+// bounds on the length of a chunk, in bytes; the average length is `1 << CHUNK_MASK_BITS`
+const MIN_CHUNK_LEN: usize = 1 << 18;
+const MAX_CHUNK_LEN: usize = 1 << 22;
+const CHUNK_MASK_BITS: u32 = 20;
+
+/// A reference to one chunk of a file that was stored chunked.
+///
+/// The plaintext of such a file's ciphertext is the serialized `Vec<ChunkRef>` of its chunks, in
+/// order.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct ChunkRef {
+    id: String,
+    len: u64,
+}
+
+impl ChunkRef {
+    #[inline]
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Splits the bytes from a source into content-defined chunks, using a gear-based rolling hash.
+///
+/// Chunk boundaries depend only on the bytes around them, so an insertion or deletion only
+/// changes the chunks around it. The gear table is derived from the key, so that the boundaries
+/// don't reveal anything about the contents either.
+pub struct Chunker<R>
+where
+    R: Read,
+{
+    gear: Vec<u64>,
+    source: BufReader<R>,
+}
+
+impl<R> Chunker<R>
+where
+    R: Read,
+{
+    /// # Parameters
+    ///
+    /// 1. `source`: the bytes to split into chunks
+    /// 1. `key_hash`: the derived key whose chunk boundary subkey the gear table is derived from
+    pub fn new(source: R, key_hash: &DerivedKey) -> Self {
+        let gear_bytes = rng_seed!(&key_hash.subkey(KeyPurpose::ChunkBoundary).0, 256 * 8);
+        let gear = gear_bytes
+            .0
+            .unsecure()
+            .chunks(8)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+
+        Self {
+            gear,
+            source: BufReader::with_capacity(DEFAULT_BUFFER_SIZE, source),
+        }
+    }
+}
+
+impl<R> Iterator for Chunker<R>
+where
+    R: Read,
+{
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mask = (1u64 << CHUNK_MASK_BITS) - 1;
+        let mut chunk = Vec::new();
+        let mut hash = 0u64;
+
+        loop {
+            let buffer = match self.source.fill_buf() {
+                Ok([]) => break,
+                Ok(buffer) => buffer,
+                Err(err) => return Some(Err(err)),
+            };
+
+            // look for a boundary in what has been buffered
+            let mut consumed = 0;
+            let mut at_boundary = false;
+            for byte in buffer {
+                consumed += 1;
+                hash = (hash << 1).wrapping_add(self.gear[*byte as usize]);
+
+                let len = chunk.len() + consumed;
+                if MIN_CHUNK_LEN <= len && (hash & mask == 0 || MAX_CHUNK_LEN <= len) {
+                    at_boundary = true;
+                    break;
+                }
+            }
+
+            chunk.extend_from_slice(&buffer[..consumed]);
+            self.source.consume(consumed);
+            if at_boundary {
+                break;
+            }
+        }
+
+        match chunk.is_empty() {
+            true => None,
+            false => Some(Ok(chunk)),
+        }
+    }
+}
+
+// key of the chunk with `id`; `CHUNK_DIRNAME` can't collide with spread directories, as `-` is not
+// in `BASE32PATH`
+fn chunk_key(id: &str) -> PathBuf {
+    Path::new(CHUNK_DIRNAME)
+        .join(&id[..2])
+        .join(format!("{}.{}", id, CHUNK_SUFFIX))
+}
+
+/// # Returns
+///
+/// `true` if anything has been stored chunked in `storage`.
+#[inline]
+pub fn has_chunks(storage: &dyn Storage) -> bool {
+    storage.list(Path::new(CHUNK_DIRNAME)).next().is_some()
+}
+
+/// # Returns
+///
+/// The id and key of every chunk in `storage`.
+pub fn stored_chunks<'a>(storage: &'a dyn Storage) -> impl Iterator<Item = CsyncResult<(String, PathBuf)>> + 'a {
+    storage.list(Path::new(CHUNK_DIRNAME)).filter_map(|key_res| match key_res {
+        Ok(key) => match key.extension() == Some(OsStr::new(CHUNK_SUFFIX)) {
+            true => key
+                .file_stem()
+                .and_then(OsStr::to_str)
+                .map(str::to_string)
+                .map(|id| Ok((id, key))),
+            false => None,
+        },
+        Err(err) => Some(Err(err)),
+    })
+}
+
+// keyed hash of `chunk`, which doesn't reveal whether 2 chunks are equal without `id_key`, the
+// chunk id subkey of the derived key
+fn chunk_id(chunk: &[u8], id_key: &DerivedKey) -> CsyncResult<String> {
+    let mut hmac_encoder = compose_encoders!(chunk, HmacEncoder => (&id_key.0, None))?;
+    hmac_encoder.read_all_to(&mut io::sink())?;
+    let hash = csync_unwrap_opt!(hmac_encoder.get_result());
+
+    compose_encoders!(
+        &hash.0.unsecure()[..32],
+        TextEncoder => &BASE32PATH
+    )?
+    .as_string()
+}
+
+/// Split `source` into chunks, and encrypt each chunk that isn't already in `storage`.
+///
+/// # Parameters
+///
+/// 1. `syncer_spec`: spec to encrypt each chunk with
+/// 1. `arena`: some directory in which only the calling thread creates and removes files
+/// 1. `source`: the plaintext to store
+/// 1. `storage`: where the chunks are stored
+/// 1. `key_hash`: the derived key to use in encrypting data
+///
+/// # Returns
+///
+/// References to the chunks of `source`, in order.
+pub fn store_chunks<R>(
+    syncer_spec: &SyncerSpec,
+    arena: &Path,
+    source: R,
+    storage: &dyn Storage,
+    key_hash: &DerivedKey,
+) -> CsyncResult<Vec<ChunkRef>>
+where
+    R: Read,
+{
+    let (cipher_spec, salt_len) = match syncer_spec {
+        SyncerSpec::Encrypt {
+            cipher_spec, salt_len, ..
+        } => (cipher_spec, *salt_len),
+        _ => panic!("Chunks should only be stored when encrypting"),
+    };
+    let tmp_dest = arena.join("store_chunks");
+    let id_key = key_hash.subkey(KeyPurpose::ChunkId);
+
+    Chunker::new(source, key_hash)
+        .map(|chunk_res| {
+            let chunk = chunk_res?;
+            let id = chunk_id(&chunk, &id_key)?;
+
+            // identical chunks are only stored once
+            let dest = chunk_key(&id);
+            if !storage.exists(&dest)? {
+                let action_spec = ActionSpec::new(&cipher_spec.resalt(salt_len), salt_len, None, None, false, key_hash)?;
+                csync_encrypt(
+                    syncer_spec,
+                    &action_spec,
+                    arena,
+                    &chunk[..],
+                    &mut fopen_w(&tmp_dest)?,
+                    key_hash,
+                )?;
+                storage.put(&dest, &tmp_dest)?;
+            }
+
+            Ok(ChunkRef {
+                id,
+                len: chunk.len() as u64,
+            })
+        })
+        .collect()
+}
+
+/// Decrypt the chunks in `chunk_refs` in order, and write them to `dest`.
+///
+/// # Parameters
+///
+/// 1. `chunk_refs`: chunks to decrypt
+/// 1. `storage`: where the chunks are stored
+/// 1. `dest`: where to write the plaintext
+/// 1. `key_hash`: the derived key that the chunks were encrypted with
+///
+/// # Returns
+///
+/// Number of bytes written to `dest`.
+pub fn restore_chunks<W>(chunk_refs: &[ChunkRef], storage: &dyn Storage, mut dest: W, key_hash: &DerivedKey) -> CsyncResult<u64>
+where
+    W: Write,
+{
+    let id_key = key_hash.subkey(KeyPurpose::ChunkId);
+    chunk_refs.iter().try_fold(0, |bytes_written, chunk_ref| {
+        let mut chunk = Vec::with_capacity(chunk_ref.len as usize);
+        csync_decrypt(storage.get(&chunk_key(&chunk_ref.id))?, Some(&mut chunk), key_hash)?;
+
+        // each chunk is authenticated on its own, but it also has to be the one that was referenced
+        match chunk.len() as u64 == chunk_ref.len && chunk_id(&chunk, &id_key)? == chunk_ref.id {
+            true => {
+                dest.write_all(&chunk)?;
+                Ok(bytes_written + chunk_ref.len)
+            }
+            false => csync_err!(AuthenticationFail),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypt::storage::mem::MemStorage;
+    use std::convert::TryFrom;
+
+    fn chunks(data: &[u8], key_hash: &DerivedKey) -> Vec<Vec<u8>> {
+        Chunker::new(data, key_hash).map(Result::unwrap).collect()
+    }
+
+    // seeded, so that boundaries that happen to be forced by `MAX_CHUNK_LEN` don't make tests flaky
+    fn seeded_data(len: usize) -> Vec<u8> {
+        let seed = CryptoSecureBytes(b"Xg7qLm2VcR9sKt4w".to_vec().into());
+        rng_seed!(&seed, len).0.unsecure().to_vec()
+    }
+
+    fn key_hash(key: &str) -> DerivedKey {
+        DerivedKey(sha512!(&key.to_string().into()))
+    }
+
+    #[test]
+    fn chunks_concat_to_source() {
+        let key_hash = key_hash("dQ3wX7nRk1YbZ5mJ");
+        [0, 1, MIN_CHUNK_LEN, MAX_CHUNK_LEN + 1, 3 * MAX_CHUNK_LEN]
+            .iter()
+            .for_each(|len| {
+                let data = seeded_data(*len);
+                let chunks = chunks(&data, &key_hash);
+
+                assert_eq!(chunks.concat(), data);
+                chunks.iter().rev().skip(1).for_each(|chunk| {
+                    assert!(MIN_CHUNK_LEN <= chunk.len() && chunk.len() <= MAX_CHUNK_LEN);
+                });
+            });
+    }
+
+    #[test]
+    fn boundaries_survive_insertion() {
+        let key_hash = key_hash("Tp8cV2hLs6QeWn0u");
+        let data = seeded_data(8 * MAX_CHUNK_LEN);
+        let shifted: Vec<_> = b"inserted".iter().chain(data.iter()).copied().collect();
+
+        // every chunk but the first is shared after an insertion at the start
+        let chunks_orig = chunks(&data, &key_hash);
+        let chunks_shifted = chunks(&shifted, &key_hash);
+        assert_eq!(chunks_orig[1..], chunks_shifted[1..]);
+    }
+
+    #[test]
+    fn chunks_round_trip_through_storage() {
+        let dir = tmpdir!().unwrap();
+        let syncer_spec = SyncerSpec::try_from(&SyncerSpecExt::Encrypt {
+            auth_spec: AuthenticatorSpec::HmacSha512,
+            checksum: false,
+            chunked: true,
+            cipher_spec: CipherSpec::ChaCha20 {
+                init_vec: CryptoSecureBytes(rng!(16).0),
+            },
+            compressor_spec: CompressorSpec::Zstd { level: 3 },
+            filter_spec: FilterSpec::default(),
+            index: false,
+            kd_spec_ext: KeyDerivSpecExt::ScryptByParams {
+                log_n: 4,
+                r: 8,
+                p: 1,
+                output_len: 512,
+                salt_len: 16,
+            },
+            out_dir: dir.path().to_path_buf(),
+            roots: Vec::new(),
+            snapshot: false,
+            source: dir.path().to_path_buf(),
+            spread_depth: 3,
+            source_kind: SourceKind::Path,
+            verbose: false,
+            volume_size_opt: None,
+            salt_len: 16,
+        })
+        .unwrap();
+        let key_hash = key_hash("Wm5rJ8tBq2XeNc6y");
+        let storage = MemStorage::default();
+        let data = seeded_data(4 * MAX_CHUNK_LEN);
+
+        // storing the same data again doesn't store any more chunks
+        let chunk_refs = store_chunks(&syncer_spec, dir.path(), &data[..], &storage, &key_hash).unwrap();
+        let num_chunks = stored_chunks(&storage).count();
+        assert_eq!(
+            store_chunks(&syncer_spec, dir.path(), &data[..], &storage, &key_hash).unwrap(),
+            chunk_refs
+        );
+        assert_eq!(stored_chunks(&storage).count(), num_chunks);
+
+        let mut restored = Vec::new();
+        restore_chunks(&chunk_refs, &storage, &mut restored, &key_hash).unwrap();
+        assert_eq!(restored, data);
+    }
+
+    #[test]
+    fn boundaries_depend_on_key() {
+        let data = seeded_data(8 * MAX_CHUNK_LEN);
+
+        let chunks_1 = chunks(&data, &key_hash("Fy4aK9oGz2DrMs7t"));
+        let chunks_2 = chunks(&data, &key_hash("Nb1eH5uCx8WqLj3v"));
+        assert_ne!(chunks_1, chunks_2);
+    }
+
+    #[test]
+    fn no_chunks_without_a_chunk_dir() {
+        let out_dir = tmpdir!().unwrap();
+        let storage = crate::crypt::storage::DirStorage::new(out_dir.path());
+        assert_eq!(stored_chunks(&storage).count(), 0);
+    }
+}
@@ -1,4 +1,9 @@
 mod action;
+mod chunk;
+mod snapshot;
+mod storage;
 mod util;
 
 pub mod syncer;
+
+pub use self::util::{csync_decrypt, csync_encrypt};
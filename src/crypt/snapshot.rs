@@ -0,0 +1,171 @@
+use crate::{
+    crypt::{chunk::*, storage::*, util::*},
+    fs_util::*,
+    prelude::*,
+    primitives::*,
+    secure_vec::*,
+    specs::prelude::*,
+    util::*,
+};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// What a snapshot records about a single plaintext.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SnapshotEntry {
+    pub chunk_refs: Option<Vec<ChunkRef>>, // `None` for directories
+    pub file_meta: FileMetaSpec,
+    pub file_type: FileType,
+    pub unix_mode: u32,
+}
+
+/// The state of every plaintext in a `csync` dir at the time of an encryption, stored encrypted
+/// in the `csync` dir itself.
+///
+/// Files are referenced by their chunks, which are never modified once they are written, so a
+/// snapshot can be restored until it gets pruned, regardless of what gets encrypted after it.
+///
+/// Each snapshot is identified by the time it was created at, in milliseconds since the unix
+/// epoch.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Snapshot {
+    entries: BTreeMap<PathBuf, SnapshotEntry>, // keyed by the plaintext paths that `subpath_par` returns
+}
+
+/// # Returns
+///
+/// Key of the snapshot with `id`.
+#[inline]
+pub fn snapshot_key(id: u64) -> PathBuf {
+    Path::new(SNAPSHOT_DIRNAME).join(format!("{}.{}", id, SNAPSHOT_SUFFIX))
+}
+
+/// # Returns
+///
+/// `true` if any snapshot is stored in `storage`.
+#[inline]
+pub fn has_snapshots(storage: &dyn Storage) -> bool {
+    storage.list(Path::new(SNAPSHOT_DIRNAME)).next().is_some()
+}
+
+/// # Returns
+///
+/// Ids of every snapshot in `storage`, oldest first.
+pub fn snapshot_ids(storage: &dyn Storage) -> CsyncResult<Vec<u64>> {
+    let mut ids = Vec::new();
+    for key_res in storage.list(Path::new(SNAPSHOT_DIRNAME)) {
+        let key = key_res?;
+        if key.extension() == Some(OsStr::new(SNAPSHOT_SUFFIX)) {
+            match key.file_stem().and_then(OsStr::to_str).map(str::parse) {
+                Some(Ok(id)) => ids.push(id),
+                _ => csync_err!(Other, format!("{:?} is not a snapshot", key))?,
+            }
+        }
+    }
+
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+impl Snapshot {
+    #[inline]
+    pub fn new(entries: BTreeMap<PathBuf, SnapshotEntry>) -> Self {
+        Self { entries }
+    }
+
+    /// # Parameters
+    ///
+    /// 1. `storage`: where the `csync` dir that the snapshot is in is stored
+    /// 1. `id`: id of the snapshot to load
+    /// 1. `derived_key`: the key that the `csync` dir was encrypted with
+    pub fn load(storage: &dyn Storage, id: u64, derived_key: &DerivedKey) -> CsyncResult<Self> {
+        let key = snapshot_key(id);
+        match storage.exists(&key)? {
+            true => {
+                let mut serialized = Vec::new();
+                csync_decrypt(storage.get(&key)?, Some(&mut serialized), derived_key)?;
+                deserialize(&serialized)
+            }
+            false => csync_err!(SnapshotNotFound, id),
+        }
+    }
+
+    /// # Parameters
+    ///
+    /// 1. `syncer_spec`: spec of the `csync` dir, which the snapshot is encrypted with
+    /// 1. `arena`: some directory in which only the calling thread creates and removes files
+    /// 1. `storage`: where to store the snapshot
+    /// 1. `derived_key`: the key that the `csync` dir was encrypted with
+    ///
+    /// # Returns
+    ///
+    /// Id of the stored snapshot.
+    pub fn store(
+        &self,
+        syncer_spec: &SyncerSpec,
+        arena: &Path,
+        storage: &dyn Storage,
+        derived_key: &DerivedKey,
+    ) -> CsyncResult<u64> {
+        match syncer_spec {
+            SyncerSpec::Encrypt {
+                cipher_spec, salt_len, ..
+            } => {
+                let action_spec = ActionSpec::new(&cipher_spec.resalt(*salt_len), *salt_len, None, None, false, derived_key)?;
+                let tmp_dest = arena.join("Snapshot_store");
+
+                csync_encrypt(
+                    syncer_spec,
+                    &action_spec,
+                    arena,
+                    serialize(self)?.as_ref(),
+                    &mut fopen_w(&tmp_dest)?,
+                    derived_key,
+                )?;
+
+                // ids have to be unique, even if 2 snapshots are created within a millisecond
+                let mut id = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+                while storage.exists(&snapshot_key(id))? {
+                    id += 1;
+                }
+
+                storage.put(&snapshot_key(id), &tmp_dest)?;
+                Ok(id)
+            }
+            _ => panic!("Snapshots should only be stored when encrypting"),
+        }
+    }
+
+    /// Remove the snapshot with `id` from `storage`. The chunks it references are left in place.
+    #[inline]
+    pub fn remove(storage: &dyn Storage, id: u64) -> CsyncResult<()> {
+        storage.delete(&snapshot_key(id))
+    }
+
+    #[inline]
+    pub fn par_entries(&self) -> impl ParallelIterator<Item = (&PathBuf, &SnapshotEntry)> {
+        self.entries.par_iter()
+    }
+
+    #[inline]
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.entries.keys()
+    }
+
+    /// # Returns
+    ///
+    /// Ids of every chunk that this snapshot references, possibly with duplicates.
+    pub fn chunk_ids(&self) -> impl Iterator<Item = &str> {
+        self.entries
+            .values()
+            .filter_map(|entry| entry.chunk_refs.as_ref())
+            .flatten()
+            .map(ChunkRef::get_id)
+    }
+}
@@ -0,0 +1,574 @@
+use crate::{
+    crypt::{storage::*, util::*},
+    fs_util::*,
+    secure_vec::*,
+    specs::prelude::*,
+    util::*,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    convert::TryInto,
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    io::{self, Seek, SeekFrom, Write},
+    sync::{Mutex, RwLock},
+};
+
+// at the start of the first volume and the end of the last one
+const MAGIC: &[u8; 4] = b"CSAR";
+const VERSION: u8 = 1;
+
+// magic, version, the length of each volume, and where the footer that was flushed last ends
+const HEADER_LEN: u64 = 4 + 1 + 8 + 8;
+// where in the header the end of the footer that was flushed last is
+const FLUSHED_END_OFFSET: u64 = 4 + 1 + 8;
+// offset and length of the table of contents, and magic
+const FOOTER_LEN: u64 = 8 + 8 + 4;
+
+const COPY_BUF_LEN: usize = 1 << 20;
+
+/// Where an object is in an archive.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+struct TocEntry {
+    len: u64,
+    modified: SystemTime,
+    offset: u64,
+}
+
+/// Packs every object into a single file, for destinations that would rather have one file than
+/// a tree of directories.
+///
+/// An archive consists of:
+/// 1. a header, which records the length of each volume and where the footer that was flushed
+///    last ends
+/// 1. the objects, one after another
+/// 1. the table of contents, which records where each object is and is encrypted like any other
+///    ciphertext
+/// 1. a footer, which records where the table of contents is
+///
+/// Archives can be split into volumes of a fixed length, named `<path>`, `<path>.1`, `<path>.2`
+/// and so on, which together hold the same bytes that a single file would.
+///
+/// The table of contents can only be read once the archive is unlocked, and is written when it's
+/// flushed. Replaced and deleted objects are left where they are, so archives only ever grow.
+///
+/// Each flush appends a new table of contents and footer after everything else, leaving the old
+/// ones where they are too. An archive whose last run stopped before it was flushed opens as it
+/// was when it was last flushed, from the footer that its header records.
+#[derive(Debug)]
+pub struct ArchiveStorage {
+    path: PathBuf,
+    tail: Mutex<Tail>,
+    toc: RwLock<Option<BTreeMap<PathBuf, TocEntry>>>, // `None` until unlocked
+    unlocked: RwLock<Option<(SyncerSpec, DerivedKey)>>,
+    volume_len: RwLock<u64>, // 0 if the archive isn't split
+}
+
+// the end of the archive, where objects get appended
+#[derive(Debug)]
+struct Tail {
+    dirty: bool, // `true` once the table of contents needs to be written again
+    end: u64,
+    file: Option<(u64, File)>,     // the volume being appended to, and its index
+    toc_range: Option<(u64, u64)>, // offset and length of the table of contents that was written last
+}
+
+impl ArchiveStorage {
+    /// # Parameters
+    ///
+    /// 1. `path`: path of the archive, or of its first volume; it doesn't have to exist
+    pub fn open(path: &Path) -> CsyncResult<Self> {
+        let not_an_archive = || CsyncErr::StorageFailed(format!("{:?} is not a complete `csync` archive", path));
+
+        let (volume_len, end, toc_range) = match path.exists() {
+            true => {
+                let mut header = [0u8; HEADER_LEN as usize];
+                fopen_r(path)?.read_exact(&mut header).map_err(|_| not_an_archive())?;
+                if &header[..4] != MAGIC || header[4] != VERSION {
+                    Err(not_an_archive())?;
+                }
+                let volume_len = u64::from_le_bytes(header[5..FLUSHED_END_OFFSET as usize].try_into()?);
+                let flushed_end = u64::from_le_bytes(header[FLUSHED_END_OFFSET as usize..].try_into()?);
+
+                // every volume but the last is full
+                let mut end = 0;
+                for (index, volume) in (0..).map(|index| (index, volume_path(path, index))) {
+                    match std::fs::metadata(&volume) {
+                        Ok(meta) if end == index * volume_len => end += meta.len(),
+                        Ok(_) => Err(not_an_archive())?,
+                        Err(_) => break,
+                    }
+                    if volume_len == 0 {
+                        break;
+                    }
+                }
+                if end < HEADER_LEN + FOOTER_LEN {
+                    Err(not_an_archive())?;
+                }
+
+                // anything after the footer that was flushed last is from a run that stopped before
+                // it was flushed
+                let footer_opt = match read_footer(path, volume_len, end)? {
+                    Some(toc_range) => Some(toc_range),
+                    None if flushed_end <= end => read_footer(path, volume_len, flushed_end)?,
+                    None => None,
+                };
+                match footer_opt {
+                    Some(toc_range) => (volume_len, end, Some(toc_range)),
+                    None => Err(not_an_archive())?,
+                }
+            }
+            false => (0, 0, None),
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            tail: Mutex::new(Tail {
+                dirty: false,
+                end,
+                file: None,
+                toc_range,
+            }),
+            // a new archive has nothing to unlock
+            toc: RwLock::new(match toc_range {
+                Some(_) => None,
+                None => Some(BTreeMap::new()),
+            }),
+            unlocked: RwLock::new(None),
+            volume_len: RwLock::new(volume_len),
+        })
+    }
+
+    #[inline]
+    fn read_range(&self, offset: u64, len: u64) -> CsyncResult<Box<dyn Read + Send>> {
+        read_range(&self.path, *self.volume_len.read().unwrap(), offset, len)
+    }
+
+    #[inline]
+    fn locked_err(&self) -> CsyncErr {
+        CsyncErr::StorageFailed(format!("{:?} has to be unlocked before it can be read", self.path))
+    }
+
+    // get ready to append to the archive, the first time it's modified since it was last flushed;
+    // the old table of contents and footer stay until a new pair is written after them
+    fn start_writing(&self, tail: &mut Tail) -> CsyncResult<()> {
+        if !tail.dirty {
+            tail.file = None;
+            if tail.toc_range.is_none() {
+                let mut header = Vec::with_capacity(HEADER_LEN as usize);
+                header.extend_from_slice(MAGIC);
+                header.push(VERSION);
+                header.extend_from_slice(&self.volume_len.read().unwrap().to_le_bytes());
+                header.extend_from_slice(&0u64.to_le_bytes());
+                self.append(tail, &header[..])?;
+            }
+            tail.dirty = true;
+        }
+        Ok(())
+    }
+
+    // # Returns
+    //
+    // Offset of the appended bytes.
+    fn append<R>(&self, tail: &mut Tail, mut src: R) -> CsyncResult<u64>
+    where
+        R: Read,
+    {
+        let volume_len = *self.volume_len.read().unwrap();
+        let start = tail.end;
+        let mut buf = vec![0u8; COPY_BUF_LEN];
+        loop {
+            let num_read = match src.read(&mut buf) {
+                Ok(0) => break,
+                Ok(num_read) => num_read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => Err(err)?,
+            };
+
+            let mut num_written = 0;
+            while num_written < num_read {
+                let (index, volume_offset) = locate(tail.end, volume_len);
+                let num_to_write = match volume_len {
+                    0 => num_read - num_written,
+                    _ => std::cmp::min((volume_len - volume_offset) as usize, num_read - num_written),
+                };
+
+                let file = match &mut tail.file {
+                    Some((file_index, file)) if *file_index == index => file,
+                    file_opt => {
+                        // nothing more gets written to the volume that was being appended to, so
+                        // it is synced now rather than when the archive is flushed
+                        if let Some((_, prev_file)) = file_opt.take() {
+                            prev_file.sync_all()?;
+                        }
+                        let mut file = OpenOptions::new()
+                            .create(true)
+                            .truncate(false)
+                            .write(true)
+                            .open(volume_path(&self.path, index))?;
+                        file.seek(SeekFrom::Start(volume_offset))?;
+                        &mut file_opt.insert((index, file)).1
+                    }
+                };
+                file.write_all(&buf[num_written..num_written + num_to_write])?;
+
+                num_written += num_to_write;
+                tail.end += num_to_write as u64;
+            }
+        }
+        Ok(start)
+    }
+}
+
+impl Storage for ArchiveStorage {
+    fn put(&self, key: &Path, local: &Path) -> CsyncResult<()> {
+        let mut tail = self.tail.lock().unwrap();
+        if self.toc.read().unwrap().is_none() {
+            Err(self.locked_err())?;
+        }
+
+        self.start_writing(&mut tail)?;
+        let offset = self.append(&mut tail, fopen_r(local)?)?;
+        let entry = TocEntry {
+            len: tail.end - offset,
+            modified: SystemTime::now(),
+            offset,
+        };
+        csync_unwrap_opt!(self.toc.write().unwrap().as_mut()).insert(key.to_path_buf(), entry);
+
+        std::fs::remove_file(local)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &Path) -> CsyncResult<Box<dyn Read + Send>> {
+        let entry_opt = match self.toc.read().unwrap().as_ref() {
+            Some(toc) => toc.get(key).copied(),
+            None => Err(self.locked_err())?,
+        };
+        match entry_opt {
+            Some(TocEntry { offset, len, .. }) => self.read_range(offset, len),
+            None => csync_err!(StorageFailed, format!("no object at {:?} in {:?}", key, self.path)),
+        }
+    }
+
+    fn stat(&self, key: &Path) -> CsyncResult<Option<ObjectMeta>> {
+        match self.toc.read().unwrap().as_ref() {
+            Some(toc) => Ok(toc.get(key).map(|entry| ObjectMeta {
+                len: entry.len,
+                modified: entry.modified,
+            })),
+            None => Err(self.locked_err()),
+        }
+    }
+
+    fn list<'a>(&'a self, prefix: &Path) -> Box<dyn Iterator<Item = CsyncResult<PathBuf>> + Send + 'a> {
+        match self.toc.read().unwrap().as_ref() {
+            Some(toc) => {
+                let keys: Vec<_> = toc.keys().filter(|key| key.starts_with(prefix)).cloned().collect();
+                Box::new(keys.into_iter().map(Ok))
+            }
+            None => Box::new(std::iter::once(Err(self.locked_err()))),
+        }
+    }
+
+    fn delete(&self, key: &Path) -> CsyncResult<()> {
+        let mut tail = self.tail.lock().unwrap();
+        let mut toc = self.toc.write().unwrap();
+        match toc.as_mut().map(|toc| toc.remove(key)) {
+            Some(Some(_)) => self.start_writing(&mut tail),
+            Some(None) => csync_err!(StorageFailed, format!("no object at {:?} in {:?}", key, self.path)),
+            None => Err(self.locked_err()),
+        }
+    }
+
+    fn locked_ciphertext(&self) -> CsyncResult<Option<Box<dyn Read + Send>>> {
+        let toc_range = self.tail.lock().unwrap().toc_range;
+        match (self.toc.read().unwrap().as_ref(), toc_range) {
+            (None, Some((toc_offset, toc_len))) => Ok(Some(self.read_range(toc_offset, toc_len)?)),
+            _ => Ok(None),
+        }
+    }
+
+    fn unlock(&self, syncer_spec: &SyncerSpec, derived_key: &DerivedKey) -> CsyncResult<()> {
+        let toc_range = self.tail.lock().unwrap().toc_range;
+        let mut toc = self.toc.write().unwrap();
+        match (toc.as_ref(), toc_range) {
+            (None, Some((toc_offset, toc_len))) => {
+                let mut serialized = Vec::new();
+                csync_decrypt(self.read_range(toc_offset, toc_len)?, Some(&mut serialized), derived_key)?;
+                *toc = Some(deserialize(&serialized)?);
+            }
+            // a new archive is split the way this run asks for
+            (_, None) => {
+                if let SyncerSpec::Encrypt { volume_size_opt, .. } = syncer_spec {
+                    *self.volume_len.write().unwrap() = volume_size_opt.unwrap_or(0);
+                }
+            }
+            _ => (),
+        }
+
+        *self.unlocked.write().unwrap() = Some((syncer_spec.clone(), derived_key.clone()));
+        Ok(())
+    }
+
+    fn flush(&self, arena: &Path) -> CsyncResult<()> {
+        let mut tail = self.tail.lock().unwrap();
+        if !tail.dirty {
+            return Ok(());
+        }
+
+        match (self.unlocked.read().unwrap().as_ref(), self.toc.read().unwrap().as_ref()) {
+            (
+                Some((
+                    syncer_spec @ SyncerSpec::Encrypt {
+                        cipher_spec, salt_len, ..
+                    },
+                    derived_key,
+                )),
+                Some(toc),
+            ) => {
+                let action_spec = ActionSpec::new(&cipher_spec.resalt(*salt_len), *salt_len, None, None, false, derived_key)?;
+                let tmp_dest = arena.join("ArchiveStorage_flush");
+                csync_encrypt(
+                    syncer_spec,
+                    &action_spec,
+                    arena,
+                    serialize(toc)?.as_ref(),
+                    &mut fopen_w(&tmp_dest)?,
+                    derived_key,
+                )?;
+
+                let toc_offset = self.append(&mut tail, fopen_r(&tmp_dest)?)?;
+                let toc_len = tail.end - toc_offset;
+                let mut footer = Vec::with_capacity(FOOTER_LEN as usize);
+                footer.extend_from_slice(&toc_offset.to_le_bytes());
+                footer.extend_from_slice(&toc_len.to_le_bytes());
+                footer.extend_from_slice(MAGIC);
+                self.append(&mut tail, &footer[..])?;
+                if let Some((_, file)) = &tail.file {
+                    file.sync_all()?;
+                }
+
+                // only recorded once everything that the footer points to is on disk
+                let mut first_volume = OpenOptions::new().write(true).open(&self.path)?;
+                first_volume.seek(SeekFrom::Start(FLUSHED_END_OFFSET))?;
+                first_volume.write_all(&tail.end.to_le_bytes())?;
+                first_volume.sync_all()?;
+                remove(&tmp_dest)?;
+
+                tail.dirty = false;
+                tail.toc_range = Some((toc_offset, toc_len));
+                Ok(())
+            }
+            _ => panic!("Archives should only be modified when encrypting"),
+        }
+    }
+}
+
+// path of the volume at `index`
+fn volume_path(path: &Path, index: u64) -> PathBuf {
+    match index {
+        0 => path.to_path_buf(),
+        _ => {
+            let mut volume: OsString = path.as_os_str().to_owned();
+            volume.push(format!(".{}", index));
+            PathBuf::from(volume)
+        }
+    }
+}
+
+// # Returns
+//
+// Offset and length of the table of contents that the footer ending at `footer_end` in the archive
+// at `path` records, if there is one there. It's only taken to be a footer if the table of
+// contents it records ends right where it starts.
+fn read_footer(path: &Path, volume_len: u64, footer_end: u64) -> CsyncResult<Option<(u64, u64)>> {
+    if footer_end < HEADER_LEN + FOOTER_LEN {
+        return Ok(None);
+    }
+    let footer_start = footer_end - FOOTER_LEN;
+    let mut footer = [0u8; FOOTER_LEN as usize];
+    read_range(path, volume_len, footer_start, FOOTER_LEN)?.read_exact(&mut footer)?;
+
+    let toc_offset = u64::from_le_bytes(footer[..8].try_into()?);
+    let toc_len = u64::from_le_bytes(footer[8..16].try_into()?);
+    match &footer[16..] == MAGIC && toc_offset >= HEADER_LEN && toc_offset.checked_add(toc_len) == Some(footer_start) {
+        true => Ok(Some((toc_offset, toc_len))),
+        false => Ok(None),
+    }
+}
+
+// index of the volume that `offset` is in, and where in that volume it is
+#[inline]
+fn locate(offset: u64, volume_len: u64) -> (u64, u64) {
+    match volume_len {
+        0 => (0, offset),
+        _ => (offset / volume_len, offset % volume_len),
+    }
+}
+
+// `len` bytes starting at `offset`, across as many volumes as they span
+fn read_range(path: &Path, volume_len: u64, offset: u64, len: u64) -> CsyncResult<Box<dyn Read + Send>> {
+    let mut reader: Box<dyn Read + Send> = Box::new(io::empty());
+    let (mut offset, end) = (offset, offset + len);
+    while offset < end {
+        let (index, volume_offset) = locate(offset, volume_len);
+        let num_to_read = match volume_len {
+            0 => end - offset,
+            _ => std::cmp::min(volume_len - volume_offset, end - offset),
+        };
+
+        let mut volume = fopen_r(volume_path(path, index))?;
+        volume.seek(SeekFrom::Start(volume_offset))?;
+        reader = Box::new(reader.chain(volume.take(num_to_read)));
+        offset += num_to_read;
+    }
+    Ok(reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashSet, convert::TryFrom};
+
+    // spec of a run that encrypts to the archive at `path`
+    fn syncer_spec(path: &Path, volume_size_opt: Option<u64>) -> SyncerSpec {
+        SyncerSpec::try_from(&SyncerSpecExt::Encrypt {
+            auth_spec: AuthenticatorSpec::HmacSha512,
+            checksum: false,
+            chunked: false,
+            cipher_spec: CipherSpec::ChaCha20 {
+                init_vec: CryptoSecureBytes(rng!(16).0),
+            },
+            compressor_spec: CompressorSpec::Zstd { level: 3 },
+            filter_spec: FilterSpec::default(),
+            index: false,
+            kd_spec_ext: KeyDerivSpecExt::ScryptByParams {
+                log_n: 4,
+                r: 8,
+                p: 1,
+                output_len: 512,
+                salt_len: 16,
+            },
+            out_dir: path.to_path_buf(),
+            roots: Vec::new(),
+            snapshot: false,
+            source: path.parent().unwrap().to_path_buf(),
+            spread_depth: 3,
+            source_kind: SourceKind::Path,
+            verbose: false,
+            volume_size_opt,
+            salt_len: 16,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn flushed_archives_can_be_reopened() {
+        let dir = tmpdir!().unwrap();
+        let arena = tmpdir!().unwrap();
+        let path = dir.path().join("Hy7cN2qWs5vB.csar");
+        let syncer_spec = syncer_spec(&path, Some(1000));
+        let derived_key = DerivedKey(sha512!(&"Pt6vQ2mWx9LcR4nJ".to_string().into()));
+
+        let put = |storage: &ArchiveStorage, key: &str, contents: &[u8]| {
+            let local = arena.path().join("local");
+            std::fs::write(&local, contents).unwrap();
+            storage.put(Path::new(key), &local).unwrap();
+        };
+        let get = |storage: &ArchiveStorage, key: &str| {
+            let mut contents = Vec::new();
+            storage.get(Path::new(key)).unwrap().read_to_end(&mut contents).unwrap();
+            contents
+        };
+        let reopen = || {
+            let storage = ArchiveStorage::open(&path).unwrap();
+            assert!(storage.locked_ciphertext().unwrap().is_some());
+            assert!(storage.stat(Path::new("a/Xc3vB8nM")).is_err());
+            storage.unlock(&syncer_spec, &derived_key).unwrap();
+            storage
+        };
+        let first = rng!(2500).0.unsecure().to_vec();
+        let second = b"second".to_vec();
+
+        let storage = ArchiveStorage::open(&path).unwrap();
+        assert!(storage.locked_ciphertext().unwrap().is_none());
+        storage.unlock(&syncer_spec, &derived_key).unwrap();
+        put(&storage, "a/Xc3vB8nM", &first);
+        put(&storage, "Zx9cV2bN", &second);
+        storage.flush(arena.path()).unwrap();
+        assert!(volume_path(&path, 3).exists());
+
+        let storage = reopen();
+        assert_eq!(get(&storage, "a/Xc3vB8nM"), first);
+        assert_eq!(get(&storage, "Zx9cV2bN"), second);
+
+        // the table of contents gets replaced
+        storage.delete(Path::new("Zx9cV2bN")).unwrap();
+        put(&storage, "Qw1eR4tY", b"third");
+        storage.flush(arena.path()).unwrap();
+
+        let storage = reopen();
+        assert_eq!(
+            storage.list(Path::new("")).map(Result::unwrap).collect::<HashSet<_>>(),
+            vec!["a/Xc3vB8nM", "Qw1eR4tY"].into_iter().map(PathBuf::from).collect()
+        );
+        assert_eq!(get(&storage, "a/Xc3vB8nM"), first);
+        assert_eq!(get(&storage, "Qw1eR4tY"), b"third");
+
+        // a missing volume is noticed
+        std::fs::remove_file(volume_path(&path, 1)).unwrap();
+        assert!(ArchiveStorage::open(&path).is_err());
+    }
+
+    #[test]
+    fn unflushed_archives_open_as_last_flushed() {
+        let dir = tmpdir!().unwrap();
+        let arena = tmpdir!().unwrap();
+        let path = dir.path().join("Lm4xT8cVq1Rz.csar");
+        let syncer_spec = syncer_spec(&path, Some(1000));
+        let derived_key = DerivedKey(sha512!(&"Wq8bN3kZs6YhD1fC".to_string().into()));
+
+        let put = |storage: &ArchiveStorage, key: &str, contents: &[u8]| {
+            let local = arena.path().join("local");
+            std::fs::write(&local, contents).unwrap();
+            storage.put(Path::new(key), &local).unwrap();
+        };
+        let reopen = || {
+            let storage = ArchiveStorage::open(&path).unwrap();
+            storage.unlock(&syncer_spec, &derived_key).unwrap();
+            storage
+        };
+        let keys = |storage: &ArchiveStorage| storage.list(Path::new("")).map(Result::unwrap).collect::<HashSet<_>>();
+        let first = rng!(2500).0.unsecure().to_vec();
+
+        let storage = reopen();
+        put(&storage, "a/Gt5nW2xK", &first);
+        storage.flush(arena.path()).unwrap();
+
+        // dropped without being flushed, after objects that span volumes were added and one removed
+        let storage = reopen();
+        storage.delete(Path::new("a/Gt5nW2xK")).unwrap();
+        put(&storage, "Vc9mJ4pR", rng!(2500).0.unsecure());
+        drop(storage);
+
+        let storage = reopen();
+        assert_eq!(keys(&storage), vec![PathBuf::from("a/Gt5nW2xK")].into_iter().collect());
+        let mut contents = Vec::new();
+        storage
+            .get(Path::new("a/Gt5nW2xK"))
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, first);
+
+        // and carries on from there
+        put(&storage, "Yd2hB7sN", b"second");
+        storage.flush(arena.path()).unwrap();
+        assert_eq!(
+            keys(&reopen()),
+            vec!["a/Gt5nW2xK", "Yd2hB7sN"].into_iter().map(PathBuf::from).collect()
+        );
+    }
+}
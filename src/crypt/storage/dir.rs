@@ -0,0 +1,78 @@
+use crate::{crypt::storage::*, fs_util::*, util::*};
+use std::{fs::rename, io};
+use walkdir::WalkDir;
+
+/// Stores each object as a file under `root`, at the path of its key.
+///
+/// This is the layout that `csync` dirs have always had, so a `csync` dir can be moved around like
+/// any other directory.
+#[derive(Clone, Debug)]
+pub struct DirStorage {
+    root: PathBuf,
+}
+
+impl DirStorage {
+    #[inline]
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+
+    // where the object at `key` is stored
+    #[inline]
+    fn path_of(&self, key: &Path) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+impl Storage for DirStorage {
+    fn put(&self, key: &Path, local: &Path) -> CsyncResult<()> {
+        let path = self.path_of(key);
+        std::fs::create_dir_all(csync_unwrap_opt!(path.parent()))?;
+
+        // replaces the old object in one step, so that it's never partially written
+        Ok(rename(local, path)?)
+    }
+
+    #[inline]
+    fn get(&self, key: &Path) -> CsyncResult<Box<dyn Read + Send>> {
+        Ok(Box::new(fopen_r(self.path_of(key))?))
+    }
+
+    fn stat(&self, key: &Path) -> CsyncResult<Option<ObjectMeta>> {
+        match std::fs::metadata(self.path_of(key)) {
+            Ok(meta) if meta.is_file() => Ok(Some(ObjectMeta {
+                len: meta.len(),
+                modified: meta.modified()?,
+            })),
+            Ok(_) => Ok(None),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err)?,
+        }
+    }
+
+    fn list<'a>(&'a self, prefix: &Path) -> Box<dyn Iterator<Item = CsyncResult<PathBuf>> + Send + 'a> {
+        let dir = self.path_of(prefix);
+        match dir.exists() {
+            true => Box::new(WalkDir::new(dir).into_iter().filter_map(move |entry_res| {
+                match entry_res {
+                    Ok(entry) => match entry.file_type().is_file() {
+                        true => Some(
+                            subpath(entry.path(), &self.root)
+                                .ok_or_else(|| CsyncErr::Other(format!("{:?} is not under {:?}", entry.path(), self.root))),
+                        ),
+                        false => None,
+                    },
+                    Err(err) => Some(Err(err.into())),
+                }
+            })),
+            false => Box::new(std::iter::empty()),
+        }
+    }
+
+    #[inline]
+    fn delete(&self, key: &Path) -> CsyncResult<()> {
+        Ok(std::fs::remove_file(self.path_of(key))?)
+    }
+}
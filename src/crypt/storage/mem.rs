@@ -0,0 +1,55 @@
+use crate::crypt::storage::*;
+use std::{collections::HashMap, io::Cursor, sync::RwLock};
+
+/// Keeps every object in memory, which lets tests run without touching the filesystem.
+#[derive(Debug, Default)]
+pub struct MemStorage {
+    objects: RwLock<HashMap<PathBuf, (Vec<u8>, SystemTime)>>,
+}
+
+impl Storage for MemStorage {
+    fn put(&self, key: &Path, local: &Path) -> CsyncResult<()> {
+        let contents = std::fs::read(local)?;
+        std::fs::remove_file(local)?;
+
+        self.objects
+            .write()
+            .unwrap()
+            .insert(key.to_path_buf(), (contents, SystemTime::now()));
+        Ok(())
+    }
+
+    fn get(&self, key: &Path) -> CsyncResult<Box<dyn Read + Send>> {
+        match self.objects.read().unwrap().get(key) {
+            Some((contents, _)) => Ok(Box::new(Cursor::new(contents.clone()))),
+            None => csync_err!(Other, format!("no object at {:?}", key)),
+        }
+    }
+
+    #[inline]
+    fn stat(&self, key: &Path) -> CsyncResult<Option<ObjectMeta>> {
+        Ok(self.objects.read().unwrap().get(key).map(|(contents, modified)| ObjectMeta {
+            len: contents.len() as u64,
+            modified: *modified,
+        }))
+    }
+
+    fn list<'a>(&'a self, prefix: &Path) -> Box<dyn Iterator<Item = CsyncResult<PathBuf>> + Send + 'a> {
+        let keys: Vec<_> = self
+            .objects
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect();
+        Box::new(keys.into_iter().map(Ok))
+    }
+
+    fn delete(&self, key: &Path) -> CsyncResult<()> {
+        match self.objects.write().unwrap().remove(key) {
+            Some(_) => Ok(()),
+            None => csync_err!(Other, format!("no object at {:?}", key)),
+        }
+    }
+}
@@ -0,0 +1,220 @@
+mod archive;
+mod dir;
+#[cfg(test)]
+pub mod mem;
+mod s3;
+mod sftp;
+
+pub use archive::ArchiveStorage;
+pub use dir::DirStorage;
+pub use s3::S3Storage;
+pub use sftp::SftpStorage;
+
+use crate::{
+    prelude::*,
+    secure_vec::*,
+    specs::{storage_spec::*, syncer_spec::*},
+};
+use std::{
+    fmt::Debug,
+    io::Read,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Metadata of a stored object.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ObjectMeta {
+    pub len: u64,
+    pub modified: SystemTime,
+}
+
+/// Where the objects of a `csync` dir are kept: its ciphertexts, chunks, snapshots and index.
+///
+/// Objects are opaque to a `Storage`, and are addressed by keys that are relative paths like
+/// `a/b/c/<ciphertext>.csync`. Implementations can map keys to whatever they store objects in, but
+/// writes have to be atomic, such that an object is either absent or complete.
+///
+/// There is no `rename`, as nothing gets renamed in a `csync` dir: `put` already moves a finished
+/// local file into place, and the key of a ciphertext is derived from the path of its plaintext,
+/// so a plaintext that moves gets a new ciphertext rather than a renamed one. Not every storage
+/// could rename in one step either; S3 can only copy and then delete.
+pub trait Storage: Debug + Send + Sync {
+    /// Move the local file at `local` to the object at `key`, replacing any object already there.
+    fn put(&self, key: &Path, local: &Path) -> CsyncResult<()>;
+
+    /// # Returns
+    ///
+    /// The contents of the object at `key`.
+    fn get(&self, key: &Path) -> CsyncResult<Box<dyn Read + Send>>;
+
+    /// # Returns
+    ///
+    /// Metadata of the object at `key`, or `None` if there is no such object.
+    fn stat(&self, key: &Path) -> CsyncResult<Option<ObjectMeta>>;
+
+    /// # Returns
+    ///
+    /// Keys of every object under `prefix`, in no particular order. An empty `prefix` lists every
+    /// object.
+    fn list<'a>(&'a self, prefix: &Path) -> Box<dyn Iterator<Item = CsyncResult<PathBuf>> + Send + 'a>;
+
+    /// Remove the object at `key`.
+    fn delete(&self, key: &Path) -> CsyncResult<()>;
+
+    #[inline]
+    fn exists(&self, key: &Path) -> CsyncResult<bool> {
+        Ok(self.stat(key)?.is_some())
+    }
+
+    /// Storages that keep their own metadata encrypted, like archives, can't list their objects
+    /// until they are unlocked with the key of the `csync` dir. Every ciphertext records the spec
+    /// that the key is derived from, so these storages provide one to load the spec from.
+    ///
+    /// # Returns
+    ///
+    /// A ciphertext of the `csync` dir, if the storage has to be unlocked before it's read.
+    #[inline]
+    fn locked_ciphertext(&self) -> CsyncResult<Option<Box<dyn Read + Send>>> {
+        Ok(None)
+    }
+
+    /// Unlock the storage, see `locked_ciphertext`.
+    ///
+    /// # Parameters
+    ///
+    /// 1. `syncer_spec`: spec of this run, which metadata written by the storage is encrypted with
+    /// 1. `derived_key`: the key that the `csync` dir is encrypted with
+    #[inline]
+    fn unlock(&self, _syncer_spec: &SyncerSpec, _derived_key: &DerivedKey) -> CsyncResult<()> {
+        Ok(())
+    }
+
+    /// Write out anything that the storage holds back until every object is stored, like the table
+    /// of contents of an archive.
+    ///
+    /// # Parameters
+    ///
+    /// 1. `arena`: some directory in which only the calling thread creates and removes files
+    #[inline]
+    fn flush(&self, _arena: &Path) -> CsyncResult<()> {
+        Ok(())
+    }
+}
+
+/// # Parameters
+///
+/// 1. `csync_dir`: where the `csync` dir is, which is parsed into a `StorageSpec`
+///
+/// # Returns
+///
+/// The storage that holds the objects of `csync_dir`.
+pub fn open_storage(csync_dir: &Path) -> CsyncResult<Box<dyn Storage>> {
+    match StorageSpec::from_location(csync_dir)? {
+        StorageSpec::Archive { path } => Ok(Box::new(ArchiveStorage::open(&path)?)),
+        StorageSpec::Dir { path } => Ok(Box::new(DirStorage::new(&path))),
+        StorageSpec::S3 { bucket, prefix } => Ok(Box::new(S3Storage::from_env(&bucket, &prefix)?)),
+        StorageSpec::Sftp { host, path, port, user } => Ok(Box::new(SftpStorage::connect(
+            &host,
+            port,
+            user.as_deref(),
+            Path::new(&path),
+        )?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mem::MemStorage, *};
+    use std::collections::HashSet;
+
+    // check that `storage` behaves the way every `Storage` should
+    fn check_storage(storage: &dyn Storage) {
+        let local_dir = tmpdir!().unwrap();
+        let put = |key: &str, contents: &str| {
+            let local = local_dir.path().join("local");
+            std::fs::write(&local, contents).unwrap();
+            storage.put(Path::new(key), &local).unwrap();
+            assert!(!local.exists());
+        };
+        let get = |key: &str| {
+            let mut contents = String::new();
+            storage.get(Path::new(key)).unwrap().read_to_string(&mut contents).unwrap();
+            contents
+        };
+        let list = |prefix: &str| -> HashSet<PathBuf> { storage.list(Path::new(prefix)).map(Result::unwrap).collect() };
+
+        put("a/b/Xc3vB8nM", "first");
+        put("a/Qw1eR4tY", "second");
+        put("Zx9cV2bN", "third");
+        assert_eq!(get("a/b/Xc3vB8nM"), "first");
+        assert_eq!(storage.stat(Path::new("a/Qw1eR4tY")).unwrap().unwrap().len, 6);
+        assert_eq!(storage.stat(Path::new("a/Lk7jH5gF")).unwrap(), None);
+        assert_eq!(
+            list("a"),
+            vec!["a/b/Xc3vB8nM", "a/Qw1eR4tY"].into_iter().map(PathBuf::from).collect()
+        );
+        assert_eq!(list("").len(), 3);
+        assert_eq!(list("Pm6nB3vC").len(), 0);
+
+        // replacing
+        put("a/Qw1eR4tY", "replaced");
+        assert_eq!(get("a/Qw1eR4tY"), "replaced");
+        assert_eq!(list("").len(), 3);
+
+        storage.delete(Path::new("a/b/Xc3vB8nM")).unwrap();
+        assert!(!storage.exists(Path::new("a/b/Xc3vB8nM")).unwrap());
+        assert_eq!(list("a"), vec![PathBuf::from("a/Qw1eR4tY")].into_iter().collect());
+
+        // leave nothing behind, for storages that outlive the test
+        storage.delete(Path::new("a/Qw1eR4tY")).unwrap();
+        storage.delete(Path::new("Zx9cV2bN")).unwrap();
+        assert_eq!(list("").len(), 0);
+    }
+
+    #[test]
+    fn archive_storage() {
+        let root = tmpdir!().unwrap();
+        check_storage(&ArchiveStorage::open(&root.path().join("Fd8kT3wNz6pA.csar")).unwrap());
+    }
+
+    #[test]
+    fn dir_storage() {
+        let root = tmpdir!().unwrap();
+        check_storage(&DirStorage::new(root.path()));
+    }
+
+    #[test]
+    fn mem_storage() {
+        check_storage(&MemStorage::default());
+    }
+
+    // only runs if `CSYNC_TEST_S3` is set to a location like `s3://bucket/prefix`, along with the
+    // variables that `S3Storage` reads, for example those of a local MinIO server
+    #[test]
+    fn s3_storage() {
+        if let Ok(location) = std::env::var("CSYNC_TEST_S3") {
+            // under a fresh prefix, so that earlier runs don't get in the way
+            let location = format!(
+                "{}/{}",
+                location.trim_end_matches('/'),
+                SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos()
+            );
+            check_storage(&*open_storage(Path::new(&location)).unwrap());
+        }
+    }
+
+    // only runs if `CSYNC_TEST_SFTP` is set to a location like `sftp://user@localhost:2222/path`,
+    // for example that of an `sshd` in a container, whose host key is in `~/.ssh/known_hosts`
+    #[test]
+    fn sftp_storage() {
+        if let Ok(location) = std::env::var("CSYNC_TEST_SFTP") {
+            let location = format!(
+                "{}/{}",
+                location.trim_end_matches('/'),
+                SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos()
+            );
+            check_storage(&*open_storage(Path::new(&location)).unwrap());
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use crate::{crypt::storage::*, fs_util::*, util::*};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use ssh2::{CheckResult, ErrorCode, FileStat, KnownHostFileKind, OpenFlags, OpenType, RenameFlags, Session, Sftp};
+use std::{
+    io,
+    net::TcpStream,
+    time::{Duration, UNIX_EPOCH},
+};
+
+const DEFAULT_PORT: u16 = 22;
+
+// private keys that are tried when the agent can't authenticate, in `~/.ssh`, like `ssh` does
+const DEFAULT_KEY_FILENAMES: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+
+// `SSH_FX_NO_SUCH_FILE`
+const FX_NO_SUCH_FILE: i32 = 2;
+
+/// Stores each object as a file under `root` on an SSH server, at the path of its key, which is
+/// the same layout that `DirStorage` has.
+///
+/// Connecting works like `ssh` does:
+/// 1. the host key has to be in `~/.ssh/known_hosts`, so connect with `ssh` once to add it
+/// 1. `ssh-agent` is tried first, then the key file in `CSYNC_SSH_KEY` if it's set, then
+///    `~/.ssh/id_ed25519`, `~/.ssh/id_ecdsa` and `~/.ssh/id_rsa`; keys with passphrases have to
+///    be added to the agent
+/// 1. the user defaults to `USER`
+pub struct SftpStorage {
+    location: String, // only for messages
+    root: PathBuf,
+    sftp: Sftp,
+}
+
+impl Debug for SftpStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SftpStorage")
+            .field("location", &self.location)
+            .field("root", &self.root)
+            .finish()
+    }
+}
+
+impl SftpStorage {
+    /// # Parameters
+    ///
+    /// 1. `host`: host of the SSH server
+    /// 1. `port`: port of the SSH server, defaults to 22
+    /// 1. `user`: user to log in as, defaults to `USER`
+    /// 1. `root`: directory under which to store objects, relative to the home dir of `user` unless
+    ///    it's absolute
+    pub fn connect(host: &str, port: Option<u16>, user: Option<&str>, root: &Path) -> CsyncResult<Self> {
+        let port = port.unwrap_or(DEFAULT_PORT);
+        let user = match user {
+            Some(user) => user.to_string(),
+            None => match std::env::var("USER") {
+                Ok(user) if !user.is_empty() => user,
+                _ => csync_err!(StorageFailed, format!("no user to log into {} as", host))?,
+            },
+        };
+        let location = format!("{}@{}:{}", user, host, port);
+        let failed =
+            |what: &str, err: &dyn std::fmt::Display| CsyncErr::StorageFailed(format!("{} {}: {}", what, location, err));
+
+        // IPv6 addresses are bracketed in locations, but not when connecting
+        let tcp = match TcpStream::connect((host.trim_start_matches('[').trim_end_matches(']'), port)) {
+            Ok(tcp) => tcp,
+            Err(err) => Err(failed("could not connect to", &err))?,
+        };
+        let mut session = Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.set_timeout(Duration::from_secs(60).as_millis() as u32);
+        if let Err(err) = session.handshake() {
+            Err(failed("could not start an SSH session with", &err))?;
+        }
+
+        check_host_key(&session, host, port, &location)?;
+        authenticate(&session, &user, &location)?;
+
+        match session.sftp() {
+            Ok(sftp) => Ok(Self {
+                location,
+                // the home dir itself
+                root: match root == Path::new("") {
+                    true => PathBuf::from("."),
+                    false => root.to_path_buf(),
+                },
+                sftp,
+            }),
+            Err(err) => Err(failed("could not start SFTP on", &err)),
+        }
+    }
+
+    // where the object at `key` is stored
+    #[inline]
+    fn path_of(&self, key: &Path) -> PathBuf {
+        self.root.join(key)
+    }
+
+    // like `mkdir -p`
+    fn create_dir_all(&self, dir: &Path) -> CsyncResult<()> {
+        match self.sftp.stat(dir) {
+            Ok(stat) if stat.is_dir() => Ok(()),
+            Ok(_) => csync_err!(StorageFailed, format!("{:?} on {} is not a directory", dir, self.location)),
+            Err(_) => {
+                if let Some(parent) = dir.parent().filter(|parent| parent != &Path::new("")) {
+                    self.create_dir_all(parent)?;
+                }
+                match self.sftp.mkdir(dir, DEFAULT_DIR_PERM_BITS as i32) {
+                    Ok(()) => Ok(()),
+                    // another thread might have created it in the meantime
+                    Err(_) if self.sftp.stat(dir).map(|stat| stat.is_dir()).unwrap_or(false) => Ok(()),
+                    Err(err) => csync_err!(
+                        StorageFailed,
+                        format!("could not create {:?} on {}: {}", dir, self.location, err)
+                    ),
+                }
+            }
+        }
+    }
+
+    // `(path, stat)` of every entry in `dir`, without `.` and `..`
+    fn read_dir(&self, dir: &Path) -> CsyncResult<Vec<(PathBuf, FileStat)>> {
+        match self.sftp.readdir(dir) {
+            Ok(entries) => Ok(entries
+                .into_iter()
+                .filter(|(path, _)| path.file_name().is_some_and(|name| name != "." && name != ".."))
+                .collect()),
+            Err(err) => csync_err!(
+                StorageFailed,
+                format!("could not list {:?} on {}: {}", dir, self.location, err)
+            ),
+        }
+    }
+}
+
+impl Storage for SftpStorage {
+    fn put(&self, key: &Path, local: &Path) -> CsyncResult<()> {
+        let path = self.path_of(key);
+        self.create_dir_all(csync_unwrap_opt!(path.parent()))?;
+
+        // written next to where it belongs and renamed into place, so that it's never partially
+        // written; only the owner can read it, like the files of a local `csync` dir
+        let tmp_name: String = thread_rng().sample_iter(&Alphanumeric).take(16).collect();
+        let tmp_path = path.with_file_name(format!(".{}.tmp", tmp_name));
+        let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+        let mut tmp_file = self
+            .sftp
+            .open_mode(&tmp_path, flags, DEFAULT_PERM_BITS as i32, OpenType::File)?;
+        io::copy(&mut fopen_r(local)?, &mut tmp_file)?;
+        // closed before it is renamed
+        drop(tmp_file);
+
+        // servers that only speak version 3 of the protocol, like OpenSSH, can't rename over a
+        // file, and `ssh2` can't ask for `posix-rename@openssh.com`; only then is the old object
+        // unlinked first
+        let renamed = match self
+            .sftp
+            .rename(&tmp_path, &path, Some(RenameFlags::ATOMIC | RenameFlags::OVERWRITE))
+        {
+            Err(_) if self.sftp.stat(&path).is_ok() => {
+                self.sftp.unlink(&path).and_then(|_| self.sftp.rename(&tmp_path, &path, None))
+            }
+            renamed => renamed,
+        };
+        if let Err(err) = renamed {
+            let _ = self.sftp.unlink(&tmp_path);
+            csync_err!(
+                StorageFailed,
+                format!("could not write {:?} on {}: {}", path, self.location, err)
+            )?;
+        }
+
+        std::fs::remove_file(local)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &Path) -> CsyncResult<Box<dyn Read + Send>> {
+        let path = self.path_of(key);
+        match self.sftp.open(&path) {
+            Ok(file) => Ok(Box::new(file)),
+            Err(err) => csync_err!(
+                StorageFailed,
+                format!("could not read {:?} on {}: {}", path, self.location, err)
+            ),
+        }
+    }
+
+    fn stat(&self, key: &Path) -> CsyncResult<Option<ObjectMeta>> {
+        match self.sftp.stat(&self.path_of(key)) {
+            Ok(stat) if stat.is_file() => Ok(Some(ObjectMeta {
+                len: csync_unwrap_opt!(stat.size),
+                modified: UNIX_EPOCH + Duration::from_secs(csync_unwrap_opt!(stat.mtime)),
+            })),
+            Ok(_) => Ok(None),
+            Err(err) if err.code() == ErrorCode::SFTP(FX_NO_SUCH_FILE) => Ok(None),
+            Err(err) => csync_err!(
+                StorageFailed,
+                format!("could not stat {:?} on {}: {}", key, self.location, err)
+            ),
+        }
+    }
+
+    fn list<'a>(&'a self, prefix: &Path) -> Box<dyn Iterator<Item = CsyncResult<PathBuf>> + Send + 'a> {
+        // directories are read as the iterator gets to them, depth first
+        let mut dirs = match self.sftp.stat(&self.path_of(prefix)) {
+            Ok(stat) if stat.is_dir() => vec![self.path_of(prefix)],
+            _ => Vec::new(),
+        };
+        let mut files = Vec::new();
+        Box::new(std::iter::from_fn(move || loop {
+            if let Some(file) = files.pop() {
+                return Some(
+                    subpath(&file, &self.root)
+                        .ok_or_else(|| CsyncErr::Other(format!("{:?} is not under {:?}", file, self.root))),
+                );
+            }
+
+            match self.read_dir(&dirs.pop()?) {
+                Ok(entries) => entries.into_iter().for_each(|(path, stat)| match stat.is_dir() {
+                    true => dirs.push(path),
+                    false => files.push(path),
+                }),
+                Err(err) => {
+                    dirs.clear();
+                    return Some(Err(err));
+                }
+            }
+        }))
+    }
+
+    fn delete(&self, key: &Path) -> CsyncResult<()> {
+        let path = self.path_of(key);
+        match self.sftp.unlink(&path) {
+            Ok(()) => Ok(()),
+            Err(err) => csync_err!(
+                StorageFailed,
+                format!("could not delete {:?} on {}: {}", path, self.location, err)
+            ),
+        }
+    }
+}
+
+// fail unless the key of the server is the one in `~/.ssh/known_hosts`
+fn check_host_key(session: &Session, host: &str, port: u16, location: &str) -> CsyncResult<()> {
+    let mut known_hosts = session.known_hosts()?;
+    if let Some(home) = std::env::var_os("HOME") {
+        let known_hosts_path = Path::new(&home).join(".ssh").join("known_hosts");
+        if known_hosts_path.exists() {
+            known_hosts.read_file(&known_hosts_path, KnownHostFileKind::OpenSSH)?;
+        }
+    }
+
+    let (key, _) = csync_unwrap_opt!(session.host_key());
+    match known_hosts.check_port(host, port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => csync_err!(
+            StorageFailed,
+            format!("the host key of {} does not match the one in `~/.ssh/known_hosts`", location)
+        ),
+        CheckResult::NotFound => csync_err!(
+            StorageFailed,
+            format!(
+                "the host key of {} is not in `~/.ssh/known_hosts`, connect with `ssh` once to add it",
+                location
+            )
+        ),
+        CheckResult::Failure => csync_err!(StorageFailed, format!("could not check the host key of {}", location)),
+    }
+}
+
+// try the agent and then the key files, like `ssh` does
+fn authenticate(session: &Session, user: &str, location: &str) -> CsyncResult<()> {
+    if session.userauth_agent(user).is_ok() && session.authenticated() {
+        return Ok(());
+    }
+
+    let key_paths: Vec<PathBuf> = std::env::var_os("CSYNC_SSH_KEY")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain(std::env::var_os("HOME").into_iter().flat_map(|home| {
+            DEFAULT_KEY_FILENAMES
+                .iter()
+                .map(move |filename| Path::new(&home).join(".ssh").join(filename))
+        }))
+        .filter(|key_path| key_path.exists())
+        .collect();
+    for key_path in key_paths {
+        if session.userauth_pubkey_file(user, None, &key_path, None).is_ok() && session.authenticated() {
+            return Ok(());
+        }
+    }
+
+    csync_err!(
+        StorageFailed,
+        format!("could not log into {} with `ssh-agent` or any key file", location)
+    )
+}
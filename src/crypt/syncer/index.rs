@@ -0,0 +1,181 @@
+use crate::{
+    crypt::{storage::*, syncer::util::*, util::*},
+    fs_util::*,
+    prelude::*,
+    primitives::*,
+    rand_util::rand_u64,
+    secure_vec::*,
+    specs::prelude::*,
+    util::*,
+};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+/// What the index records about a single plaintext.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct IndexEntry {
+    pub cipherpath: PathBuf,
+    pub file_meta: FileMetaSpec,
+    pub file_type: FileType,
+}
+
+/// A mapping from each plaintext in a `csync` dir to its ciphertext, stored encrypted in the
+/// `csync` dir itself, so that incremental runs don't have to decrypt every ciphertext's header.
+///
+/// The index only ever saves work; it is rebuilt from the ciphertexts if it is missing, fails to
+/// decrypt, or is stale. Each stored index has a random generation, which is also stored in the
+/// plain next to it; the generation is removed before any ciphertext changes, and only written
+/// again once the index has been, so a run that stops in between leaves an index to rebuild.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Index {
+    entries: HashMap<PathBuf, IndexEntry>, // keyed by the plaintext paths that `subpath_par` returns
+    generation: u64,
+}
+
+impl Index {
+    /// # Returns
+    ///
+    /// `true` if `storage` has an index stored in it.
+    #[inline]
+    pub fn exists(storage: &dyn Storage) -> CsyncResult<bool> {
+        storage.exists(Path::new(INDEX_FILENAME))
+    }
+
+    /// # Parameters
+    ///
+    /// 1. `storage`: where the `csync` dir whose index to load is stored
+    /// 1. `spread_depth`: spread depth of the ciphertexts in `storage`
+    /// 1. `derived_key`: the key that the `csync` dir was encrypted with
+    ///
+    /// # Returns
+    ///
+    /// The index stored in `storage`, or one rebuilt from its ciphertexts if the stored one can't
+    /// be used.
+    pub fn load_or_rebuild(storage: &dyn Storage, spread_depth: u8, derived_key: &DerivedKey) -> CsyncResult<Self> {
+        match (Index::load(storage, derived_key), Index::stored_generation(storage)) {
+            (Ok(index), Ok(Some(generation))) if index.generation == generation => Ok(index),
+            _ => Index::rebuild(storage, spread_depth, derived_key),
+        }
+    }
+
+    /// Mark the index stored in `storage`, if any, as stale. Call this before changing any
+    /// ciphertext in `storage`.
+    pub fn mark_stale(storage: &dyn Storage) -> CsyncResult<()> {
+        let generation_key = Path::new(INDEX_GENERATION_FILENAME);
+        match storage.exists(generation_key)? {
+            true => storage.delete(generation_key),
+            false => Ok(()),
+        }
+    }
+
+    // the generation of the index stored in `storage`, if it isn't stale
+    fn stored_generation(storage: &dyn Storage) -> CsyncResult<Option<u64>> {
+        let generation_key = Path::new(INDEX_GENERATION_FILENAME);
+        match storage.exists(generation_key)? {
+            true => {
+                let mut generation = String::new();
+                storage.get(generation_key)?.read_to_string(&mut generation)?;
+                Ok(generation.trim().parse().ok())
+            }
+            false => Ok(None),
+        }
+    }
+
+    // decrypt the index stored in `storage`
+    fn load(storage: &dyn Storage, derived_key: &DerivedKey) -> CsyncResult<Self> {
+        let mut serialized = Vec::new();
+        csync_decrypt(storage.get(Path::new(INDEX_FILENAME))?, Some(&mut serialized), derived_key)?;
+        deserialize(&serialized)
+    }
+
+    // recover every entry from the keys and headers of the ciphertexts in `storage`
+    fn rebuild(storage: &dyn Storage, spread_depth: u8, derived_key: &DerivedKey) -> CsyncResult<Self> {
+        let entries = ciphertexts(storage)
+            .par_bridge()
+            .map(|cipherpath_res| -> CsyncResult<(PathBuf, IndexEntry)> {
+                let cipherpath = cipherpath_res?;
+                let (path, file_type, _) = cipherpath_to_path(spread_depth, &cipherpath, derived_key)?;
+                let (_, action_spec) = load_syncer_action_specs(storage.get(&cipherpath)?)?;
+
+                Ok((
+                    path,
+                    IndexEntry {
+                        cipherpath,
+                        file_meta: csync_unwrap_opt!(action_spec.get_file_meta(derived_key)?),
+                        file_type,
+                    },
+                ))
+            })
+            .collect::<CsyncResult<_>>()?;
+
+        Ok(Self { entries, generation: 0 })
+    }
+
+    /// Store the index as a new generation, which the index stored in `storage` becomes once both
+    /// are written.
+    ///
+    /// # Parameters
+    ///
+    /// 1. `syncer_spec`: spec of the `csync` dir, which the index is encrypted with
+    /// 1. `arena`: some directory in which only the calling thread creates and removes files
+    /// 1. `storage`: where to store the index
+    /// 1. `derived_key`: the key that the `csync` dir was encrypted with
+    pub fn store(
+        &mut self,
+        syncer_spec: &SyncerSpec,
+        arena: &Path,
+        storage: &dyn Storage,
+        derived_key: &DerivedKey,
+    ) -> CsyncResult<()> {
+        match syncer_spec {
+            SyncerSpec::Encrypt {
+                cipher_spec, salt_len, ..
+            } => {
+                let action_spec = ActionSpec::new(&cipher_spec.resalt(*salt_len), *salt_len, None, None, false, derived_key)?;
+                let tmp_dest = arena.join("Index_store");
+                self.generation = rand_u64(None, 0, u64::MAX);
+
+                csync_encrypt(
+                    syncer_spec,
+                    &action_spec,
+                    arena,
+                    serialize(self)?.as_ref(),
+                    &mut fopen_w(&tmp_dest)?,
+                    derived_key,
+                )?;
+
+                // replaces the old index in one step, so that it's never partially written
+                storage.put(Path::new(INDEX_FILENAME), &tmp_dest)?;
+
+                std::fs::write(&tmp_dest, self.generation.to_string())?;
+                storage.put(Path::new(INDEX_GENERATION_FILENAME), &tmp_dest)
+            }
+            _ => panic!("The index should only be stored when encrypting"),
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, path: &Path) -> Option<&IndexEntry> {
+        self.entries.get(path)
+    }
+
+    #[inline]
+    pub fn insert(&mut self, path: PathBuf, entry: IndexEntry) {
+        self.entries.insert(path, entry);
+    }
+
+    #[inline]
+    pub fn remove(&mut self, path: &Path) -> Option<IndexEntry> {
+        self.entries.remove(path)
+    }
+
+    #[inline]
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.entries.keys()
+    }
+}
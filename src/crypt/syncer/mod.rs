@@ -1,5 +1,13 @@
+mod index;
+mod observer;
+mod plan;
 mod util;
 
+pub use self::{
+    observer::{ScanSummary, SyncEvent, SyncObserver, SyncSummary},
+    plan::{Change, PlannedChange},
+};
+
 /// TODO
 ///
 /// 1. syncing to a non-empty dir
@@ -7,15 +15,34 @@ mod util;
 ///     1. if decrypting, force the dir to be non empty
 /// 1. if using metadata that is recovered, make sure that passwords match
 use crate::{
-    crypt::{action::*, syncer::util::*},
+    crypt::{
+        action::*,
+        chunk::{has_chunks, store_chunks, stored_chunks},
+        snapshot::*,
+        storage::*,
+        syncer::{index::*, util::*},
+        util::{csync_decrypt, csync_decrypt_file, load_chunk_refs, load_syncer_action_specs},
+    },
     fs_util::*,
+    logger::redact,
     prelude::*,
+    primitives::*,
     secure_vec::*,
-    specs::prelude::*,
+    specs::{prelude::*, storage_spec::*},
+    util::*,
+};
+use rayon::{iter::Either, prelude::*};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    convert::TryFrom,
+    ffi::OsStr,
+    fs::File,
+    io::{Read, Write},
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use rayon::prelude::*;
-use std::ffi::OsStr;
-use std::{convert::TryFrom, io, os::unix::fs::PermissionsExt, path::Path};
 use tempfile::TempDir;
 use walkdir::WalkDir;
 
@@ -66,9 +93,19 @@ pub struct Syncer {
     arena: TempDir,
     // every field above is either user-supplied or derived from the metadata below
     derived_key: DerivedKey,
+    // present if the `csync` dir has an index, or is getting one
+    index: Option<RwLock<Index>>,
+    // whether the stored index has been marked stale since it was last stored by this syncer
+    index_marked_stale: Mutex<bool>,
     init_key: InitialKey,
+    // notified of what happens while syncing
+    observers: Vec<Box<dyn SyncObserver>>,
+    // present if decrypting from a snapshot
+    snapshot: Option<Snapshot>,
     //
     spec: SyncerSpec,
+    // where the `csync` dir is stored
+    storage: Box<dyn Storage>,
 }
 
 ///
@@ -81,6 +118,153 @@ impl Syncer {
         self.spec.clone()
     }
 
+    /// Let `observer` know of everything that happens while syncing, from now on.
+    #[inline]
+    pub fn subscribe<O>(&mut self, observer: O)
+    where
+        O: SyncObserver + 'static,
+    {
+        self.observers.push(Box::new(observer));
+    }
+
+    // let the observers know of the event that `get_event` creates, which only gets created if
+    // there is someone to tell
+    #[inline]
+    fn emit<F>(&self, get_event: F)
+    where
+        F: FnOnce() -> SyncEvent,
+    {
+        if !self.observers.is_empty() {
+            let event = get_event();
+            self.observers.iter().for_each(|observer| observer.on_event(&event));
+        }
+    }
+
+    // manifest `action`, letting the observers know when it starts and how it went
+    fn manifest_observed<'a>(&'a self, action: Action<'a>) -> CsyncResult<Action<'a>> {
+        if self.observers.is_empty() {
+            return action.manifest(self.arena.path(), &*self.storage, &self.derived_key);
+        }
+
+        let (src, dest) = (action.src.clone(), action.dest.clone());
+        self.emit(|| SyncEvent::FileStarted {
+            src: src.clone(),
+            dest: dest.clone(),
+        });
+        let start = start_timer();
+        match action.manifest(self.arena.path(), &*self.storage, &self.derived_key) {
+            Ok(action) => {
+                let (src_bytes, dest_bytes) = action.get_lens().unwrap_or_default();
+                self.emit(|| SyncEvent::FileDone {
+                    src,
+                    dest,
+                    src_bytes,
+                    dest_bytes,
+                    duration: end_timer(&start),
+                });
+                Ok(action)
+            }
+            Err(err) => {
+                self.emit(|| SyncEvent::Error {
+                    path_opt: Some(src),
+                    err: err.clone(),
+                });
+                Err(err)
+            }
+        }
+    }
+
+    // `res`, letting the observers know if it is an error that isn't about any particular file
+    #[inline]
+    fn observed<T>(&self, res: CsyncResult<T>) -> CsyncResult<T> {
+        res.inspect_err(|err| {
+            self.emit(|| SyncEvent::Error {
+                path_opt: None,
+                err: err.clone(),
+            })
+        })
+    }
+
+    /// Encrypt or decrypt everything that has to be, depending on the spec of this syncer, and let
+    /// the observers know once it's finished.
+    ///
+    /// # Returns
+    ///
+    /// Number of files that were synced, how many bytes were read and written, and how long it took.
+    pub fn sync(&self) -> CsyncResult<SyncSummary> {
+        let start = start_timer();
+        let (num_files, src_bytes, dest_bytes) = match &self.spec {
+            SyncerSpec::Encrypt { .. } => sum_lens(self.sync_enc()?)?,
+            SyncerSpec::Decrypt { .. } => sum_lens(self.sync_dec()?)?,
+            SyncerSpec::Clean { .. } => csync_err!(
+                UnsupportedOperation,
+                String::from("a syncer that cleans a `csync` directory can't sync it")
+            )?,
+        };
+
+        let summary = SyncSummary {
+            num_files,
+            src_bytes,
+            dest_bytes,
+            duration: end_timer(&start),
+        };
+        log::info!(
+            "synced {} files, reading {} bytes and writing {} in {:?}",
+            num_files,
+            src_bytes,
+            dest_bytes,
+            summary.duration
+        );
+        self.emit(|| SyncEvent::Finished(summary));
+        Ok(summary)
+    }
+
+    /// Count everything that `sync` would look at, without reading anything but metadata, and let
+    /// the observers know. Nothing is compared with what is already there, so files that would be
+    /// skipped are counted too.
+    ///
+    /// # Returns
+    ///
+    /// Number of files and how many bytes `sync` would report reading for all of them, which is
+    /// the plaintext when encrypting, and the ciphertexts or the sizes in the snapshot when
+    /// decrypting.
+    pub fn scan(&self) -> CsyncResult<ScanSummary> {
+        let (num_files, src_bytes) = match &self.spec {
+            SyncerSpec::Encrypt { filter_spec, .. } => {
+                sum_counts(meta_map(&self.source_roots()?, filter_spec)?.map(|meta_res| Ok((1, meta_res?.4.get_len()))))?
+            }
+            SyncerSpec::Decrypt {
+                snapshot_opt,
+                spread_depth,
+                ..
+            } => match (&self.snapshot, snapshot_opt) {
+                (Some(snapshot), Some(_)) => sum_counts(
+                    snapshot
+                        .par_entries()
+                        .filter(|(path, _)| self.restores(path))
+                        .map(|(_, entry)| Ok((1, entry.file_meta.get_len()))),
+                )?,
+                _ => sum_counts(ciphertexts(&*self.storage).par_bridge().map(|cipherpath_res| {
+                    let cipherpath = cipherpath_res?;
+                    let (path, _, _) = cipherpath_to_path(*spread_depth, &cipherpath, &self.derived_key)?;
+                    match self.restores(&path) {
+                        true => Ok((1, self.stored_len(&cipherpath)?)),
+                        false => Ok((0, 0)),
+                    }
+                }))?,
+            },
+            SyncerSpec::Clean { .. } => csync_err!(
+                UnsupportedOperation,
+                String::from("a syncer that cleans a `csync` directory can't scan it")
+            )?,
+        };
+
+        let summary = ScanSummary { num_files, src_bytes };
+        log::info!("scanned {} files of {} bytes", num_files, src_bytes);
+        self.emit(|| SyncEvent::Scanned(summary));
+        Ok(summary)
+    }
+
     /// # Parameters
     ///
     /// # Returns
@@ -91,51 +275,90 @@ impl Syncer {
             //
             SyncerSpecExt::Encrypt { .. } => Syncer::with_spec_ext(spec_ext, init_key),
             //
-            SyncerSpecExt::Decrypt { .. } => Syncer::from_dir(spec_ext, &init_key),
-            //
-            SyncerSpecExt::Clean { .. } => todo!(), // probably should use from_dir
+            SyncerSpecExt::Decrypt { .. }
+            | SyncerSpecExt::Cat { .. }
+            | SyncerSpecExt::ExportTar { .. }
+            | SyncerSpecExt::Clean { .. } => Syncer::from_dir(spec_ext, &init_key, false),
         }
     }
 
-    // init from an existing csync dir by loading the metadata
-    fn from_dir(spec_ext: &SyncerSpecExt, init_key: &InitialKey) -> CsyncResult<Self> {
+    // init from an existing csync dir by loading the metadata, without writing anything if `dry_run`
+    fn from_dir(spec_ext: &SyncerSpecExt, init_key: &InitialKey, dry_run: bool) -> CsyncResult<Self> {
         //
         macro_rules! from_dir {
-            ( $source:expr, $out_dir:expr, $metadata_par_dir:expr ) => {{
+            ( $source:expr, $out_dir:expr, $csync_dir:expr ) => {{
                 //
                 match $source == $out_dir {
                     //
                     true => csync_err!(SourceEqOutdir, $source.to_path_buf())?,
                     //
                     false => {
-                        let (syncer_spec, action_spec) = Syncer::load_syncer_action_spec($metadata_par_dir)?;
-
-                        let derived_key = match &syncer_spec {
-                            SyncerSpec::Encrypt {
-                                key_deriv_spec,
-                                verbose,
-                                ..
-                            } => {
-                                let (derived_key, _) = time!(
-                                    *verbose,
-                                    "Generating/authenticating the derived key",
-                                    key_deriv_spec.derive(&init_key.0 .0)?
-                                );
-                                derived_key
-                            }
-                            _ => panic!("Loaded metadata should only be of the variant `SyncerSpec::Encrypt`"),
-                        };
-
-                        action_spec.verify_derived_key(&derived_key)?;
+                        let storage = open_storage($csync_dir)?;
+                        let (syncer_spec, derived_key) = Syncer::load_authenticated_spec(&*storage, init_key)?;
 
                         // let hashed_key = Syncer::verify_syncer_spec(&syncer_spec, &action_spec, &init_key)?;
                         match spec_ext {
                             //
-                            SyncerSpecExt::Encrypt { .. } => {
+                            SyncerSpecExt::Encrypt {
+                                checksum,
+                                chunked,
+                                filter_spec,
+                                index,
+                                roots,
+                                snapshot,
+                                source,
+                                source_kind,
+                                volume_size_opt,
+                                ..
+                            } => {
+                                // options that only apply to this run aren't part of the loaded spec
+                                let mut syncer_spec = syncer_spec;
+                                if let SyncerSpec::Encrypt {
+                                    checksum: loaded_checksum,
+                                    chunked: loaded_chunked,
+                                    filter_spec: loaded_filter_spec,
+                                    index: loaded_index,
+                                    roots: loaded_roots,
+                                    snapshot: loaded_snapshot,
+                                    source: loaded_source,
+                                    source_kind: loaded_source_kind,
+                                    volume_size_opt: loaded_volume_size_opt,
+                                    ..
+                                } = &mut syncer_spec
+                                {
+                                    match source_kind {
+                                        SourceKind::Path => (),
+                                        // a stream only replaces the stream of the same name
+                                        SourceKind::Stream if loaded_source == source => (),
+                                        SourceKind::Stream => csync_err!(
+                                            StreamNameMismatch,
+                                            PathBuf::from(csync_unwrap_opt!(loaded_source.file_name()))
+                                        )?,
+                                        // an archive is only imported to a new `csync` dir, which
+                                        // nothing is left over in
+                                        SourceKind::Tar => csync_err!(IncrementalEncryptionDisabledForNow)?,
+                                    };
+                                    *loaded_source_kind = *source_kind;
+                                    *loaded_volume_size_opt = *volume_size_opt;
+                                    *loaded_checksum = *checksum;
+                                    *loaded_filter_spec = filter_spec.clone();
+                                    *loaded_roots = roots.iter().map(SourceRoot::canonicalize).collect::<CsyncResult<_>>()?;
+                                    // once there are snapshots, keep taking them; they reference
+                                    // files by their chunks, so keep storing files chunked too
+                                    *loaded_snapshot = *snapshot || has_snapshots(&*storage);
+                                    *loaded_chunked = *chunked || *loaded_snapshot || has_chunks(&*storage);
+                                    *loaded_index = *index;
+                                }
                                 Syncer::with_spec(syncer_spec, init_key.clone(), Some(derived_key))
                             }
                             //
-                            SyncerSpecExt::Decrypt { .. } => match syncer_spec {
+                            SyncerSpecExt::Decrypt {
+                                conflict_spec,
+                                delete,
+                                roots,
+                                snapshot_opt,
+                                ..
+                            } => match syncer_spec {
                                 //
                                 SyncerSpec::Encrypt {
                                     authenticator_spec,
@@ -148,15 +371,26 @@ impl Syncer {
                                     salt_len,
                                     ..
                                 } => {
-                                    std::fs::create_dir_all($out_dir)?;
+                                    let out_dir = match dry_run {
+                                        // not even `out_dir` gets created
+                                        true => canonicalize_existing($out_dir)?,
+                                        false => {
+                                            std::fs::create_dir_all($out_dir)?;
+                                            $out_dir.canonicalize()?
+                                        }
+                                    };
                                     Syncer::with_spec(
                                         SyncerSpec::Decrypt {
                                             authenticator_spec,
                                             cipher_spec,
                                             compressor_spec,
                                             key_deriv_spec,
-                                            out_dir: $out_dir.canonicalize()?,
-                                            source: $source.canonicalize()?,
+                                            conflict_spec: conflict_spec.clone(),
+                                            delete: *delete,
+                                            roots: roots.clone(),
+                                            snapshot_opt: *snapshot_opt,
+                                            out_dir,
+                                            source: StorageSpec::from_location($source)?.canonical_location()?,
                                             init_salt,
                                             spread_depth,
                                             verbose,
@@ -180,43 +414,141 @@ impl Syncer {
             SyncerSpecExt::Encrypt { source, out_dir, .. } => from_dir!(source, out_dir, out_dir),
             //
             SyncerSpecExt::Decrypt { source, out_dir, .. } => from_dir!(source, out_dir, source),
-            //
-            SyncerSpecExt::Clean { .. } => todo!(),
+            // nothing gets written, so there is no `out_dir` to check; the loaded spec is used
+            // as-is, except that it should point to where the `csync` dir currently is
+            SyncerSpecExt::Cat { source, .. } | SyncerSpecExt::ExportTar { source, .. } => {
+                let storage = open_storage(source)?;
+                match Syncer::load_authenticated_spec(&*storage, init_key)? {
+                    (
+                        SyncerSpec::Encrypt {
+                            authenticator_spec,
+                            cipher_spec,
+                            compressor_spec,
+                            key_deriv_spec,
+                            source: orig_source,
+                            init_salt,
+                            spread_depth,
+                            verbose,
+                            salt_len,
+                            ..
+                        },
+                        derived_key,
+                    ) => Ok(Self {
+                        arena: tmpdir!()?,
+                        derived_key,
+                        index: None,
+                        index_marked_stale: Mutex::new(false),
+                        init_key: init_key.clone(),
+                        observers: Vec::new(),
+                        snapshot: None,
+                        spec: SyncerSpec::Encrypt {
+                            authenticator_spec,
+                            checksum: false,
+                            chunked: false,
+                            cipher_spec,
+                            compressor_spec,
+                            filter_spec: FilterSpec::default(),
+                            index: false,
+                            key_deriv_spec,
+                            out_dir: StorageSpec::from_location(source)?.canonical_location()?,
+                            roots: Vec::new(),
+                            snapshot: false,
+                            source: orig_source,
+                            source_kind: SourceKind::Path,
+                            init_salt,
+                            spread_depth,
+                            verbose,
+                            volume_size_opt: None,
+                            salt_len,
+                        },
+                        storage,
+                    }),
+                    _ => panic!("Loaded metadata should only be of the variant `SyncerSpec::Encrypt`"),
+                }
+            }
+            // nothing gets encrypted or decrypted, so only the key is needed from the loaded spec
+            SyncerSpecExt::Clean {
+                retention_spec,
+                source,
+                verbose,
+            } => {
+                let storage = open_storage(source)?;
+                let (_, derived_key) = Syncer::load_authenticated_spec(&*storage, init_key)?;
+                Ok(Self {
+                    arena: tmpdir!()?,
+                    derived_key,
+                    index: None,
+                    index_marked_stale: Mutex::new(false),
+                    init_key: init_key.clone(),
+                    observers: Vec::new(),
+                    snapshot: None,
+                    spec: SyncerSpec::Clean {
+                        retention_spec: retention_spec.clone(),
+                        source: StorageSpec::from_location(source)?.canonical_location()?,
+                        verbose: *verbose,
+                    },
+                    storage,
+                })
+            }
         }
     }
 
+    // Load the metadata in `storage`, then derive a key from `init_key` and check it against the
+    // loaded metadata.
+    fn load_authenticated_spec(storage: &dyn Storage, init_key: &InitialKey) -> CsyncResult<(SyncerSpec, DerivedKey)> {
+        let (syncer_spec, action_spec) = Syncer::load_syncer_action_spec(storage)?;
+
+        let derived_key = match &syncer_spec {
+            SyncerSpec::Encrypt {
+                key_deriv_spec, verbose, ..
+            } => {
+                let (derived_key, _) = time!(
+                    *verbose,
+                    "Generating/authenticating the derived key",
+                    key_deriv_spec.derive(&init_key.0 .0)?
+                );
+                derived_key
+            }
+            _ => panic!("Loaded metadata should only be of the variant `SyncerSpec::Encrypt`"),
+        };
+
+        action_spec.verify_derived_key(&derived_key)?;
+        storage.unlock(&syncer_spec, &derived_key)?;
+        Ok((syncer_spec, derived_key))
+    }
+
     // # Parameters
     fn with_spec_ext(spec_ext: &SyncerSpecExt, init_key: InitialKey) -> CsyncResult<Self> {
         // if from_dir works, use it
         // if not, start fresh from
-        match Syncer::from_dir(spec_ext, &init_key) {
+        match Syncer::from_dir(spec_ext, &init_key, false) {
             Ok(syncer) => match spec_ext {
-                SyncerSpecExt::Encrypt { verbose, .. } | SyncerSpecExt::Decrypt { verbose, .. } => {
-                    if *verbose {
-                        eprintln!("Metadata recovered: csync will use this instead of provided options.");
-                        // TODO ask for confirmation?
-                    }
+                SyncerSpecExt::Encrypt { .. } | SyncerSpecExt::Decrypt { .. } => {
+                    // TODO ask for confirmation?
+                    log::info!("Metadata recovered: csync will use this instead of provided options.");
                     Ok(syncer)
                 }
                 _ => todo!(),
             },
+            // the `csync` dir could be used, just not for this stream
+            Err(err @ StreamNameMismatch(_)) => Err(err),
             Err(_) => match spec_ext {
                 SyncerSpecExt::Encrypt { out_dir, .. } => {
                     // if from_dir failed, outdir must either be empty or non-existent
-                    match (out_dir.exists(), out_dir.is_dir()) {
-                        (false, _) => (),
-                        (true, true) => match std::fs::read_dir(out_dir).map(Iterator::count)? {
-                            0 => (),
-                            _ => csync_err!(IncrementalEncryptionDisabledForNow)?,
-                        },
-                        (true, false) => csync_err!(OutdirIsNotDir, out_dir.to_path_buf())?,
+                    if !is_fresh_out_dir(out_dir)? {
+                        csync_err!(IncrementalEncryptionDisabledForNow)?;
+                    }
+                    if StorageSpec::from_location(out_dir)?.is_dir() {
+                        std::fs::create_dir_all(out_dir)?;
                     }
 
-                    std::fs::create_dir_all(out_dir)?;
                     let spec = SyncerSpec::try_from(spec_ext)?;
                     Syncer::with_spec(spec, init_key, None)
                 }
-                SyncerSpecExt::Decrypt { .. } | SyncerSpecExt::Clean { .. } => todo!(),
+                SyncerSpecExt::Decrypt { .. }
+                | SyncerSpecExt::Cat { .. }
+                | SyncerSpecExt::ExportTar { .. }
+                | SyncerSpecExt::Clean { .. } => todo!(),
             },
         }
     }
@@ -232,6 +564,7 @@ impl Syncer {
                 source,
                 out_dir,
                 key_deriv_spec,
+                spread_depth,
                 verbose,
                 ..
             }
@@ -239,22 +572,40 @@ impl Syncer {
                 source,
                 out_dir,
                 key_deriv_spec,
+                spread_depth,
                 verbose,
                 ..
             } => {
-                if !source.exists() {
+                // the `csync` dir can be in remote storage, which only gets checked once it's opened,
+                // and streams and archives don't exist anywhere but where they are read from
+                let virtual_source = matches!(
+                    spec,
+                    SyncerSpec::Encrypt {
+                        source_kind: SourceKind::Stream | SourceKind::Tar,
+                        ..
+                    }
+                );
+                let (source_spec, out_dir_spec) = (StorageSpec::from_location(source)?, StorageSpec::from_location(out_dir)?);
+                if !source_spec.is_remote() && !virtual_source && !source.exists() {
                     csync_err!(SourceDoesNotExist, source.to_path_buf())?;
                 }
 
-                debug_assert!(is_canonical(source).unwrap());
-                debug_assert!(is_canonical(out_dir).unwrap());
+                debug_assert!(!source_spec.is_dir() || virtual_source || is_canonical(source).unwrap());
+                debug_assert!(!out_dir_spec.is_dir() || !out_dir.exists() || is_canonical(out_dir).unwrap());
 
                 // do this here because canonicalization requires the path to exist
                 // std::fs::create_dir_all(&out_dir)?;
                 check_out_dir(&out_dir, &spec)?;
 
-                let source = source.canonicalize()?.to_path_buf();
-                let out_dir = out_dir.canonicalize()?.to_path_buf();
+                let source = match virtual_source {
+                    true => source.to_path_buf(),
+                    false => source_spec.canonical_location()?,
+                };
+                // only a dry run decrypts to an `out_dir` that doesn't exist yet
+                let out_dir = match out_dir_spec.is_dir() && !out_dir.exists() {
+                    true => canonicalize_existing(out_dir)?,
+                    false => out_dir_spec.canonical_location()?,
+                };
 
                 match source.file_name() {
                     //
@@ -268,11 +619,57 @@ impl Syncer {
                             None => time!(*verbose, "Generating a derived key", key_deriv_spec.derive(&init_key.0 .0)?).0,
                         };
 
+                        let storage = match &spec {
+                            SyncerSpec::Encrypt { .. } => open_storage(&out_dir)?,
+                            _ => open_storage(&source)?,
+                        };
+                        storage.unlock(&spec, &derived_key)?;
+
+                        // only encryption and deletions look anything up in the index, which remote
+                        // `csync` dirs always keep, as reading the header of each ciphertext is a request
+                        let use_index = match &spec {
+                            SyncerSpec::Encrypt {
+                                source_kind: SourceKind::Stream | SourceKind::Tar,
+                                ..
+                            } => false,
+                            SyncerSpec::Encrypt { index, .. } => {
+                                *index || out_dir_spec.is_remote() || Index::exists(&*storage)?
+                            }
+                            SyncerSpec::Decrypt {
+                                delete: true,
+                                snapshot_opt: None,
+                                ..
+                            } => Index::exists(&*storage)?,
+                            _ => false,
+                        };
+                        let index = match use_index {
+                            true => Some(RwLock::new(
+                                time!(
+                                    *verbose,
+                                    "Loading the index",
+                                    Index::load_or_rebuild(&*storage, *spread_depth, &derived_key)?
+                                )
+                                .0,
+                            )),
+                            false => None,
+                        };
+                        let snapshot = match &spec {
+                            SyncerSpec::Decrypt {
+                                snapshot_opt: Some(id), ..
+                            } => Some(Snapshot::load(&*storage, *id, &derived_key)?),
+                            _ => None,
+                        };
+
                         Ok(Self {
                             arena: tmpdir!()?,
                             init_key,
+                            index,
+                            index_marked_stale: Mutex::new(false),
                             derived_key,
+                            observers: Vec::new(),
+                            snapshot,
                             spec,
+                            storage,
                         })
                     }
                 }
@@ -281,85 +678,513 @@ impl Syncer {
     }
 
     // Load metadata from an existing `csync` directory.
-    fn load_syncer_action_spec(source: &Path) -> CsyncResult<(SyncerSpec, ActionSpec)> {
-        match source.exists() {
-            true => {
-                let result_opt = WalkDir::new(source)
-                    .follow_links(true)
-                    .into_iter()
-                    .filter_map(|entry| match entry.map(walkdir::DirEntry::into_path) {
-                        Ok(pbuf) => match pbuf.extension().map(OsStr::to_str) {
-                            Some(Some("csync")) => match crate::crypt::util::load_syncer_action_specs(&pbuf) {
-                                Ok(specs) => Some(specs),
-                                _ => None,
-                            },
-                            _ => None,
-                        },
-                        _ => None,
-                    })
-                    .nth(0);
+    fn load_syncer_action_spec(storage: &dyn Storage) -> CsyncResult<(SyncerSpec, ActionSpec)> {
+        if let Some(ciphertext) = storage.locked_ciphertext()? {
+            return crate::crypt::util::load_syncer_action_specs(ciphertext);
+        }
 
-                match result_opt {
-                    Some(specs) => Ok(specs),
-                    None => csync_err!(MetadataLoadFailed, "Could not open any of the csync files".to_string()),
-                }
-            }
-            false => csync_err!(ControlFlow),
+        let result_opt = ciphertexts(storage)
+            .filter_map(|cipherpath_res| {
+                cipherpath_res
+                    .and_then(|cipherpath| storage.get(&cipherpath))
+                    .and_then(crate::crypt::util::load_syncer_action_specs)
+                    .ok()
+            })
+            .next();
+
+        match result_opt {
+            Some(specs) => Ok(specs),
+            None => csync_err!(MetadataLoadFailed, "Could not open any of the csync files".to_string()),
         }
     }
 
     /// 1. for the root cfile,
     ///
-    pub fn sync_enc<'a>(&'a self) -> CsyncResult<impl ParallelIterator<Item = CsyncResult<Action>> + 'a> {
+    pub(crate) fn sync_enc<'a>(&'a self) -> CsyncResult<impl ParallelIterator<Item = CsyncResult<Action<'a>>> + 'a> {
         match &self.spec {
             SyncerSpec::Encrypt { .. } => {
                 self.check_rep();
 
                 let iter = self.sync_enc_dry()?;
 
-                Ok(iter.map(move |action| action?.manifest(self.arena.path(), &self.derived_key)))
+                Ok(iter.map(move |action_res| self.manifest_enc(action_res)))
             }
             _ => todo!(),
         }
     }
 
+    // manifest an action of `sync_enc_dry`, and record it in the index
+    fn manifest_enc<'a>(&'a self, action_res: CsyncResult<Action<'a>>) -> CsyncResult<Action<'a>> {
+        let action = self.observed(action_res)?;
+        self.observed(self.mark_index_stale())?;
+        let action = self.manifest_observed(action)?;
+        self.observed(self.update_index(&action))?;
+        Ok(action)
+    }
+
+    /// Encrypt whatever changed at or under each of `touched`, and remove the ciphertexts of what
+    /// no longer exists there, then let the observers know once it's finished. Only the parts of
+    /// the source that lead to `touched` are walked, so this takes as long as the change is big
+    /// rather than as long as the source is; `csync watch` runs this for each batch of changes.
     ///
-    pub fn sync_enc_dry<'a>(&'a self) -> CsyncResult<impl ParallelIterator<Item = CsyncResult<Action>> + 'a> {
+    /// Call `store_index` and `flush` afterwards, like after `sync`. Whatever fails is also told to
+    /// the observers as a `SyncEvent::Error`, so a failed batch can be reported and the next one
+    /// synced as usual.
+    ///
+    /// # Parameters
+    ///
+    /// 1. `touched`: paths under the source, joined onto it, that were created, changed, removed,
+    ///    or renamed from or to
+    ///
+    /// # Returns
+    ///
+    /// What `sync` returns, and the number of ciphertexts that were removed.
+    pub fn sync_touched(&self, touched: &BTreeSet<PathBuf>) -> CsyncResult<(SyncSummary, usize)> {
         match &self.spec {
             SyncerSpec::Encrypt {
-                source,
+                filter_spec,
                 out_dir,
+                source,
                 spread_depth,
-                init_salt,
+                ..
+            } => {
+                self.check_rep();
+                self.observed(check_out_dir(out_dir, &self.spec))?;
+                let start = start_timer();
+
+                // whatever was removed from under `touched` has to be found among what was recorded
+                let touched: BTreeSet<PathBuf> = touched.iter().filter(|path| path.starts_with(source)).cloned().collect();
+                let within: HashSet<PathBuf> = touched.iter().filter_map(|path| self.rel_path_of(path)).collect();
+                let is_within = |path: &Path| path.ancestors().any(|ancestor| within.contains(ancestor));
+
+                // the index saves decrypting every cipherpath
+                let recorded: Vec<(PathBuf, PathBuf)> = match &self.index {
+                    Some(index) => {
+                        let index = index.read().unwrap();
+                        index
+                            .paths()
+                            .filter(|path| is_within(path))
+                            .filter_map(|path| Some((path.clone(), index.get(path)?.cipherpath.clone())))
+                            .collect()
+                    }
+                    None => self.observed(
+                        ciphertexts(&*self.storage)
+                            .par_bridge()
+                            .map(|cipherpath_res| -> CsyncResult<(PathBuf, PathBuf)> {
+                                let cipherpath = cipherpath_res?;
+                                Ok((
+                                    cipherpath_to_path(*spread_depth, &cipherpath, &self.derived_key)?.0,
+                                    cipherpath,
+                                ))
+                            })
+                            .filter(|recorded_res| match recorded_res {
+                                Ok((path, _)) => is_within(path),
+                                Err(_) => true,
+                            })
+                            .collect::<CsyncResult<_>>(),
+                    )?,
+                };
+
+                let parent = csync_unwrap_opt!(source.parent());
+                let (num_removed, _) =
+                    sum_counts(
+                        recorded
+                            .into_par_iter()
+                            .map(|(path, cipherpath)| match parent.join(&path).exists() {
+                                true => Ok((0, 0)),
+                                false => {
+                                    log::debug!("removing {:?}, as {} no longer exists", cipherpath, redact(&path));
+                                    self.observed(self.mark_index_stale())?;
+                                    self.observed(self.storage.delete(&cipherpath))?;
+                                    if let Some(index) = &self.index {
+                                        index.write().unwrap().remove(&path);
+                                    }
+                                    Ok((1, 0))
+                                }
+                            }),
+                    )?;
+
+                // the source is walked as the only root, which is the only one that gets watched
+                let metas = self.observed(meta_map_within(
+                    &[(source.clone(), source.clone())],
+                    filter_spec,
+                    Some(Arc::new(touched)),
+                ))?;
+                let (num_files, src_bytes, dest_bytes) =
+                    sum_lens(self.enc_actions(metas).map(|action_res| self.manifest_enc(action_res)))?;
+
+                let summary = SyncSummary {
+                    num_files,
+                    src_bytes,
+                    dest_bytes,
+                    duration: end_timer(&start),
+                };
+                log::info!(
+                    "synced {} touched files and removed {} in {:?}",
+                    num_files,
+                    num_removed,
+                    summary.duration
+                );
+                self.emit(|| SyncEvent::Finished(summary));
+                Ok((summary, num_removed))
+            }
+            _ => csync_err!(
+                UnsupportedOperation,
+                String::from("only a syncer that encrypts can sync what was touched")
+            ),
+        }
+    }
+
+    /// Encrypt the stream that the source of this syncer stands for, which gets replaced if it has
+    /// been encrypted before.
+    ///
+    /// # Parameters
+    ///
+    /// 1. `src`: the plaintext of the stream, read until its end
+    ///
+    /// # Returns
+    ///
+    /// Number of bytes read from `src`, and number of bytes in the ciphertext.
+    pub fn sync_enc_stream<R>(&self, src: R) -> CsyncResult<(u64, u64)>
+    where
+        R: Read,
+    {
+        match &self.spec {
+            SyncerSpec::Encrypt {
+                source,
+                salt_len,
+                source_kind: SourceKind::Stream,
+                ..
+            } => {
+                self.check_rep();
+
+                // neither the mode nor the metadata of a stream can be read up front
+                let action = Action::new(
+                    &self.spec,
+                    *salt_len,
+                    source,
+                    &self.cipherpath_of(source, FileType::File)?,
+                    FileType::File,
+                    Some(DEFAULT_PERM_BITS),
+                    None,
+                    &self.derived_key,
+                )?;
+                self.mark_index_stale()?;
+                let action = action.manifest_from(self.arena.path(), &*self.storage, &self.derived_key, src)?;
+
+                match action.get_lens() {
+                    Some(lens) => Ok(lens),
+                    None => csync_err!(NonFatalReportFailed),
+                }
+            }
+            _ => csync_err!(
+                UnsupportedOperation,
+                String::from("only a syncer that encrypts a stream can encrypt one")
+            ),
+        }
+    }
+
+    /// Encrypt the entries of a tar archive as if they were under the source of this syncer, which
+    /// stands for the directory they get decrypted to. Entries are encrypted one at a time as they
+    /// are read, so nothing is extracted to the filesystem.
+    ///
+    /// # Parameters
+    ///
+    /// 1. `archive`: a tar archive, read until its end
+    ///
+    /// # Returns
+    ///
+    /// Number of entries that were encrypted, number of bytes in their plaintexts, and number of
+    /// bytes in their ciphertexts.
+    pub fn sync_enc_tar<R>(&self, archive: R) -> CsyncResult<(usize, u64, u64)>
+    where
+        R: Read,
+    {
+        match &self.spec {
+            SyncerSpec::Encrypt {
+                source,
                 salt_len,
+                source_kind: SourceKind::Tar,
+                ..
+            } => {
+                self.check_rep();
+
+                // encrypt what would be at `src_pbuf`, whose plaintext is read from `src_opt` unless
+                // it's a directory; returns the length of its ciphertext
+                let encrypt = |src_pbuf: &Path,
+                               file_type: FileType,
+                               unix_mode: u32,
+                               file_meta: FileMetaSpec,
+                               src_opt: Option<&mut dyn Read>|
+                 -> CsyncResult<u64> {
+                    let action = Action::new(
+                        &self.spec,
+                        *salt_len,
+                        src_pbuf,
+                        &self.cipherpath_of(src_pbuf, file_type)?,
+                        file_type,
+                        Some(unix_mode),
+                        Some(&file_meta),
+                        &self.derived_key,
+                    )?;
+                    self.mark_index_stale()?;
+                    let action = match src_opt {
+                        Some(src) => action.manifest_from(self.arena.path(), &*self.storage, &self.derived_key, src)?,
+                        None => action.manifest(self.arena.path(), &*self.storage, &self.derived_key)?,
+                    };
+                    match action.get_lens() {
+                        Some((_, dest_len)) => Ok(dest_len),
+                        None => csync_err!(NonFatalReportFailed),
+                    }
+                };
+
+                let (mut num_entries, mut src_bytes, mut dest_bytes) = (0, 0, 0);
+                let mut has_root = false;
+                // relative paths of the symlinks in the archive, and of the directories that hold
+                // any of its entries
+                let (mut symlinks, mut parents) = (HashSet::new(), HashSet::new());
+                let mut archive = tar::Archive::new(archive);
+                for entry_res in archive.entries()? {
+                    let mut entry = entry_res?;
+                    let path = entry.path()?.into_owned();
+                    let rel_path = archive_entry_path(&path)?;
+                    let src_pbuf = match rel_path.as_os_str().is_empty() {
+                        true => source.to_path_buf(),
+                        false => source.join(&rel_path),
+                    };
+
+                    let header = entry.header();
+                    // nothing can be under a symlink, as it would get decrypted to wherever the
+                    // symlink points to
+                    let is_under_symlink = rel_path.ancestors().skip(1).any(|parent| symlinks.contains(parent))
+                        || (header.entry_type().is_symlink() && parents.contains(&rel_path));
+                    let file_type = match header.entry_type() {
+                        // the top of the archive can only be a directory
+                        _ if src_pbuf == *source && !header.entry_type().is_dir() => csync_err!(UnsupportedArchiveEntry, path)?,
+                        _ if is_under_symlink => csync_err!(UnsupportedArchiveEntry, path)?,
+                        tar::EntryType::Regular | tar::EntryType::Continuous => FileType::File,
+                        tar::EntryType::Directory => FileType::Dir,
+                        tar::EntryType::Symlink => FileType::Symlink,
+                        _ => csync_err!(UnsupportedArchiveEntry, path)?,
+                    };
+                    parents.extend(rel_path.ancestors().skip(1).map(Path::to_path_buf));
+                    if file_type == FileType::Symlink {
+                        symlinks.insert(rel_path.clone());
+                    }
+                    let unix_mode = header.mode()?;
+                    let modified = UNIX_EPOCH + Duration::from_secs(header.mtime()?);
+
+                    let mut src = CountingReader::new(&mut entry);
+                    dest_bytes += match file_type {
+                        FileType::File => {
+                            let len = src.get_ref().size();
+                            encrypt(
+                                &src_pbuf,
+                                file_type,
+                                unix_mode,
+                                FileMetaSpec::new(len, modified),
+                                Some(&mut src),
+                            )?
+                        }
+                        FileType::Dir => encrypt(&src_pbuf, file_type, unix_mode, FileMetaSpec::new(0, modified), None)?,
+                        FileType::Symlink => {
+                            let target = csync_unwrap_opt!(src.get_ref().link_name()?).into_owned();
+                            let target_bytes = target.as_os_str().as_bytes();
+                            let file_meta = FileMetaSpec::new(target_bytes.len() as u64, modified);
+                            src_bytes += target_bytes.len() as u64;
+                            encrypt(&src_pbuf, file_type, unix_mode, file_meta, Some(&mut &target_bytes[..]))?
+                        }
+                    };
+                    src_bytes += src.count;
+                    num_entries += 1;
+                    has_root |= src_pbuf == *source;
+                }
+
+                // archives don't need an entry for their top, but it still has to decrypt to a directory
+                if !has_root {
+                    let file_meta = FileMetaSpec::new(0, SystemTime::now());
+                    dest_bytes += encrypt(source, FileType::Dir, DEFAULT_DIR_PERM_BITS, file_meta, None)?;
+                    num_entries += 1;
+                }
+
+                Ok((num_entries, src_bytes, dest_bytes))
+            }
+            _ => csync_err!(
+                UnsupportedOperation,
+                String::from("only a syncer that encrypts a tar archive can encrypt one")
+            ),
+        }
+    }
+
+    // record a manifested encryption in the index, if there is one
+    fn update_index(&self, action: &Action) -> CsyncResult<()> {
+        match (&self.spec, &self.index) {
+            (SyncerSpec::Encrypt { spread_depth, .. }, Some(index)) => {
+                let entry = IndexEntry {
+                    cipherpath: action.dest.clone(),
+                    file_meta: csync_unwrap_opt!(action.get_file_meta(&self.derived_key)?),
+                    file_type: action.get_file_type(),
+                };
+                // `src` can be under a root other than the source, which only the cipherpath tells
+                let (path, _, _) = cipherpath_to_path(*spread_depth, &action.dest, &self.derived_key)?;
+                index.write().unwrap().insert(path, entry);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // before the first change to a ciphertext since the index was last stored, mark the stored
+    // index as stale, so that it gets rebuilt unless `store_index` is called after the change
+    fn mark_index_stale(&self) -> CsyncResult<()> {
+        let mut index_marked_stale = self.index_marked_stale.lock().unwrap();
+        if !*index_marked_stale {
+            Index::mark_stale(&*self.storage)?;
+            *index_marked_stale = true;
+        }
+        Ok(())
+    }
+
+    /// Store the index in the `csync` dir, if there is one. Call this once encryption is done.
+    pub fn store_index(&self) -> CsyncResult<()> {
+        match (&self.spec, &self.index) {
+            (SyncerSpec::Encrypt { .. }, Some(index)) => {
+                let mut index_marked_stale = self.index_marked_stale.lock().unwrap();
+                index
+                    .write()
+                    .unwrap()
+                    .store(&self.spec, self.arena.path(), &*self.storage, &self.derived_key)?;
+                *index_marked_stale = false;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Write out whatever the storage of the `csync` dir holds back, like the table of contents of
+    /// an archive. Call this once nothing else is left to store.
+    #[inline]
+    pub fn flush(&self) -> CsyncResult<()> {
+        self.storage.flush(self.arena.path())
+    }
+
+    /// Record a snapshot of every plaintext in the source, if snapshots are being taken. Call this
+    /// once encryption is done.
+    ///
+    /// Each file is recorded as the chunks its ciphertext references, which means decrypting the
+    /// header and chunk references of every ciphertext. Files that were stored whole before
+    /// snapshots were first taken get stored chunked now.
+    ///
+    /// # Returns
+    ///
+    /// Id of the snapshot, if one was recorded.
+    pub fn create_snapshot(&self) -> CsyncResult<Option<u64>> {
+        match &self.spec {
+            SyncerSpec::Encrypt {
+                filter_spec,
+                snapshot: true,
+                verbose,
                 ..
             } => {
                 self.check_rep();
+
+                let (entries_res, _) = time!(
+                    *verbose,
+                    "Creating a snapshot",
+                    meta_map(&self.source_roots()?, filter_spec)?
+                        .map(|meta_res| -> CsyncResult<Option<(PathBuf, SnapshotEntry)>> {
+                            let (_, _, enc_pbuf, _, _, file_type) = meta_res?;
+                            let cipherpath = self.cipherpath_of(&enc_pbuf, file_type)?;
+                            // created after the source was encrypted
+                            if !self.storage.exists(&cipherpath)? {
+                                return Ok(None);
+                            }
+
+                            let (chunk_refs, action_spec) = match file_type {
+                                FileType::File => match load_chunk_refs(&*self.storage, &cipherpath, &self.derived_key)? {
+                                    Some((chunk_refs, action_spec)) => (Some(chunk_refs), action_spec),
+                                    None => {
+                                        let action_arena = self.arena.path().join(thread_id::get().to_string());
+                                        std::fs::create_dir_all(&action_arena)?;
+
+                                        let tmp_dest = action_arena.join("Syncer_create_snapshot");
+                                        let (_, action_spec) = csync_decrypt(
+                                            self.storage.get(&cipherpath)?,
+                                            Some(fopen_w(&tmp_dest)?),
+                                            &self.derived_key,
+                                        )?;
+                                        let chunk_refs = store_chunks(
+                                            &self.spec,
+                                            &action_arena,
+                                            fopen_r(&tmp_dest)?,
+                                            &*self.storage,
+                                            &self.derived_key,
+                                        )?;
+                                        remove(&tmp_dest)?;
+                                        (Some(chunk_refs), action_spec)
+                                    }
+                                },
+                                FileType::Dir => (
+                                    None,
+                                    csync_decrypt(self.storage.get(&cipherpath)?, Option::<File>::None, &self.derived_key)?.1,
+                                ),
+                                FileType::Symlink => panic!("Sources should be walked following symlinks"),
+                            };
+
+                            let entry = SnapshotEntry {
+                                chunk_refs,
+                                file_meta: csync_unwrap_opt!(action_spec.get_file_meta(&self.derived_key)?),
+                                file_type,
+                                unix_mode: csync_unwrap_opt!(action_spec.get_unix_mode()),
+                            };
+                            Ok(Some((csync_unwrap_opt!(self.rel_path_of(&enc_pbuf)), entry)))
+                        })
+                        .filter_map(Result::transpose)
+                        .collect::<CsyncResult<BTreeMap<_, _>>>()
+                );
+
+                Snapshot::new(entries_res?)
+                    .store(&self.spec, self.arena.path(), &*self.storage, &self.derived_key)
+                    .map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    ///
+    pub(crate) fn sync_enc_dry<'a>(&'a self) -> CsyncResult<impl ParallelIterator<Item = CsyncResult<Action<'a>>> + 'a> {
+        match &self.spec {
+            SyncerSpec::Encrypt {
+                filter_spec, out_dir, ..
+            } => {
+                self.check_rep();
                 check_out_dir(out_dir, &self.spec)?;
+                self.emit(|| SyncEvent::ScanStarted);
 
-                Ok(meta_map(source).filter_map(move |meta_res| match meta_res {
-                    Ok((_, src_pbuf, perms, src_modtime, file_type)) => {
-                        let spread = match path_to_spread(*spread_depth, &init_salt, &src_pbuf) {
-                            Ok(x) => x,
-                            Err(err) => return Some(csync_err!(Other, format!("{}", err))),
-                        };
-                        let spread_hash = match spread_to_hash(&spread) {
-                            Ok(x) => x,
-                            Err(err) => return Some(Err(err)),
-                        };
+                Ok(self.enc_actions(meta_map(&self.source_roots()?, filter_spec)?))
+            }
+            _ => todo!(),
+        }
+    }
 
-                        let cipherpath = {
-                            let cipher_basename =
-                                match path_to_cipherpath(source, &src_pbuf, file_type, &spread_hash, &self.derived_key) {
-                                    Ok(cipherpath) => cipherpath,
-                                    Err(err) => return Some(Err(err)),
-                                };
-                            out_dir.join(spread).join(cipher_basename)
+    // an action for each of `metas` that has to be encrypted, letting the observers know of the ones
+    // that are skipped
+    fn enc_actions<'a, I>(&'a self, metas: I) -> impl ParallelIterator<Item = CsyncResult<Action<'a>>> + 'a
+    where
+        I: ParallelIterator<Item = CsyncResult<MetaEntry>> + 'a,
+    {
+        match &self.spec {
+            SyncerSpec::Encrypt { checksum, salt_len, .. } => {
+                metas.filter_map(move |meta_res| match meta_res {
+                    Ok((_, src_pbuf, enc_pbuf, perms, file_meta, file_type)) => {
+                        let cipherpath = match self.cipherpath_of(&enc_pbuf, file_type) {
+                            Ok(cipherpath) => cipherpath,
+                            Err(err) => return Some(Err(err)),
                         };
 
                         // sugar
                         macro_rules! action {
-                            ( $dest:expr ) => {
+                            ( $dest:expr, $file_meta:expr ) => {
                                 Some(Action::new(
                                     &self.spec,
                                     *salt_len,
@@ -367,41 +1192,78 @@ impl Syncer {
                                     $dest,
                                     file_type,
                                     Some(perms.mode()),
+                                    Some(&$file_meta),
                                     &self.derived_key,
                                 ))
                             };
                         };
+                        macro_rules! with_checksum {
+                            ( $file_meta:expr ) => {
+                                match $file_meta.with_checksum(&src_pbuf, &self.derived_key) {
+                                    Ok(file_meta) => file_meta,
+                                    Err(err) => return Some(Err(err)),
+                                }
+                            };
+                        }
+
+                        // only hash the contents up front if they are what gets compared
+                        let file_meta = match (checksum, file_type) {
+                            (true, FileType::File) => with_checksum!(file_meta),
+                            _ => file_meta,
+                        };
 
-                        match std::fs::metadata(&cipherpath) {
-                            Ok(meta) => match meta.modified() {
-                                // both files exist, so compare their modified times
-                                Ok(enc_mod) => match src_modtime.duration_since(enc_mod) {
-                                    // src was modified after enc, so include it
-                                    Ok(duration) if 0 < duration.as_nanos() => action!(&cipherpath),
-                                    // enc was modified after src, so don't include it
-                                    _ => None,
+                        // the index saves reading the header of `cipherpath`
+                        let should_encrypt = match &self.index {
+                            Some(index) => match self.rel_path_of(&enc_pbuf) {
+                                Some(path) => match index.read().unwrap().get(&path) {
+                                    Some(entry) => Ok(!file_meta.is_unchanged_from(&entry.file_meta)),
+                                    None => Ok(true),
                                 },
-                                Err(err) => panic!("{:?}", err),
+                                None => csync_err!(Other, format!("{:?} is not next to the source", enc_pbuf)),
                             },
-                            Err(err) if err.kind() == io::ErrorKind::NotFound => action!(&cipherpath),
-                            Err(err) => panic!("{:?}", err),
+                            None => should_encrypt_to(&file_meta, &*self.storage, &cipherpath, &self.derived_key),
+                        };
+
+                        match should_encrypt {
+                            // the checksum is always recorded, so that `--checksum` can be used later
+                            Ok(true) => {
+                                log::debug!(
+                                    "encrypting {} to {:?}, as it is new or changed",
+                                    redact(&src_pbuf),
+                                    cipherpath
+                                );
+                                match (checksum, file_type) {
+                                    (false, FileType::File) => action!(&cipherpath, with_checksum!(file_meta)),
+                                    _ => action!(&cipherpath, file_meta),
+                                }
+                            }
+                            Ok(false) => {
+                                log::debug!("skipping {}, as {:?} is up to date", redact(&src_pbuf), cipherpath);
+                                self.emit(|| SyncEvent::FileSkipped {
+                                    src: src_pbuf.to_path_buf(),
+                                    dest: cipherpath,
+                                    src_bytes: file_meta.get_len(),
+                                });
+                                None
+                            }
+                            Err(err) => Some(Err(err)),
                         }
                     }
                     Err(err) => Some(Err(err)),
-                }))
+                })
             }
-            _ => todo!(),
+            _ => panic!("Files should only be encrypted with an encryption spec"),
         }
     }
 
     ///
-    pub fn sync_dec<'a>(&'a self) -> CsyncResult<impl ParallelIterator<Item = CsyncResult<Action>> + 'a> {
+    pub(crate) fn sync_dec<'a>(&'a self) -> CsyncResult<impl ParallelIterator<Item = CsyncResult<Action<'a>>> + 'a> {
         match &self.spec {
             SyncerSpec::Decrypt { .. } => {
                 self.check_rep();
 
                 let iter = self.sync_dec_dry()?;
-                Ok(iter.map(move |action| action?.manifest(self.arena.path(), &self.derived_key)))
+                Ok(iter.map(move |action_res| self.manifest_observed(self.observed(action_res)?)))
             }
             _ => {
                 dbg!(&self.spec);
@@ -411,10 +1273,11 @@ impl Syncer {
     }
 
     ///
-    pub fn sync_dec_dry<'a>(&'a self) -> CsyncResult<impl ParallelIterator<Item = CsyncResult<Action>> + 'a> {
+    pub(crate) fn sync_dec_dry<'a>(&'a self) -> CsyncResult<impl ParallelIterator<Item = CsyncResult<Action<'a>>> + 'a> {
         match &self.spec {
             SyncerSpec::Decrypt {
-                source,
+                conflict_spec,
+                snapshot_opt,
                 out_dir,
                 spread_depth,
                 salt_len,
@@ -422,39 +1285,896 @@ impl Syncer {
             } => {
                 self.check_rep();
                 check_out_dir(out_dir, &self.spec)?;
+                self.emit(|| SyncEvent::ScanStarted);
+
+                // the snapshot records everything that gets restored, so no ciphertext is read
+                if let (Some(snapshot), Some(id)) = (&self.snapshot, snapshot_opt) {
+                    let snapshot_key = snapshot_key(*id);
+                    return Ok(Either::Left(
+                        snapshot
+                            .par_entries()
+                            .filter(move |(path, _)| self.restores(path))
+                            .map(move |(path, entry)| -> CsyncResult<Option<Action>> {
+                                let dest = out_dir.join(path);
+                                let get_recorded = || Ok(Some(entry.file_meta.clone()));
+                                match should_decrypt_to(conflict_spec, entry.file_type, &dest, get_recorded)? {
+                                    true => {
+                                        log::debug!("restoring {} from {:?}", redact(&dest), snapshot_key);
+                                        Action::new(
+                                            &self.spec,
+                                            *salt_len,
+                                            &snapshot_key,
+                                            &dest,
+                                            entry.file_type,
+                                            Some(entry.unix_mode),
+                                            Some(&entry.file_meta),
+                                            &self.derived_key,
+                                        )
+                                        .map(|action| Some(action.with_snapshot_entry(entry)))
+                                    }
+                                    false => {
+                                        log::debug!("skipping {}, which {:?} keeps as it is", redact(&dest), conflict_spec);
+                                        self.emit(|| SyncEvent::FileSkipped {
+                                            src: snapshot_key.clone(),
+                                            dest,
+                                            src_bytes: entry.file_meta.get_len(),
+                                        });
+                                        Ok(None)
+                                    }
+                                }
+                            })
+                            .filter_map(Result::transpose),
+                    ));
+                }
+
+                Ok(Either::Right(
+                    ciphertexts(&*self.storage)
+                        .par_bridge()
+                        .map(move |cipherpath_res| -> CsyncResult<Option<Action>> {
+                            let cipherpath = cipherpath_res?;
+                            let (path, file_type, _) = cipherpath_to_path(*spread_depth, &cipherpath, &self.derived_key)?;
+                            if !self.restores(&path) {
+                                return Ok(None);
+                            }
+                            let dest = out_dir.join(path);
+                            let get_recorded = || {
+                                self.storage
+                                    .get(&cipherpath)
+                                    .and_then(crate::crypt::util::load_syncer_action_specs)
+                                    .and_then(|(_, action_spec)| action_spec.get_file_meta(&self.derived_key))
+                            };
+                            match should_decrypt_to(conflict_spec, file_type, &dest, get_recorded)? {
+                                true => {
+                                    log::debug!("decrypting {:?} to {}", cipherpath, redact(&dest));
+                                    Action::new(
+                                        &self.spec,
+                                        *salt_len,
+                                        &cipherpath,
+                                        &dest,
+                                        file_type,
+                                        None,
+                                        None,
+                                        &self.derived_key,
+                                    )
+                                    .map(Some)
+                                }
+                                false => {
+                                    log::debug!("skipping {}, which {:?} keeps as it is", redact(&dest), conflict_spec);
+                                    self.emit(|| SyncEvent::FileSkipped {
+                                        src_bytes: self.stored_len(&cipherpath).unwrap_or(0),
+                                        src: cipherpath,
+                                        dest,
+                                    });
+                                    Ok(None)
+                                }
+                            }
+                        })
+                        .filter_map(Result::transpose),
+                ))
+            }
+            _ => todo!(),
+        }
+    }
+
+    /// Remove whatever `sync_dec_delete_dry` yields.
+    pub fn sync_dec_delete<'a>(&'a self) -> CsyncResult<impl ParallelIterator<Item = CsyncResult<PathBuf>> + 'a> {
+        Ok(self.sync_dec_delete_dry()?.map(|path_res| {
+            let path = path_res?;
+            log::debug!("removing {}, which is no longer in the `csync` dir", redact(&path));
+            // don't follow symlinks
+            match std::fs::symlink_metadata(&path)?.is_dir() {
+                true => std::fs::remove_dir_all(&path)?,
+                false => std::fs::remove_file(&path)?,
+            };
+            Ok(path)
+        }))
+    }
+
+    /// # Returns
+    ///
+    /// Paths under the decrypted root in `out_dir` that do not exist in the `csync` directory.
+    /// Directories are yielded without their contents.
+    ///
+    /// Unlike the other iterators, this holds every plaintext path in memory at once.
+    pub fn sync_dec_delete_dry<'a>(&'a self) -> CsyncResult<impl ParallelIterator<Item = CsyncResult<PathBuf>> + 'a> {
+        match &self.spec {
+            SyncerSpec::Decrypt {
+                out_dir, spread_depth, ..
+            } => {
+                self.check_rep();
 
-                Ok(WalkDir::new(source)
+                // every path that something in the `csync` dir decrypts to, which the snapshot or
+                // the index saves decrypting every cipherpath for
+                let keep: HashSet<PathBuf> = match (&self.snapshot, &self.index) {
+                    (Some(snapshot), _) => snapshot.paths().cloned().collect(),
+                    (None, Some(index)) => index.read().unwrap().paths().cloned().collect(),
+                    (None, None) => ciphertexts(&*self.storage)
+                        .par_bridge()
+                        .map(|cipherpath_res| -> CsyncResult<PathBuf> {
+                            Ok(cipherpath_to_path(*spread_depth, &cipherpath_res?, &self.derived_key)?.0)
+                        })
+                        .collect::<CsyncResult<_>>()?,
+                };
+                // roots that aren't being decrypted are left alone
+                let keep: HashSet<PathBuf> = keep
                     .into_iter()
-                    .enumerate()
+                    .filter(|path| self.restores(path))
+                    .map(|path| out_dir.join(path))
+                    .collect();
+
+                // only look under the decrypted roots, so that nothing else in `out_dir` is touched;
+                // there is nothing to remove under roots that haven't been decrypted yet
+                let roots: HashSet<PathBuf> = keep
+                    .iter()
+                    .filter_map(|path| subpath(path, out_dir)?.components().next().map(|comp| out_dir.join(comp)))
+                    .filter(|root| root.exists())
+                    .collect();
+
+                let mut extraneous = Vec::new();
+                for root in roots {
+                    let mut walker = WalkDir::new(root).into_iter();
+                    while let Some(entry_res) = walker.next() {
+                        let entry = entry_res?;
+                        if !keep.contains(entry.path()) {
+                            if entry.file_type().is_dir() {
+                                walker.skip_current_dir();
+                            }
+                            extraneous.push(entry.into_path());
+                        }
+                    }
+                }
+
+                Ok(extraneous.into_par_iter().map(Ok))
+            }
+            _ => csync_err!(
+                UnsupportedOperation,
+                String::from("only a syncer that decrypts can find what decrypting would remove")
+            ),
+        }
+    }
+
+    /// Remove the snapshots that the retention spec doesn't keep, then remove every chunk that is
+    /// referenced by neither a remaining snapshot nor a current ciphertext.
+    ///
+    /// # Returns
+    ///
+    /// Number of snapshots removed, and number of chunks removed.
+    pub fn clean(&self) -> CsyncResult<(usize, usize)> {
+        let (snapshot_ids, chunk_keys) = self.clean_dry()?;
+        for id in &snapshot_ids {
+            Snapshot::remove(&*self.storage, *id)?;
+        }
+        for key in &chunk_keys {
+            self.storage.delete(key)?;
+        }
+
+        Ok((snapshot_ids.len(), chunk_keys.len()))
+    }
+
+    // ids of the snapshots that `clean` removes, and keys of the chunks that it removes
+    fn clean_dry(&self) -> CsyncResult<(Vec<u64>, Vec<PathBuf>)> {
+        match &self.spec {
+            SyncerSpec::Clean { retention_spec, .. } => {
+                self.check_rep();
+
+                // the chunks that only the pruned snapshots reference get removed along with them
+                let ids = snapshot_ids(&*self.storage)?;
+                let retained = retention_spec.retained(&ids);
+                let removed_ids = ids.iter().filter(|id| !retained.contains(id)).copied().collect();
+
+                let mut referenced: HashSet<String> = ciphertexts(&*self.storage)
                     .par_bridge()
-                    .filter(|(_, entry_res)| match entry_res {
-                        Ok(entry) => match entry.metadata() {
-                            // only work with files that end with .syncr
-                            Ok(meta) => meta.is_file() && entry.path().extension() == Some(OsStr::new(FILE_SUFFIX)),
-                            Err(_) => true,
-                        },
-                        Err(_) => true,
+                    .map(|cipherpath_res| -> CsyncResult<Vec<String>> {
+                        Ok(match load_chunk_refs(&*self.storage, &cipherpath_res?, &self.derived_key)? {
+                            Some((chunk_refs, _)) => {
+                                chunk_refs.iter().map(|chunk_ref| chunk_ref.get_id().to_string()).collect()
+                            }
+                            None => Vec::new(),
+                        })
                     })
-                    .map(move |(_, entry_res)| -> CsyncResult<Action> {
-                        let cipherpath = entry_res?.path().canonicalize()?;
-                        debug_assert!(is_canonical(&cipherpath).unwrap());
-                        let (path, file_type, _) = cipherpath_to_path(*spread_depth, source, &cipherpath, &self.derived_key)?;
-                        Action::new(
-                            &self.spec,
-                            *salt_len,
-                            &cipherpath,
-                            &out_dir.join(path),
-                            file_type,
-                            None,
-                            &self.derived_key,
-                        )
-                    }))
+                    .collect::<CsyncResult<Vec<_>>>()?
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                for id in retained {
+                    referenced.extend(
+                        Snapshot::load(&*self.storage, id, &self.derived_key)?
+                            .chunk_ids()
+                            .map(String::from),
+                    );
+                }
+
+                // collected first, so that nothing is removed from `storage` while it is listed
+                let mut removed_keys = Vec::new();
+                for chunk_res in stored_chunks(&*self.storage).collect::<Vec<_>>() {
+                    let (id, key) = chunk_res?;
+                    if !referenced.contains(&id) {
+                        removed_keys.push(key);
+                    }
+                }
+
+                Ok((removed_ids, removed_keys))
             }
             _ => todo!(),
         }
     }
 
+    /// Find out what syncing with `spec_ext` would do, without writing anything. No key is derived
+    /// other than to authenticate against an existing `csync` dir, and nothing gets encrypted or
+    /// decrypted.
+    ///
+    /// Encrypting to where there is no `csync` dir yet would create everything, which is reported
+    /// without using `init_key` at all.
+    ///
+    /// # Returns
+    ///
+    /// Every path that would be created, updated, skipped or deleted, in that order and then in the
+    /// order of their paths.
+    pub fn dry_run(spec_ext: &SyncerSpecExt, init_key: InitialKey) -> CsyncResult<Vec<PlannedChange>> {
+        let mut changes = match spec_ext {
+            SyncerSpecExt::Encrypt {
+                filter_spec,
+                out_dir,
+                roots,
+                source,
+                source_kind: SourceKind::Path,
+                ..
+            } if is_fresh_out_dir(out_dir)? => {
+                if !source.exists() {
+                    csync_err!(SourceDoesNotExist, source.to_path_buf())?;
+                }
+                let roots = roots.iter().map(SourceRoot::canonicalize).collect::<CsyncResult<Vec<_>>>()?;
+                meta_map(&walked_roots(&source.canonicalize()?, &roots)?, filter_spec)?
+                    .map(|meta_res| {
+                        let (_, src_pbuf, _, _, file_meta, file_type) = meta_res?;
+                        Ok(PlannedChange {
+                            change: Change::Create,
+                            path: src_pbuf,
+                            bytes: planned_len(file_type, file_meta.get_len()),
+                        })
+                    })
+                    .collect::<CsyncResult<_>>()?
+            }
+            SyncerSpecExt::Encrypt {
+                source_kind: SourceKind::Path,
+                ..
+            }
+            | SyncerSpecExt::Decrypt { .. }
+            | SyncerSpecExt::Clean { .. } => Syncer::from_dir(spec_ext, &init_key, true)?.plan()?,
+            _ => csync_err!(
+                UnsupportedOperation,
+                String::from("only paths can be dry run, as reading streams and archives consumes them")
+            )?,
+        };
+
+        changes.par_sort_unstable();
+        Ok(changes)
+    }
+
+    // what syncing would do; what is already up to date is only known from the events that the dry
+    // iterators emit about it
+    fn plan(mut self) -> CsyncResult<Vec<PlannedChange>> {
+        if let SyncerSpec::Clean { .. } = &self.spec {
+            let (snapshot_ids, chunk_keys) = self.clean_dry()?;
+            return snapshot_ids
+                .into_iter()
+                .map(snapshot_key)
+                .chain(chunk_keys)
+                .map(|key| {
+                    Ok(PlannedChange {
+                        change: Change::Delete,
+                        bytes: self.stored_len(&key)?,
+                        path: key,
+                    })
+                })
+                .collect();
+        }
+
+        let skipped = Arc::new(Mutex::new(Vec::new()));
+        {
+            let skipped = Arc::clone(&skipped);
+            self.subscribe(move |event: &SyncEvent| {
+                if let SyncEvent::FileSkipped { src, dest, .. } = event {
+                    skipped.lock().unwrap().push((src.clone(), dest.clone()));
+                }
+            });
+        }
+
+        let mut changes = match &self.spec {
+            SyncerSpec::Encrypt { .. } => self
+                .sync_enc_dry()?
+                .map(|action_res| {
+                    let action = action_res?;
+                    Ok(PlannedChange {
+                        change: match self.storage.stat(&action.dest)? {
+                            Some(_) => Change::Update,
+                            None => Change::Create,
+                        },
+                        bytes: planned_len(action.get_file_type(), action.get_recorded_len().unwrap_or(0)),
+                        path: action.src,
+                    })
+                })
+                .collect::<CsyncResult<Vec<_>>>()?,
+            SyncerSpec::Decrypt { delete, .. } => {
+                let mut changes = self
+                    .sync_dec_dry()?
+                    .map(|action_res| {
+                        let action = action_res?;
+                        // restored from chunks if it is restored from a snapshot
+                        let src_len = match action.get_recorded_len() {
+                            Some(len) => len,
+                            None => self.stored_len(&action.src)?,
+                        };
+                        Ok(PlannedChange {
+                            change: match std::fs::symlink_metadata(&action.dest) {
+                                Ok(_) => Change::Update,
+                                Err(_) => Change::Create,
+                            },
+                            bytes: planned_len(action.get_file_type(), src_len),
+                            path: action.dest,
+                        })
+                    })
+                    .collect::<CsyncResult<Vec<_>>>()?;
+                if *delete {
+                    changes.par_extend(
+                        self.sync_dec_delete_dry()?
+                            .map(|path_res| {
+                                let path = path_res?;
+                                Ok(PlannedChange {
+                                    change: Change::Delete,
+                                    bytes: tree_len(&path)?,
+                                    path,
+                                })
+                            })
+                            .collect::<CsyncResult<Vec<_>>>()?,
+                    );
+                }
+                changes
+            }
+            SyncerSpec::Clean { .. } => unreachable!(),
+        };
+
+        // the plaintext is what gets reported, which is `src` when encrypting
+        let skipped = std::mem::take(&mut *skipped.lock().unwrap());
+        for (src, dest) in skipped {
+            let path = match &self.spec {
+                SyncerSpec::Encrypt { .. } => src,
+                _ => dest,
+            };
+            changes.push(PlannedChange {
+                change: Change::Skip,
+                bytes: match std::fs::symlink_metadata(&path)? {
+                    meta if meta.is_dir() => 0,
+                    meta => meta.len(),
+                },
+                path,
+            });
+        }
+
+        Ok(changes)
+    }
+
+    // size in bytes of the object at `key`
+    #[inline]
+    fn stored_len(&self, key: &Path) -> CsyncResult<u64> {
+        Ok(csync_unwrap_opt!(self.storage.stat(key)?).len)
+    }
+
+    /// Decrypt a single file and write its plaintext to `dest`.
+    ///
+    /// The cipherpath of `path` is recomputed the same way `sync_enc_dry` computes it, so nothing
+    /// other than that one ciphertext is read, and nothing is written to the filesystem.
+    ///
+    /// # Parameters
+    ///
+    /// 1. `path_opt`: path of the file relative to the parent of the encrypted source, which is how
+    ///    it would appear under the output directory of `sync_dec`; the source itself if `None`,
+    ///    which then has to be a file or a stream
+    /// 1. `dest`: where the plaintext will be written to
+    ///
+    /// # Returns
+    ///
+    /// Number of bytes in the ciphertext that was read.
+    ///
+    /// Note that plaintext is written to `dest` as it is decrypted, and the authentication of the
+    /// ciphertext only completes at the end; if this returns an `Err(_)`, whatever has been
+    /// written to `dest` should not be trusted.
+    pub fn cat<W>(&self, path_opt: Option<&Path>, dest: W) -> CsyncResult<u64>
+    where
+        W: Write,
+    {
+        match &self.spec {
+            SyncerSpec::Encrypt { source, .. } => {
+                self.check_rep();
+
+                let path = match path_opt {
+                    Some(path) => path,
+                    None => Path::new(csync_unwrap_opt!(source.file_name())),
+                };
+
+                // only allow paths that stay under the parent of `source`, which the source and
+                // every other root are encrypted as being in
+                if path.components().any(|comp| !matches!(comp, Component::Normal(_))) {
+                    csync_err!(PathNotFoundInCsyncDir, path.to_path_buf())?;
+                }
+                let src_pbuf = csync_unwrap_opt!(source.parent()).join(path);
+
+                let cipherpath = self.cipherpath_of(&src_pbuf, FileType::File)?;
+
+                match self.storage.stat(&cipherpath)? {
+                    Some(object_meta) => {
+                        csync_decrypt_file(&*self.storage, &cipherpath, dest, &self.derived_key)?;
+                        Ok(object_meta.len)
+                    }
+                    None => csync_err!(PathNotFoundInCsyncDir, path.to_path_buf()),
+                }
+            }
+            _ => csync_err!(
+                UnsupportedOperation,
+                String::from("only a syncer built from a `csync` directory can write one of its files")
+            ),
+        }
+    }
+
+    /// Decrypt every plaintext into a tar archive written to `dest`, sorted by their paths so that
+    /// directories come before their contents. Nothing is written to the filesystem.
+    ///
+    /// The contents of a directory at the source are at the top of the archive, which is how
+    /// `sync_enc_tar` reads them, while a file or stream at the source is the only entry.
+    ///
+    /// Unlike the other iterators, this holds every plaintext path in memory at once.
+    ///
+    /// # Parameters
+    ///
+    /// 1. `dest`: where the archive will be written to
+    ///
+    /// # Returns
+    ///
+    /// Number of entries in the archive, number of bytes in the ciphertexts that were read, and
+    /// number of bytes in the archive.
+    ///
+    /// Like with `cat`, the contents of each entry are written to `dest` as they are decrypted; if
+    /// this returns an `Err(_)`, the archive written to `dest` should not be trusted.
+    pub fn export_tar<W>(&self, dest: W) -> CsyncResult<(usize, u64, u64)>
+    where
+        W: Write,
+    {
+        match &self.spec {
+            SyncerSpec::Encrypt {
+                source, spread_depth, ..
+            } => {
+                self.check_rep();
+
+                let mut entries = ciphertexts(&*self.storage)
+                    .par_bridge()
+                    .map(|cipherpath_res| -> CsyncResult<(PathBuf, FileType, PathBuf)> {
+                        let cipherpath = cipherpath_res?;
+                        let (path, file_type, _) = cipherpath_to_path(*spread_depth, &cipherpath, &self.derived_key)?;
+                        Ok((path, file_type, cipherpath))
+                    })
+                    .collect::<CsyncResult<Vec<_>>>()?;
+                entries.sort_by(|(path_a, _, _), (path_b, _, _)| path_a.cmp(path_b));
+
+                let root = Path::new(csync_unwrap_opt!(source.file_name()));
+                let (mut num_entries, mut src_bytes) = (0, 0);
+                let mut builder = tar::Builder::new(CountingWriter::new(dest));
+                for (path, file_type, cipherpath) in entries {
+                    let rel_path = csync_unwrap_opt!(subpath(&path, root));
+                    let entry_path = match (rel_path.as_os_str().is_empty(), file_type) {
+                        (false, _) => rel_path,
+                        // a directory at the source is the top of the archive itself
+                        (true, FileType::Dir) => continue,
+                        (true, _) => path,
+                    };
+
+                    let object_meta = csync_unwrap_opt!(self.storage.stat(&cipherpath)?);
+                    src_bytes += object_meta.len;
+
+                    // only a peek for the header of the entry, which has to match the action spec
+                    // that gets authenticated along with the contents
+                    let (_, action_spec) = load_syncer_action_specs(self.storage.get(&cipherpath)?)?;
+                    let file_meta_opt = action_spec.get_file_meta(&self.derived_key)?;
+
+                    let mut header = tar::Header::new_gnu();
+                    header.set_mode(csync_unwrap_opt!(action_spec.get_unix_mode()) & 0o7777);
+                    // streams have no metadata of their own, so their ciphertexts stand in
+                    let modified = file_meta_opt
+                        .as_ref()
+                        .map_or(object_meta.modified, FileMetaSpec::get_modified);
+                    header.set_mtime(modified.duration_since(UNIX_EPOCH).map_or(0, |dur| dur.as_secs()));
+                    header.set_size(0);
+
+                    let authenticated_spec = match file_type {
+                        FileType::Dir => {
+                            header.set_entry_type(tar::EntryType::Directory);
+                            builder.append_data(&mut header, &entry_path, std::io::empty())?;
+                            csync_decrypt(self.storage.get(&cipherpath)?, Option::<File>::None, &self.derived_key)?.1
+                        }
+                        FileType::Symlink => {
+                            let mut target = Vec::new();
+                            let authenticated_spec =
+                                csync_decrypt_file(&*self.storage, &cipherpath, &mut target, &self.derived_key)?;
+                            header.set_entry_type(tar::EntryType::Symlink);
+                            builder.append_link(&mut header, &entry_path, OsStr::from_bytes(&target))?;
+                            authenticated_spec
+                        }
+                        FileType::File => {
+                            // the size goes in the header, before the contents
+                            let len = match &file_meta_opt {
+                                Some(file_meta) => file_meta.get_len(),
+                                None => {
+                                    let mut sink = CountingWriter::new(std::io::sink());
+                                    csync_decrypt_file(&*self.storage, &cipherpath, &mut sink, &self.derived_key)?;
+                                    sink.count
+                                }
+                            };
+                            header.set_entry_type(tar::EntryType::Regular);
+                            header.set_size(len);
+                            builder.append_data(&mut header, &entry_path, std::io::empty())?;
+
+                            // the contents are decrypted straight into the archive, then padded to
+                            // the next block like `append_data` would
+                            let mut contents = CountingWriter::new(builder.get_mut());
+                            let authenticated_spec =
+                                csync_decrypt_file(&*self.storage, &cipherpath, &mut contents, &self.derived_key)?;
+                            if contents.count != len {
+                                csync_err!(AuthenticationFail)?;
+                            }
+                            let padding = (TAR_BLOCK_SIZE - len % TAR_BLOCK_SIZE) % TAR_BLOCK_SIZE;
+                            builder
+                                .get_mut()
+                                .write_all(&[0; TAR_BLOCK_SIZE as usize][..padding as usize])?;
+                            authenticated_spec
+                        }
+                    };
+                    if authenticated_spec != action_spec {
+                        csync_err!(AuthenticationFail)?;
+                    }
+                    num_entries += 1;
+                }
+                let mut dest = builder.into_inner()?;
+                dest.flush()?;
+
+                Ok((num_entries, src_bytes, dest.count))
+            }
+            _ => csync_err!(
+                UnsupportedOperation,
+                String::from("only a syncer built from a `csync` directory can export it as a tar archive")
+            ),
+        }
+    }
+
+    // key of the ciphertext that the plaintext at `src_pbuf` gets encrypted to
+    fn cipherpath_of(&self, src_pbuf: &Path, file_type: FileType) -> CsyncResult<PathBuf> {
+        match &self.spec {
+            SyncerSpec::Encrypt {
+                source,
+                spread_depth,
+                init_salt,
+                ..
+            } => {
+                // `src_pbuf` is under the source or under one of the roots next to it
+                let parent = csync_unwrap_opt!(source.parent());
+                let rel_path = csync_unwrap_opt!(subpath(src_pbuf, parent));
+                let src_root = parent.join(csync_unwrap_opt!(rel_path.components().next()));
+
+                let spread = path_to_spread(*spread_depth, init_salt, src_pbuf)?;
+                let spread_hash = spread_to_hash(&spread)?;
+                let cipher_basename = path_to_cipherpath(&src_root, src_pbuf, file_type, &spread_hash, &self.derived_key)?;
+                Ok(spread.join(cipher_basename))
+            }
+            _ => panic!("Cipherpaths should only be computed from an encryption spec"),
+        }
+    }
+
+    // Each root that gets encrypted, along with the path that it is encrypted as. Roots other than
+    // the source are encrypted as if they were next to it, under their own names.
+    fn source_roots(&self) -> CsyncResult<Vec<(PathBuf, PathBuf)>> {
+        match &self.spec {
+            SyncerSpec::Encrypt { roots, source, .. } => walked_roots(source, roots),
+            _ => panic!("Roots should only be walked when encrypting"),
+        }
+    }
+
+    // Path of `enc_pbuf` relative to the parent of the source, which is how it appears under the
+    // output directory of `sync_dec`.
+    fn rel_path_of(&self, enc_pbuf: &Path) -> Option<PathBuf> {
+        match &self.spec {
+            SyncerSpec::Encrypt { source, .. } => subpath(enc_pbuf, source.parent()?),
+            _ => panic!("Plaintext paths should only be computed from an encryption spec"),
+        }
+    }
+
+    // Whether `path`, relative to the output directory, is under one of the roots that are being
+    // decrypted.
+    fn restores(&self, path: &Path) -> bool {
+        match &self.spec {
+            SyncerSpec::Decrypt { roots, .. } => match path.components().next() {
+                Some(comp) => roots.is_empty() || roots.iter().any(|name| comp.as_os_str() == name.as_str()),
+                None => false,
+            },
+            _ => panic!("Only decryption restores roots"),
+        }
+    }
+
     // Miscellaneous checks.
     #[inline]
     fn check_rep(&self) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::specs::{key_deriv_spec_ext::KeyDerivSpecExt, retention_spec::RetentionSpec, syncer_builder::SyncerBuilder};
+    use std::sync::{Arc, Mutex};
+
+    // a syncer that encrypts `source` to `out_dir` with a key that is quick to derive
+    fn enc_syncer(source: &Path, out_dir: &Path, index: bool) -> Syncer {
+        let spec = SyncerBuilder::encrypt(source, out_dir)
+            .kdf(KeyDerivSpecExt::Pbkdf2ByParams {
+                alg_opt: None,
+                num_iter: 1,
+                salt_len: 16,
+            })
+            .index(index)
+            .salt_len(16)
+            .build()
+            .unwrap();
+        Syncer::new(&spec, InitialKey::from_password("password".as_bytes())).unwrap()
+    }
+
+    // encrypt `source` to `out_dir`, returning the summary and every event that was emitted
+    fn sync_observed(source: &Path, out_dir: &Path) -> (SyncSummary, Vec<SyncEvent>) {
+        let mut syncer = enc_syncer(source, out_dir, false);
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+        syncer.subscribe(move |event: &SyncEvent| events_clone.lock().unwrap().push(event.clone()));
+
+        syncer.scan().unwrap();
+        let summary = syncer.sync().unwrap();
+        let events = events.lock().unwrap().clone();
+        (summary, events)
+    }
+
+    #[test]
+    fn observers_see_every_file() {
+        let source = tmpdir!().unwrap();
+        let out_dir = tmpdir!().unwrap();
+        std::fs::create_dir(source.path().join("dir")).unwrap();
+        std::fs::write(source.path().join("a"), b"some plaintext").unwrap();
+        std::fs::write(source.path().join("dir").join("b"), vec![7u8; 1 << 16]).unwrap();
+
+        // the source, `dir`, `a` and `dir/b`
+        let (summary, events) = sync_observed(source.path(), out_dir.path());
+        assert_eq!(summary.num_files, 4);
+
+        let count = |pred: fn(&SyncEvent) -> bool| events.iter().filter(|event| pred(event)).count();
+        assert_eq!(count(|event| matches!(event, SyncEvent::ScanStarted)), 1);
+        assert_eq!(count(|event| matches!(event, SyncEvent::FileStarted { .. })), 4);
+        assert_eq!(count(|event| matches!(event, SyncEvent::FileSkipped { .. })), 0);
+        assert_eq!(count(|event| matches!(event, SyncEvent::Error { .. })), 0);
+
+        // the sizes of each file add up to the totals
+        let (src_bytes, dest_bytes) = events.iter().fold((0, 0), |(src_acc, dest_acc), event| match event {
+            SyncEvent::FileDone {
+                src_bytes, dest_bytes, ..
+            } => (src_acc + src_bytes, dest_acc + dest_bytes),
+            _ => (src_acc, dest_acc),
+        });
+        assert_eq!((src_bytes, dest_bytes), (summary.src_bytes, summary.dest_bytes));
+        assert!(src_bytes >= 14 + (1 << 16));
+
+        // the scan is of everything, before anything is synced
+        let scanned = ScanSummary { num_files: 4, src_bytes };
+        assert!(matches!(events.first(), Some(SyncEvent::Scanned(summary)) if *summary == scanned));
+        match events.last() {
+            Some(SyncEvent::Finished(finished)) => assert_eq!(*finished, summary),
+            last => panic!("{:?} isn't the summary", last),
+        };
+
+        // nothing changed, so everything gets skipped
+        let (summary, events) = sync_observed(source.path(), out_dir.path());
+        assert_eq!(summary.num_files, 0);
+        assert_eq!(
+            events
+                .iter()
+                .filter(|event| matches!(event, SyncEvent::FileSkipped { .. }))
+                .count(),
+            4
+        );
+
+        // skipped files count for as much as they would have if they were synced
+        let skipped_bytes: u64 = events
+            .iter()
+            .map(|event| match event {
+                SyncEvent::FileSkipped { src_bytes, .. } => *src_bytes,
+                _ => 0,
+            })
+            .sum();
+        assert!(matches!(events.first(), Some(SyncEvent::Scanned(summary)) if *summary == scanned));
+        assert_eq!(skipped_bytes, scanned.src_bytes);
+    }
+
+    #[test]
+    fn only_touched_paths_are_synced() {
+        for index in [false, true].iter() {
+            let source = tmpdir!().unwrap();
+            let out_dir = tmpdir!().unwrap();
+            let dec_dir = tmpdir!().unwrap();
+            let root = source.path().canonicalize().unwrap();
+            std::fs::create_dir(root.join("Jd4w")).unwrap();
+            for (path, contents) in [
+                ("Hx2c", "Hx2c"),
+                ("Rm9p", "Rm9p"),
+                ("Jd4w/Ub7e", "Ub7e"),
+                ("Jd4w/Wf3k", "Wf3k"),
+            ]
+            .iter()
+            {
+                std::fs::write(root.join(path), contents).unwrap();
+            }
+
+            let syncer = enc_syncer(&root, out_dir.path(), *index);
+            assert_eq!(syncer.sync().unwrap().num_files, 6);
+
+            // `Rm9p` changes without being touched, which leaves it as it was
+            std::fs::write(root.join("Hx2c"), "Hx2c, changed").unwrap();
+            std::fs::write(root.join("Rm9p"), "Rm9p, changed").unwrap();
+            std::fs::remove_file(root.join("Jd4w/Ub7e")).unwrap();
+            std::fs::rename(root.join("Jd4w"), root.join("Pz8v")).unwrap();
+            std::fs::write(root.join("Ck5y"), "Ck5y").unwrap();
+
+            let touched: BTreeSet<_> = ["Hx2c", "Jd4w/Ub7e", "Jd4w", "Pz8v", "Ck5y"]
+                .iter()
+                .map(|path| root.join(path))
+                .collect();
+            let (summary, num_removed) = syncer.sync_touched(&touched).unwrap();
+            syncer.store_index().unwrap();
+            // the source, `Hx2c`, `Pz8v`, `Pz8v/Wf3k` and `Ck5y`; `Jd4w`, `Jd4w/Ub7e` and `Jd4w/Wf3k`
+            assert_eq!((summary.num_files, num_removed), (5, 3));
+
+            let spec = SyncerBuilder::decrypt(out_dir.path(), dec_dir.path()).build().unwrap();
+            let dec_syncer = Syncer::new(&spec, InitialKey::from_password("password".as_bytes())).unwrap();
+            dec_syncer.sync().unwrap();
+
+            let dec_root = dec_dir.path().join(root.file_name().unwrap());
+            let read = |path: &str| std::fs::read_to_string(dec_root.join(path)).unwrap();
+            assert_eq!(read("Hx2c"), "Hx2c, changed");
+            assert_eq!(read("Rm9p"), "Rm9p");
+            assert_eq!(read("Pz8v/Wf3k"), "Wf3k");
+            assert_eq!(read("Ck5y"), "Ck5y");
+            assert!(!dec_root.join("Jd4w").exists());
+        }
+    }
+
+    #[test]
+    fn clean_syncers_only_clean() {
+        let source = tmpdir!().unwrap();
+        let out_dir = tmpdir!().unwrap();
+        std::fs::write(source.path().join("Fq2m"), "Fq2m").unwrap();
+        enc_syncer(source.path(), out_dir.path(), false).sync().unwrap();
+
+        let spec = SyncerSpecExt::Clean {
+            retention_spec: RetentionSpec::default(),
+            source: out_dir.path().to_path_buf(),
+            verbose: false,
+        };
+        let syncer = Syncer::new(&spec, InitialKey::from_password("password".as_bytes())).unwrap();
+        assert!(matches!(syncer.scan(), Err(UnsupportedOperation(_))));
+        assert!(matches!(syncer.sync(), Err(UnsupportedOperation(_))));
+        assert!(matches!(syncer.cat(None, std::io::sink()), Err(UnsupportedOperation(_))));
+        assert!(matches!(syncer.sync_dec_delete_dry(), Err(UnsupportedOperation(_))));
+    }
+
+    #[test]
+    fn only_paths_are_dry_run() {
+        let source = tmpdir!().unwrap();
+        let spec = SyncerSpecExt::Cat {
+            path_opt: None,
+            source: source.path().to_path_buf(),
+            verbose: false,
+        };
+        let res = Syncer::dry_run(&spec, InitialKey::from_password("password".as_bytes()));
+        assert!(matches!(res, Err(UnsupportedOperation(_))));
+    }
+
+    #[test]
+    fn failed_batches_are_observed_and_leave_the_syncer_usable() {
+        let source = tmpdir!().unwrap();
+        let out_dirs = tmpdir!().unwrap();
+        let root = source.path().canonicalize().unwrap();
+        let out_dir = out_dirs.path().join("Vk6d");
+        let aside = out_dirs.path().join("Gs1q");
+        std::fs::write(root.join("Yb3t"), "Yb3t").unwrap();
+
+        let mut syncer = enc_syncer(&root, &out_dir, true);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+        syncer.subscribe(move |event: &SyncEvent| events_clone.lock().unwrap().push(event.clone()));
+        syncer.sync().unwrap();
+
+        // the `csync` dir being replaced by a file fails the whole batch
+        std::fs::write(root.join("Yb3t"), "Yb3t, changed").unwrap();
+        let touched: BTreeSet<_> = std::iter::once(root.join("Yb3t")).collect();
+        std::fs::rename(&out_dir, &aside).unwrap();
+        std::fs::write(&out_dir, "").unwrap();
+        events.lock().unwrap().clear();
+        assert!(matches!(syncer.sync_touched(&touched), Err(OutdirIsNotDir(_))));
+        assert!(matches!(
+            events.lock().unwrap().as_slice(),
+            [SyncEvent::Error {
+                path_opt: None,
+                err: OutdirIsNotDir(_)
+            }]
+        ));
+
+        // once it is back, the next batch goes through
+        std::fs::remove_file(&out_dir).unwrap();
+        std::fs::rename(&aside, &out_dir).unwrap();
+        let (summary, num_removed) = syncer.sync_touched(&touched).unwrap();
+        syncer.store_index().unwrap();
+        // only `Yb3t` changed
+        assert_eq!((summary.num_files, num_removed), (1, 0));
+    }
+
+    #[test]
+    fn interrupted_runs_leave_the_index_to_be_rebuilt() {
+        let source = tmpdir!().unwrap();
+        let out_dir = tmpdir!().unwrap();
+        let dec_dir = tmpdir!().unwrap();
+        let root = source.path().canonicalize().unwrap();
+        let path = root.join("Tq7n");
+        let write = |contents: &str, modified: SystemTime| {
+            std::fs::write(&path, contents).unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+        let modified = UNIX_EPOCH + Duration::from_secs(1 << 30);
+
+        write("Vb3x", modified);
+        let syncer = enc_syncer(&root, out_dir.path(), true);
+        syncer.sync().unwrap();
+        syncer.store_index().unwrap();
+
+        // stops before storing the index, which still records `Tq7n` as it was before
+        write("Lc8e", modified + Duration::from_secs(1));
+        enc_syncer(&root, out_dir.path(), false).sync().unwrap();
+
+        // which would make `Tq7n` look unchanged if the index were trusted
+        write("Vb3x", modified);
+        let syncer = enc_syncer(&root, out_dir.path(), false);
+        assert_eq!(syncer.sync().unwrap().num_files, 1);
+        syncer.store_index().unwrap();
+
+        let spec = SyncerBuilder::decrypt(out_dir.path(), dec_dir.path()).build().unwrap();
+        let dec_syncer = Syncer::new(&spec, InitialKey::from_password("password".as_bytes())).unwrap();
+        dec_syncer.sync().unwrap();
+        let dec_path = dec_dir.path().join(root.file_name().unwrap()).join("Tq7n");
+        assert_eq!(std::fs::read_to_string(dec_path).unwrap(), "Vb3x");
+    }
+}
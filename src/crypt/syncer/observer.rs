@@ -0,0 +1,72 @@
+use crate::prelude::*;
+use std::{fmt, path::PathBuf, time::Duration};
+
+/// Something that happened while a `Syncer` was syncing, in the order that it happened for any
+/// one file. Files are synced in parallel, so events of different files interleave.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum SyncEvent {
+    /// The source is about to be scanned for what has to be synced.
+    ScanStarted,
+    /// The source was scanned ahead of syncing by `Syncer::scan`, which found this much to look at.
+    Scanned(ScanSummary),
+    /// `src` is about to be synced to `dest`.
+    FileStarted { src: PathBuf, dest: PathBuf },
+    /// `src` was synced to `dest`; sizes are in bytes.
+    #[non_exhaustive]
+    FileDone {
+        src: PathBuf,
+        dest: PathBuf,
+        src_bytes: u64,
+        dest_bytes: u64,
+        duration: Duration,
+    },
+    /// `src` didn't have to be synced, as `dest` is already up to date; `src_bytes` is what its
+    /// size would have counted for in `FileDone`.
+    FileSkipped { src: PathBuf, dest: PathBuf, src_bytes: u64 },
+    /// Syncing failed, at `path_opt` if it was a specific file that failed.
+    Error { path_opt: Option<PathBuf>, err: CsyncErr },
+    /// Everything was synced.
+    Finished(SyncSummary),
+}
+
+/// Totals of a finished sync.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SyncSummary {
+    pub num_files: usize,
+    pub src_bytes: u64,
+    pub dest_bytes: u64,
+    pub duration: Duration,
+}
+
+/// Totals of what a scan found, whether or not it has to be synced. Sizes are in the same bytes as
+/// `src_bytes` in `FileDone` and `FileSkipped`, so the two can be compared for progress.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ScanSummary {
+    pub num_files: usize,
+    pub src_bytes: u64,
+}
+
+/// Receives the events of a `Syncer` that it subscribed to through `Syncer::subscribe`.
+///
+/// Events are delivered from whichever thread they happened on, so `on_event` should return quickly.
+pub trait SyncObserver: Send + Sync {
+    fn on_event(&self, event: &SyncEvent);
+}
+
+impl fmt::Debug for dyn SyncObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SyncObserver")
+    }
+}
+
+/// Any closure that takes an event can subscribe.
+impl<F> SyncObserver for F
+where
+    F: Fn(&SyncEvent) + Send + Sync,
+{
+    #[inline]
+    fn on_event(&self, event: &SyncEvent) {
+        self(event)
+    }
+}
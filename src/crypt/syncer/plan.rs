@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+
+/// What a dry run found would happen to a path.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[non_exhaustive]
+pub enum Change {
+    /// It doesn't exist yet, and would be created.
+    Create,
+    /// It already exists, and would be overwritten.
+    Update,
+    /// It is already up to date, and would be left alone.
+    Skip,
+    /// It would be removed.
+    Delete,
+}
+
+/// A path that a dry run looked at, as returned by `Syncer::dry_run`.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct PlannedChange {
+    pub change: Change,
+    /// The plaintext path when encrypting or decrypting, or the key in the `csync` dir when cleaning.
+    pub path: PathBuf,
+    /// Size of what it would be synced from, of the plaintext that is already up to date, or of
+    /// what would be removed; `0` for directories.
+    pub bytes: u64,
+}
+
+impl Change {
+    /// # Returns
+    ///
+    /// Every kind of change, in the order they are reported in.
+    #[inline]
+    pub fn all() -> &'static [Change] {
+        &[Change::Create, Change::Update, Change::Skip, Change::Delete]
+    }
+
+    /// # Returns
+    ///
+    /// Lowercase name of this change, like `create`.
+    #[inline]
+    pub fn name(self) -> &'static str {
+        match self {
+            Change::Create => "create",
+            Change::Update => "update",
+            Change::Skip => "skip",
+            Change::Delete => "delete",
+        }
+    }
+}
@@ -5,20 +5,23 @@
 ///     1. if decrypting, force the dir to be non empty
 /// 1. if using metadata that is recovered, make sure that passwords match
 use crate::{
+    crypt::{action::Action, storage::*},
     encoder::{crypt_encoder::*, openssl::*, text::*},
     fs_util::*,
     prelude::*,
     primitives::*,
     secure_vec::*,
-    specs::prelude::*,
+    specs::{prelude::*, storage_spec::*},
     util::*,
 };
 use itertools::Itertools;
 use rayon::prelude::*;
 use std::{
+    collections::BTreeSet,
+    ffi::OsStr,
     fs::{read_dir, Permissions},
-    path::{Path, PathBuf},
-    time::SystemTime,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
 };
 use walkdir::WalkDir;
 
@@ -125,77 +128,237 @@ pub fn report_syncer_spec(spec: &SyncerSpec) -> String {
                     ),
                 }
             );
-            action_desc + &salt_desc + &spread_depth_desc + &auth_desc + &comp_desc + &cipher_desc + &key_deriv_desc
+            let change_desc = match spec {
+                SyncerSpec::Encrypt { checksum, .. } => format_body!(
+                    "Change detection",
+                    match checksum {
+                        true => ("checksum", "keyed hash of the contents"),
+                        false => ("quick", "size and modified time"),
+                    }
+                ),
+                _ => String::new(),
+            };
+            let storage_desc = match spec {
+                SyncerSpec::Encrypt { chunked, snapshot, .. } => format_body!(
+                    "Storage",
+                    match (chunked, snapshot) {
+                        (_, true) => ("snapshots", "chunked, with history"),
+                        (true, false) => ("chunked", "content-defined, deduplicated"),
+                        (false, false) => ("whole files", "_"),
+                    }
+                ),
+                SyncerSpec::Decrypt { snapshot_opt, .. } => format_body!(
+                    "Restoring",
+                    match snapshot_opt {
+                        Some(id) => ("snapshot", id.to_string()),
+                        None => ("latest", String::from("_")),
+                    }
+                ),
+                _ => String::new(),
+            };
+            let conflict_desc = match spec {
+                SyncerSpec::Decrypt { conflict_spec, .. } => format_body!(
+                    "Conflict policy",
+                    match conflict_spec {
+                        ConflictSpec::Fail => ("fail", String::from("_")),
+                        ConflictSpec::Overwrite { .. } => ("overwrite", backup_desc(conflict_spec)),
+                        ConflictSpec::SkipExisting => ("skip-existing", String::from("_")),
+                        ConflictSpec::NewerWins { .. } => ("newer-wins", backup_desc(conflict_spec)),
+                        ConflictSpec::Incremental { .. } => ("incremental", backup_desc(conflict_spec)),
+                    }
+                ),
+                _ => String::new(),
+            };
+            action_desc
+                + &salt_desc
+                + &spread_depth_desc
+                + &auth_desc
+                + &comp_desc
+                + &cipher_desc
+                + &key_deriv_desc
+                + &change_desc
+                + &storage_desc
+                + &conflict_desc
         }
         SyncerSpec::Clean { verbose, .. } if *verbose => todo!(),
         _ => String::new(),
     }
 }
 
+// describe the backup behavior of `conflict_spec`
+fn backup_desc(conflict_spec: &ConflictSpec) -> String {
+    match conflict_spec.backup_suffix() {
+        Some(suffix) => format!("backup suffix {:?}", suffix),
+        None => String::from("no backup"),
+    }
+}
+
+/// Uid, path, path as it is encrypted, permissions, metadata and type of a file under a source.
+pub type MetaEntry = (usize, PathBuf, PathBuf, Permissions, FileMetaSpec, FileType);
+
 /// # Parameters
 ///
-/// 1. `root`:
+/// 1. `roots`: each root to walk, along with the path that it is encrypted as
+/// 1. `filter_spec`: which files under the roots to leave out
 ///
 /// # Returns
 ///
-/// Various metadata for each file under `root`. The purpose of this function is to minimize the
-/// number of IO calls.
-pub fn meta_map(root: &Path) -> impl ParallelIterator<Item = CsyncResult<(usize, PathBuf, Permissions, SystemTime, FileType)>> {
-    debug_assert!(is_canonical(&root).unwrap());
-    WalkDir::new(root)
-        .follow_links(true)
-        .into_iter()
-        .enumerate()
-        .par_bridge()
-        .map(|(uid, entry_res)| {
-            let entry = entry_res?;
-            // :: DirEntry -> (PathBuf, SystemTime)
-            // only handle regular files and dirs
-            let meta = entry.metadata()?;
-            let ftype = entry.file_type();
-            //
-            macro_rules! ok {
-                ( $modified:expr, $file_type:expr ) => {
-                    Ok((
-                        uid,
-                        entry.into_path().canonicalize()?,
-                        meta.permissions(),
-                        $modified,
-                        $file_type,
-                    ))
+/// Various metadata for each file under `roots` that isn't filtered out. The purpose of this
+/// function is to minimize the number of IO calls.
+pub fn meta_map(
+    roots: &[(PathBuf, PathBuf)],
+    filter_spec: &FilterSpec,
+) -> CsyncResult<impl ParallelIterator<Item = CsyncResult<MetaEntry>>> {
+    meta_map_within(roots, filter_spec, None)
+}
+
+/// Like `meta_map`, but only for what `FilterSpec::walk_within` walks under each root if there is
+/// `within_opt`.
+pub fn meta_map_within(
+    roots: &[(PathBuf, PathBuf)],
+    filter_spec: &FilterSpec,
+    within_opt: Option<Arc<BTreeSet<PathBuf>>>,
+) -> CsyncResult<impl ParallelIterator<Item = CsyncResult<MetaEntry>>> {
+    let walks = roots
+        .iter()
+        .map(|(root, enc_root)| {
+            debug_assert!(is_canonical(root).unwrap());
+            let (root, enc_root) = (root.clone(), enc_root.clone());
+            let walk = match &within_opt {
+                Some(within) => filter_spec.walk_within(&root, Arc::clone(within))?,
+                None => filter_spec.walk(&root)?,
+            };
+            Ok(walk.map(move |entry_res| -> CsyncResult<_> {
+                let entry = entry_res?;
+                // not canonicalized, as the path of a symlink is where the link is
+                let enc_path = match entry.depth() {
+                    0 => enc_root.clone(),
+                    _ => enc_root.join(entry.path().strip_prefix(&root)?),
                 };
-            }
-            //
-            match meta.modified()? {
-                // everything good
-                modified if ftype.is_file() => ok!(modified, FileType::File),
-                modified if ftype.is_dir() => ok!(modified, FileType::Dir),
-                // not a file or dir, maybe support later
-                _ => csync_err!(Other, format!("filetype not supported")),
-            }
+                Ok((entry, enc_path))
+            }))
         })
+        .collect::<CsyncResult<Vec<_>>>()?;
+
+    Ok(walks.into_iter().flatten().enumerate().par_bridge().map(|(uid, walked_res)| {
+        let (entry, enc_path) = walked_res?;
+        // :: DirEntry -> (PathBuf, SystemTime)
+        // only handle regular files and dirs
+        let meta = entry.metadata()?;
+        let ftype = meta.file_type();
+        //
+        macro_rules! ok {
+            ( $file_meta:expr, $file_type:expr ) => {
+                Ok((
+                    uid,
+                    entry.into_path().canonicalize()?,
+                    enc_path,
+                    meta.permissions(),
+                    $file_meta,
+                    $file_type,
+                ))
+            };
+        }
+        //
+        match FileMetaSpec::from_metadata(&meta)? {
+            // everything good
+            file_meta if ftype.is_file() => ok!(file_meta, FileType::File),
+            file_meta if ftype.is_dir() => ok!(file_meta, FileType::Dir),
+            // not a file or dir, maybe support later
+            _ => csync_err!(Other, format!("filetype not supported")),
+        }
+    }))
+}
+
+/// # Parameters
+///
+/// 1. `path`: path of an entry in a tar archive
+///
+/// # Returns
+///
+/// `path` relative to the top of the archive, which is empty for the top itself. Leading `/`s are
+/// dropped like `tar x` drops them, but entries that would end up above the top are rejected.
+pub fn archive_entry_path(path: &Path) -> CsyncResult<PathBuf> {
+    path.components().try_fold(PathBuf::new(), |mut rel_path, comp| match comp {
+        Component::Normal(name) => {
+            rel_path.push(name);
+            Ok(rel_path)
+        }
+        Component::CurDir | Component::RootDir => Ok(rel_path),
+        Component::ParentDir | Component::Prefix(_) => csync_err!(UnsupportedArchiveEntry, path.to_path_buf()),
+    })
+}
+
+/// # Returns
+///
+/// Keys of every ciphertext in `storage`.
+pub fn ciphertexts(storage: &dyn Storage) -> impl Iterator<Item = CsyncResult<PathBuf>> + Send + '_ {
+    storage.list(Path::new("")).filter(|key_res| match key_res {
+        Ok(key) => key.extension() == Some(OsStr::new(FILE_SUFFIX)),
+        Err(_) => true,
+    })
+}
+
+/// # Returns
+///
+/// Number of manifested actions in `actions` and the sums of their sizes in bytes, or the first
+/// error in `actions`.
+pub fn sum_lens<'a, I>(actions: I) -> CsyncResult<(usize, u64, u64)>
+where
+    I: ParallelIterator<Item = CsyncResult<Action<'a>>>,
+{
+    actions
+        .map(|action_res| {
+            let (src_bytes, dest_bytes) = action_res?.get_lens().unwrap_or_default();
+            Ok((1, src_bytes, dest_bytes))
+        })
+        .reduce(
+            || Ok((0, 0, 0)),
+            |acc_res_a, acc_res_b| {
+                let (count_a, src_bytes_a, dest_bytes_a) = acc_res_a?;
+                let (count_b, src_bytes_b, dest_bytes_b) = acc_res_b?;
+                Ok((count_a + count_b, src_bytes_a + src_bytes_b, dest_bytes_a + dest_bytes_b))
+            },
+        )
+}
+
+/// # Returns
+///
+/// Number of files and bytes counted in `counts`, or the first error in `counts`.
+pub fn sum_counts<I>(counts: I) -> CsyncResult<(usize, u64)>
+where
+    I: ParallelIterator<Item = CsyncResult<(usize, u64)>>,
+{
+    counts.reduce(
+        || Ok((0, 0)),
+        |acc_res_a, acc_res_b| {
+            let (count_a, bytes_a) = acc_res_a?;
+            let (count_b, bytes_b) = acc_res_b?;
+            Ok((count_a + count_b, bytes_a + bytes_b))
+        },
+    )
 }
 
 //
 pub fn check_out_dir(out_dir: &Path, spec: &SyncerSpec) -> CsyncResult<()> {
     // `out_dir` doesn't have to exist, but requires case-by-case checks
     match out_dir.exists() {
+        // an archive that already exists has been verified by loading its metadata
+        true if !StorageSpec::from_location(out_dir)?.is_dir() => Ok(()),
         //
         true if out_dir.is_dir() => match spec {
-            SyncerSpec::Encrypt { .. } => {
-                // TODO check for missing files
-                if std::fs::read_dir(out_dir)?.count() > 0 {
-                    todo!();
-                }
-                Ok(())
-            }
-            SyncerSpec::Decrypt { .. } => match read_dir(out_dir)?.count() {
+            // a nonempty `out_dir` has already been verified to be a `csync` dir by loading its
+            // metadata, and its files are compared one at a time with `should_encrypt_to`
+            SyncerSpec::Encrypt { .. } => Ok(()),
+            SyncerSpec::Decrypt { conflict_spec, .. } => match read_dir(out_dir)?.count() {
                 //
                 0 => Ok(()),
+                // existing files will be dealt with according to `conflict_spec`
+                _ if conflict_spec != &ConflictSpec::Fail => Ok(()),
                 //
                 _ => csync_err!(DecryptionOutdirIsNonempty, out_dir.to_path_buf()),
             },
-            SyncerSpec::Clean { .. } => todo!(),
+            SyncerSpec::Clean { .. } => Ok(()),
         },
         //
         true => csync_err!(OutdirIsNotDir, out_dir.to_path_buf()),
@@ -204,13 +367,143 @@ pub fn check_out_dir(out_dir: &Path, spec: &SyncerSpec) -> CsyncResult<()> {
     }
 }
 
+/// # Returns
+///
+/// `true` if there is nothing at `out_dir` yet, which is where a new `csync` dir can be encrypted
+/// to; `false` if there is something there, which can only be encrypted to if it is a `csync` dir.
+pub fn is_fresh_out_dir(out_dir: &Path) -> CsyncResult<bool> {
+    match StorageSpec::from_location(out_dir)? {
+        StorageSpec::Archive { .. } => Ok(!out_dir.exists()),
+        StorageSpec::Dir { .. } => match (out_dir.exists(), out_dir.is_dir()) {
+            (false, _) => Ok(true),
+            (true, true) => Ok(read_dir(out_dir)?.next().is_none()),
+            (true, false) => csync_err!(OutdirIsNotDir, out_dir.to_path_buf()),
+        },
+        StorageSpec::S3 { .. } | StorageSpec::Sftp { .. } => Ok(open_storage(out_dir)?.list(Path::new("")).next().is_none()),
+    }
+}
+
+/// # Returns
+///
+/// Every root to walk with `meta_map`: the source and the extra `roots`, each paired with where it
+/// is encrypted as if it were, which is next to the source.
+pub fn walked_roots(source: &Path, roots: &[SourceRoot]) -> CsyncResult<Vec<(PathBuf, PathBuf)>> {
+    let parent = csync_unwrap_opt!(source.parent());
+    Ok(std::iter::once((source.to_path_buf(), source.to_path_buf()))
+        .chain(roots.iter().map(|root| (root.path.clone(), parent.join(&root.name))))
+        .collect())
+}
+
+/// # Returns
+///
+/// `len` of a file of `file_type`, as it counts towards the bytes of a dry run; directories are
+/// only a name and a mode.
+#[inline]
+pub fn planned_len(file_type: FileType, len: u64) -> u64 {
+    match file_type {
+        FileType::Dir => 0,
+        _ => len,
+    }
+}
+
+/// # Returns
+///
+/// Total size in bytes of the files at or under `path`, without following symlinks.
+pub fn tree_len(path: &Path) -> CsyncResult<u64> {
+    WalkDir::new(path).into_iter().try_fold(0, |total, entry_res| {
+        let meta = entry_res?.metadata()?;
+        Ok(total + if meta.is_dir() { 0 } else { meta.len() })
+    })
+}
+
+/// # Parameters
+///
+/// 1. `file_meta`: current metadata of the plaintext; its contents are compared only if it has a
+///    checksum
+/// 1. `storage`: where the `csync` dir is stored
+/// 1. `cipherpath`: key of the ciphertext that the plaintext would be encrypted to
+/// 1. `derived_key`: the key that `cipherpath` was encrypted with
+///
+/// # Returns
+///
+/// `true` if the plaintext should be encrypted to `cipherpath`, `false` if `cipherpath` is up to
+/// date.
+///
+/// Only the header of `cipherpath` is read, and only if it exists, which `stat` answers from the
+/// listing of storages that keep one. Remote `csync` dirs always keep an index instead, so this
+/// reads local headers only.
+pub fn should_encrypt_to(
+    file_meta: &FileMetaSpec,
+    storage: &dyn Storage,
+    cipherpath: &Path,
+    derived_key: &DerivedKey,
+) -> CsyncResult<bool> {
+    match storage.stat(cipherpath)? {
+        // anything that can't be compared is treated as changed
+        Some(_) => match storage
+            .get(cipherpath)
+            .and_then(crate::crypt::util::load_syncer_action_specs)
+            .and_then(|(_, action_spec)| action_spec.get_file_meta(derived_key))
+        {
+            Ok(Some(recorded)) => Ok(!file_meta.is_unchanged_from(&recorded)),
+            _ => Ok(true),
+        },
+        None => Ok(true),
+    }
+}
+
+/// # Parameters
+///
+/// 1. `conflict_spec`: what to do if `dest` already exists
+/// 1. `file_type`: type of the plaintext that would be decrypted
+/// 1. `dest`: where the plaintext would be written to
+/// 1. `get_recorded`: gets the metadata of the plaintext recorded at the time of its encryption,
+///    which is only called if it needs to be compared
+///
+/// # Returns
+///
+/// `true` if `src` should be decrypted to `dest`, `false` if it should be skipped.
+pub fn should_decrypt_to<F>(
+    conflict_spec: &ConflictSpec,
+    file_type: FileType,
+    dest: &Path,
+    get_recorded: F,
+) -> CsyncResult<bool>
+where
+    F: FnOnce() -> CsyncResult<Option<FileMetaSpec>>,
+{
+    match std::fs::symlink_metadata(dest) {
+        Ok(dest_meta) => match conflict_spec {
+            ConflictSpec::Fail | ConflictSpec::Overwrite { .. } => Ok(true),
+            ConflictSpec::SkipExisting => Ok(false),
+            // the plaintext is compared as it was when it was encrypted, not by when its ciphertext
+            // was written; anything that can't be compared is treated as newer
+            ConflictSpec::NewerWins { .. } => match get_recorded() {
+                Ok(Some(file_meta)) => Ok(dest_meta.modified()? <= file_meta.get_modified()),
+                _ => Ok(true),
+            },
+            // nothing to update in a directory that already exists
+            ConflictSpec::Incremental { .. } if file_type == FileType::Dir => Ok(!dest_meta.is_dir()),
+            // anything that can't be compared is treated as changed, and gets authenticated
+            // when it is decrypted
+            ConflictSpec::Incremental { .. } => match get_recorded() {
+                Ok(Some(file_meta)) => Ok(!(dest_meta.is_file() && file_meta.matches(&dest_meta))),
+                _ => Ok(true),
+            },
+        },
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(true),
+        Err(err) => Err(err)?,
+    }
+}
+
 // # Parameters
 //
 // 1. `spread_depth`: number of layers used in spreading; a max of `64 ^ spread_depth` number of
 //    distinct directories can be created
 // 2. `path`: the path with which spread dirs will be created
 pub fn path_to_spread(spread_depth: u8, init_salt: &CryptoSecureBytes, path: &Path) -> CsyncResult<PathBuf> {
-    debug_assert!(is_canonical(&path).unwrap());
+    // `path` need not exist, for example when looking up a file that was encrypted on another machine
+    debug_assert!(path.is_absolute());
 
     // 'spread dirs are the depth-n dirs created with sha512 in order to spread out the files
     // into different dirs
@@ -259,8 +552,8 @@ pub fn path_to_cipherpath(
     derived_key: &DerivedKey,
 ) -> CsyncResult<PathBuf> {
     debug_assert!(src_path.starts_with(src_root));
-    debug_assert!(is_canonical(src_root).unwrap());
-    debug_assert!(is_canonical(src_path).unwrap());
+    debug_assert!(src_root.is_absolute());
+    debug_assert!(src_path.is_absolute());
     let aug_src_rel_path = {
         let src_rel_path = csync_unwrap_opt!(subpath_par(src_path, src_root));
 
@@ -292,6 +585,7 @@ pub fn path_to_cipherpath(
         let filetype_prefix = match file_type {
             FileType::File => "f",
             FileType::Dir => "d",
+            FileType::Symlink => "l",
         };
 
         Path::new(&rand_bytes_string).join(filetype_prefix).join(src_rel_path)
@@ -318,17 +612,14 @@ pub fn path_to_cipherpath(
     Ok(PathBuf::from(format!("{}.{}", without_ext, FILE_SUFFIX)))
 }
 
-//
+// `cipherpath` is the key of a ciphertext, relative to the `csync` dir
 pub fn cipherpath_to_path(
     spread_depth: u8,
-    src_root: &Path,
     cipherpath: &Path,
     derived_key: &DerivedKey,
 ) -> CsyncResult<(PathBuf, FileType, CryptoSecureBytes)> {
-    debug_assert!(is_canonical(&src_root).unwrap());
-    debug_assert!(cipherpath.is_absolute());
-    let rel_path = subpath(cipherpath, src_root).unwrap();
-    let comps: Vec<_> = rel_path.components().collect();
+    debug_assert!(cipherpath.is_relative());
+    let comps: Vec<_> = cipherpath.components().collect();
     let spread_hash = spread_to_hash(Path::new(
         &comps
             .iter()
@@ -369,9 +660,33 @@ pub fn cipherpath_to_path(
     let ftype = match decrypted_comps.next().unwrap().as_os_str().to_str() {
         Some("f") => FileType::File,
         Some("d") => FileType::Dir,
+        Some("l") => FileType::Symlink,
         _ => panic!("wrong number of comps"),
     };
     let decrypted_pbuf = PathBuf::from(decrypted_comps.as_path());
 
     Ok((decrypted_pbuf, ftype, spread_hash))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn newer_wins_compares_recorded_modified_times() {
+        let out_dir = tmpdir!().unwrap();
+        let dest = out_dir.path().join("Ju6p");
+        std::fs::write(&dest, "Ju6p").unwrap();
+        let modified = std::fs::metadata(&dest).unwrap().modified().unwrap();
+
+        let conflict_spec = ConflictSpec::NewerWins { backup_suffix: None };
+        let should_decrypt = |recorded_opt: Option<FileMetaSpec>| {
+            should_decrypt_to(&conflict_spec, FileType::File, &dest, || Ok(recorded_opt)).unwrap()
+        };
+        let hour = Duration::from_secs(3600);
+        assert!(!should_decrypt(Some(FileMetaSpec::new(4, modified - hour))));
+        assert!(should_decrypt(Some(FileMetaSpec::new(4, modified + hour))));
+        assert!(should_decrypt(None));
+    }
+}
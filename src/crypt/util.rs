@@ -1,4 +1,5 @@
 use crate::{
+    crypt::{chunk::*, storage::*},
     encoder::{hmac::*, identity::*, openssl::*, zstd::*},
     fs_util::*,
     prelude::*,
@@ -23,6 +24,12 @@ const PAD_DELIMITER: u8 = 0;
 pub const MIN_DIR_RAND_DATA_LEN: u64 = 1 << 4;
 pub const MAX_DIR_RAND_DATA_LEN: u64 = 1 << 10;
 
+// the authenticated part of a ciphertext starts with a frame of `FORMAT_MAGIC` followed by the
+// format version, which ciphertexts written before there were versions lack; their first frame is
+// the serialized syncer spec, which can't start with `FORMAT_MAGIC`
+const FORMAT_MAGIC: &[u8] = b"csync-format";
+pub const FORMAT_VERSION: u8 = 1;
+
 // # Parameters
 //
 // 1. `data`: some data to serialize
@@ -69,10 +76,31 @@ fn deser<R, T>(source: &mut R) -> CsyncResult<T>
 where
     R: Read,
     T: Clone + Debug + DeserializeOwned,
+{
+    deserialize(&deser_bytes(source)?[..])
+}
+
+// like `crate::crypt::util::deser`, but without deserializing the bytes that were read
+fn deser_bytes<R>(source: &mut R) -> CsyncResult<Vec<u8>>
+where
+    R: Read,
 {
     let data_len = u8s_to_u32(&read_exact(4, source)?);
-    let data_bytes = read_exact(data_len as usize, source)?;
-    deserialize(&data_bytes[..])
+    read_exact(data_len as usize, source)
+}
+
+// # Returns
+//
+// The frame that marks a ciphertext as being in the current format.
+fn format_frame() -> SecureBytes {
+    let frame_len = u32_to_u8s(FORMAT_MAGIC.len() as u32 + 1);
+    frame_len
+        .iter()
+        .chain(FORMAT_MAGIC)
+        .chain(&[FORMAT_VERSION])
+        .copied()
+        .collect::<Vec<_>>()
+        .into()
 }
 
 // # Parameters
@@ -158,11 +186,12 @@ where
     let rand_padding = random_padding(MIN_RANDPAD_LEN, MAX_RANDPAD_LEN);
 
     //
+    let format_ser = format_frame();
     let syncer_spec_ser = ser(syncer_spec)?;
     let action_spec_ser = ser(action_spec)?;
 
     // 1. create an encrypted content where
-    //     1. the header contains the syncer and action specs
+    //     1. the header contains the format version and the syncer and action specs
     //     2. the body is the random padding and the actual content
     // 2. encrypt `body` and write to `tmpf_path` which is everything but the signature
     // 3. compute the authenticated signature and return it
@@ -174,7 +203,8 @@ where
         ) => {{
             // the content to produce authenticated signatures for
             let mut auth_encoder = compose_encoders!(
-                    syncer_spec_ser.unsecure()
+                    format_ser.unsecure()
+                    .chain(syncer_spec_ser.unsecure())
                     .chain(action_spec_ser.unsecure())
                     .chain(compose_encoders!(
                         (&rand_padding[..]).chain(source),
@@ -269,9 +299,15 @@ where
         )?,
     };
 
-    //
-    let syncer_spec: SyncerSpec = deser(&mut auth_encoder)?;
-    let action_spec: ActionSpec = deser(&mut auth_encoder)?;
+    // ciphertexts without a format frame are in format version 0
+    let first_frame = deser_bytes(&mut auth_encoder)?;
+    let (format_version, syncer_spec): (u8, SyncerSpec) = match first_frame.strip_prefix(FORMAT_MAGIC) {
+        Some(&[format_version]) if format_version <= FORMAT_VERSION => (format_version, deser(&mut auth_encoder)?),
+        Some(&[format_version]) => csync_err!(UnsupportedFormatVersion, format_version)?,
+        Some(_) => csync_err!(SerdeFailed)?,
+        None => (0, deserialize(&first_frame)?),
+    };
+    let action_spec = ActionSpec::from_bytes(&deser_bytes(&mut auth_encoder)?, format_version)?;
 
     macro_rules! decrypt {
         (
@@ -343,12 +379,99 @@ where
     Ok((syncer_spec, action_spec))
 }
 
-/// # Parame
-pub fn load_syncer_action_specs<P>(path: P) -> CsyncResult<(SyncerSpec, ActionSpec)>
+/// Decrypt the ciphertext of a file, following its chunks if it was stored chunked.
+///
+/// # Parameters
+///
+/// 1. `storage`: where the `csync` dir is stored
+/// 1. `cipherpath`: key of the ciphertext to decrypt
+/// 1. `dest`: where to write the plaintext
+/// 1. `key_hash`: the derived key that `cipherpath` was encrypted with
+///
+/// # Returns
+///
+/// The action spec of `cipherpath`.
+pub fn csync_decrypt_file<W>(
+    storage: &dyn Storage,
+    cipherpath: &Path,
+    dest: W,
+    key_hash: &DerivedKey,
+) -> CsyncResult<ActionSpec>
+where
+    W: Write,
+{
+    match load_chunk_refs(storage, cipherpath, key_hash)? {
+        Some((chunk_refs, action_spec)) => {
+            restore_chunks(&chunk_refs, storage, dest, key_hash)?;
+            Ok(action_spec)
+        }
+        None => Ok(csync_decrypt(storage.get(cipherpath)?, Some(dest), key_hash)?.1),
+    }
+}
+
+/// # Parameters
+///
+/// 1. `storage`: where the `csync` dir is stored
+/// 1. `cipherpath`: key of the ciphertext of a file
+/// 1. `key_hash`: the derived key that `cipherpath` was encrypted with
+///
+/// # Returns
+///
+/// The chunks that `cipherpath` references and its action spec, or `None` if it wasn't stored
+/// chunked; in which case nothing but its header is read.
+pub fn load_chunk_refs(
+    storage: &dyn Storage,
+    cipherpath: &Path,
+    key_hash: &DerivedKey,
+) -> CsyncResult<Option<(Vec<ChunkRef>, ActionSpec)>> {
+    // only a peek; the header gets authenticated along with the rest of the ciphertext below
+    let (_, action_spec) = load_syncer_action_specs(storage.get(cipherpath)?)?;
+    match action_spec.is_chunked() {
+        true => {
+            let mut chunk_refs_ser = Vec::new();
+            let (_, action_spec) = csync_decrypt(storage.get(cipherpath)?, Some(&mut chunk_refs_ser), key_hash)?;
+            Ok(Some((deserialize(&chunk_refs_ser)?, action_spec)))
+        }
+        false => Ok(None),
+    }
+}
+
+/// # Parameters
+///
+/// 1. `src`: a ciphertext
+///
+/// # Returns
+///
+/// The syncer and action specs in the header of `src`, which are not authenticated.
+pub fn load_syncer_action_specs<R>(src: R) -> CsyncResult<(SyncerSpec, ActionSpec)>
 where
-    P: std::convert::AsRef<Path>,
+    R: Read,
 {
     let garbage_key = DerivedKey(sha512!(&vec![].into()));
-    let (_, syncer_spec, action_spec) = csync_decrypt_core(fopen_r(path)?, Option::<File>::None, &garbage_key)?;
+    let (_, syncer_spec, action_spec) = csync_decrypt_core(src, Option::<File>::None, &garbage_key)?;
     Ok((syncer_spec, action_spec))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::deterministic_hash;
+
+    #[test]
+    fn decrypts_ciphertexts_from_before_format_versions() {
+        // a file encrypted by `csync` before ciphertexts had a format version
+        let ciphertext: &[u8] = include_bytes!("testdata/format_v0.csync");
+        let init_key = deterministic_hash(b"bexley-marrow".to_vec());
+
+        let (syncer_spec, _) = load_syncer_action_specs(ciphertext).unwrap();
+        let key_hash = match syncer_spec {
+            SyncerSpec::Encrypt { key_deriv_spec, .. } => key_deriv_spec.derive(&init_key.0).unwrap(),
+            _ => panic!("expected an encrypt spec"),
+        };
+        let mut plaintext = Vec::new();
+        let (_, action_spec) = csync_decrypt(ciphertext, Some(&mut plaintext), &key_hash).unwrap();
+        assert_eq!(plaintext, b"gwynfor ottaline\n");
+        assert!(!action_spec.is_chunked());
+        assert_eq!(action_spec.get_file_meta(&key_hash).unwrap(), None);
+    }
+}
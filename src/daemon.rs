@@ -0,0 +1,797 @@
+use crate::{
+    clargs::Opts,
+    config::{self, Config, Value},
+    prelude::*,
+    specs::{storage_spec::StorageSpec, syncer_spec_ext::SyncerSpecExt},
+    InitialKey, SyncSummary, Syncer,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    ffi::OsString,
+    fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::{
+        fs::{DirBuilderExt, MetadataExt, PermissionsExt},
+        io::AsRawFd,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// What `csync daemon` asks `csyncd` for, as a line of JSON like `{"command":"sync-now","repo":"laptop"}`.
+///
+/// `unlock` carries the initial key rather than the password, which never leaves `csync daemon`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    Status,
+    SyncNow { repo: String },
+    Lock { repo: String },
+    Unlock { repo: String, init_key: InitialKey },
+}
+
+/// What `csyncd` answers with, as a line of JSON like `{"result":"done"}`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "result", rename_all = "kebab-case")]
+pub enum Response {
+    Status {
+        repos: Vec<RepoStatus>,
+    },
+    Synced {
+        num_files: usize,
+        src_bytes: u64,
+        dest_bytes: u64,
+        duration_ms: u64,
+    },
+    Done,
+    Failed {
+        message: String,
+    },
+}
+
+/// How a repository that `csyncd` keeps in sync is doing.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RepoStatus {
+    pub name: String,
+    pub unlocked: bool,
+    pub syncing: bool,
+    // seconds since the epoch at which the last sync that went through finished
+    #[serde(rename = "last_synced")]
+    pub last_synced_opt: Option<u64>,
+    pub last_num_files: usize,
+    // why the last sync failed, if it did
+    #[serde(rename = "last_error")]
+    pub last_error_opt: Option<String>,
+}
+
+/// A repository that `csyncd` keeps in sync, read from a table under `[csyncd]` in the config file,
+/// like
+///
+/// ```toml
+/// [csyncd.laptop]
+/// profile = "nightly" # encrypt like `csync encrypt --profile nightly`
+/// every = 3600        # seconds between syncs
+/// watch = true        # also sync whatever changes under the source as it changes
+/// ```
+#[derive(Clone, Debug)]
+pub struct RepoSpec {
+    pub name: String,
+    pub spec_ext: SyncerSpecExt,
+    pub every_opt: Option<Duration>,
+    pub watch: bool,
+}
+
+impl RepoSpec {
+    /// # Returns
+    ///
+    /// Every repository in the config file at `config_path`, by the order of their names.
+    pub fn from_config(config_path: &Path) -> CsyncResult<Vec<Self>> {
+        let config = Config::load(config_path)?;
+        config
+            .profiles(DAEMON_TABLE)
+            .map(|(name, values)| Self::from_table(config_path, name, values))
+            .collect()
+    }
+
+    // the repository in `[csyncd.<name>]`, whose encryption is checked like that of `csync encrypt`
+    fn from_table(config_path: &Path, name: &str, values: &BTreeMap<String, Value>) -> CsyncResult<Self> {
+        let invalid = |message: String| CsyncErr::ConfigInvalid(format!("`[{}.{}]` {}", DAEMON_TABLE, name, message));
+
+        let mut profile_opt = None;
+        let mut every_opt = None;
+        let mut watch = false;
+        for (key, value) in values.iter() {
+            match (key.as_str(), value) {
+                ("profile", Value::Str(profile)) => profile_opt = Some(profile),
+                ("every", Value::Int(secs)) if *secs > 0 => every_opt = Some(Duration::from_secs(*secs as u64)),
+                ("watch", Value::Bool(b)) => watch = *b,
+                ("profile", _) | ("every", _) | ("watch", _) => Err(invalid(format!("has an invalid `{}`", key)))?,
+                _ => Err(invalid(format!("has an unknown key `{}`", key)))?,
+            }
+        }
+        let profile = profile_opt.ok_or_else(|| invalid(String::from("has no `profile`")))?;
+
+        // parsed like `csync encrypt --config <config_path> --profile <profile>` would be
+        let mut args: Vec<OsString> = vec!["csync".into(), "encrypt".into(), "--config".into(), config_path.into()];
+        args.extend(vec!["--profile".into(), profile.into(), "--quiet".into()]);
+        // an index is what finds the ciphertexts of removed files without decrypting every cipherpath
+        if watch {
+            args.push("--index".into());
+        }
+        let (args, positionals) = config::with_config(args)?;
+        let opts = Opts::try_from_config_args(args, positionals).map_err(|err| {
+            let message = err.message.lines().next().unwrap_or_default().to_string();
+            invalid(format!(
+                "has a profile `{}` that `csync encrypt` rejects: {}",
+                profile, message
+            ))
+        })?;
+
+        match &opts {
+            Opts::Encrypt { dry_run: true, .. } => Err(invalid(format!("has a profile `{}` with `dry-run`", profile)))?,
+            Opts::Encrypt { source, out_dir, .. } => {
+                if !source.is_dir() {
+                    Err(invalid(format!("has to encrypt a directory, but {:?} isn't one", source)))?;
+                }
+                if let StorageSpec::Archive { .. } = StorageSpec::from_location(out_dir)? {
+                    Err(invalid(String::from("cannot encrypt to a `.csar` archive")))?;
+                }
+            }
+            _ => panic!("Only encryptions are kept in sync"),
+        }
+        Ok(Self {
+            name: name.to_string(),
+            spec_ext: SyncerSpecExt::try_from(&opts)?,
+            every_opt,
+            watch,
+        })
+    }
+}
+
+// what the worker of a repository does next
+enum Job {
+    // sync everything, letting whoever asked know how it went
+    Full(Option<mpsc::Sender<CsyncResult<SyncSummary>>>),
+    // sync only the paths that changed
+    Touched(BTreeSet<PathBuf>),
+}
+
+// what can change about a repository while `csyncd` runs
+#[derive(Debug, Default)]
+struct RepoState {
+    // present while unlocked; a sync that is running holds on to it until it's done, even if the
+    // repository gets locked meanwhile
+    syncer_opt: Option<Arc<Syncer>>,
+    syncing: bool,
+    last_synced_opt: Option<u64>,
+    last_num_files: usize,
+    last_error_opt: Option<String>,
+}
+
+#[derive(Debug)]
+struct Repo {
+    spec: RepoSpec,
+    state: Mutex<RepoState>,
+}
+
+impl Repo {
+    // run `job` on the worker of this repository, which is the only thread that syncs it
+    fn run(&self, job: Job) {
+        let (touched_opt, reply_opt) = match job {
+            Job::Full(reply_opt) => (None, reply_opt),
+            Job::Touched(touched) => (Some(touched), None),
+        };
+
+        let syncer = {
+            let mut state = self.state.lock().unwrap();
+            match state.syncer_opt.clone() {
+                Some(syncer) => {
+                    state.syncing = true;
+                    syncer
+                }
+                // scheduled syncs and changes are skipped while locked, since unlocking syncs
+                // everything anyway
+                None => {
+                    if let Some(reply) = reply_opt {
+                        let _ = reply.send(csync_err!(
+                            DaemonRequestFailed,
+                            format!("`{}` is locked; unlock it first", self.spec.name)
+                        ));
+                    }
+                    return;
+                }
+            }
+        };
+
+        let res = Self::sync(&syncer, touched_opt.as_ref());
+        drop(syncer);
+        {
+            let mut state = self.state.lock().unwrap();
+            state.syncing = false;
+            match &res {
+                Ok(summary) => {
+                    log::debug!("synced {} files of `{}`", summary.num_files, self.spec.name);
+                    state.last_synced_opt = Some(now_secs());
+                    state.last_num_files = summary.num_files;
+                    state.last_error_opt = None;
+                }
+                Err(err) => {
+                    log::error!("could not sync `{}`: {}", self.spec.name, err);
+                    state.last_error_opt = Some(format!("{}", err));
+                }
+            }
+        }
+        if let Some(reply) = reply_opt {
+            let _ = reply.send(res);
+        }
+    }
+
+    // sync with `syncer` like `csync encrypt` does, or like `csync watch` does if only the paths in
+    // `touched_opt` changed
+    fn sync(syncer: &Syncer, touched_opt: Option<&BTreeSet<PathBuf>>) -> CsyncResult<SyncSummary> {
+        let summary = match touched_opt {
+            Some(touched) => syncer.sync_touched(touched)?.0,
+            None => syncer.sync()?,
+        };
+        syncer.store_index()?;
+        if touched_opt.is_none() {
+            syncer.create_snapshot()?;
+        }
+        syncer.flush()?;
+        Ok(summary)
+    }
+
+    //
+    fn status(&self) -> RepoStatus {
+        let state = self.state.lock().unwrap();
+        RepoStatus {
+            name: self.spec.name.clone(),
+            unlocked: state.syncer_opt.is_some(),
+            syncing: state.syncing,
+            last_synced_opt: state.last_synced_opt,
+            last_num_files: state.last_num_files,
+            last_error_opt: state.last_error_opt.clone(),
+        }
+    }
+}
+
+/// Keeps repositories in sync, each with a key that is only held, in locked memory, between
+/// `unlock` and `lock`.
+///
+/// Each repository has a worker thread of its own, which runs its syncs one after another with the
+/// same `Syncer`, whether they are scheduled, triggered by changes, or asked for.
+#[derive(Debug)]
+pub struct Daemon {
+    repos: BTreeMap<String, (Arc<Repo>, mpsc::Sender<Job>)>,
+}
+
+impl Daemon {
+    /// Start the workers of `repo_specs`, which are all locked to begin with.
+    pub fn new(repo_specs: Vec<RepoSpec>) -> Self {
+        let repos = repo_specs
+            .into_iter()
+            .map(|spec| {
+                let (jobs, job_recv) = mpsc::channel();
+                let repo = Arc::new(Repo {
+                    spec,
+                    state: Mutex::default(),
+                });
+                let worker = Arc::clone(&repo);
+                thread::spawn(move || job_recv.into_iter().for_each(|job| worker.run(job)));
+                (repo.spec.name.clone(), (repo, jobs))
+            })
+            .collect();
+        Self { repos }
+    }
+
+    /// Start syncing every repository every so often, and as its source changes, if it's set up to
+    /// be.
+    pub fn start_triggers(&self) -> CsyncResult<()> {
+        for (repo, jobs) in self.repos.values() {
+            if let Some(every) = repo.spec.every_opt {
+                let jobs = jobs.clone();
+                thread::spawn(move || {
+                    thread::sleep(every);
+                    while jobs.send(Job::Full(None)).is_ok() {
+                        thread::sleep(every);
+                    }
+                });
+            }
+            if repo.spec.watch {
+                watch(repo, jobs.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// # Returns
+    ///
+    /// What `csyncd` answers `request` with.
+    pub fn handle(&self, request: Request) -> Response {
+        match self.try_handle(request) {
+            Ok(response) => response,
+            // which is already what the client will say it is
+            Err(CsyncErr::DaemonRequestFailed(message)) => Response::Failed { message },
+            Err(err) => Response::Failed {
+                message: format!("{}", err),
+            },
+        }
+    }
+
+    //
+    fn try_handle(&self, request: Request) -> CsyncResult<Response> {
+        match request {
+            Request::Status => Ok(Response::Status {
+                repos: self.repos.values().map(|(repo, _)| repo.status()).collect(),
+            }),
+            Request::SyncNow { repo } => {
+                let (reply, reply_recv) = mpsc::channel();
+                self.jobs_of(&repo)?.send(Job::Full(Some(reply)))?;
+                let SyncSummary {
+                    num_files,
+                    src_bytes,
+                    dest_bytes,
+                    duration,
+                } = reply_recv.recv()??;
+                Ok(Response::Synced {
+                    num_files,
+                    src_bytes,
+                    dest_bytes,
+                    duration_ms: duration.as_millis() as u64,
+                })
+            }
+            Request::Lock { repo } => {
+                self.repo_of(&repo)?.state.lock().unwrap().syncer_opt = None;
+                log::info!("locked `{}`", repo);
+                Ok(Response::Done)
+            }
+            Request::Unlock { repo, init_key } => {
+                // deriving the key takes a while, which the state isn't locked for
+                let syncer = Syncer::new(&self.repo_of(&repo)?.spec.spec_ext, init_key)?;
+                self.repo_of(&repo)?.state.lock().unwrap().syncer_opt = Some(Arc::new(syncer));
+                log::info!("unlocked `{}`", repo);
+                self.jobs_of(&repo)?.send(Job::Full(None))?;
+                Ok(Response::Done)
+            }
+        }
+    }
+
+    //
+    fn repo_of(&self, name: &str) -> CsyncResult<&Repo> {
+        match self.repos.get(name) {
+            Some((repo, _)) => Ok(repo),
+            None => csync_err!(DaemonRequestFailed, format!("there is no repository named `{}`", name)),
+        }
+    }
+
+    //
+    fn jobs_of(&self, name: &str) -> CsyncResult<&mpsc::Sender<Job>> {
+        match self.repos.get(name) {
+            Some((_, jobs)) => Ok(jobs),
+            None => csync_err!(DaemonRequestFailed, format!("there is no repository named `{}`", name)),
+        }
+    }
+
+    /// Answer every request that comes through `listener`, each on a thread of its own, which only
+    /// returns if the listener fails.
+    pub fn serve(self: Arc<Self>, listener: UnixListener) -> CsyncResult<()> {
+        for stream_res in listener.incoming() {
+            let stream = stream_res?;
+            let daemon = Arc::clone(&self);
+            thread::spawn(move || {
+                if let Err(err) = daemon.answer(stream) {
+                    log::warn!("could not answer a request: {}", err);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    // read a request from `stream`, and write what it's answered with back
+    fn answer(&self, mut stream: UnixStream) -> CsyncResult<()> {
+        // whoever else got through to the socket is left without an answer
+        let uid = peer_uid(&stream)?;
+        if uid != current_uid() {
+            csync_err!(
+                DaemonRequestFailed,
+                format!("uid {} is not the user that `csyncd` runs as", uid)
+            )?;
+        }
+
+        let mut line = Vec::new();
+        BufReader::new(&stream).read_until(b'\n', &mut line)?;
+        let request_res = serde_json::from_slice(&line);
+        // which can hold a key
+        zero(&mut line);
+
+        let response = match request_res {
+            Ok(request) => self.handle(request),
+            Err(err) => Response::Failed {
+                message: format!("invalid request: {}", err),
+            },
+        };
+        serde_json::to_writer(&mut stream, &response)?;
+        stream.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+// keep sending what changes under the source of `repo` to its worker, on a thread of its own
+#[cfg(target_os = "linux")]
+fn watch(repo: &Repo, jobs: mpsc::Sender<Job>) -> CsyncResult<()> {
+    use crate::watcher::Watcher;
+
+    let (source, filter_spec) = match &repo.spec.spec_ext {
+        SyncerSpecExt::Encrypt { source, filter_spec, .. } => (source.canonicalize()?, filter_spec),
+        _ => panic!("Only encryptions are watched"),
+    };
+    let mut watcher = Watcher::new(&source, filter_spec)?;
+    let debounce = Duration::from_millis(DEFAULT_DEBOUNCE_MS_STR.parse().unwrap());
+    let name = repo.spec.name.clone();
+    thread::spawn(move || loop {
+        let job = match watcher.next_batch(debounce) {
+            // whatever changed was lost, so everything gets looked at
+            Ok(batch) if batch.overflowed => Job::Full(None),
+            Ok(batch) if batch.paths.is_empty() => continue,
+            Ok(batch) => Job::Touched(batch.paths),
+            Err(err) => {
+                log::error!("stopped watching `{}`: {}", name, err);
+                break;
+            }
+        };
+        if jobs.send(job).is_err() {
+            break;
+        }
+    });
+    Ok(())
+}
+
+// `inotify` is what changes are watched with
+#[cfg(not(target_os = "linux"))]
+fn watch(repo: &Repo, _jobs: mpsc::Sender<Job>) -> CsyncResult<()> {
+    csync_err!(
+        ConfigInvalid,
+        format!("`[{}.{}]` can only be watched on Linux", DAEMON_TABLE, repo.spec.name)
+    )
+}
+
+/// # Returns
+///
+/// What `socket` answers `request` with, where `csyncd` is expected to listen. Nothing is sent unless
+/// the socket is private to the current user and a process of theirs is listening on it, as
+/// `request` can hold a key.
+pub fn request(socket: &Path, request: &Request) -> CsyncResult<Response> {
+    let mut stream = UnixStream::connect(socket).map_err(|_| CsyncErr::DaemonUnreachable(socket.to_path_buf()))?;
+    check_private(socket)?;
+    if peer_uid(&stream)? != current_uid() {
+        csync_err!(DaemonSocketNotPrivate, socket.to_path_buf())?;
+    }
+    let mut line = serde_json::to_vec(request)?;
+    line.push(b'\n');
+    let write_res = stream.write_all(&line);
+    // which can hold a key
+    zero(&mut line);
+    write_res?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    match serde_json::from_str(&line)? {
+        Response::Failed { message } => csync_err!(DaemonRequestFailed, message),
+        response => Ok(response),
+    }
+}
+
+/// # Returns
+///
+/// The socket that `csyncd` listens on by default, which is `csyncd.sock` under `$XDG_RUNTIME_DIR`,
+/// or under `csyncd-<uid>` in the temp dir without one.
+pub fn default_socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) if !runtime_dir.is_empty() => PathBuf::from(runtime_dir).join(DAEMON_SOCKET_FILENAME),
+        _ => std::env::temp_dir()
+            .join(format!("csyncd-{}", current_uid()))
+            .join(DAEMON_SOCKET_FILENAME),
+    }
+}
+
+/// # Returns
+///
+/// A listener on `socket` that only the current user can connect to. The directory of `socket` is
+/// created for only the current user if it doesn't exist, and has to be one that nobody else can
+/// get into otherwise, which also keeps them out before the permissions of the socket are set. A
+/// socket that is left over from a `csyncd` that is no longer running is replaced.
+pub fn bind(socket: &Path) -> CsyncResult<UnixListener> {
+    let dir = socket_dir(socket);
+    if !dir.exists() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(DEFAULT_DIR_PERM_BITS)
+            .create(dir)?;
+    }
+    check_private(socket)?;
+
+    if fs::symlink_metadata(socket).is_ok() {
+        match UnixStream::connect(socket) {
+            Ok(_) => csync_err!(Other, format!("`csyncd` is already listening on {:?}", socket))?,
+            Err(_) => fs::remove_file(socket)?,
+        }
+    }
+    let listener = UnixListener::bind(socket)?;
+    fs::set_permissions(socket, fs::Permissions::from_mode(DEFAULT_PERM_BITS))?;
+    Ok(listener)
+}
+
+// `socket` and its directory have to belong to the current user, and nobody else can get into the
+// directory, so that nobody else could have put the socket there or could connect to it
+fn check_private(socket: &Path) -> CsyncResult<()> {
+    let uid = current_uid();
+    let dir_meta = fs::metadata(socket_dir(socket))?;
+    let socket_uid_opt = fs::symlink_metadata(socket).ok().map(|meta| meta.uid());
+    match dir_meta.uid() == uid && dir_meta.mode() & 0o077 == 0 && socket_uid_opt.is_none_or(|owner| owner == uid) {
+        true => Ok(()),
+        false => csync_err!(DaemonSocketNotPrivate, socket.to_path_buf()),
+    }
+}
+
+// the directory that `socket` is in, which is the working directory for a bare file name
+fn socket_dir(socket: &Path) -> &Path {
+    match socket.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+#[inline]
+fn current_uid() -> u32 {
+    unsafe { libc::getuid() }
+}
+
+// uid of the process on the other end of `stream`
+#[cfg(target_os = "linux")]
+fn peer_uid(stream: &UnixStream) -> CsyncResult<u32> {
+    let mut cred = libc::ucred { pid: 0, uid: 0, gid: 0 };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    match ret {
+        0 => Ok(cred.uid),
+        _ => Err(std::io::Error::last_os_error())?,
+    }
+}
+
+// `SO_PEERCRED` is Linux's, and the BSDs have `getpeereid` instead
+#[cfg(not(target_os = "linux"))]
+fn peer_uid(stream: &UnixStream) -> CsyncResult<u32> {
+    let (mut uid, mut gid) = (0, 0);
+    match unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } {
+        0 => Ok(uid),
+        _ => Err(std::io::Error::last_os_error())?,
+    }
+}
+
+// seconds since the epoch
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|dur| dur.as_secs())
+        .unwrap_or(0)
+}
+
+// overwrite `buf` with zeros in a way that isn't optimized away
+fn zero(buf: &mut [u8]) {
+    buf.iter_mut().for_each(|byte| unsafe { std::ptr::write_volatile(byte, 0) });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::specs::key_deriv_spec_ext::KeyDerivSpecExt;
+    use crate::specs::syncer_builder::SyncerBuilder;
+
+    // a repository with a key that is quick to derive
+    fn repo_spec(name: &str, source: &Path, out_dir: &Path) -> RepoSpec {
+        let spec_ext = SyncerBuilder::encrypt(source, out_dir)
+            .kdf(KeyDerivSpecExt::Pbkdf2ByParams {
+                alg_opt: None,
+                num_iter: 1,
+                salt_len: 16,
+            })
+            .salt_len(16)
+            .build()
+            .unwrap();
+        RepoSpec {
+            name: name.to_string(),
+            spec_ext,
+            every_opt: None,
+            watch: false,
+        }
+    }
+
+    fn unlock(repo: &str) -> Request {
+        Request::Unlock {
+            repo: repo.to_string(),
+            init_key: InitialKey::from_password("Qz4wHn1e".as_bytes()),
+        }
+    }
+
+    fn status_of(daemon: &Daemon) -> Vec<RepoStatus> {
+        match daemon.handle(Request::Status) {
+            Response::Status { repos } => repos,
+            response => panic!("{:?}", response),
+        }
+    }
+
+    #[test]
+    fn requests_are_lines_of_json() {
+        let request = Request::SyncNow {
+            repo: String::from("Fo3k"),
+        };
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"command":"sync-now","repo":"Fo3k"}"#
+        );
+        assert_eq!(serde_json::to_string(&Response::Done).unwrap(), r#"{"result":"done"}"#);
+
+        // keys make it through too
+        let request = unlock("Fo3k");
+        let json = serde_json::to_string(&request).unwrap();
+        assert_eq!(serde_json::from_str::<Request>(&json).unwrap(), request);
+    }
+
+    #[test]
+    fn repos_are_synced_only_while_unlocked() {
+        let source = tmpdir!().unwrap();
+        let out_dir = tmpdir!().unwrap();
+        fs::write(source.path().join("Pn8c"), "Pn8c").unwrap();
+        let daemon = Daemon::new(vec![repo_spec("Bv5t", source.path(), out_dir.path())]);
+
+        // locked to begin with
+        let sync_now = Request::SyncNow {
+            repo: String::from("Bv5t"),
+        };
+        match daemon.handle(sync_now.clone()) {
+            Response::Failed { message } => assert!(message.contains("locked"), "{}", message),
+            response => panic!("{:?}", response),
+        }
+        assert!(!status_of(&daemon)[0].unlocked);
+
+        // the sync that unlocking queues comes before this one, so this has nothing left to do
+        assert_eq!(daemon.handle(unlock("Bv5t")), Response::Done);
+        match daemon.handle(sync_now.clone()) {
+            Response::Synced { num_files, .. } => assert_eq!(num_files, 0),
+            response => panic!("{:?}", response),
+        }
+        let status = &status_of(&daemon)[0];
+        assert!(status.unlocked && status.last_synced_opt.is_some() && status.last_error_opt.is_none());
+
+        // the same syncer is used again, which only syncs what changed: the new file, and the
+        // root that it was created in
+        fs::write(source.path().join("Ws2y"), "Ws2y").unwrap();
+        match daemon.handle(sync_now.clone()) {
+            Response::Synced { num_files, .. } => assert_eq!(num_files, 2),
+            response => panic!("{:?}", response),
+        }
+
+        assert_eq!(
+            daemon.handle(Request::Lock {
+                repo: String::from("Bv5t")
+            }),
+            Response::Done
+        );
+        assert!(!status_of(&daemon)[0].unlocked);
+        assert!(matches!(daemon.handle(sync_now), Response::Failed { .. }));
+
+        match daemon.handle(unlock("Hj7r")) {
+            Response::Failed { message } => assert!(message.contains("Hj7r"), "{}", message),
+            response => panic!("{:?}", response),
+        }
+    }
+
+    #[test]
+    fn requests_go_through_the_socket() {
+        let source = tmpdir!().unwrap();
+        let out_dir = tmpdir!().unwrap();
+        let socket_dir = tmpdir!().unwrap();
+        fs::set_permissions(socket_dir.path(), fs::Permissions::from_mode(DEFAULT_DIR_PERM_BITS)).unwrap();
+        let socket = socket_dir.path().join("Ke6m.sock");
+        assert_eq!(
+            request(&socket, &Request::Status).unwrap_err().exit_code(),
+            CsyncErr::DaemonUnreachable(PathBuf::new()).exit_code()
+        );
+
+        let listener = bind(&socket).unwrap();
+        assert_eq!(fs::metadata(&socket).unwrap().permissions().mode() & 0o777, DEFAULT_PERM_BITS);
+        let daemon = Arc::new(Daemon::new(vec![repo_spec("Ua9d", source.path(), out_dir.path())]));
+        thread::spawn(move || daemon.serve(listener));
+
+        assert_eq!(request(&socket, &unlock("Ua9d")).unwrap(), Response::Done);
+        match request(&socket, &Request::Status).unwrap() {
+            Response::Status { repos } => assert_eq!(repos[0].name, "Ua9d"),
+            response => panic!("{:?}", response),
+        }
+        let lock_unknown = Request::Lock {
+            repo: String::from("Zr1x"),
+        };
+        assert_eq!(
+            request(&socket, &lock_unknown).unwrap_err().exit_code(),
+            CsyncErr::DaemonRequestFailed(String::new()).exit_code()
+        );
+        // a second `csyncd` can't take over the socket
+        assert!(bind(&socket).is_err());
+    }
+
+    #[test]
+    fn sockets_are_only_used_in_private_dirs() {
+        let parent = tmpdir!().unwrap();
+        let not_private = CsyncErr::DaemonSocketNotPrivate(PathBuf::new()).exit_code();
+
+        // the directory of the socket is made for it
+        let socket = parent.path().join("Yd5p").join("Nw2g.sock");
+        drop(bind(&socket).unwrap());
+        let dir_mode = fs::metadata(parent.path().join("Yd5p")).unwrap().permissions().mode();
+        assert_eq!(dir_mode & 0o777, DEFAULT_DIR_PERM_BITS);
+
+        // but one that others can get into isn't used, by either end
+        fs::set_permissions(parent.path(), fs::Permissions::from_mode(0o755)).unwrap();
+        let socket = parent.path().join("Nw2g.sock");
+        assert_eq!(bind(&socket).unwrap_err().exit_code(), not_private);
+        let _listener = UnixListener::bind(&socket).unwrap();
+        assert_eq!(request(&socket, &Request::Status).unwrap_err().exit_code(), not_private);
+    }
+
+    #[test]
+    fn repos_are_read_from_the_config() {
+        let source = tmpdir!().unwrap();
+        let config_dir = tmpdir!().unwrap();
+        let config_path = config_dir.path().join("config.toml");
+        let write_config = |repo_table: &str| {
+            let config = format!(
+                "[encrypt.Gc0v]\nsource = {:?}\nout-dir = {:?}\n\n[csyncd.Ry4l]\n{}\n",
+                source.path(),
+                config_dir.path().join("Ry4l"),
+                repo_table
+            );
+            fs::write(&config_path, config).unwrap();
+        };
+
+        write_config("profile = \"Gc0v\"\nevery = 60\nwatch = true");
+        let repo_specs = RepoSpec::from_config(&config_path).unwrap();
+        assert_eq!(repo_specs.len(), 1);
+        assert_eq!(repo_specs[0].name, "Ry4l");
+        assert_eq!(repo_specs[0].every_opt, Some(Duration::from_secs(60)));
+        assert!(repo_specs[0].watch);
+        match &repo_specs[0].spec_ext {
+            SyncerSpecExt::Encrypt { index, verbose, .. } => assert!(*index && !*verbose),
+            spec_ext => panic!("{:?}", spec_ext),
+        }
+
+        [
+            "every = 60",
+            "profile = \"Gc0v\"\nevery = 0",
+            "profile = \"Gc0v\"\nTx3b = 1",
+            "profile = \"Ml8q\"",
+        ]
+        .iter()
+        .for_each(|repo_table| {
+            write_config(repo_table);
+            assert_eq!(
+                RepoSpec::from_config(&config_path).unwrap_err().exit_code(),
+                CsyncErr::ConfigInvalid(String::new()).exit_code(),
+                "{}",
+                repo_table
+            );
+        });
+    }
+}
@@ -2,7 +2,7 @@ use crate::prelude::*;
 use std::{
     fs::File,
     os::unix::fs::{OpenOptionsExt, PermissionsExt},
-    path::Path,
+    path::{Path, PathBuf},
 };
 use tempfile::{self, NamedTempFile, TempDir};
 
@@ -11,12 +11,14 @@ pub fn remove<P>(path: P) -> std::io::Result<()>
 where
     P: AsRef<Path>,
 {
-    match path.as_ref().exists() {
-        true => match path.as_ref().is_dir() {
+    // a symlink is removed itself, rather than what it points to
+    match std::fs::symlink_metadata(&path) {
+        Ok(meta) => match meta.is_dir() {
             true => std::fs::remove_dir_all(path),
             false => std::fs::remove_file(path),
         },
-        false => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
     }
 }
 
@@ -90,6 +92,23 @@ where
     Ok(&path.as_ref().canonicalize()? == path.as_ref())
 }
 
+/// `path` canonicalized as far as it exists, with the rest of it appended as-is; for paths that
+/// would only be created later.
+pub fn canonicalize_existing<P>(path: P) -> std::io::Result<PathBuf>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    match path.canonicalize() {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => match (path.parent(), path.file_name()) {
+            (Some(parent), Some(file_name)) if parent == Path::new("") => Ok(Path::new(".").canonicalize()?.join(file_name)),
+            (Some(parent), Some(file_name)) => Ok(canonicalize_existing(parent)?.join(file_name)),
+            _ => Err(err),
+        },
+        canonical_res => canonical_res,
+    }
+}
+
 /// Open a file with a write permission, creating the file if it does not already exist.
 ///
 /// 1. if the file already exists, the existing content will be truncated
@@ -222,6 +241,29 @@ mod tests {
         }
     }
 
+    mod canonicalize_existing {
+        use super::*;
+
+        #[test]
+        fn existing_path_is_canonicalized() {
+            let tmpd = tmpdir!().unwrap();
+            let path = tmpd.path().join("Wd4k");
+            std::fs::create_dir(&path).unwrap();
+            assert_eq!(canonicalize_existing(path.join(".")).unwrap(), path.canonicalize().unwrap());
+        }
+
+        #[test]
+        fn missing_part_is_appended() {
+            let tmpd = tmpdir!().unwrap();
+            let path = tmpd.path().join(".").join("Ms7b").join("Ye2q");
+            assert_eq!(
+                canonicalize_existing(&path).unwrap(),
+                tmpd.path().canonicalize().unwrap().join("Ms7b").join("Ye2q")
+            );
+            assert!(!tmpd.path().join("Ms7b").exists());
+        }
+    }
+
     ///
     mod walkdir_assumptions {
         use super::*;
@@ -1,6 +1,6 @@
 pub use crate::encoder::text::*;
 use crate::{prelude::*, secure_vec::*};
-use ring::{digest, pbkdf2};
+use ring::{digest, hmac, pbkdf2};
 use scrypt::{scrypt, ScryptParams};
 use std::num::NonZeroU32;
 
@@ -56,6 +56,54 @@ where
     sha512!(&key.into())
 }
 
+impl InitialKey {
+    /// # Returns
+    ///
+    /// The initial key that the `csync` binary uses for `password`, so that `csync` dirs created
+    /// by either can be read by the other.
+    #[inline]
+    pub fn from_password<T>(password: T) -> Self
+    where
+        T: Into<SecureBytes>,
+    {
+        Self(deterministic_hash(password))
+    }
+}
+
+/// What a subkey of a derived key is used for; each purpose gets a key of its own, so that nothing
+/// computed with one key can be compared against or used in place of what's computed with another.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeyPurpose {
+    Checksum,      // keyed hashes of plaintext files
+    ChunkBoundary, // the gear table that chunk boundaries are found with
+    ChunkId,       // keyed hashes that name chunks
+    FileMeta,      // encryption of the metadata of plaintext files
+}
+
+impl KeyPurpose {
+    #[inline]
+    fn label(self) -> &'static [u8] {
+        match self {
+            KeyPurpose::Checksum => b"csync-checksum",
+            KeyPurpose::ChunkBoundary => b"csync-chunk-boundary",
+            KeyPurpose::ChunkId => b"csync-chunk-id",
+            KeyPurpose::FileMeta => b"csync-file-meta",
+        }
+    }
+}
+
+impl DerivedKey {
+    /// # Returns
+    ///
+    /// The key for `purpose`, which is the `HMAC-SHA512` of the label of `purpose` keyed by
+    /// `self`.
+    pub fn subkey(&self, purpose: KeyPurpose) -> DerivedKey {
+        let key = hmac::Key::new(hmac::HMAC_SHA512, self.0 .0.unsecure());
+        let tag = hmac::sign(&key, purpose.label());
+        DerivedKey(CryptoSecureBytes(tag.as_ref().to_vec().into()))
+    }
+}
+
 /// output_len_opt must be less than
 pub fn scrypt_custom(
     params: ScryptParams,
@@ -115,6 +163,21 @@ mod tests {
         vec!["", "a", "asf", "123", "asfoij123r98!@$%#@$Q%#$T"]
     }
 
+    #[test]
+    fn subkeys_differ_by_purpose() {
+        let key_hash = DerivedKey(deterministic_hash(b"Hq4oTz8yKe1WmB6s".to_vec()));
+        let purposes = [
+            KeyPurpose::Checksum,
+            KeyPurpose::ChunkBoundary,
+            KeyPurpose::ChunkId,
+            KeyPurpose::FileMeta,
+        ];
+        let subkeys: HashSet<_> = purposes.iter().map(|purpose| key_hash.subkey(*purpose)).collect();
+        assert_eq!(subkeys.len(), purposes.len());
+        assert!(!subkeys.contains(&key_hash));
+        assert_eq!(key_hash.subkey(KeyPurpose::ChunkId), key_hash.subkey(KeyPurpose::ChunkId));
+    }
+
     #[test]
     fn deterministic_hash_is_deterministic() {
         let hashes: HashSet<_> = (0..8)
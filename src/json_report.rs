@@ -0,0 +1,314 @@
+use csync::{
+    clargs::OutputFormat, AuthenticatorSpec, CipherSpec, CompressorSpec, ConflictSpec, CryptoSecureBytes, CsyncErr,
+    KeyDerivSpec, Pbkdf2Algorithm, PlannedChange, ScanSummary, SyncEvent, SyncObserver, SyncerSpec,
+};
+use serde_json::{json, Map, Value};
+use std::{path::Path, sync::Mutex};
+
+/// Reports what `csync` did as JSON on stdout, either as a single object once everything is done
+/// or as an object per line as it happens. Every line of the latter has an `event` field, and the
+/// last one is always a `finished` event with the exit code.
+pub struct JsonReport {
+    // whether each event is written as soon as it happens
+    streaming: bool,
+    state: Mutex<State>,
+}
+
+// what is kept until the end
+#[derive(Default)]
+struct State {
+    errors: Vec<Value>,
+    files: Vec<Value>,
+    // an error that fails a file is also what fails the whole run, which is only reported once
+    last_err_opt: Option<CsyncErr>,
+    spec: Value,
+    stats: Map<String, Value>,
+}
+
+impl JsonReport {
+    /// # Returns
+    ///
+    /// A report in `output_format`, or `None` if it isn't one of the JSON formats.
+    pub fn new(output_format: OutputFormat) -> Option<Self> {
+        let streaming = match output_format {
+            OutputFormat::Human => return None,
+            OutputFormat::Json => false,
+            OutputFormat::Ndjson => true,
+        };
+        Some(Self {
+            streaming,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    /// Report the algorithms and parameters that `spec` uses, without any of its salts.
+    pub fn spec(&self, spec: &SyncerSpec) {
+        match self.streaming {
+            true => print_line("spec", spec_json(spec)),
+            false => self.state.lock().unwrap().spec = spec_json(spec),
+        }
+    }
+
+    /// Report that `path` was removed, as it no longer exists in the `csync` dir.
+    pub fn removed(&self, path: &Path) {
+        self.file(json!({ "status": "removed", "dest": path_json(path) }));
+    }
+
+    /// Report what a dry run found would happen to a path, with the kind of change as its status.
+    pub fn planned(&self, planned: &PlannedChange) {
+        self.file(json!({
+            "status": planned.change.name(),
+            "path": path_json(&planned.path),
+            "bytes": planned.bytes,
+        }));
+    }
+
+    /// Report the final statistic `value` as `key`.
+    pub fn stat<V>(&self, key: &str, value: V)
+    where
+        V: Into<Value>,
+    {
+        self.state.lock().unwrap().stats.insert(key.to_string(), value.into());
+    }
+
+    /// Write whatever hasn't been written yet, along with the exit code that `err_opt` results in.
+    pub fn finish(&self, err_opt: Option<&CsyncErr>) {
+        if let Some(err) = err_opt {
+            if self.state.lock().unwrap().last_err_opt.as_ref() != Some(err) {
+                self.error(None, err);
+            }
+        }
+
+        let exit_code = err_opt.map_or(0, CsyncErr::exit_code);
+        let state = self.state.lock().unwrap();
+        match self.streaming {
+            true => print_line("finished", json!({ "exit_code": exit_code, "stats": state.stats })),
+            false => println!(
+                "{}",
+                json!({
+                    "errors": state.errors,
+                    "exit_code": exit_code,
+                    "files": state.files,
+                    "spec": state.spec,
+                    "stats": state.stats,
+                })
+            ),
+        }
+    }
+
+    // a file that was synced, skipped or removed, or would be
+    fn file(&self, file: Value) {
+        match self.streaming {
+            true => print_line("file", file),
+            false => self.state.lock().unwrap().files.push(file),
+        }
+    }
+
+    // `err` happened, at `path_opt` if it was a specific file that failed
+    fn error(&self, path_opt: Option<&Path>, err: &CsyncErr) {
+        let error = json!({
+            "exit_code": err.exit_code(),
+            "kind": err.kind(),
+            "message": err.to_string(),
+            "path": path_opt.map(path_json),
+        });
+
+        let mut state = self.state.lock().unwrap();
+        state.last_err_opt = Some(err.clone());
+        match self.streaming {
+            true => print_line("error", error),
+            false => state.errors.push(error),
+        }
+    }
+}
+
+impl SyncObserver for JsonReport {
+    fn on_event(&self, event: &SyncEvent) {
+        match event {
+            SyncEvent::ScanStarted if self.streaming => print_line("scan_started", json!({})),
+            SyncEvent::FileStarted { src, dest, .. } if self.streaming => {
+                print_line("file_started", json!({ "src": path_json(src), "dest": path_json(dest) }))
+            }
+            SyncEvent::FileDone {
+                src,
+                dest,
+                src_bytes,
+                dest_bytes,
+                duration,
+                ..
+            } => self.file(json!({
+                "status": "synced",
+                "src": path_json(src),
+                "dest": path_json(dest),
+                "src_bytes": src_bytes,
+                "dest_bytes": dest_bytes,
+                "duration_secs": duration.as_secs_f64(),
+            })),
+            SyncEvent::Scanned(ScanSummary { num_files, src_bytes }) if self.streaming => {
+                print_line("scanned", json!({ "files": num_files, "src_bytes": src_bytes }))
+            }
+            SyncEvent::FileSkipped {
+                src, dest, src_bytes, ..
+            } => self.file(json!({
+                "status": "skipped",
+                "src": path_json(src),
+                "dest": path_json(dest),
+                "src_bytes": src_bytes,
+            })),
+            SyncEvent::Error { path_opt, err, .. } => self.error(path_opt.as_deref(), err),
+            // the totals are reported once everything is done, along with the rest of the stats
+            _ => (),
+        }
+    }
+}
+
+// write `fields` on a line of its own, tagged with the kind of event it is
+fn print_line(event: &str, fields: Value) {
+    let mut line = Map::new();
+    line.insert(String::from("event"), Value::from(event));
+    if let Value::Object(fields) = fields {
+        line.extend(fields);
+    }
+    println!("{}", Value::Object(line));
+}
+
+// paths aren't always valid UTF-8, which JSON strings have to be
+fn path_json(path: &Path) -> Value {
+    Value::from(path.to_string_lossy().into_owned())
+}
+
+// length in bits
+fn bit_len(bytes: &CryptoSecureBytes) -> usize {
+    8 * bytes.0.unsecure().len()
+}
+
+// the same things that `--verbose` reports, using the names that the command line takes
+fn spec_json(spec: &SyncerSpec) -> Value {
+    match spec {
+        SyncerSpec::Encrypt {
+            authenticator_spec,
+            checksum,
+            chunked,
+            cipher_spec,
+            compressor_spec,
+            index,
+            init_salt,
+            key_deriv_spec,
+            out_dir,
+            snapshot,
+            source,
+            spread_depth,
+            ..
+        } => json!({
+            "command": "encrypt",
+            "source": path_json(source),
+            "out_dir": path_json(out_dir),
+            "salt_bits": bit_len(init_salt),
+            "spread_depth": spread_depth,
+            "auth": auth_json(authenticator_spec),
+            "cipher": cipher_json(cipher_spec),
+            "compressor": compressor_json(compressor_spec),
+            "key_deriv": key_deriv_json(key_deriv_spec),
+            "checksum": checksum,
+            "chunked": chunked,
+            "index": index,
+            "snapshot": snapshot,
+        }),
+        SyncerSpec::Decrypt {
+            authenticator_spec,
+            cipher_spec,
+            compressor_spec,
+            conflict_spec,
+            delete,
+            init_salt,
+            key_deriv_spec,
+            out_dir,
+            roots,
+            snapshot_opt,
+            source,
+            spread_depth,
+            ..
+        } => json!({
+            "command": "decrypt",
+            "source": path_json(source),
+            "out_dir": path_json(out_dir),
+            "salt_bits": bit_len(init_salt),
+            "spread_depth": spread_depth,
+            "auth": auth_json(authenticator_spec),
+            "cipher": cipher_json(cipher_spec),
+            "compressor": compressor_json(compressor_spec),
+            "key_deriv": key_deriv_json(key_deriv_spec),
+            "conflict": conflict_json(conflict_spec),
+            "delete": delete,
+            "roots": roots,
+            "snapshot": snapshot_opt,
+        }),
+        SyncerSpec::Clean {
+            retention_spec, source, ..
+        } => json!({
+            "command": "clean",
+            "source": path_json(source),
+            "keep_daily": retention_spec.keep_daily,
+            "keep_last": retention_spec.keep_last,
+            "keep_weekly": retention_spec.keep_weekly,
+        }),
+        _ => Value::Null,
+    }
+}
+
+fn auth_json(authenticator_spec: &AuthenticatorSpec) -> Value {
+    match authenticator_spec {
+        AuthenticatorSpec::HmacSha512 => json!("hmac-sha512"),
+    }
+}
+
+fn cipher_json(cipher_spec: &CipherSpec) -> Value {
+    match cipher_spec {
+        CipherSpec::Aes256Cbc { init_vec } => json!({ "alg": "aes256cbc", "salt_bits": bit_len(init_vec) }),
+        CipherSpec::ChaCha20 { init_vec } => json!({ "alg": "chacha20", "salt_bits": bit_len(init_vec) }),
+    }
+}
+
+fn compressor_json(compressor_spec: &CompressorSpec) -> Value {
+    match compressor_spec {
+        CompressorSpec::Zstd { level } => json!({ "alg": "zstd", "level": level }),
+    }
+}
+
+fn key_deriv_json(key_deriv_spec: &KeyDerivSpec) -> Value {
+    match key_deriv_spec {
+        KeyDerivSpec::Pbkdf2 { alg, num_iter, salt } => json!({
+            "alg": "pbkdf2",
+            "pbkdf2_alg": match alg {
+                Pbkdf2Algorithm::HmacSha512 => "hmac-sha512",
+            },
+            "num_iter": num_iter,
+            "salt_bits": bit_len(salt),
+        }),
+        KeyDerivSpec::Scrypt {
+            log_n,
+            r,
+            p,
+            output_len,
+            salt,
+        } => json!({
+            "alg": "scrypt",
+            "log_n": log_n,
+            "r": r,
+            "p": p,
+            "output_bits": 8 * output_len,
+            "salt_bits": bit_len(salt),
+        }),
+    }
+}
+
+fn conflict_json(conflict_spec: &ConflictSpec) -> Value {
+    let policy = match conflict_spec {
+        ConflictSpec::Fail => "fail",
+        ConflictSpec::Overwrite { .. } => "overwrite",
+        ConflictSpec::SkipExisting => "skip-existing",
+        ConflictSpec::NewerWins { .. } => "newer-wins",
+        ConflictSpec::Incremental { .. } => "incremental",
+    };
+    json!({ "policy": policy, "backup_suffix": conflict_spec.backup_suffix() })
+}
@@ -0,0 +1,102 @@
+//! CryptSync (`csync`) compresses and encrypts a large set of files and directories into a
+//! `csync` directory, and decrypts them back out of it.
+//!
+//! This crate is what the `csync` binary is built on. Everything exported from the root of the
+//! crate is its stable API, which follows semver:
+//! 1. [`Syncer`], which encrypts, decrypts and cleans `csync` directories
+//! 1. [`csync_encrypt`] and [`csync_decrypt`], which encrypt and decrypt a single file without a
+//!    `Syncer`
+//! 1. [`SyncerSpecExt`] and the other spec types, which describe what a `Syncer` does, and
+//!    [`SyncerBuilder`], which builds and checks them
+//! 1. [`SyncObserver`], which gets told about every [`SyncEvent`] of a `Syncer` it subscribed to
+//! 1. [`PlannedChange`], each path that `Syncer::dry_run` finds would change
+//! 1. [`CryptEncoder`], the trait that every encoder is built on
+//! 1. [`CsyncErr`] and [`CsyncResult`], which every fallible function returns
+//!
+//! Enums marked `#[non_exhaustive]` can gain variants, and variants marked so can gain fields, in
+//! any minor release; match them with a wildcard or `..`, and build specs with [`SyncerBuilder`].
+//!
+//! Modules and macros that are hidden from these docs only exist for the binary, and can change in
+//! any release.
+//!
+//! # Example
+//!
+//! Decrypting a `csync` directory, which is what `csync decrypt` does:
+//!
+//! ```no_run
+//! use csync::{CsyncResult, InitialKey, SyncEvent, Syncer, SyncerBuilder};
+//!
+//! fn decrypt(password: &str) -> CsyncResult<usize> {
+//!     let spec = SyncerBuilder::decrypt("csync-dir", "decrypted").build()?;
+//!     let mut syncer = Syncer::new(&spec, InitialKey::from_password(password.as_bytes()))?;
+//!
+//!     // print every file as soon as it's decrypted
+//!     syncer.subscribe(|event: &SyncEvent| {
+//!         if let SyncEvent::FileDone { dest, .. } = event {
+//!             println!("{:?}", dest);
+//!         }
+//!     });
+//!     Ok(syncer.sync()?.num_files)
+//! }
+//! ```
+
+// dependency free
+mod primitives;
+pub mod secure_vec;
+
+#[macro_use]
+mod prelude;
+
+#[macro_use]
+#[doc(hidden)]
+pub mod fs_util;
+#[macro_use]
+#[doc(hidden)]
+pub mod hasher;
+#[macro_use]
+mod rand_util;
+
+#[macro_use]
+#[doc(hidden)]
+pub mod util;
+
+#[doc(hidden)]
+pub mod logger;
+
+#[macro_use]
+mod encoder;
+
+pub mod specs;
+#[allow(dead_code)] // because most functions in this mod are only used in tests
+mod test_util;
+
+#[doc(hidden)]
+pub mod clargs;
+#[doc(hidden)]
+pub mod config;
+mod crypt;
+#[doc(hidden)]
+pub mod daemon;
+#[cfg(target_os = "linux")]
+#[doc(hidden)]
+pub mod watcher;
+
+#[cfg(test)]
+mod tests_e2e;
+
+////////////////////////////////  ////////////////////////////////
+
+pub use crate::{
+    crypt::{
+        csync_decrypt, csync_encrypt,
+        syncer::{Change, PlannedChange, ScanSummary, SyncEvent, SyncObserver, SyncSummary, Syncer},
+    },
+    encoder::crypt_encoder::CryptEncoder,
+    prelude::{CsyncErr, CsyncResult},
+    primitives::FileType,
+    secure_vec::{CryptoSecureBytes, DerivedKey, InitialKey, SecureBytes, SecureVec},
+    specs::{prelude::*, retention_spec::RetentionSpec, storage_spec::StorageSpec},
+};
+
+#[doc(hidden)]
+pub use crate::prelude::consts;
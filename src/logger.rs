@@ -0,0 +1,151 @@
+use crate::prelude::*;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::{
+    fmt,
+    fs::File,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+// whether plaintext paths are logged as they are, rather than redacted
+static SHOW_PATHS: AtomicBool = AtomicBool::new(false);
+
+/// A plaintext path as it appears in the log, which is `<redacted>` unless `init` was told to
+/// show paths. Keys in a `csync` dir reveal nothing about the plaintext, and are logged as they are.
+pub struct Redacted<'a>(&'a Path);
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match SHOW_PATHS.load(Ordering::Relaxed) {
+            true => write!(f, "{:?}", self.0),
+            false => write!(f, "<redacted>"),
+        }
+    }
+}
+
+/// # Returns
+///
+/// `path`, to be logged without revealing it unless paths are shown.
+#[inline]
+pub fn redact<P>(path: &P) -> Redacted<'_>
+where
+    P: AsRef<Path> + ?Sized,
+{
+    Redacted(path.as_ref())
+}
+
+/// # Returns
+///
+/// The most detailed level that gets logged: errors only if `quiet`, warnings by default, and
+/// one more level of detail for each `-v` in `verbosity`.
+pub fn level_of(quiet: bool, verbosity: u8) -> LevelFilter {
+    match (quiet, verbosity) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Warn,
+        (false, 1) => LevelFilter::Info,
+        (false, 2) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    }
+}
+
+// writes each record on a line of its own, with how long after the start it was logged
+struct Logger {
+    level: LevelFilter,
+    start: Instant,
+    file_opt: Option<Mutex<File>>,
+}
+
+impl Log for Logger {
+    #[inline]
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let line = format!(
+                "[{:>9.3}s {:<5}] {}",
+                self.start.elapsed().as_secs_f64(),
+                record.level(),
+                record.args()
+            );
+            match &self.file_opt {
+                // there is nowhere left to report a log that can't be written
+                Some(file) => drop(writeln!(file.lock().unwrap(), "{}", line)),
+                None => eprintln!("{}", line),
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file_opt {
+            drop(file.lock().unwrap().flush());
+        }
+    }
+}
+
+/// Log everything up to `level` from here on, to `log_file_opt` if there is one and to stderr
+/// otherwise. Can only be called once.
+///
+/// # Parameters
+///
+/// 1. `log_file_opt`: file to append the log to, which is created with the same permission bits as
+///    any other file `csync` creates
+/// 1. `show_paths`: whether to log plaintext paths as they are, rather than `<redacted>`
+pub fn init(level: LevelFilter, log_file_opt: Option<&Path>, show_paths: bool) -> CsyncResult<()> {
+    let file_opt = match log_file_opt {
+        Some(log_file) => Some(Mutex::new(
+            std::fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .mode(DEFAULT_PERM_BITS)
+                .open(log_file)?,
+        )),
+        None => None,
+    };
+    SHOW_PATHS.store(show_paths, Ordering::Relaxed);
+
+    let logger = Logger {
+        level,
+        start: Instant::now(),
+        file_opt,
+    };
+    match log::set_boxed_logger(Box::new(logger)) {
+        Ok(()) => {
+            log::set_max_level(level);
+            Ok(())
+        }
+        Err(_) => csync_err!(Other, String::from("the logger was already initialized")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels() {
+        assert_eq!(level_of(true, 2), LevelFilter::Error);
+        assert_eq!(level_of(false, 0), LevelFilter::Warn);
+        assert_eq!(level_of(false, 1), LevelFilter::Info);
+        assert_eq!(level_of(false, 2), LevelFilter::Debug);
+        assert_eq!(level_of(false, 7), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn paths_are_redacted_unless_shown() {
+        let path = Path::new("Qz4r/Lm8w");
+        assert_eq!(redact(path).to_string(), "<redacted>");
+
+        SHOW_PATHS.store(true, Ordering::Relaxed);
+        let shown = redact(path).to_string();
+        SHOW_PATHS.store(false, Ordering::Relaxed);
+        assert_eq!(shown, "\"Qz4r/Lm8w\"");
+    }
+}
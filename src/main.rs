@@ -1,61 +1,36 @@
-// dependency free
-mod primitives;
-mod secure_vec;
-
-#[macro_use]
-mod prelude;
-
-#[macro_use]
-mod fs_util;
-#[macro_use]
-mod hasher;
-#[macro_use]
-mod rand_util;
-
-#[macro_use]
-mod util;
-
-mod specs;
-
-#[macro_use]
-mod encoder;
-#[allow(dead_code)] // because most functions in this mod are only used in tests
-mod test_util;
-
-mod clargs;
-mod crypt;
-
-#[cfg(test)]
-mod tests_e2e;
-
 mod cli;
+mod json_report;
 
 ////////////////////////////////  ////////////////////////////////
 
-use crate::{
-    clargs::{Opts, Opts::*},
-    crypt::syncer::*,
-    hasher::deterministic_hash,
-    prelude::*,
-    secure_vec::*,
-    specs::prelude::*,
+#[cfg(target_os = "linux")]
+use csync::watcher::Watcher;
+use csync::{
+    clargs::{DaemonCommand, Opts, Opts::*},
+    config,
+    consts::*,
+    daemon::{self, Request, Response},
+    fs_util::*,
+    logger, time,
     util::*,
+    Change, CsyncResult, InitialKey, PlannedChange, ScanSummary, SourceKind, SyncEvent, SyncObserver, SyncSummary, Syncer,
+    SyncerSpec, SyncerSpecExt,
 };
+use json_report::JsonReport;
 use rayon::prelude::*;
 use std::{
+    collections::VecDeque,
     convert::TryFrom,
     fmt,
-    sync::mpsc::Receiver,
-    time::{Duration, Instant},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use structopt::StructOpt;
 use termion::color;
 
 ////////////////////////////////  ////////////////////////////////
 
 // TODO
-// 1. dry run flag to show which files would be run on
-// 2. incremental build
 // 3. https://docs.rs/crossterm/0.19.0/crossterm/ for indicating how many texts
 // 4. https://crates.io/crates/indicatif for human readable bytes and yarnish.rs and human
 //    durations
@@ -77,6 +52,7 @@ struct RunResult {
 struct SyncStats {
     dest_bytes: f64,
     num_files: usize,
+    num_removed: usize,
     src_bytes: f64,
     total_dur: Duration,
     total_thru: f64,
@@ -84,20 +60,41 @@ struct SyncStats {
 
 //
 fn main() {
-    // parse the cli args
-    let opts = clargs::Opts::from_args();
+    // parse the cli args, along with what the config file has for the ones that aren't given
+    let opts = match config::with_config(std::env::args_os().collect()) {
+        Ok((args, positionals)) => Opts::from_config_args(args, positionals),
+        Err(err) => {
+            eprintln!("{}: {}", color!(Red, "[csync error]"), err);
+            std::process::exit(err.exit_code());
+        }
+    };
+
+    // nothing but JSON is written in the JSON formats
+    let report_opt = JsonReport::new(opts.output_format()).map(Arc::new);
 
     //
-    match run(&opts) {
+    match (run(&opts, report_opt.as_ref()), &report_opt) {
         //
-        Ok(RunResult { sync_stats, .. }) => {
+        (Ok(Some(RunResult { sync_stats, .. })), None) => {
             eprintln!("\n{}", sync_stats);
         }
+        (Ok(Some(RunResult { sync_stats, .. })), Some(report)) => {
+            sync_stats.report(report);
+            report.finish(None);
+        }
+        (Ok(None), Some(report)) => report.finish(None),
+        // nothing was synced, so there is nothing to report
+        (Ok(None), None) => (),
         //
-        Err(err) => {
+        (Err(err), _) => {
             //
-            let err_header = color!(Red, "[csync error]");
-            eprintln!("{}: {}", err_header, err);
+            match &report_opt {
+                Some(report) => report.finish(Some(&err)),
+                None => {
+                    let err_header = color!(Red, "[csync error]");
+                    eprintln!("{}: {}", err_header, err);
+                }
+            }
 
             // this is used to uniquely identify the types of errors, for testing purposes
             let exit_code = err.exit_code();
@@ -108,47 +105,141 @@ fn main() {
     }
 }
 
-//
-fn reporting_thread(start: Instant, receiver: Receiver<Option<(usize, usize)>>) -> std::thread::JoinHandle<()> {
-    //
-    std::thread::spawn(move || {
-        //
-        let mut file_count = 0;
-        let mut bytes_read = 0;
-        let mut bytes_writ = 0;
-        //
-        loop {
-            // TODO change from unwrap to ignoring behavior
-            //
-            // \r and print the err file \n if error
-            // the ncontinue with the updating line
-            match receiver.recv().unwrap() {
-                Some((src_bytes, dst_bytes)) => {
-                    let elapsed = end_timer(&start);
-
-                    file_count += 1;
-                    bytes_read += src_bytes;
-                    bytes_writ += dst_bytes;
-
-                    let format_v = |value: f64, unit| {
-                        let (adj_value, adj_unit) = adjust_value(value, unit);
-                        color!(Green, "{:>7} {}", adj_value, adj_unit)
-                    };
-
-                    // TODO format time, report compression ratios
-                    eprint!(
-                        "\r{} | {} -> {} in {:>7} = {}...",
-                        format_v(file_count as f64, "files"),
-                        format_v(bytes_read as f64, "B"),
-                        format_v(bytes_writ as f64, "B"),
-                        color!(Green, "{:.3?}", elapsed),
-                        format_v(bytes_read as f64 / (elapsed.as_nanos() as f64) * 1e9, "B/s")
-                    );
-                }
-                None => break,
+// keeps a line up to date with how much has been synced so far, out of what was scanned
+struct Progress {
+    start: Instant,
+    // a line is only rewritten in place on a terminal; elsewhere, plain lines are printed now and then
+    is_tty: bool,
+    state: Mutex<ProgressState>,
+}
+
+#[derive(Default)]
+struct ProgressState {
+    scanned_opt: Option<ScanSummary>,
+    // number of files synced or skipped, and number of bytes those account for
+    files_seen: usize,
+    bytes_seen: u64,
+    // number of bytes read and written by the files that were synced
+    bytes_read: u64,
+    bytes_writ: u64,
+    // when recently `bytes_seen` was what it was, oldest first
+    samples: VecDeque<(Duration, u64)>,
+    printed_at_opt: Option<Duration>,
+}
+
+impl Progress {
+    #[inline]
+    fn new() -> Self {
+        Self {
+            start: start_timer(),
+            is_tty: termion::is_tty(&std::io::stderr()),
+            state: Mutex::new(ProgressState::default()),
+        }
+    }
+
+    // the line that shows `state`, at `elapsed` since the start
+    fn line(&self, state: &ProgressState, elapsed: Duration) -> String {
+        let paint = |text: String| match self.is_tty {
+            true => color!(Green, "{}", text),
+            false => text,
+        };
+        let format_v = |value: f64, unit| {
+            let (adj_value, adj_unit) = adjust_value(value, unit);
+            paint(format!("{:>7} {}", adj_value, adj_unit))
+        };
+
+        // bytes per second over the last few seconds, so that it follows files of different sizes
+        let rate = match state.samples.front() {
+            Some((then, bytes_then)) if *then < elapsed => {
+                (state.bytes_seen - bytes_then) as f64 / (elapsed - *then).as_secs_f64()
             }
+            _ => 0.0,
+        };
+        let ratio = match state.bytes_read {
+            0 => 0.0,
+            bytes_read => state.bytes_writ as f64 / bytes_read as f64,
+        };
+
+        let mut line = format!("{} files", paint(format!("{:>7}", state.files_seen)));
+        if let Some(scanned) = &state.scanned_opt {
+            let done = match scanned.src_bytes {
+                0 => state.files_seen as f64 / scanned.num_files.max(1) as f64,
+                total => state.bytes_seen as f64 / total as f64,
+            };
+            line += &format!(
+                " of {:<7} | {}",
+                scanned.num_files,
+                paint(format!("{:>5.1}%", done.min(1.0) * 100.0))
+            );
         }
-    })
+        line += &format!(
+            " | {} -> {} ({}) | {}",
+            format_v(state.bytes_read as f64, "B"),
+            format_v(state.bytes_writ as f64, "B"),
+            paint(format!("{:.2}x", ratio)),
+            format_v(rate, "B/s"),
+        );
+        if let Some(scanned) = &state.scanned_opt {
+            let bytes_left = scanned.src_bytes.saturating_sub(state.bytes_seen);
+            line += &match rate {
+                _ if bytes_left == 0 => String::new(),
+                rate if rate > 0.0 => format!(" | ETA {}", paint(format_secs(bytes_left as f64 / rate))),
+                _ => format!(" | ETA {}", paint(String::from("?"))),
+            };
+        }
+        line
+    }
+}
+
+impl SyncObserver for Progress {
+    fn on_event(&self, event: &SyncEvent) {
+        // held until the line is printed, so that lines of different threads don't interleave
+        let mut state = self.state.lock().unwrap();
+        match event {
+            SyncEvent::Scanned(scanned) => {
+                state.scanned_opt = Some(*scanned);
+                return;
+            }
+            SyncEvent::FileDone {
+                src_bytes, dest_bytes, ..
+            } => {
+                state.files_seen += 1;
+                state.bytes_seen += src_bytes;
+                state.bytes_read += src_bytes;
+                state.bytes_writ += dest_bytes;
+            }
+            SyncEvent::FileSkipped { src_bytes, .. } => {
+                state.files_seen += 1;
+                state.bytes_seen += src_bytes;
+            }
+            _ => return,
+        };
+
+        let elapsed = end_timer(&self.start);
+        let bytes_seen = state.bytes_seen;
+        state.samples.push_back((elapsed, bytes_seen));
+        while let Some((then, _)) = state.samples.front() {
+            match elapsed - *then > Duration::from_secs(PROGRESS_RATE_WINDOW_SECS) {
+                true => state.samples.pop_front(),
+                false => break,
+            };
+        }
+
+        match (self.is_tty, state.printed_at_opt) {
+            (true, _) => eprint!("\r{}...", self.line(&state, elapsed)),
+            (false, Some(printed_at)) if elapsed - printed_at < Duration::from_secs(PROGRESS_PLAIN_INTERVAL_SECS) => (),
+            (false, _) => {
+                eprintln!("{}", self.line(&state, elapsed));
+                state.printed_at_opt = Some(elapsed);
+            }
+        };
+    }
+}
+
+// `secs` as `h:mm:ss`
+fn format_secs(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
 }
 
 impl fmt::Display for SyncStats {
@@ -163,6 +254,9 @@ impl fmt::Display for SyncStats {
             }};
         }
         pretty!(REPORT_HEADER_NUM_FILES, self.num_files as f64, "files")?;
+        if self.num_removed > 0 {
+            pretty!(REPORT_HEADER_NUM_REMOVED, self.num_removed as f64, "files")?;
+        }
         pretty!(REPORT_HEADER_DATA_READ, self.src_bytes, "B")?;
         pretty!(REPORT_HEADER_DATA_WRITTEN, self.dest_bytes, "B")?;
         pretty!(REPORT_HEADER_THROUGHPUT, self.total_thru, "B/sec")?;
@@ -172,12 +266,30 @@ impl fmt::Display for SyncStats {
 
 //
 impl SyncStats {
+    // the same things that `Display` shows, in bytes and seconds
+    fn report(&self, report: &JsonReport) {
+        report.stat("files_synced", self.num_files);
+        report.stat("files_removed", self.num_removed);
+        report.stat("bytes_read", self.src_bytes as u64);
+        report.stat("bytes_stored", self.dest_bytes as u64);
+        report.stat("throughput", self.total_thru);
+        report.stat("duration_secs", self.total_dur.as_secs_f64());
+    }
+
     //
     #[inline]
-    fn new(num_files: usize, src_bytes: f64, dest_bytes: f64, total_thru: f64, total_dur: Duration) -> Self {
+    fn new(
+        num_files: usize,
+        num_removed: usize,
+        src_bytes: f64,
+        dest_bytes: f64,
+        total_thru: f64,
+        total_dur: Duration,
+    ) -> Self {
         Self {
             dest_bytes,
             num_files,
+            num_removed,
             src_bytes,
             total_dur,
             total_thru,
@@ -187,7 +299,27 @@ impl SyncStats {
 
 // TODO use macro to circomvent this again
 //
-fn run(opts: &Opts) -> CsyncResult<RunResult> {
+fn run(opts: &Opts, report_opt: Option<&Arc<JsonReport>>) -> CsyncResult<Option<RunResult>> {
+    //
+    let (log_opts, quiet) = opts.log_opts();
+    logger::init(
+        logger::level_of(quiet, log_opts.verbose),
+        log_opts.log_file_opt.as_deref(),
+        log_opts.log_paths,
+    )?;
+
+    // which asks `csyncd` to do what it says, rather than syncing anything itself
+    if let Daemon {
+        command,
+        socket_opt,
+        quiet,
+        ..
+    } = opts
+    {
+        let socket = socket_opt.clone().unwrap_or_else(daemon::default_socket_path);
+        return ask_daemon(command, &socket, *quiet);
+    }
+
     //
     let external_spec = SyncerSpecExt::try_from(opts)?;
 
@@ -197,6 +329,10 @@ fn run(opts: &Opts) -> CsyncResult<RunResult> {
             num_threads_opt: Some(n),
             ..
         }
+        | Watch {
+            num_threads_opt: Some(n),
+            ..
+        }
         | Decrypt {
             num_threads_opt: Some(n),
             ..
@@ -213,101 +349,379 @@ fn run(opts: &Opts) -> CsyncResult<RunResult> {
     // the key that the user entered
     // TODO also, don't confirm if incremental build
     let confirm_password = match opts {
-        Encrypt { .. } => true,
-        Decrypt { .. } | Clean { .. } => false,
+        // nothing gets encrypted with it in a dry run
+        Encrypt { dry_run: true, .. } => false,
+        Encrypt { .. } | Watch { .. } | ImportTar { .. } => true,
+        Decrypt { .. } | Cat { .. } | ExportTar { .. } | Clean { .. } | Daemon { .. } => false,
     };
     //let init_key = get_password(confirm_password)?;
 
-    let init_key = cli::get_password(confirm_password)?;
-    // TODO do an initial scan to get file count and size count to get an approxdmate duration?
+    let init_key = match opts {
+        Encrypt {
+            password_file_opt: Some(password_file),
+            ..
+        }
+        | Watch {
+            password_file_opt: Some(password_file),
+            ..
+        }
+        | ImportTar {
+            password_file_opt: Some(password_file),
+            ..
+        } => cli::read_password_file(password_file)?,
+        Encrypt { source: stdin, .. } | ImportTar { archive: stdin, .. } if stdin == Path::new(STDIO_PATH) => {
+            cli::get_password(confirm_password, true)?
+        }
+        _ => cli::get_password(confirm_password, false)?,
+    };
+
+    // nothing gets synced, so there is nothing to report but what would be
+    if opts.dry_run() {
+        let changes = Syncer::dry_run(&external_spec, InitialKey(init_key))?;
+        report_dry_run(&changes, report_opt);
+        return Ok(None);
+    }
 
     //
-    macro_rules! handle {
-        //
-        ( $syncer:expr, $action:ident, $verbose:expr ) => {{
-            // syncer-related
-            let syncer = $syncer;
-            let syncer_spec = syncer.get_spec();
-            let actions = syncer.$action()?;
+    let mut syncer = Syncer::new(&external_spec, InitialKey(init_key))?;
+    if let Some(report) = report_opt {
+        report.spec(&syncer.get_spec());
+    }
+    // which only returns if something goes wrong
+    if let (Watch { debounce_ms, .. }, SyncerSpecExt::Encrypt { verbose, .. }) = (opts, &external_spec) {
+        return watch(&syncer, Duration::from_millis(*debounce_ms), *verbose);
+    }
+    Ok(match external_spec {
+        // stdin is reserved for the plaintext
+        SyncerSpecExt::Encrypt {
+            source_kind: SourceKind::Stream,
+            ..
+        } => {
+            let stdin = std::io::stdin();
+            let (lens_res, time_taken) = time!(syncer.sync_enc_stream(stdin.lock()));
+            let (src_bytes, dest_bytes) = lens_res?;
+            syncer.flush()?;
+
+            let throughput = (src_bytes as f64) / (time_taken.as_nanos() as f64) * 1e9;
+            Some(RunResult {
+                sync_stats: SyncStats::new(1, 0, src_bytes as f64, dest_bytes as f64, throughput, time_taken),
+                syncer_spec: syncer.get_spec(),
+            })
+        }
+        // stdin is reserved for the archive, if it is `-`
+        SyncerSpecExt::Encrypt {
+            source_kind: SourceKind::Tar,
+            ..
+        } => {
+            let (lens_res, time_taken) = match opts {
+                ImportTar { archive, .. } if archive == Path::new(STDIO_PATH) => {
+                    let stdin = std::io::stdin();
+                    time!(syncer.sync_enc_tar(stdin.lock()))
+                }
+                ImportTar { archive, .. } => time!(syncer.sync_enc_tar(fopen_r(archive)?)),
+                _ => panic!("Only `import-tar` imports archives"),
+            };
+            let (num_entries, src_bytes, dest_bytes) = lens_res?;
+            syncer.flush()?;
+
+            let throughput = (src_bytes as f64) / (time_taken.as_nanos() as f64) * 1e9;
+            Some(RunResult {
+                sync_stats: SyncStats::new(num_entries, 0, src_bytes as f64, dest_bytes as f64, throughput, time_taken),
+                syncer_spec: syncer.get_spec(),
+            })
+        }
+        SyncerSpecExt::Encrypt { verbose, .. } => {
+            let run_result = sync(&mut syncer, verbose, 0, report_opt)?;
+            syncer.store_index()?;
+
+            match (syncer.create_snapshot()?, report_opt) {
+                (Some(id), Some(report)) => report.stat("snapshot_created", id),
+                (Some(id), None) if verbose => eprintln!("{:>32} {:>7}", format!("{}:", REPORT_HEADER_SNAPSHOT_CREATED), id),
+                _ => (),
+            }
+            syncer.flush()?;
+            Some(run_result)
+        }
+        SyncerSpecExt::Decrypt { delete, verbose, .. } => {
+            // remove before decrypting, so that backups made by the conflict policy are kept
+            let num_removed = match delete {
+                true => syncer
+                    .sync_dec_delete()?
+                    .inspect(|path_res| match (path_res, report_opt) {
+                        (Ok(path), Some(report)) => report.removed(path),
+                        _ => (),
+                    })
+                    .collect::<CsyncResult<Vec<_>>>()?
+                    .len(),
+                false => 0,
+            };
+            Some(sync(&mut syncer, verbose, num_removed, report_opt)?)
+        }
+        // stdout is reserved for the plaintext
+        SyncerSpecExt::Cat { path_opt, .. } => {
+            let stdout = std::io::stdout();
+            syncer.cat(path_opt.as_deref(), stdout.lock())?;
+            None
+        }
+        SyncerSpecExt::ExportTar { out_opt, .. } => {
+            let (lens_res, time_taken) = match out_opt {
+                Some(out) => time!(syncer.export_tar(std::fs::File::create(out)?)),
+                // stdout is reserved for the archive
+                None => {
+                    let stdout = std::io::stdout();
+                    time!(syncer.export_tar(stdout.lock()))
+                }
+            };
+            let (num_entries, src_bytes, dest_bytes) = lens_res?;
 
-            //
-            if $verbose {
-                eprintln!("\nUsing {} threads...", rayon::current_num_threads());
+            let throughput = (src_bytes as f64) / (time_taken.as_nanos() as f64) * 1e9;
+            Some(RunResult {
+                sync_stats: SyncStats::new(num_entries, 0, src_bytes as f64, dest_bytes as f64, throughput, time_taken),
+                syncer_spec: syncer.get_spec(),
+            })
+        }
+        SyncerSpecExt::Clean { verbose, .. } => {
+            let (num_snapshots_removed, num_chunks_removed) = syncer.clean()?;
+            syncer.flush()?;
+            if let Some(report) = report_opt {
+                report.stat("snapshots_removed", num_snapshots_removed);
+                report.stat("chunks_removed", num_chunks_removed);
+            }
+            if verbose {
+                eprintln!(
+                    "\n{:>32} {:>7}\n{:>32} {:>7}",
+                    format!("{}:", REPORT_HEADER_SNAPSHOTS_REMOVED),
+                    num_snapshots_removed,
+                    format!("{}:", REPORT_HEADER_CHUNKS_REMOVED),
+                    num_chunks_removed
+                );
+            }
+            None
+        }
+        _ => {
+            return Err(csync::CsyncErr::UnsupportedOperation(String::from(
+                "this `csync` binary doesn't know how to run this spec",
+            )))
+        }
+    })
+}
+
+// encrypt everything with `syncer` like `encrypt` does, then keep encrypting whatever changes under
+// the source as it changes, with the same key, until interrupted
+#[cfg(target_os = "linux")]
+fn watch(syncer: &Syncer, debounce: Duration, verbose: bool) -> CsyncResult<Option<RunResult>> {
+    let (source, filter_spec) = match syncer.get_spec() {
+        SyncerSpec::Encrypt { source, filter_spec, .. } => (source, filter_spec),
+        _ => panic!("Only encryptions are watched"),
+    };
+    // watching starts first, so that nothing that changes during the first sync is missed
+    let mut watcher = Watcher::new(&source, &filter_spec)?;
+
+    let SyncSummary {
+        num_files,
+        src_bytes,
+        dest_bytes,
+        duration,
+    } = syncer.sync()?;
+    syncer.store_index()?;
+    syncer.flush()?;
+    if verbose {
+        let throughput = (src_bytes as f64) / (duration.as_nanos() as f64) * 1e9;
+        let sync_stats = SyncStats::new(num_files, 0, src_bytes as f64, dest_bytes as f64, throughput, duration);
+        eprintln!("\n{}\n\nWatching {:?} for changes...", sync_stats, source);
+    }
+
+    loop {
+        let batch = watcher.next_batch(debounce)?;
+        // whatever changed was lost, so everything gets looked at
+        let touched = match batch.overflowed {
+            true => std::iter::once(source.clone()).collect(),
+            false => batch.paths,
+        };
+        if touched.is_empty() {
+            continue;
+        }
+
+        // a batch that failed is left for the next one that touches the same paths, while only
+        // the watcher failing ends the session
+        let synced = syncer.sync_touched(&touched).and_then(|synced| {
+            syncer.store_index()?;
+            syncer.flush()?;
+            Ok(synced)
+        });
+        match synced {
+            Ok((summary, num_removed)) if verbose && (summary.num_files > 0 || num_removed > 0) => {
+                eprintln!(
+                    "{} {} files synced and {} removed in {:?}",
+                    color!(Green, "[csync watch]"),
+                    summary.num_files,
+                    num_removed,
+                    summary.duration
+                );
             }
+            Ok(_) => (),
+            Err(err) => eprintln!("{} failed to sync a batch of changes: {}", color!(Red, "[csync watch]"), err),
+        }
+    }
+}
 
-            // channel for the thread that updates progress real time
-            let (sender, receiver) = std::sync::mpsc::channel();
-            let sender_for_termination = sender.clone();
-            let sender = std::sync::Mutex::new(sender);
-
-            let report_thread = reporting_thread(start_timer(), receiver);
-
-            // TODO just report error using filtermap instead of stopping the whole thing
-            let (result, time_taken) = time!(actions
-                .map(move |action_res| {
-                    let action = action_res?;
-                    // action and how long that action took
-                    match (std::fs::metadata(action.src), std::fs::metadata(action.dest)) {
-                        // sizes oif the src and dest files in bytes
-                        // TODO reduce meta calls by including this in meta map and propagating it
-                        (Ok(meta_src), Ok(meta_dst)) => {
-                            let src_bytes = meta_src.len();
-                            let dst_bytes = meta_dst.len();
-                            if $verbose {
-                                sender
-                                    .lock()
-                                    .unwrap()
-                                    .send(Some((src_bytes as usize, dst_bytes as usize)))
-                                    .unwrap();
-                            }
-                            Ok((src_bytes as f64, dst_bytes as f64))
-                        }
-                        _ => csync_err!(NonFatalReportFailed),
-                    }
-                })
-                .fold(
-                    || CsyncResult::Ok((0usize, 0f64, 0f64)),
-                    |acc_res, res| {
-                        let (count, src_size_acc, dst_size_acc) = acc_res?;
-                        let (src_size, dst_size) = res?;
-
-                        Ok((
-                            count + 1,               // counting one more
-                            src_size_acc + src_size, // sum up the size of src files in bytes
-                            dst_size_acc + dst_size, // sum up the size of src files in bytes
-                        ))
-                    }
-                )
-                .reduce(
-                    || Ok((0usize, 0f64, 0f64)),
-                    |acc_res_a, acc_res_b| {
-                        let (count_a, src_size_a, dst_size_a) = acc_res_a?;
-                        let (count_b, src_size_b, dst_size_b) = acc_res_b?;
-                        Ok((count_a + count_b, src_size_a + src_size_b, dst_size_a + dst_size_b))
-                    },
-                ));
-
-            sender_for_termination.send(None).unwrap();
-            report_thread.join().unwrap();
-
-            let (count, bytes_src, bytes_dst) = result?;
-
-            let throughput = (bytes_src as f64) / (time_taken.as_nanos() as f64) * 1e9;
-            RunResult {
-                sync_stats: SyncStats::new(count, bytes_src, bytes_dst, throughput, time_taken),
-                syncer_spec,
+// `inotify` is what changes are watched with
+#[cfg(not(target_os = "linux"))]
+fn watch(_syncer: &Syncer, _debounce: Duration, _verbose: bool) -> CsyncResult<Option<RunResult>> {
+    Err(csync::CsyncErr::CommandLineArgumentConflict(String::from(
+        "`csync watch` is only supported on Linux",
+    )))
+}
+
+// ask `csyncd` on `socket` to do what `command` says, and print what it answers with
+fn ask_daemon(command: &DaemonCommand, socket: &Path, quiet: bool) -> CsyncResult<Option<RunResult>> {
+    let request = match command {
+        DaemonCommand::Status => Request::Status,
+        DaemonCommand::SyncNow { repo } => Request::SyncNow { repo: repo.clone() },
+        DaemonCommand::Lock { repo } => Request::Lock { repo: repo.clone() },
+        DaemonCommand::Unlock { repo, password_file_opt } => Request::Unlock {
+            repo: repo.clone(),
+            init_key: InitialKey(match password_file_opt {
+                Some(password_file) => cli::read_password_file(password_file)?,
+                None => cli::get_password(false, false)?,
+            }),
+        },
+    };
+
+    match daemon::request(socket, &request)? {
+        Response::Status { repos } => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|dur| dur.as_secs())
+                .unwrap_or(0);
+            println!(
+                "{:<16} {:<9} {:>12} {:>7}  {}",
+                "REPOSITORY", "STATE", "LAST SYNCED", "FILES", "LAST ERROR"
+            );
+            for status in repos {
+                let state = match (status.unlocked, status.syncing) {
+                    (_, true) => "syncing",
+                    (true, false) => "unlocked",
+                    (false, false) => "locked",
+                };
+                let last_synced = match status.last_synced_opt {
+                    Some(secs) => format!("{} ago", format_secs(now.saturating_sub(secs) as f64)),
+                    None => String::from("never"),
+                };
+                println!(
+                    "{:<16} {:<9} {:>12} {:>7}  {}",
+                    status.name,
+                    state,
+                    last_synced,
+                    status.last_num_files,
+                    status.last_error_opt.as_deref().unwrap_or("")
+                );
             }
-        }};
+        }
+        Response::Synced {
+            num_files,
+            src_bytes,
+            dest_bytes,
+            duration_ms,
+        } if !quiet => {
+            let duration = Duration::from_millis(duration_ms);
+            let throughput = (src_bytes as f64) / (duration.as_nanos().max(1) as f64) * 1e9;
+            let sync_stats = SyncStats::new(num_files, 0, src_bytes as f64, dest_bytes as f64, throughput, duration);
+            eprintln!("\n{}", sync_stats);
+        }
+        _ => (),
     }
+    Ok(None)
+}
 
-    //
-    let syncer = Syncer::new(&external_spec, InitialKey(init_key))?;
-    // use macro here because `actions = syncer.$action()?` results in an opaque type, which makes
-    // match arms have incompatible type
-    Ok(match external_spec {
-        SyncerSpecExt::Encrypt { verbose, .. } => handle!(syncer, sync_enc, verbose),
-        SyncerSpecExt::Decrypt { verbose, .. } => handle!(syncer, sync_dec, verbose),
-        SyncerSpecExt::Clean { .. } => todo!(),
+// list every change that a dry run found on stdout, and how many files and bytes each kind of
+// change comes to on stderr, unless there is a `report` to put them in
+fn report_dry_run(changes: &[PlannedChange], report_opt: Option<&Arc<JsonReport>>) {
+    match report_opt {
+        Some(report) => {
+            report.stat("dry_run", true);
+            changes.iter().for_each(|planned| report.planned(planned));
+        }
+        None => changes.iter().for_each(|PlannedChange { change, path, bytes }| {
+            let (adj_value, unit) = adjust_value(*bytes as f64, "B");
+            println!("{:<6} {:>7} {:<2} {}", change.name(), adj_value, unit, path.display());
+        }),
+    }
+
+    for change in Change::all().iter() {
+        let (num_files, num_bytes) = changes
+            .iter()
+            .filter(|planned| planned.change == *change)
+            .fold((0, 0), |(num_files, num_bytes), planned| {
+                (num_files + 1, num_bytes + planned.bytes)
+            });
+        match report_opt {
+            Some(report) => {
+                report.stat(&format!("files_to_{}", change.name()), num_files);
+                report.stat(&format!("bytes_to_{}", change.name()), num_bytes);
+            }
+            // kinds of changes that a subcommand never makes would only be noise
+            None if num_files == 0 => (),
+            None => {
+                let header = match change {
+                    Change::Create => REPORT_HEADER_TO_CREATE,
+                    Change::Update => REPORT_HEADER_TO_UPDATE,
+                    Change::Skip => REPORT_HEADER_TO_SKIP,
+                    Change::Delete => REPORT_HEADER_TO_DELETE,
+                    _ => change.name(),
+                };
+                let (adj_value, unit) = adjust_value(num_bytes as f64, "B");
+                eprintln!(
+                    "{:>32} {:>7} files, {:>7} {}",
+                    format!("{}:", header),
+                    num_files,
+                    adj_value,
+                    unit
+                );
+            }
+        }
+    }
+}
+
+// encrypt or decrypt everything with `syncer`, keeping track of the progress if `verbose` and
+// reporting each file if there is a `report`
+fn sync(
+    syncer: &mut Syncer,
+    verbose: bool,
+    num_removed: usize,
+    report_opt: Option<&Arc<JsonReport>>,
+) -> CsyncResult<RunResult> {
+    if verbose {
+        eprintln!("\nUsing {} threads...", rayon::current_num_threads());
+        syncer.subscribe(Progress::new());
+    }
+    if let Some(report) = report_opt {
+        let report = Arc::clone(report);
+        syncer.subscribe(move |event: &SyncEvent| report.on_event(event));
+    }
+    // only the progress needs to know how much there is up front
+    if verbose {
+        syncer.scan()?;
+    }
+
+    let SyncSummary {
+        num_files,
+        src_bytes,
+        dest_bytes,
+        duration,
+    } = syncer.sync()?;
+
+    let throughput = (src_bytes as f64) / (duration.as_nanos() as f64) * 1e9;
+    Ok(RunResult {
+        sync_stats: SyncStats::new(
+            num_files,
+            num_removed,
+            src_bytes as f64,
+            dest_bytes as f64,
+            throughput,
+            duration,
+        ),
+        syncer_spec: syncer.get_spec(),
     })
 }
 
@@ -9,6 +9,14 @@ pub const BASE32PATH: Encoding = new_encoding! {
 // TODO units for the ones below
 
 pub const REPORT_HEADER_NUM_FILES: &str = "Files synced";
+pub const REPORT_HEADER_NUM_REMOVED: &str = "Files removed";
+pub const REPORT_HEADER_SNAPSHOT_CREATED: &str = "Snapshot created";
+pub const REPORT_HEADER_SNAPSHOTS_REMOVED: &str = "Snapshots removed";
+pub const REPORT_HEADER_CHUNKS_REMOVED: &str = "Chunks removed";
+pub const REPORT_HEADER_TO_CREATE: &str = "Files to create";
+pub const REPORT_HEADER_TO_UPDATE: &str = "Files to update";
+pub const REPORT_HEADER_TO_SKIP: &str = "Files to skip";
+pub const REPORT_HEADER_TO_DELETE: &str = "Files to delete";
 pub const REPORT_HEADER_DATA_READ: &str = "Data read";
 pub const REPORT_HEADER_DATA_WRITTEN: &str = "Data stored";
 pub const REPORT_HEADER_THROUGHPUT: &str = "Throughput";
@@ -16,13 +24,43 @@ pub const REPORT_HEADER_DURATION: &str = "Duration";
 
 pub const DEFAULT_BUFFER_SIZE: usize = 1 << 14; // buffer size in bytes
 pub const DEFAULT_PERM_BITS: u32 = 0o600; // permission bits of files created by `csync`
+pub const DEFAULT_DIR_PERM_BITS: u32 = 0o700; // permission bits of directories created by `csync`
 pub const DEFAULT_SALT: [u8; 512] = [0u8; 512]; //
 pub const DEFAULT_REHASH_OUTPUT_LEN: usize = 512;
 
 pub const DEFAULT_ZSTD_LEVEL: u8 = 3; // same as the `zstd` executable
 pub const DEFAULT_ZSTD_LEVEL_STR: &str = "3"; // same as the `zstd` executable
+pub const MAX_ZSTD_LEVEL: u8 = 19; // highest level that isn't one of `zstd`'s `--ultra` levels
+pub const MAX_SPREAD_DEPTH: u8 = 64; // well within the length of the hash that spread dirs are named by
+pub const MIN_KEY_LEN: usize = 32; // in bytes; the length of the keys that the ciphers take
+pub const MIN_SALT_LEN: u16 = 16; // in bytes; shorter random salts and nonces could repeat
+
+// the rest are the same as the defaults of `csync encrypt`
+pub const DEFAULT_KEY_DERIV_TIME: u16 = 2; // in seconds
+pub const DEFAULT_SALT_LEN: u16 = 512; // in bytes
+pub const DEFAULT_SCRYPT_OUTPUT_LEN: usize = 512; // in bytes
+pub const DEFAULT_SPREAD_DEPTH: u8 = 3;
 
 pub const FILE_SUFFIX: &str = "csync"; // extension for encrypted files
+pub const CHUNK_SUFFIX: &str = "csync-chunk"; // extension for encrypted chunks of files
+pub const SNAPSHOT_SUFFIX: &str = "csync-snapshot"; // extension for encrypted snapshots
+pub const CHUNK_DIRNAME: &str = "csync-chunks"; // where chunks are stored in a `csync` dir
+pub const SNAPSHOT_DIRNAME: &str = "csync-snapshots"; // where snapshots are stored in a `csync` dir
+pub const INDEX_FILENAME: &str = "index.csync-index"; // encrypted index at the root of a `csync` dir
+pub const INDEX_GENERATION_FILENAME: &str = "index.csync-generation"; // generation of the index, unless it's stale
+pub const CSYNCIGNORE_FILENAME: &str = ".csyncignore"; // patterns of what to leave out of the directory it's in
+
+pub const STDIO_PATH: &str = "-"; // stands for stdin or stdout in place of a path
+pub const TAR_BLOCK_SIZE: u64 = 512; // entries of a tar archive are padded to a multiple of this
+pub const DEFAULT_STREAM_NAME: &str = "stdin"; // name of a stream that is encrypted without `--name`
+
+pub const PROGRESS_RATE_WINDOW_SECS: u64 = 5; // the throughput shown while syncing is of the last this many seconds
+pub const PROGRESS_PLAIN_INTERVAL_SECS: u64 = 10; // how often progress is printed when stderr isn't a terminal
+
+pub const DEFAULT_DEBOUNCE_MS_STR: &str = "500"; // how long `csync watch` waits for changes to settle
+pub const WATCH_MAX_DELAY_SECS: u64 = 30; // how long `csync watch` keeps collecting changes before syncing them
+pub const DAEMON_TABLE: &str = "csyncd"; // repositories that `csyncd` keeps in sync are tables under this one
+pub const DAEMON_SOCKET_FILENAME: &str = "csyncd.sock"; // what `csyncd` listens on, under `$XDG_RUNTIME_DIR`
 
 #[cfg(test)]
 mod tests {
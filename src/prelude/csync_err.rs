@@ -13,11 +13,17 @@ pub type CsyncResult<T> = Result<T, CsyncErr>;
 ///
 /// Each variant results in a unique exit code, which can be used for testing.
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
 pub enum CsyncErr {
     AuthenticationFail,                    // checksum verification failed for this file
     CommandLineArgumentConflict(String),   //
+    ConfigInvalid(String),                 // the config file couldn't be read, or has no such profile
     ControlFlow,                           //
+    DaemonRequestFailed(String),           // `csyncd` couldn't do what it was asked to
+    DaemonSocketNotPrivate(PathBuf),       // other users could reach `csyncd` through this socket
+    DaemonUnreachable(PathBuf),            // nothing is listening on this socket
     DecryptionOutdirIsNonempty(PathBuf),   // when decrypting, outdir must be empty
+    DestinationThroughSymlink(PathBuf),    // decrypting would write through this symlink under the outdir
     HashSpecConflict,                      //
     IncrementalEncryptionDisabledForNow,   //
     MetadataLoadFailed(String),            // couldn't load this metadata file
@@ -26,10 +32,17 @@ pub enum CsyncErr {
     OutdirIsNotDir(PathBuf),               // ...  decrypting ...
     PasswordConfirmationFail,              //
     PathContainsInvalidUtf8Bytes(PathBuf), //
+    PathNotFoundInCsyncDir(PathBuf),       // no ciphertext corresponds to this plaintext path
     SerdeFailed,                           //
+    SnapshotNotFound(u64),                 // no snapshot was created at this time
     SourceDoesNotExist(PathBuf),           //
     SourceDoesNotHaveFilename(PathBuf),    //
     SourceEqOutdir(PathBuf),               //
+    StorageFailed(String),                 // the storage of the `csync` dir couldn't be accessed
+    StreamNameMismatch(PathBuf),           // the `csync` dir holds a stream of another name
+    UnsupportedArchiveEntry(PathBuf),      // tar entry that isn't a file, dir or symlink under the archive
+    UnsupportedFormatVersion(u8),          // a ciphertext written by a newer `csync`
+    UnsupportedOperation(String),          // a syncer or action was asked for something it can't do
 }
 
 ///
@@ -41,8 +54,13 @@ impl CsyncErr {
         match self {
             AuthenticationFail => 32,
             CommandLineArgumentConflict(_) => 33,
+            ConfigInvalid(_) => 53,
             ControlFlow => 34,
+            DaemonRequestFailed(_) => 55,
+            DaemonSocketNotPrivate(_) => 59,
+            DaemonUnreachable(_) => 54,
             DecryptionOutdirIsNonempty(_) => 35,
+            DestinationThroughSymlink(_) => 60,
             HashSpecConflict => 36,
             IncrementalEncryptionDisabledForNow => 37,
             MetadataLoadFailed(_) => 39,
@@ -51,10 +69,52 @@ impl CsyncErr {
             OutdirIsNotDir(_) => 42,
             PasswordConfirmationFail => 43,
             PathContainsInvalidUtf8Bytes(_) => 44,
+            PathNotFoundInCsyncDir(_) => 49,
             SerdeFailed => 45,
+            SnapshotNotFound(_) => 50,
             SourceDoesNotExist(_) => 46,
             SourceDoesNotHaveFilename(_) => 47,
             SourceEqOutdir(_) => 48,
+            StorageFailed(_) => 51,
+            StreamNameMismatch(_) => 57,
+            UnsupportedArchiveEntry(_) => 52,
+            UnsupportedFormatVersion(_) => 56,
+            UnsupportedOperation(_) => 58,
+        }
+    }
+
+    /// Name of the variant, which classifies the error in machine-readable output along with
+    /// `exit_code`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AuthenticationFail => "AuthenticationFail",
+            CommandLineArgumentConflict(_) => "CommandLineArgumentConflict",
+            ConfigInvalid(_) => "ConfigInvalid",
+            ControlFlow => "ControlFlow",
+            DaemonRequestFailed(_) => "DaemonRequestFailed",
+            DaemonSocketNotPrivate(_) => "DaemonSocketNotPrivate",
+            DaemonUnreachable(_) => "DaemonUnreachable",
+            DecryptionOutdirIsNonempty(_) => "DecryptionOutdirIsNonempty",
+            DestinationThroughSymlink(_) => "DestinationThroughSymlink",
+            HashSpecConflict => "HashSpecConflict",
+            IncrementalEncryptionDisabledForNow => "IncrementalEncryptionDisabledForNow",
+            MetadataLoadFailed(_) => "MetadataLoadFailed",
+            NonFatalReportFailed => "NonFatalReportFailed",
+            Other(_) => "Other",
+            OutdirIsNotDir(_) => "OutdirIsNotDir",
+            PasswordConfirmationFail => "PasswordConfirmationFail",
+            PathContainsInvalidUtf8Bytes(_) => "PathContainsInvalidUtf8Bytes",
+            PathNotFoundInCsyncDir(_) => "PathNotFoundInCsyncDir",
+            SerdeFailed => "SerdeFailed",
+            SnapshotNotFound(_) => "SnapshotNotFound",
+            SourceDoesNotExist(_) => "SourceDoesNotExist",
+            SourceDoesNotHaveFilename(_) => "SourceDoesNotHaveFilename",
+            SourceEqOutdir(_) => "SourceEqOutdir",
+            StorageFailed(_) => "StorageFailed",
+            StreamNameMismatch(_) => "StreamNameMismatch",
+            UnsupportedArchiveEntry(_) => "UnsupportedArchiveEntry",
+            UnsupportedFormatVersion(_) => "UnsupportedFormatVersion",
+            UnsupportedOperation(_) => "UnsupportedOperation",
         }
     }
 }
@@ -73,8 +133,13 @@ impl Display for CsyncErr {
         match self {
             AuthenticationFail => w!("Authentication failed."),
             CommandLineArgumentConflict(message) => w!("Conflicting command line args provided: `{}`", message),
+            ConfigInvalid(message) => w!("Could not use the config file: {}", message),
             ControlFlow => w!("Control flow"),
+            DaemonRequestFailed(message) => w!("`csyncd` could not do that: {}", message),
+            DaemonSocketNotPrivate(pbuf) => w!("Other users could reach `csyncd` through {:?}, so it is not used", pbuf),
+            DaemonUnreachable(pbuf) => w!("Could not reach `csyncd` on {:?}; is it running?", pbuf),
             DecryptionOutdirIsNonempty(pbuf) => w!("Cannot decrypt to `--outdir={:?}` because it is not empty.", pbuf),
+            DestinationThroughSymlink(pbuf) => w!("{:?} is a symlink, so nothing is decrypted to a path under it", pbuf),
             HashSpecConflict => w!("Cannot specify the strength of the hash with params AND time."),
             IncrementalEncryptionDisabledForNow => w!("Incremental Encryption is disabled for now"),
             MetadataLoadFailed(message) => w!("Could not load metadata file, password is prbs wrong: {}", message),
@@ -83,21 +148,39 @@ impl Display for CsyncErr {
             OutdirIsNotDir(pbuf) => w!("Cannot use `--outdir={:?}` because it is not a directory.", pbuf),
             PasswordConfirmationFail => w!("Could not confirm password"),
             PathContainsInvalidUtf8Bytes(pbuf) => w!("{:?} could not be converted to a string", pbuf),
+            PathNotFoundInCsyncDir(pbuf) => w!("{:?} could not be found in the `csync` directory", pbuf),
             SerdeFailed => w!("(De)serialization failed"),
+            SnapshotNotFound(id) => w!("Snapshot {} could not be found in the `csync` directory", id),
             SourceDoesNotExist(pbuf) => w!("Source does not exist under {:?}", pbuf),
             SourceDoesNotHaveFilename(pbuf) => w!("Source {:?} does not have a basename", pbuf),
             SourceEqOutdir(pbuf) => w!("Source cannot also be outdir: {:?}", pbuf),
+            StorageFailed(message) => w!("Could not access the storage of the `csync` directory: {}", message),
+            StreamNameMismatch(pbuf) => w!(
+                "This `csync` directory holds the stream {:?}, which only a stream of the same name can replace",
+                pbuf
+            ),
+            UnsupportedArchiveEntry(pbuf) => w!(
+                "{:?} in the archive is not a file, directory or symlink under it, or is under a symlink",
+                pbuf
+            ),
+            UnsupportedFormatVersion(version) => w!(
+                "A ciphertext is in format version {}, which is newer than this `csync` can read",
+                version
+            ),
+            UnsupportedOperation(message) => w!("Unsupported operation: {}", message),
         }
     }
 }
 
 ///
+#[doc(hidden)]
+#[macro_export]
 macro_rules! csync_err {
     ( $variant:ident ) => {
-        Err(CsyncErr::$variant)
+        Err($crate::CsyncErr::$variant)
     };
     ( $variant:ident, $( $field:expr ),* ) => {
-        Err(CsyncErr::$variant($( $field ),*))
+        Err($crate::CsyncErr::$variant($( $field ),*))
     };
 }
 
@@ -143,8 +226,13 @@ mod tests {
         let variants = vec![
             AuthenticationFail,
             CommandLineArgumentConflict(String::new()),
+            ConfigInvalid(String::new()),
             ControlFlow,
+            DaemonRequestFailed(String::new()),
+            DaemonSocketNotPrivate(PathBuf::from("")),
+            DaemonUnreachable(PathBuf::from("")),
             DecryptionOutdirIsNonempty(PathBuf::from("")),
+            DestinationThroughSymlink(PathBuf::from("")),
             HashSpecConflict,
             IncrementalEncryptionDisabledForNow,
             MetadataLoadFailed("".to_string()),
@@ -153,10 +241,17 @@ mod tests {
             OutdirIsNotDir(PathBuf::from("")),
             PasswordConfirmationFail,
             PathContainsInvalidUtf8Bytes(PathBuf::from("")),
+            PathNotFoundInCsyncDir(PathBuf::from("")),
             SerdeFailed,
+            SnapshotNotFound(0),
             SourceDoesNotExist(PathBuf::from("")),
             SourceDoesNotHaveFilename(PathBuf::from("")),
             SourceEqOutdir(PathBuf::from("")),
+            StorageFailed(String::new()),
+            StreamNameMismatch(PathBuf::from("")),
+            UnsupportedArchiveEntry(PathBuf::from("")),
+            UnsupportedFormatVersion(0),
+            UnsupportedOperation(String::new()),
         ];
         // write it like this so that compilation fails when adding a new variant
         let exit_code_vec: Vec<_> = variants
@@ -164,8 +259,13 @@ mod tests {
             .filter(|v| match v {
                 AuthenticationFail => true,
                 CommandLineArgumentConflict(_) => true,
+                ConfigInvalid(_) => true,
                 ControlFlow => true,
+                DaemonRequestFailed(_) => true,
+                DaemonSocketNotPrivate(_) => true,
+                DaemonUnreachable(_) => true,
                 DecryptionOutdirIsNonempty(_) => true,
+                DestinationThroughSymlink(_) => true,
                 HashSpecConflict => true,
                 IncrementalEncryptionDisabledForNow => true,
                 MetadataLoadFailed(_) => true,
@@ -174,10 +274,17 @@ mod tests {
                 OutdirIsNotDir(_) => true,
                 PasswordConfirmationFail => true,
                 PathContainsInvalidUtf8Bytes(_) => true,
+                PathNotFoundInCsyncDir(_) => true,
                 SerdeFailed => true,
+                SnapshotNotFound(_) => true,
                 SourceDoesNotExist(_) => true,
                 SourceDoesNotHaveFilename(_) => true,
                 SourceEqOutdir(_) => true,
+                StorageFailed(_) => true,
+                StreamNameMismatch(_) => true,
+                UnsupportedArchiveEntry(_) => true,
+                UnsupportedFormatVersion(_) => true,
+                UnsupportedOperation(_) => true,
             })
             .map(CsyncErr::exit_code)
             .collect();
@@ -188,5 +295,12 @@ mod tests {
         exit_code_set.into_iter().for_each(|exit_code| {
             assert!(0 < exit_code && exit_code < 256);
         });
+
+        // each kind is the name of its variant
+        variants.iter().for_each(|variant| {
+            assert!(format!("{:?}", variant).starts_with(variant.kind()));
+        });
+        let kind_set: HashSet<_> = variants.iter().map(CsyncErr::kind).collect();
+        assert_eq!(variants.len(), kind_set.len());
     }
 }
@@ -1,4 +1,4 @@
-mod consts;
+pub mod consts;
 #[macro_use]
 mod csync_err;
 
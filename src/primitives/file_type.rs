@@ -1,6 +1,10 @@
+use serde::{Deserialize, Serialize};
+
 ///
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum FileType {
     Dir,
     File,
+    // only imported from tar archives, as sources are walked following symlinks
+    Symlink,
 }
@@ -75,7 +75,7 @@ pub fn rand_u64(seed_opt: Option<&CryptoSecureBytes>, min: u64, max: u64) -> u64
 ///
 /// # Examples
 ///
-/// ```
+/// ```ignore
 /// // generate `n` number of bytes in range `[0, 256)`
 /// let bytes = rng!(n);
 ///
@@ -1,14 +1,28 @@
 use crate::{
+    encoder::{crypt_encoder::*, openssl::*},
+    hasher::KeyPurpose,
     prelude::*,
     secure_vec::*,
-    specs::{cipher_spec::*, key_deriv_spec::*, rehash_spec::*},
+    specs::{cipher_spec::*, file_meta_spec::*, key_deriv_spec::*, rehash_spec::*},
+    util::*,
 };
 use serde::{Deserialize, Serialize};
 
 ///
 #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct ActionSpec {
-    cipher_spec: CipherSpec, // included because each action gets a random salt
+    chunked: bool,                            // if true, the plaintext is a list of chunks
+    cipher_spec: CipherSpec,                  // included because each action gets a random salt
+    file_meta: Option<(CipherSpec, Vec<u8>)>, // encrypted `FileMetaSpec` and the spec used to encrypt it
+    unix_mode: Option<u32>,
+    rehash_spec: RehashSpec,
+    rehash: RehashedKey,
+}
+
+// `ActionSpec` as it was serialized in format version 0, before files had metadata or chunks
+#[derive(Clone, Debug, Deserialize)]
+struct ActionSpecV0 {
+    cipher_spec: CipherSpec,
     unix_mode: Option<u32>,
     rehash_spec: RehashSpec,
     rehash: RehashedKey,
@@ -20,8 +34,17 @@ impl ActionSpec {
     ///
     /// 1. `cipher_spec`:
     /// 1. `unix_mode`:
+    /// 1. `file_meta_opt`: metadata of the plaintext, which will be stored encrypted
+    /// 1. `chunked`: whether the contents are stored as separate chunks
     /// 1. `key_hash`:
-    pub fn new(cipher_spec: &CipherSpec, salt_len: u16, unix_mode: Option<u32>, key_hash: &DerivedKey) -> CsyncResult<Self> {
+    pub fn new(
+        cipher_spec: &CipherSpec,
+        salt_len: u16,
+        unix_mode: Option<u32>,
+        file_meta_opt: Option<&FileMetaSpec>,
+        chunked: bool,
+        key_hash: &DerivedKey,
+    ) -> CsyncResult<Self> {
         // rehash the key hash
         let rehash_spec: RehashSpec = RehashSpec::with_key_deriv_spec(KeyDerivSpec::Scrypt {
             log_n: 12,
@@ -32,15 +55,74 @@ impl ActionSpec {
         });
         let rehash = rehash_spec.rehash(key_hash)?;
 
+        // encrypted with a salt of its own, so that no salt is used twice with the same key
+        let file_meta = match file_meta_opt {
+            Some(file_meta) => {
+                let file_meta_cipher_spec = cipher_spec.resalt(salt_len);
+                let ciphertext = crypt_file_meta(&file_meta_cipher_spec, serialize(file_meta)?.as_ref(), key_hash, true)?;
+                Some((file_meta_cipher_spec, ciphertext))
+            }
+            None => None,
+        };
+
         //
         Ok(Self {
+            chunked,
             cipher_spec: cipher_spec.clone(),
+            file_meta,
             rehash,
             rehash_spec,
             unix_mode,
         })
     }
 
+    /// # Parameters
+    ///
+    /// 1. `bytes`: a serialized action spec
+    /// 1. `format_version`: format version of the ciphertext whose header `bytes` is from
+    ///
+    /// # Returns
+    ///
+    /// The deserialized action spec; specs from before format version 1 have neither metadata nor
+    /// chunks.
+    pub fn from_bytes(bytes: &[u8], format_version: u8) -> CsyncResult<Self> {
+        match format_version {
+            0 => {
+                let spec: ActionSpecV0 = deserialize(bytes)?;
+                Ok(Self {
+                    chunked: false,
+                    cipher_spec: spec.cipher_spec,
+                    file_meta: None,
+                    rehash: spec.rehash,
+                    rehash_spec: spec.rehash_spec,
+                    unix_mode: spec.unix_mode,
+                })
+            }
+            _ => deserialize(bytes),
+        }
+    }
+
+    /// # Returns
+    ///
+    /// The decrypted metadata of the plaintext, if it was recorded.
+    ///
+    /// Note that this is not authenticated on its own; a ciphertext is only authenticated once
+    /// it has been decrypted in its entirety.
+    pub fn get_file_meta(&self, key_hash: &DerivedKey) -> CsyncResult<Option<FileMetaSpec>> {
+        match &self.file_meta {
+            Some((file_meta_cipher_spec, ciphertext)) => {
+                let plaintext = crypt_file_meta(file_meta_cipher_spec, ciphertext, key_hash, false)?;
+                deserialize(&plaintext).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    #[inline]
+    pub fn is_chunked(&self) -> bool {
+        self.chunked
+    }
+
     #[inline]
     pub fn get_unix_mode(&self) -> Option<u32> {
         self.unix_mode.clone()
@@ -51,3 +133,22 @@ impl ActionSpec {
         self.rehash_spec.verify(&self.rehash, key_hash)
     }
 }
+
+// en/decrypt the serialized `FileMetaSpec` with the subkey of `key_hash` for metadata
+fn crypt_file_meta(cipher_spec: &CipherSpec, data: &[u8], key_hash: &DerivedKey, encrypt: bool) -> CsyncResult<Vec<u8>> {
+    let key_hash = key_hash.subkey(KeyPurpose::FileMeta);
+    match (cipher_spec, encrypt) {
+        (CipherSpec::Aes256Cbc { init_vec }, true) => {
+            compose_encoders!(data, Aes256CbcEnc => (&key_hash.0, Some(init_vec)))?.as_vec()
+        }
+        (CipherSpec::Aes256Cbc { init_vec }, false) => {
+            compose_encoders!(data, Aes256CbcDec => (&key_hash.0, Some(init_vec)))?.as_vec()
+        }
+        (CipherSpec::ChaCha20 { init_vec }, true) => {
+            compose_encoders!(data, ChaCha20Enc => (&key_hash.0, Some(init_vec)))?.as_vec()
+        }
+        (CipherSpec::ChaCha20 { init_vec }, false) => {
+            compose_encoders!(data, ChaCha20Dec => (&key_hash.0, Some(init_vec)))?.as_vec()
+        }
+    }
+}
@@ -16,6 +16,20 @@ macro_rules! rand_salt {
 
 ///
 impl CipherSpec {
+    /// `Aes256Cbc` with a random `init_vec` that is `salt_len` bytes long.
+    pub fn aes256cbc(salt_len: u16) -> Self {
+        Self::Aes256Cbc {
+            init_vec: rand_salt!(salt_len),
+        }
+    }
+
+    /// `ChaCha20` with a random `init_vec` that is `salt_len` bytes long.
+    pub fn chacha20(salt_len: u16) -> Self {
+        Self::ChaCha20 {
+            init_vec: rand_salt!(salt_len),
+        }
+    }
+
     ///
     pub fn resalt(&self, salt_len: u16) -> Self {
         match self {
@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+
+/// Specifies what to do when decrypting a file to a path that already exists.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum ConflictSpec {
+    /// Refuse to decrypt to a non-empty directory in the first place.
+    Fail,
+    /// Replace whatever is at the path, after renaming it to the path with `backup_suffix`
+    /// appended if one is given.
+    Overwrite { backup_suffix: Option<String> },
+    /// Leave whatever is at the path untouched.
+    SkipExisting,
+    /// Same as `Overwrite`, but only if the existing file was modified before the ciphertext was.
+    NewerWins { backup_suffix: Option<String> },
+    /// Same as `Overwrite`, but only if the existing file differs in size or modified time from
+    /// the plaintext at the time of its encryption.
+    Incremental { backup_suffix: Option<String> },
+}
+
+impl ConflictSpec {
+    /// # Returns
+    ///
+    /// The suffix to append to existing paths before they get overwritten, if any.
+    #[inline]
+    pub fn backup_suffix(&self) -> Option<&str> {
+        match self {
+            Self::Overwrite { backup_suffix } | Self::NewerWins { backup_suffix } | Self::Incremental { backup_suffix } => {
+                backup_suffix.as_deref()
+            }
+            Self::Fail | Self::SkipExisting => None,
+        }
+    }
+}
+
+impl Default for ConflictSpec {
+    #[inline]
+    fn default() -> Self {
+        Self::Fail
+    }
+}
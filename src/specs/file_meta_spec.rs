@@ -0,0 +1,100 @@
+use crate::{
+    encoder::{crypt_encoder::*, hmac::*},
+    fs_util::*,
+    hasher::KeyPurpose,
+    prelude::*,
+    secure_vec::*,
+};
+use serde::{Deserialize, Serialize};
+use std::{fs::Metadata, path::Path, time::SystemTime};
+
+/// Metadata of a plaintext file, recorded at the time of its encryption.
+///
+/// This gets stored encrypted in the `ActionSpec` of each ciphertext, so that the plaintext can
+/// be compared against files on disk without decrypting the whole ciphertext.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct FileMetaSpec {
+    checksum: Option<Vec<u8>>, // keyed hash of the contents; only computed for regular files
+    len: u64,
+    modified: SystemTime,
+}
+
+impl FileMetaSpec {
+    /// # Parameters
+    ///
+    /// 1. `len`: size of the plaintext in bytes
+    /// 1. `modified`: modified time of the plaintext
+    #[inline]
+    pub fn new(len: u64, modified: SystemTime) -> Self {
+        Self {
+            checksum: None,
+            len,
+            modified,
+        }
+    }
+
+    /// # Parameters
+    ///
+    /// 1. `meta`: metadata of the plaintext file
+    pub fn from_metadata(meta: &Metadata) -> CsyncResult<Self> {
+        Ok(Self {
+            checksum: None,
+            len: meta.len(),
+            modified: meta.modified()?,
+        })
+    }
+
+    /// # Parameters
+    ///
+    /// 1. `path`: the plaintext file that `self` describes
+    /// 1. `key_hash`: the derived key whose checksum subkey keys the `HMAC-SHA512` of the contents
+    ///    of `path`
+    ///
+    /// # Returns
+    ///
+    /// `self`, but with the keyed hash of the contents of `path`.
+    pub fn with_checksum(self, path: &Path, key_hash: &DerivedKey) -> CsyncResult<Self> {
+        let checksum_key = key_hash.subkey(KeyPurpose::Checksum);
+        let mut hmac_encoder = compose_encoders!(fopen_r(path)?, HmacEncoder => (&checksum_key.0, None))?;
+        hmac_encoder.read_all_to(&mut std::io::sink())?;
+
+        Ok(Self {
+            checksum: hmac_encoder.get_result().map(|checksum| checksum.0.unsecure().to_vec()),
+            ..self
+        })
+    }
+
+    #[inline]
+    pub fn get_len(&self) -> u64 {
+        self.len
+    }
+
+    #[inline]
+    pub fn get_modified(&self) -> SystemTime {
+        self.modified
+    }
+
+    /// # Returns
+    ///
+    /// `true` if `meta` has the same size and modified time as what this spec recorded.
+    #[inline]
+    pub fn matches(&self, meta: &Metadata) -> bool {
+        meta.len() == self.len && meta.modified().ok() == Some(self.modified)
+    }
+
+    /// # Parameters
+    ///
+    /// 1. `recorded`: the spec that was recorded when the same file was last encrypted
+    ///
+    /// # Returns
+    ///
+    /// `true` if the file described by `self` can be assumed to be the same as when `recorded`
+    /// was. Contents are compared if both specs have checksums, otherwise size and modified time
+    /// are.
+    pub fn is_unchanged_from(&self, recorded: &FileMetaSpec) -> bool {
+        match (&self.checksum, &recorded.checksum) {
+            (Some(checksum), Some(recorded_checksum)) => self.len == recorded.len && checksum == recorded_checksum,
+            _ => self.len == recorded.len && self.modified == recorded.modified,
+        }
+    }
+}
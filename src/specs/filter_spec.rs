@@ -0,0 +1,205 @@
+use crate::prelude::*;
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Walk, WalkBuilder,
+};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Specifies which files and directories under the source get encrypted.
+///
+/// Patterns follow the syntax of `.gitignore` files, and are relative to the source. On top of
+/// them, `.csyncignore` files anywhere under the source leave out whatever they match, the same way
+/// `.gitignore` files do.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct FilterSpec {
+    pub excludes: Vec<String>, // patterns of what to leave out
+    pub git_ignore: bool,      // also leave out whatever `.gitignore` files match
+    pub includes: Vec<String>, // patterns of what to keep even if `excludes` match it
+    pub one_file_system: bool, // don't descend into other file systems than that of the source
+}
+
+impl FilterSpec {
+    /// # Parameters
+    ///
+    /// 1. `root`: the source that the patterns are relative to
+    ///
+    /// # Returns
+    ///
+    /// Matcher of the paths under `root` that `excludes` match and `includes` don't.
+    pub fn matcher(&self, root: &Path) -> CsyncResult<Gitignore> {
+        let mut builder = GitignoreBuilder::new(root);
+        // later patterns take precedence, like they do in a `.gitignore`
+        for exclude in &self.excludes {
+            builder.add_line(None, exclude)?;
+        }
+        for include in &self.includes {
+            builder.add_line(None, &format!("!{}", include))?;
+        }
+        Ok(builder.build()?)
+    }
+
+    /// # Parameters
+    ///
+    /// 1. `root`: the source to walk
+    ///
+    /// # Returns
+    ///
+    /// Every path under `root` that isn't filtered out, following symlinks. Directories that are
+    /// filtered out are not descended into.
+    pub fn walk(&self, root: &Path) -> CsyncResult<Walk> {
+        self.walk_filtered(root, None)
+    }
+
+    /// Like `walk`, but only for the paths that are one of `within`, under one of them, or above one
+    /// of them. Nothing else is descended into, which makes this as cheap as `within` is small.
+    ///
+    /// # Parameters
+    ///
+    /// 1. `root`: the source to walk
+    /// 1. `within`: paths under `root`, joined onto it like the paths that get walked are
+    pub fn walk_within(&self, root: &Path, within: Arc<BTreeSet<PathBuf>>) -> CsyncResult<Walk> {
+        self.walk_filtered(root, Some(within))
+    }
+
+    //
+    fn walk_filtered(&self, root: &Path, within_opt: Option<Arc<BTreeSet<PathBuf>>>) -> CsyncResult<Walk> {
+        let matcher = self.matcher(root)?;
+        Ok(WalkBuilder::new(root)
+            .standard_filters(false)
+            .follow_links(true)
+            .git_ignore(self.git_ignore)
+            .require_git(false)
+            .same_file_system(self.one_file_system)
+            .add_custom_ignore_filename(CSYNCIGNORE_FILENAME)
+            // the root itself is never filtered out
+            .filter_entry(move |entry| {
+                let is_dir = entry.file_type().is_some_and(|file_type| file_type.is_dir());
+                let is_wanted = match &within_opt {
+                    Some(within) => is_within(entry.path(), within),
+                    None => true,
+                };
+                entry.depth() == 0 || (is_wanted && !matcher.matched(entry.path(), is_dir).is_ignore())
+            })
+            .build())
+    }
+}
+
+// whether `path` is one of `within`, under one of them, or above one of them; paths under `path`
+// sort right after it
+fn is_within(path: &Path, within: &BTreeSet<PathBuf>) -> bool {
+    path.ancestors().any(|ancestor| within.contains(ancestor))
+        || within
+            .range(path.to_path_buf()..)
+            .next()
+            .is_some_and(|next| next.starts_with(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::BTreeSet, fs, path::PathBuf};
+
+    // paths under `root` that `spec` walks, relative to `root`
+    fn walked(spec: &FilterSpec, root: &Path) -> BTreeSet<PathBuf> {
+        spec.walk(root)
+            .unwrap()
+            .map(|entry| entry.unwrap().path().strip_prefix(root).unwrap().to_path_buf())
+            .filter(|path| !path.as_os_str().is_empty())
+            .collect()
+    }
+
+    // a tree with a build directory, logs, and ignore files of both kinds
+    fn tree() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        for dir in ["src", "target/debug", "logs"].iter() {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+        }
+        for file in [
+            "src/main.rs",
+            "src/gen.rs",
+            "target/debug/csync",
+            "logs/a.log",
+            "logs/keep.log",
+        ]
+        .iter()
+        {
+            fs::write(root.path().join(file), file).unwrap();
+        }
+        fs::write(root.path().join("src").join(CSYNCIGNORE_FILENAME), "gen.rs\n").unwrap();
+        fs::write(root.path().join(".gitignore"), "logs/\n").unwrap();
+        root
+    }
+
+    fn paths(paths: &[&str]) -> BTreeSet<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn csyncignore_files_are_always_honoured() {
+        let root = tree();
+        assert_eq!(
+            walked(&FilterSpec::default(), root.path()),
+            paths(&[
+                ".gitignore",
+                "logs",
+                "logs/a.log",
+                "logs/keep.log",
+                "src",
+                "src/.csyncignore",
+                "src/main.rs",
+                "target",
+                "target/debug",
+                "target/debug/csync",
+            ])
+        );
+    }
+
+    #[test]
+    fn excluded_dirs_are_pruned() {
+        let root = tree();
+        let spec = FilterSpec {
+            excludes: vec![String::from("target/"), String::from("*.log")],
+            includes: vec![String::from("keep.log")],
+            ..FilterSpec::default()
+        };
+        assert_eq!(
+            walked(&spec, root.path()),
+            paths(&[
+                ".gitignore",
+                "logs",
+                "logs/keep.log",
+                "src",
+                "src/.csyncignore",
+                "src/main.rs"
+            ])
+        );
+    }
+
+    #[test]
+    fn gitignore_files_are_optional() {
+        let root = tree();
+        let spec = FilterSpec {
+            git_ignore: true,
+            ..FilterSpec::default()
+        };
+        assert!(!walked(&spec, root.path()).contains(Path::new("logs")));
+        assert!(walked(&FilterSpec::default(), root.path()).contains(Path::new("logs")));
+    }
+
+    #[test]
+    fn walks_within_only_descend_where_asked() {
+        let root = tree();
+        let within: BTreeSet<_> = ["src/main.rs", "logs"].iter().map(|path| root.path().join(path)).collect();
+        let walked: BTreeSet<_> = FilterSpec::default()
+            .walk_within(root.path(), Arc::new(within))
+            .unwrap()
+            .map(|entry| entry.unwrap().path().strip_prefix(root.path()).unwrap().to_path_buf())
+            .filter(|path| !path.as_os_str().is_empty())
+            .collect();
+        assert_eq!(walked, paths(&["logs", "logs/a.log", "logs/keep.log", "src", "src/main.rs"]));
+    }
+}
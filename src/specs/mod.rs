@@ -1,11 +1,17 @@
 pub mod authenticator_spec;
 pub mod cipher_spec;
 pub mod compressor_spec;
+pub mod conflict_spec;
+pub mod file_meta_spec;
+pub mod filter_spec;
 pub mod key_deriv_spec;
 pub mod key_deriv_spec_ext;
 pub mod rehash_spec;
+pub mod retention_spec;
+pub mod storage_spec;
 
 pub mod action_spec;
+pub mod syncer_builder;
 pub mod syncer_spec;
 pub mod syncer_spec_ext;
 
@@ -1,4 +1,4 @@
 pub use crate::specs::{
-    action_spec::*, authenticator_spec::*, cipher_spec::*, compressor_spec::*, key_deriv_spec::*, key_deriv_spec_ext::*,
-    syncer_spec::*, syncer_spec_ext::*,
+    action_spec::*, authenticator_spec::*, cipher_spec::*, compressor_spec::*, conflict_spec::*, file_meta_spec::*,
+    filter_spec::*, key_deriv_spec::*, key_deriv_spec_ext::*, syncer_builder::*, syncer_spec::*, syncer_spec_ext::*,
 };
@@ -22,7 +22,7 @@ impl RehashSpec {
         match &self.rehash(key_hash)? == rehash {
             true => Ok(()),
             false => {
-                eprintln!("rehash spec auth fail");
+                log::debug!("the rehashed key doesn't match the one recorded in the spec");
                 csync_err!(AuthenticationFail)
             }
         }
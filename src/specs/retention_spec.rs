@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+
+// lengths of the periods that snapshots are bucketed into, in milliseconds
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+const WEEK_DAYS: u64 = 7;
+
+/// Specifies which snapshots to keep when cleaning a `csync` dir.
+///
+/// Each rule keeps some snapshots on its own, and a snapshot is kept if any rule keeps it. If no
+/// rule is given at all, every snapshot is kept.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct RetentionSpec {
+    pub keep_daily: Option<usize>, // keep the latest snapshot of each of the last `n` days that have one
+    pub keep_last: Option<usize>,  // keep the latest `n` snapshots
+    pub keep_weekly: Option<usize>, // keep the latest snapshot of each of the last `n` weeks that have one
+}
+
+impl RetentionSpec {
+    /// # Parameters
+    ///
+    /// 1. `created`: creation times of the snapshots, in milliseconds since the unix epoch
+    ///
+    /// # Returns
+    ///
+    /// The creation times of the snapshots to keep.
+    pub fn retained(&self, created: &[u64]) -> HashSet<u64> {
+        // newest first
+        let newest_first: Vec<_> = created.iter().copied().collect::<BTreeSet<_>>().into_iter().rev().collect();
+
+        match (self.keep_last, self.keep_daily, self.keep_weekly) {
+            (None, None, None) => newest_first.into_iter().collect(),
+            (keep_last, keep_daily, keep_weekly) => {
+                // days are counted in UTC; weeks start on mondays, as the epoch was on a thursday
+                let by_day = |millis: &u64| millis / DAY_MILLIS;
+                let by_week = |millis: &u64| (millis / DAY_MILLIS + 3) / WEEK_DAYS;
+
+                newest_first
+                    .iter()
+                    .take(keep_last.unwrap_or(0))
+                    .copied()
+                    .chain(latest_per_period(&newest_first, keep_daily.unwrap_or(0), by_day))
+                    .chain(latest_per_period(&newest_first, keep_weekly.unwrap_or(0), by_week))
+                    .collect()
+            }
+        }
+    }
+}
+
+// the newest of `newest_first` in each of the `n` latest periods that `period_of` puts them in
+fn latest_per_period<F>(newest_first: &[u64], n: usize, period_of: F) -> Vec<u64>
+where
+    F: Fn(&u64) -> u64,
+{
+    let mut periods = HashSet::new();
+    newest_first
+        .iter()
+        .filter(|millis| periods.insert(period_of(millis)))
+        .take(n)
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR_MILLIS: u64 = 60 * 60 * 1000;
+
+    // a monday, at midnight UTC
+    const MONDAY: u64 = 1_600_041_600_000;
+
+    fn retained(spec: &RetentionSpec, created: &[u64]) -> BTreeSet<u64> {
+        spec.retained(created).into_iter().collect()
+    }
+
+    #[test]
+    fn keeps_everything_without_rules() {
+        let created = [MONDAY, MONDAY + HOUR_MILLIS, MONDAY + DAY_MILLIS];
+        assert_eq!(
+            retained(&RetentionSpec::default(), &created),
+            created.iter().copied().collect()
+        );
+    }
+
+    #[test]
+    fn keep_last() {
+        let spec = RetentionSpec {
+            keep_last: Some(2),
+            ..RetentionSpec::default()
+        };
+        let created = [MONDAY + 2, MONDAY, MONDAY + 1];
+        assert_eq!(retained(&spec, &created), vec![MONDAY + 1, MONDAY + 2].into_iter().collect());
+    }
+
+    #[test]
+    fn keep_daily_keeps_the_latest_of_each_day() {
+        let spec = RetentionSpec {
+            keep_daily: Some(2),
+            ..RetentionSpec::default()
+        };
+        let created = [
+            MONDAY,
+            MONDAY + HOUR_MILLIS,
+            MONDAY + DAY_MILLIS,
+            MONDAY + 3 * DAY_MILLIS,
+            MONDAY + 3 * DAY_MILLIS + HOUR_MILLIS,
+        ];
+        assert_eq!(
+            retained(&spec, &created),
+            vec![MONDAY + DAY_MILLIS, MONDAY + 3 * DAY_MILLIS + HOUR_MILLIS]
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn keep_weekly_starts_weeks_on_mondays() {
+        let spec = RetentionSpec {
+            keep_weekly: Some(2),
+            ..RetentionSpec::default()
+        };
+        // the sunday before `MONDAY` is in the week before it
+        let sunday = MONDAY - HOUR_MILLIS;
+        let created = [sunday - WEEK_DAYS * DAY_MILLIS, sunday, MONDAY, MONDAY + 6 * DAY_MILLIS];
+        assert_eq!(
+            retained(&spec, &created),
+            vec![sunday, MONDAY + 6 * DAY_MILLIS].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn rules_are_combined() {
+        let spec = RetentionSpec {
+            keep_daily: Some(1),
+            keep_last: Some(1),
+            keep_weekly: Some(2),
+        };
+        let created = [MONDAY - WEEK_DAYS * DAY_MILLIS, MONDAY, MONDAY + HOUR_MILLIS];
+        assert_eq!(
+            retained(&spec, &created),
+            vec![MONDAY - WEEK_DAYS * DAY_MILLIS, MONDAY + HOUR_MILLIS]
+                .into_iter()
+                .collect()
+        );
+    }
+}
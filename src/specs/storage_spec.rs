@@ -0,0 +1,242 @@
+use crate::{prelude::*, util::*};
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+const ARCHIVE_EXTENSION: &str = "csar";
+const S3_SCHEME: &str = "s3://";
+const SFTP_SCHEME: &str = "sftp://";
+
+/// Specifies where a `csync` dir is stored, which is parsed from the location given on the command
+/// line in place of a directory.
+///
+/// 1. `*.csar`: a single-file archive, see `ArchiveStorage`
+/// 1. `s3://bucket/prefix`: objects under `prefix` in an S3-compatible bucket, see `S3Storage`
+/// 1. `sftp://[user@]host[:port]/path`: files under `path` on an SSH server, see `SftpStorage`;
+///    `path` is absolute, unless it starts with `/~/` in which case it's relative to the home dir
+/// 1. anything else: a directory on the local filesystem
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum StorageSpec {
+    Archive {
+        path: PathBuf,
+    },
+    Dir {
+        path: PathBuf,
+    },
+    S3 {
+        bucket: String,
+        prefix: String, // no leading or trailing `/`
+    },
+    Sftp {
+        host: String,
+        path: String, // relative to the home dir unless it starts with `/`, and has no trailing `/`
+        port: Option<u16>,
+        user: Option<String>,
+    },
+}
+
+impl StorageSpec {
+    /// # Parameters
+    ///
+    /// 1. `location`: where the `csync` dir is, as given on the command line
+    pub fn from_location(location: &Path) -> CsyncResult<Self> {
+        match path_as_string(location) {
+            Some(location_str) if location_str.starts_with(S3_SCHEME) => {
+                let mut parts = location_str[S3_SCHEME.len()..].splitn(2, '/');
+                match parts.next() {
+                    Some(bucket) if !bucket.is_empty() => Ok(StorageSpec::S3 {
+                        bucket: bucket.to_string(),
+                        prefix: parts.next().unwrap_or("").trim_matches('/').to_string(),
+                    }),
+                    _ => csync_err!(StorageFailed, format!("{:?} does not name a bucket", location)),
+                }
+            }
+            Some(location_str) if location_str.starts_with(SFTP_SCHEME) => {
+                let rest = &location_str[SFTP_SCHEME.len()..];
+                let (authority, path) = match rest.find('/') {
+                    Some(i) => rest.split_at(i),
+                    None => (rest, ""),
+                };
+
+                // the path is kept as is, but `url` takes care of the rest, like IPv6 addresses
+                let url = match url::Url::parse(&format!("{}{}", SFTP_SCHEME, authority)) {
+                    Ok(url) if url.host_str().is_some_and(|host| !host.is_empty()) => url,
+                    _ => csync_err!(StorageFailed, format!("{:?} does not name a host", location))?,
+                };
+                Ok(StorageSpec::Sftp {
+                    host: csync_unwrap_opt!(url.host_str()).to_string(),
+                    path: match path.starts_with("/~/") || path == "/~" {
+                        true => path[2..].trim_matches('/').to_string(),
+                        false => match path.trim_end_matches('/') {
+                            "" => String::from("/"),
+                            path => path.to_string(),
+                        },
+                    },
+                    port: url.port(),
+                    user: Some(url.username()).filter(|user| !user.is_empty()).map(String::from),
+                })
+            }
+            _ => Ok(match location.extension().and_then(OsStr::to_str) {
+                Some(ARCHIVE_EXTENSION) => StorageSpec::Archive {
+                    path: location.to_path_buf(),
+                },
+                _ => StorageSpec::Dir {
+                    path: location.to_path_buf(),
+                },
+            }),
+        }
+    }
+
+    /// `true` if the `csync` dir is a directory on the local filesystem.
+    #[inline]
+    pub fn is_dir(&self) -> bool {
+        match self {
+            StorageSpec::Dir { .. } => true,
+            StorageSpec::Archive { .. } | StorageSpec::S3 { .. } | StorageSpec::Sftp { .. } => false,
+        }
+    }
+
+    /// `true` if the `csync` dir is stored somewhere other than the local filesystem.
+    #[inline]
+    pub fn is_remote(&self) -> bool {
+        match self {
+            StorageSpec::Archive { .. } | StorageSpec::Dir { .. } => false,
+            StorageSpec::S3 { .. } | StorageSpec::Sftp { .. } => true,
+        }
+    }
+
+    /// # Returns
+    ///
+    /// The location of the `csync` dir in a canonical form, which is what gets recorded in its
+    /// metadata. Directories have to exist to be canonicalized.
+    pub fn canonical_location(&self) -> CsyncResult<PathBuf> {
+        match self {
+            // only the directory that an archive is in has to exist
+            StorageSpec::Archive { path } => {
+                let parent = match csync_unwrap_opt!(path.parent()) {
+                    parent if parent == Path::new("") => Path::new("."),
+                    parent => parent,
+                };
+                Ok(parent.canonicalize()?.join(csync_unwrap_opt!(path.file_name())))
+            }
+            StorageSpec::Dir { path } => Ok(path.canonicalize()?),
+            StorageSpec::S3 { bucket, prefix } => Ok(match prefix.is_empty() {
+                true => PathBuf::from(format!("{}{}", S3_SCHEME, bucket)),
+                false => PathBuf::from(format!("{}{}/{}", S3_SCHEME, bucket, prefix)),
+            }),
+            StorageSpec::Sftp { host, path, port, user } => {
+                let user_at = user.as_ref().map(|user| format!("{}@", user)).unwrap_or_default();
+                let colon_port = port.map(|port| format!(":{}", port)).unwrap_or_default();
+                let path = match path.starts_with('/') {
+                    true => path.clone(),
+                    false => format!("/~/{}", path).trim_end_matches('/').to_string(),
+                };
+                Ok(PathBuf::from(format!(
+                    "{}{}{}{}{}",
+                    SFTP_SCHEME, user_at, host, colon_port, path
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn s3_locations() {
+        let spec = StorageSpec::from_location(Path::new("s3://backups/hosts/a/")).unwrap();
+        assert_eq!(
+            spec,
+            StorageSpec::S3 {
+                bucket: String::from("backups"),
+                prefix: String::from("hosts/a"),
+            }
+        );
+        assert!(spec.is_remote());
+        assert_eq!(spec.canonical_location().unwrap(), PathBuf::from("s3://backups/hosts/a"));
+
+        let spec = StorageSpec::from_location(Path::new("s3://backups")).unwrap();
+        assert_eq!(spec.canonical_location().unwrap(), PathBuf::from("s3://backups"));
+
+        assert!(StorageSpec::from_location(Path::new("s3://")).is_err());
+        assert!(StorageSpec::from_location(Path::new("s3:///prefix")).is_err());
+    }
+
+    #[test]
+    fn sftp_locations() {
+        let spec = StorageSpec::from_location(Path::new("sftp://backup@central:2222/srv/csync/")).unwrap();
+        assert_eq!(
+            spec,
+            StorageSpec::Sftp {
+                host: String::from("central"),
+                path: String::from("/srv/csync"),
+                port: Some(2222),
+                user: Some(String::from("backup")),
+            }
+        );
+        assert!(spec.is_remote());
+        assert_eq!(
+            spec.canonical_location().unwrap(),
+            PathBuf::from("sftp://backup@central:2222/srv/csync")
+        );
+
+        let spec = StorageSpec::from_location(Path::new("sftp://central/~/csync")).unwrap();
+        assert_eq!(
+            spec,
+            StorageSpec::Sftp {
+                host: String::from("central"),
+                path: String::from("csync"),
+                port: None,
+                user: None,
+            }
+        );
+        assert_eq!(spec.canonical_location().unwrap(), PathBuf::from("sftp://central/~/csync"));
+
+        [
+            ("sftp://central", "sftp://central/"),
+            ("sftp://central/~", "sftp://central/~"),
+        ]
+        .iter()
+        .for_each(|(location, canonical)| {
+            let spec = StorageSpec::from_location(Path::new(location)).unwrap();
+            assert_eq!(spec.canonical_location().unwrap(), PathBuf::from(canonical));
+        });
+
+        assert!(StorageSpec::from_location(Path::new("sftp://")).is_err());
+        assert!(StorageSpec::from_location(Path::new("sftp:///srv/csync")).is_err());
+    }
+
+    #[test]
+    fn archive_locations() {
+        let dir = tmpdir!().unwrap();
+        let path = dir.path().join("backup.csar");
+        let spec = StorageSpec::from_location(&path).unwrap();
+        assert_eq!(spec, StorageSpec::Archive { path: path.clone() });
+        assert!(!spec.is_dir());
+        assert!(!spec.is_remote());
+        assert_eq!(
+            spec.canonical_location().unwrap(),
+            dir.path().canonicalize().unwrap().join("backup.csar")
+        );
+    }
+
+    #[test]
+    fn other_locations_are_dirs() {
+        ["out", "/tmp/out", "s3:/out", "./s3://out", "sftp:/out", "out.csar.1"]
+            .iter()
+            .for_each(|location| {
+                let spec = StorageSpec::from_location(Path::new(location)).unwrap();
+                assert_eq!(
+                    spec,
+                    StorageSpec::Dir {
+                        path: PathBuf::from(location)
+                    }
+                );
+                assert!(spec.is_dir());
+                assert!(!spec.is_remote());
+            });
+    }
+}
@@ -0,0 +1,449 @@
+use crate::{
+    prelude::*,
+    specs::{
+        authenticator_spec::*,
+        cipher_spec::*,
+        compressor_spec::*,
+        conflict_spec::*,
+        filter_spec::*,
+        key_deriv_spec_ext::*,
+        storage_spec::*,
+        syncer_spec::{SourceKind, SourceRoot},
+        syncer_spec_ext::*,
+    },
+};
+use std::path::{Path, PathBuf};
+
+/// Typed construction of a `SyncerSpecExt`, for using `csync` as a library.
+///
+/// Everything that isn't set has the same default as the corresponding flag of `csync encrypt` or
+/// `csync decrypt`, except that nothing is reported unless `verbose` is set. `build` checks the spec
+/// the same way that the command line is checked, so that any combination that `Syncer` can't work
+/// with is a `CsyncErr` rather than a panic.
+///
+/// # Examples
+///
+/// ```no_run
+/// use csync::{CipherSpec, KeyDerivSpecExt, SyncerBuilder};
+///
+/// let spec = SyncerBuilder::encrypt("docs", "docs.csync")
+///     .cipher(CipherSpec::aes256cbc(512))
+///     .kdf(KeyDerivSpecExt::ScryptByTime {
+///         time: 4,
+///         output_len: 512,
+///         salt_len: 512,
+///     })
+///     .spread_depth(2)
+///     .build()?;
+/// # Ok::<(), csync::CsyncErr>(())
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct SyncerBuilder;
+
+impl SyncerBuilder {
+    /// Start building a spec that encrypts `source` into the `csync` dir `out_dir`.
+    pub fn encrypt<P1, P2>(source: P1, out_dir: P2) -> EncryptBuilder
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        EncryptBuilder {
+            auth_spec: AuthenticatorSpec::default(),
+            checksum: false,
+            chunked: false,
+            cipher_spec: CipherSpec::chacha20(DEFAULT_SALT_LEN),
+            compressor_spec: CompressorSpec::Zstd {
+                level: DEFAULT_ZSTD_LEVEL,
+            },
+            filter_spec: FilterSpec::default(),
+            index: false,
+            kd_spec_ext: KeyDerivSpecExt::ScryptByTime {
+                time: DEFAULT_KEY_DERIV_TIME,
+                output_len: DEFAULT_SCRYPT_OUTPUT_LEN,
+                salt_len: DEFAULT_SALT_LEN,
+            },
+            out_dir: out_dir.as_ref().to_path_buf(),
+            roots: Vec::new(),
+            snapshot: false,
+            source: source.as_ref().to_path_buf(),
+            spread_depth: DEFAULT_SPREAD_DEPTH,
+            verbose: false,
+            volume_size_opt: None,
+            salt_len: DEFAULT_SALT_LEN,
+        }
+    }
+
+    /// Start building a spec that decrypts the `csync` dir `source` into `out_dir`.
+    pub fn decrypt<P1, P2>(source: P1, out_dir: P2) -> DecryptBuilder
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        DecryptBuilder {
+            conflict_spec: ConflictSpec::default(),
+            delete: false,
+            roots: Vec::new(),
+            snapshot_opt: None,
+            out_dir: out_dir.as_ref().to_path_buf(),
+            source: source.as_ref().to_path_buf(),
+            verbose: false,
+        }
+    }
+}
+
+/// Builds a `SyncerSpecExt::Encrypt`; see `SyncerBuilder::encrypt`.
+#[derive(Clone, Debug)]
+pub struct EncryptBuilder {
+    auth_spec: AuthenticatorSpec,
+    checksum: bool,
+    chunked: bool,
+    cipher_spec: CipherSpec,
+    compressor_spec: CompressorSpec,
+    filter_spec: FilterSpec,
+    index: bool,
+    kd_spec_ext: KeyDerivSpecExt,
+    //
+    out_dir: PathBuf,
+    roots: Vec<SourceRoot>,
+    snapshot: bool,
+    source: PathBuf,
+    //
+    spread_depth: u8,
+    verbose: bool,
+    volume_size_opt: Option<u64>,
+    //
+    salt_len: u16,
+}
+
+impl EncryptBuilder {
+    /// Authentication algorithm to use; `--auth`.
+    pub fn auth(mut self, auth_spec: AuthenticatorSpec) -> Self {
+        self.auth_spec = auth_spec;
+        self
+    }
+
+    /// Compare the contents of files rather than their metadata; `--checksum`.
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Store files as deduplicated chunks; `--chunked`.
+    pub fn chunked(mut self, chunked: bool) -> Self {
+        self.chunked = chunked;
+        self
+    }
+
+    /// Encryption algorithm to use; `--cipher`. Its `init_vec` is replaced by a random one that is
+    /// `salt_len` bytes long, like every other salt.
+    pub fn cipher(mut self, cipher_spec: CipherSpec) -> Self {
+        self.cipher_spec = cipher_spec;
+        self
+    }
+
+    /// Compression algorithm to use; `--compressor` and `--zstd-level`.
+    pub fn compressor(mut self, compressor_spec: CompressorSpec) -> Self {
+        self.compressor_spec = compressor_spec;
+        self
+    }
+
+    /// Which files under the source to leave out; `--exclude`, `--include` and the like.
+    pub fn filter(mut self, filter_spec: FilterSpec) -> Self {
+        self.filter_spec = filter_spec;
+        self
+    }
+
+    /// Keep an encrypted index of the `csync` dir; `--index`.
+    pub fn index(mut self, index: bool) -> Self {
+        self.index = index;
+        self
+    }
+
+    /// Key-derivation algorithm to use, and its parameters; `--key-deriv-alg` and the like.
+    pub fn kdf(mut self, kd_spec_ext: KeyDerivSpecExt) -> Self {
+        self.kd_spec_ext = kd_spec_ext;
+        self
+    }
+
+    /// Also encrypt `path` as if it were next to the source and named `name`; `--root`. Can be
+    /// called more than once.
+    pub fn root<S, P>(mut self, name: S, path: P) -> Self
+    where
+        S: Into<String>,
+        P: AsRef<Path>,
+    {
+        self.roots.push(SourceRoot {
+            name: name.into(),
+            path: path.as_ref().to_path_buf(),
+        });
+        self
+    }
+
+    /// Record a snapshot after encrypting; `--snapshot`.
+    pub fn snapshot(mut self, snapshot: bool) -> Self {
+        self.snapshot = snapshot;
+        self
+    }
+
+    /// Spread the encrypted files over `64^spread_depth` directories; `--spread-depth`.
+    pub fn spread_depth(mut self, spread_depth: u8) -> Self {
+        self.spread_depth = spread_depth;
+        self
+    }
+
+    /// Report what is being done to stderr; the opposite of `--quiet`.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Split a `.csar` archive into volumes of this many bytes; `--volume-size`.
+    pub fn volume_size(mut self, volume_size: u64) -> Self {
+        self.volume_size_opt = Some(volume_size);
+        self
+    }
+
+    /// Length of every salt, in bytes; `--salt-len`.
+    pub fn salt_len(mut self, salt_len: u16) -> Self {
+        self.salt_len = salt_len;
+        self
+    }
+
+    /// # Returns
+    ///
+    /// The spec that was built, or the first reason that it can't be used.
+    pub fn build(self) -> CsyncResult<SyncerSpecExt> {
+        if !self.source.exists() {
+            csync_err!(SourceDoesNotExist, self.source.clone())?;
+        }
+        check_roots("root", &self.source, &self.roots)?;
+        check_encryption_params(self.spread_depth, &self.kd_spec_ext, self.salt_len)?;
+        match (check_encryption_out_dir(&self.out_dir)?, self.volume_size_opt) {
+            (StorageSpec::Archive { .. }, _) | (_, None) => (),
+            _ => csync_err!(
+                CommandLineArgumentConflict,
+                "`volume_size` can only be used when encrypting to a `.csar` archive".to_string()
+            )?,
+        }
+        match self.compressor_spec {
+            CompressorSpec::Zstd { level } if !(1..=MAX_ZSTD_LEVEL).contains(&level) => csync_err!(
+                CommandLineArgumentConflict,
+                format!("`zstd` levels range from 1 to {}, which {} doesn't", MAX_ZSTD_LEVEL, level)
+            )?,
+            CompressorSpec::Zstd { .. } => (),
+        }
+        match self.filter_spec.matcher(&self.source) {
+            Ok(_) => (),
+            Err(err) => csync_err!(
+                CommandLineArgumentConflict,
+                format!("`filter` takes patterns like those in `.gitignore` files: {}", err)
+            )?,
+        }
+
+        Ok(SyncerSpecExt::Encrypt {
+            auth_spec: self.auth_spec,
+            checksum: self.checksum,
+            chunked: self.chunked,
+            cipher_spec: self.cipher_spec.resalt(self.salt_len),
+            compressor_spec: self.compressor_spec,
+            filter_spec: self.filter_spec,
+            index: self.index,
+            kd_spec_ext: self.kd_spec_ext,
+            out_dir: self.out_dir,
+            roots: self.roots,
+            snapshot: self.snapshot,
+            source: self.source,
+            source_kind: SourceKind::Path,
+            spread_depth: self.spread_depth,
+            verbose: self.verbose,
+            volume_size_opt: self.volume_size_opt,
+            salt_len: self.salt_len,
+        })
+    }
+}
+
+/// Builds a `SyncerSpecExt::Decrypt`; see `SyncerBuilder::decrypt`.
+#[derive(Clone, Debug)]
+pub struct DecryptBuilder {
+    conflict_spec: ConflictSpec,
+    delete: bool,
+    roots: Vec<String>,
+    snapshot_opt: Option<u64>,
+    //
+    out_dir: PathBuf,
+    source: PathBuf,
+    verbose: bool,
+}
+
+impl DecryptBuilder {
+    /// What to do with files that are already in `out_dir`; `--overwrite` and the like.
+    pub fn conflict(mut self, conflict_spec: ConflictSpec) -> Self {
+        self.conflict_spec = conflict_spec;
+        self
+    }
+
+    /// Remove files under the decrypted roots that aren't in the `csync` dir; `--delete`.
+    pub fn delete(mut self, delete: bool) -> Self {
+        self.delete = delete;
+        self
+    }
+
+    /// Only decrypt the root named `name`; `--root`. Can be called more than once.
+    pub fn root<S>(mut self, name: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.roots.push(name.into());
+        self
+    }
+
+    /// Restore the snapshot with this id rather than the latest files; `--snapshot`.
+    pub fn snapshot(mut self, id: u64) -> Self {
+        self.snapshot_opt = Some(id);
+        self
+    }
+
+    /// Report what is being done to stderr; the opposite of `--quiet`.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// # Returns
+    ///
+    /// The spec that was built, or the first reason that it can't be used.
+    pub fn build(self) -> CsyncResult<SyncerSpecExt> {
+        // `csync` dirs in remote storage are checked once they are opened
+        if !StorageSpec::from_location(&self.source)?.is_remote() && !self.source.exists() {
+            csync_err!(SourceDoesNotExist, self.source.clone())?;
+        }
+        for name in &self.roots {
+            check_name("root", name)?;
+        }
+        check_decryption_out_dir(&self.out_dir, &self.conflict_spec)?;
+
+        Ok(SyncerSpecExt::Decrypt {
+            conflict_spec: self.conflict_spec,
+            delete: self.delete,
+            roots: self.roots,
+            snapshot_opt: self.snapshot_opt,
+            out_dir: self.out_dir,
+            source: self.source,
+            verbose: self.verbose,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clargs::Opts;
+    use std::convert::TryFrom;
+    use structopt::StructOpt;
+
+    // cipher specs only differ by their random `init_vec`s
+    fn without_init_vec(mut spec: SyncerSpecExt) -> SyncerSpecExt {
+        match &mut spec {
+            SyncerSpecExt::Encrypt {
+                cipher_spec, salt_len, ..
+            } => {
+                match cipher_spec {
+                    CipherSpec::ChaCha20 { init_vec } => assert_eq!(init_vec.0.unsecure().len(), *salt_len as usize),
+                    _ => panic!("{:?} isn't `chacha20`", cipher_spec),
+                }
+                *cipher_spec = CipherSpec::chacha20(0);
+            }
+            _ => panic!("{:?} doesn't encrypt", spec),
+        };
+        spec
+    }
+
+    #[test]
+    fn same_defaults_as_cli() {
+        let source = tmpdir!().unwrap();
+        let out_dir = tmpdir!().unwrap();
+        let (source, out_dir) = (source.path(), out_dir.path());
+
+        let opts = Opts::from_iter(&["csync", "encrypt", source.to_str().unwrap(), "-o", out_dir.to_str().unwrap()]);
+        let from_cli = SyncerSpecExt::try_from(&opts).unwrap();
+        let built = SyncerBuilder::encrypt(source, out_dir).verbose(true).build().unwrap();
+        assert_eq!(without_init_vec(built), without_init_vec(from_cli));
+
+        let opts = Opts::from_iter(&["csync", "decrypt", source.to_str().unwrap(), "-o", out_dir.to_str().unwrap()]);
+        let from_cli = SyncerSpecExt::try_from(&opts).unwrap();
+        let built = SyncerBuilder::decrypt(source, out_dir).verbose(true).build().unwrap();
+        assert_eq!(built, from_cli);
+    }
+
+    #[test]
+    fn invalid_encryption_specs() {
+        let source = tmpdir!().unwrap();
+        let out_dir = tmpdir!().unwrap();
+        let (source, out_dir) = (source.path(), out_dir.path());
+        let source_name = source.file_name().unwrap().to_str().unwrap();
+
+        let exit_code = |builder: EncryptBuilder| builder.build().unwrap_err().exit_code();
+        let conflict = CsyncErr::CommandLineArgumentConflict(String::new()).exit_code();
+
+        let builder = SyncerBuilder::encrypt(source, out_dir);
+        assert_eq!(
+            exit_code(SyncerBuilder::encrypt(source.join("Kz3q"), out_dir)),
+            CsyncErr::SourceDoesNotExist(PathBuf::new()).exit_code()
+        );
+        vec![
+            builder.clone().compressor(CompressorSpec::Zstd { level: 0 }),
+            builder.clone().compressor(CompressorSpec::Zstd { level: 20 }),
+            builder.clone().volume_size(1 << 20),
+            builder.clone().root(source_name, out_dir),
+            builder.clone().root("Wn5d", out_dir).root("Wn5d", out_dir),
+            builder.clone().root("Wn5d/Hc8e", out_dir),
+            builder.clone().spread_depth(200),
+            builder.clone().salt_len(0),
+            builder.clone().kdf(KeyDerivSpecExt::Pbkdf2ByParams {
+                alg_opt: None,
+                num_iter: 0,
+                salt_len: 16,
+            }),
+            builder.clone().kdf(KeyDerivSpecExt::Pbkdf2ByTime {
+                alg_opt: None,
+                time: 0,
+                salt_len: 16,
+            }),
+            builder.clone().kdf(KeyDerivSpecExt::ScryptByTime {
+                time: 1,
+                output_len: 0,
+                salt_len: 16,
+            }),
+            builder.clone().kdf(KeyDerivSpecExt::ScryptByParams {
+                log_n: 1,
+                r: 1,
+                p: 1,
+                output_len: 512,
+                salt_len: 4,
+            }),
+        ]
+        .into_iter()
+        .for_each(|builder| assert_eq!(exit_code(builder), conflict));
+    }
+
+    #[test]
+    fn invalid_decryption_specs() {
+        let source = tmpdir!().unwrap();
+        let out_dir = tmpdir!().unwrap();
+        let (source, out_dir) = (source.path(), out_dir.path());
+        std::fs::write(out_dir.join("Tq6x"), "Tq6x").unwrap();
+
+        assert_eq!(
+            SyncerBuilder::decrypt(source, out_dir).build().unwrap_err(),
+            CsyncErr::DecryptionOutdirIsNonempty(out_dir.to_path_buf())
+        );
+        assert_eq!(
+            SyncerBuilder::decrypt(source, out_dir.join("Tq6x")).build().unwrap_err(),
+            CsyncErr::OutdirIsNotDir(out_dir.join("Tq6x"))
+        );
+        assert!(SyncerBuilder::decrypt(source, out_dir)
+            .conflict(ConflictSpec::SkipExisting)
+            .build()
+            .is_ok());
+    }
+}
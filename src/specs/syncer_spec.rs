@@ -1,20 +1,42 @@
 use crate::{
     prelude::*,
     secure_vec::*,
-    specs::{authenticator_spec::*, cipher_spec::*, compressor_spec::*, key_deriv_spec::*, syncer_spec_ext::*},
+    specs::{
+        authenticator_spec::*, cipher_spec::*, compressor_spec::*, conflict_spec::*, filter_spec::*, key_deriv_spec::*,
+        retention_spec::*, storage_spec::*, syncer_spec_ext::*,
+    },
 };
 use serde::{Deserialize, Serialize};
 use std::{fmt::Debug, path::PathBuf};
 
 ///
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[non_exhaustive]
 pub enum SyncerSpec {
+    #[non_exhaustive]
     Encrypt {
         //
         authenticator_spec: AuthenticatorSpec,
         cipher_spec: CipherSpec,
         compressor_spec: CompressorSpec,
         key_deriv_spec: KeyDerivSpec,
+        // an option of this run only, so it isn't stored along with the rest of the spec
+        #[serde(skip)]
+        checksum: bool,
+        #[serde(skip)]
+        chunked: bool,
+        #[serde(skip)]
+        filter_spec: FilterSpec,
+        #[serde(skip)]
+        index: bool,
+        #[serde(skip)]
+        roots: Vec<SourceRoot>,
+        #[serde(skip)]
+        snapshot: bool,
+        #[serde(skip)]
+        source_kind: SourceKind,
+        #[serde(skip)]
+        volume_size_opt: Option<u64>,
         //
         out_dir: PathBuf,
         source: PathBuf,
@@ -25,6 +47,7 @@ pub enum SyncerSpec {
         //
         salt_len: u16,
     },
+    #[non_exhaustive]
     Decrypt {
         //
         authenticator_spec: AuthenticatorSpec,
@@ -32,6 +55,11 @@ pub enum SyncerSpec {
         compressor_spec: CompressorSpec,
         key_deriv_spec: KeyDerivSpec,
         //
+        conflict_spec: ConflictSpec,
+        delete: bool,
+        roots: Vec<String>,
+        snapshot_opt: Option<u64>,
+        //
         out_dir: PathBuf,
         source: PathBuf,
         //
@@ -41,12 +69,46 @@ pub enum SyncerSpec {
         //
         salt_len: u16,
     },
+    #[non_exhaustive]
     Clean {
+        retention_spec: RetentionSpec,
         source: PathBuf,
         verbose: bool,
     },
 }
 
+/// Where the plaintexts of an encryption are read from.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum SourceKind {
+    /// files and directories under the source
+    #[default]
+    Path,
+    /// a single stream from stdin, stored as a file at the source
+    Stream,
+    /// the entries of a tar archive, stored under the source
+    Tar,
+}
+
+/// A directory or file that is encrypted along with the source, as if it were next to the source
+/// and named `name`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SourceRoot {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl SourceRoot {
+    /// # Returns
+    ///
+    /// `self`, but with a canonical `path`.
+    pub fn canonicalize(&self) -> CsyncResult<Self> {
+        Ok(Self {
+            name: self.name.clone(),
+            path: self.path.canonicalize()?,
+        })
+    }
+}
+
 impl std::convert::TryFrom<&SyncerSpecExt> for SyncerSpec {
     type Error = CsyncErr;
 
@@ -55,31 +117,55 @@ impl std::convert::TryFrom<&SyncerSpecExt> for SyncerSpec {
         match spec_ext {
             SyncerSpecExt::Encrypt {
                 auth_spec,
+                checksum,
+                chunked,
                 cipher_spec,
                 compressor_spec,
+                filter_spec,
+                index,
                 kd_spec_ext,
                 out_dir,
+                roots,
+                snapshot,
                 source,
+                source_kind,
                 spread_depth,
                 verbose,
+                volume_size_opt,
                 salt_len,
             } => {
                 let key_deriv_spec = KeyDerivSpec::try_from(kd_spec_ext)?;
 
                 Ok(SyncerSpec::Encrypt {
                     authenticator_spec: auth_spec.clone(),
+                    checksum: *checksum,
+                    // snapshots reference files by their chunks
+                    chunked: *chunked || *snapshot,
                     cipher_spec: cipher_spec.clone(),
                     compressor_spec: compressor_spec.clone(),
+                    filter_spec: filter_spec.clone(),
+                    index: *index,
                     init_salt: CryptoSecureBytes(rng!(*salt_len as usize).0),
                     key_deriv_spec,
-                    out_dir: out_dir.canonicalize()?,
-                    source: source.canonicalize()?,
+                    out_dir: StorageSpec::from_location(out_dir)?.canonical_location()?,
+                    roots: roots.iter().map(SourceRoot::canonicalize).collect::<CsyncResult<_>>()?,
+                    snapshot: *snapshot,
+                    // streams and archives have no path of their own to canonicalize
+                    source: match source_kind {
+                        SourceKind::Path => source.canonicalize()?,
+                        SourceKind::Stream | SourceKind::Tar => source.to_path_buf(),
+                    },
+                    source_kind: *source_kind,
                     spread_depth: *spread_depth,
                     verbose: *verbose,
+                    volume_size_opt: *volume_size_opt,
                     salt_len: *salt_len,
                 })
             }
-            SyncerSpecExt::Decrypt { .. } | SyncerSpecExt::Clean { .. } => {
+            SyncerSpecExt::Decrypt { .. }
+            | SyncerSpecExt::Cat { .. }
+            | SyncerSpecExt::ExportTar { .. }
+            | SyncerSpecExt::Clean { .. } => {
                 panic!("`SyncerSpecExt` -> `SyncerSpec` conversion should only be used for encrypting")
             }
         }
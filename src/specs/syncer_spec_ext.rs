@@ -1,34 +1,81 @@
 use crate::{
     clargs::*,
     prelude::*,
-    secure_vec::*,
-    specs::{authenticator_spec::*, cipher_spec::*, compressor_spec::*, key_deriv_spec_ext::*},
+    specs::{
+        authenticator_spec::*,
+        cipher_spec::*,
+        compressor_spec::*,
+        conflict_spec::*,
+        filter_spec::*,
+        key_deriv_spec_ext::*,
+        retention_spec::*,
+        storage_spec::*,
+        syncer_spec::{SourceKind, SourceRoot},
+    },
+};
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    fmt::Debug,
+    path::{Component, Path, PathBuf},
 };
-use std::{fmt::Debug, path::PathBuf};
 
 ///
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
 pub enum SyncerSpecExt {
+    #[non_exhaustive]
     Encrypt {
         auth_spec: AuthenticatorSpec,
+        checksum: bool,
+        chunked: bool,
         cipher_spec: CipherSpec,
         compressor_spec: CompressorSpec,
+        filter_spec: FilterSpec,
+        index: bool,
         kd_spec_ext: KeyDerivSpecExt,
         //
         out_dir: PathBuf,
+        roots: Vec<SourceRoot>,
+        snapshot: bool,
         source: PathBuf,
+        source_kind: SourceKind,
         //
         spread_depth: u8,
         verbose: bool,
+        volume_size_opt: Option<u64>,
         //
         salt_len: u16,
     },
+    #[non_exhaustive]
     Decrypt {
+        conflict_spec: ConflictSpec,
+        delete: bool,
+        roots: Vec<String>,
+        snapshot_opt: Option<u64>,
+        //
         out_dir: PathBuf,
         source: PathBuf,
         verbose: bool,
     },
+    // `path_opt` is `None` when the source of the `csync` dir itself is decrypted, which has to be a
+    // single file or stream
+    #[non_exhaustive]
+    Cat {
+        path_opt: Option<PathBuf>,
+        source: PathBuf,
+        verbose: bool,
+    },
+    // the archive is written to stdout if `out_opt` is `None`
+    #[non_exhaustive]
+    ExportTar {
+        out_opt: Option<PathBuf>,
+        source: PathBuf,
+        verbose: bool,
+    },
+    #[non_exhaustive]
     Clean {
+        retention_spec: RetentionSpec,
         source: PathBuf,
         verbose: bool,
     },
@@ -41,152 +88,643 @@ impl std::convert::TryFrom<&Opts> for SyncerSpecExt {
     //
     fn try_from(opts: &Opts) -> Result<Self, Self::Error> {
         match opts {
-            Opts::Encrypt { source, .. } | Opts::Decrypt { source, .. } | Opts::Clean { source, .. } => {
-                //
+            Opts::Encrypt {
+                name_opt, roots, source, ..
+            } => {
+                match (source == Path::new(STDIO_PATH), name_opt) {
+                    (true, Some(name)) => check_name("--name", name)?,
+                    (true, None) => (),
+                    (false, Some(_)) => csync_err!(
+                        CommandLineArgumentConflict,
+                        "`--name` can only be used when the source is `-`".to_string()
+                    )?,
+                    //
+                    (false, None) => {
+                        if !source.exists() {
+                            csync_err!(SourceDoesNotExist, source.clone())?;
+                        }
+                    }
+                };
+                check_roots("--root", source, &extract_roots(roots))?;
+            }
+            Opts::Watch { source, .. } => {
                 if !source.exists() {
                     csync_err!(SourceDoesNotExist, source.clone())?;
                 }
+                if !source.is_dir() {
+                    csync_err!(
+                        CommandLineArgumentConflict,
+                        "`csync watch` can only watch a directory".to_string()
+                    )?;
+                }
+            }
+            Opts::ImportTar { archive, name_opt, .. } => {
+                if let Some(name) = name_opt {
+                    check_name("--name", name)?;
+                }
+                if archive != Path::new(STDIO_PATH) && !archive.exists() {
+                    csync_err!(SourceDoesNotExist, archive.clone())?;
+                }
+            }
+            // which asks `csyncd` to sync rather than syncing anything itself
+            Opts::Daemon { .. } => csync_err!(
+                CommandLineArgumentConflict,
+                "`csync daemon` has nothing to sync on its own".to_string()
+            )?,
+            // `csync` dirs in remote storage are checked once they are opened
+            Opts::Decrypt { source, .. }
+            | Opts::Cat { source, .. }
+            | Opts::ExportTar { source, .. }
+            | Opts::Clean { source, .. } => {
+                if !StorageSpec::from_location(source)?.is_remote() && !source.exists() {
+                    csync_err!(SourceDoesNotExist, source.clone())?;
+                }
             }
         };
         match opts {
-            Opts::Encrypt { out_dir, .. } => {
-                if out_dir.exists() {
-                    match out_dir.is_dir() {
-                        true => match std::fs::read_dir(out_dir).map(Iterator::count)? {
-                            0 => (),
-                            _ => csync_err!(IncrementalEncryptionDisabledForNow)?,
-                        },
-                        false => csync_err!(OutdirIsNotDir, out_dir.to_path_buf())?,
-                    }
+            // a nonempty `out_dir` is allowed if it's a `csync` dir, which can only be checked once
+            // the password is known
+            Opts::Encrypt {
+                dry_run,
+                index,
+                out_dir,
+                roots,
+                snapshot,
+                source,
+                volume_size_opt,
+                ..
+            } => {
+                // reading a stream consumes it, which leaves nothing to encrypt afterwards
+                if source == Path::new(STDIO_PATH) && *dry_run {
+                    csync_err!(
+                        CommandLineArgumentConflict,
+                        "`--dry-run` cannot be used when the source is `-`".to_string()
+                    )?;
+                }
+                // neither can record a stream, whose metadata isn't known until it has been read
+                if source == Path::new(STDIO_PATH) && (*index || *snapshot) {
+                    csync_err!(
+                        CommandLineArgumentConflict,
+                        "`--index` and `--snapshot` cannot be used when the source is `-`".to_string()
+                    )?;
+                }
+                // a `csync` dir that holds a stream can't hold anything else
+                if source == Path::new(STDIO_PATH) && !roots.is_empty() {
+                    csync_err!(
+                        CommandLineArgumentConflict,
+                        "`--root` cannot be used when the source is `-`".to_string()
+                    )?;
+                }
+                // nor is there anything in a stream to leave out
+                if source == Path::new(STDIO_PATH) && extract_filter_spec(opts)? != FilterSpec::default() {
+                    csync_err!(
+                        CommandLineArgumentConflict,
+                        "`--exclude` and the other filters cannot be used when the source is `-`".to_string()
+                    )?;
+                }
+                match (check_encryption_out_dir(out_dir)?, volume_size_opt) {
+                    (StorageSpec::Archive { .. }, _) | (_, None) => (),
+                    _ => csync_err!(
+                        CommandLineArgumentConflict,
+                        "`--volume-size` can only be used when encrypting to a `.csar` archive".to_string()
+                    )?,
                 }
             }
-            Opts::Decrypt { out_dir, .. } => {
-                if out_dir.exists() {
-                    match out_dir.is_dir() {
-                        true => match std::fs::read_dir(out_dir).map(Iterator::count)? {
-                            0 => (),
-                            _ => csync_err!(DecryptionOutdirIsNonempty, out_dir.to_path_buf())?,
-                        },
-                        false => csync_err!(OutdirIsNotDir, out_dir.to_path_buf())?,
-                    }
+            Opts::ImportTar { out_dir, .. } => {
+                check_encryption_out_dir(out_dir)?;
+            }
+            // an archive can't be written to again once it's been written
+            Opts::Watch { out_dir, .. } => {
+                if let StorageSpec::Archive { .. } = check_encryption_out_dir(out_dir)? {
+                    csync_err!(
+                        CommandLineArgumentConflict,
+                        "`csync watch` cannot encrypt to a `.csar` archive".to_string()
+                    )?;
+                }
+            }
+            Opts::Decrypt {
+                delete,
+                dry_run,
+                out_dir,
+                output,
+                roots,
+                snapshot_opt,
+                ..
+            } if out_dir == Path::new(STDIO_PATH) => {
+                if *delete || !roots.is_empty() || snapshot_opt.is_some() || extract_conflict_spec(opts)? != ConflictSpec::Fail
+                {
+                    csync_err!(
+                        CommandLineArgumentConflict,
+                        "`--delete`, `--root`, `--snapshot` and conflict policies cannot be used when decrypting to stdout"
+                            .to_string()
+                    )?;
+                }
+                // there is only ever the one file to write
+                if *dry_run {
+                    csync_err!(
+                        CommandLineArgumentConflict,
+                        "`--dry-run` cannot be used when decrypting to stdout".to_string()
+                    )?;
+                }
+                // stdout is reserved for the plaintext
+                if *output != OutputFormat::Human {
+                    csync_err!(
+                        CommandLineArgumentConflict,
+                        "`--output json` and `--output ndjson` cannot be used when decrypting to stdout".to_string()
+                    )?;
+                }
+            }
+            Opts::Decrypt { out_dir, roots, .. } => {
+                for name in roots {
+                    check_name("--root", name)?;
                 }
+                check_decryption_out_dir(out_dir, &extract_conflict_spec(opts)?)?;
             }
-            Opts::Clean { .. } => {}
+            Opts::Cat { .. } | Opts::ExportTar { .. } | Opts::Clean { .. } | Opts::Daemon { .. } => {}
         };
 
         Ok(match opts {
             Opts::Encrypt {
-                auth,
-                cipher,
-                compressor,
-                salt_len,
+                checksum,
+                chunked,
+                encryption_opts,
+                index,
+                name_opt,
                 out_dir,
+                output,
+                roots,
+                snapshot,
                 source,
-                spread_depth,
                 quiet,
-                zstd_level,
+                volume_size_opt,
                 ..
             } => {
                 //
-                let kd_spec_ext = extract_kd_opt(&opts)?;
+                let kd_spec_ext = extract_kd_opt(encryption_opts)?;
+                check_encryption_params(encryption_opts.spread_depth, &kd_spec_ext, encryption_opts.salt_len)?;
+                let (auth_spec, cipher_spec, compressor_spec) = extract_algorithm_specs(encryption_opts)?;
 
-                let auth_spec = match auth.as_str() {
-                    "hmac-sha512" => AuthenticatorSpec::HmacSha512,
-                    _ => todo!(),
-                };
-                let cipher_spec = match cipher.as_str() {
-                    "aes256cbc" => CipherSpec::Aes256Cbc {
-                        init_vec: CryptoSecureBytes(rng!(*salt_len as usize).0),
+                SyncerSpecExt::Encrypt {
+                    auth_spec,
+                    checksum: *checksum,
+                    chunked: *chunked,
+                    cipher_spec,
+                    compressor_spec,
+                    filter_spec: extract_filter_spec(opts)?,
+                    index: *index,
+                    kd_spec_ext,
+                    spread_depth: encryption_opts.spread_depth,
+                    out_dir: out_dir.to_path_buf(),
+                    roots: extract_roots(roots),
+                    snapshot: *snapshot,
+                    // a stream is encrypted as if it were a file at the root, so that it decrypts to
+                    // a file of its name
+                    source: match source == Path::new(STDIO_PATH) {
+                        true => Path::new("/").join(name_opt.as_deref().unwrap_or(DEFAULT_STREAM_NAME)),
+                        false => source.to_path_buf(),
                     },
-                    "chacha20" => CipherSpec::ChaCha20 {
-                        init_vec: CryptoSecureBytes(rng!(*salt_len as usize).0),
+                    source_kind: match source == Path::new(STDIO_PATH) {
+                        true => SourceKind::Stream,
+                        false => SourceKind::Path,
                     },
-                    _ => todo!(),
-                };
-                let compressor_spec = match compressor.as_str() {
-                    "zstd" => CompressorSpec::Zstd { level: *zstd_level },
-                    _ => todo!(),
-                };
+                    // nothing but JSON is reported in the JSON formats
+                    verbose: !*quiet && *output == OutputFormat::Human,
+                    volume_size_opt: *volume_size_opt,
+                    salt_len: encryption_opts.salt_len,
+                }
+            }
+            Opts::Watch {
+                checksum,
+                encryption_opts,
+                out_dir,
+                source,
+                quiet,
+                ..
+            } => {
+                //
+                let kd_spec_ext = extract_kd_opt(encryption_opts)?;
+                check_encryption_params(encryption_opts.spread_depth, &kd_spec_ext, encryption_opts.salt_len)?;
+                let (auth_spec, cipher_spec, compressor_spec) = extract_algorithm_specs(encryption_opts)?;
 
                 SyncerSpecExt::Encrypt {
                     auth_spec,
+                    checksum: *checksum,
+                    chunked: false,
                     cipher_spec,
                     compressor_spec,
+                    filter_spec: extract_filter_spec(opts)?,
+                    // which is what finds the ciphertexts of what was removed
+                    index: true,
                     kd_spec_ext,
-                    spread_depth: *spread_depth,
+                    spread_depth: encryption_opts.spread_depth,
                     out_dir: out_dir.to_path_buf(),
+                    roots: Vec::new(),
+                    snapshot: false,
                     source: source.to_path_buf(),
+                    source_kind: SourceKind::Path,
+                    verbose: !*quiet,
+                    volume_size_opt: None,
+                    salt_len: encryption_opts.salt_len,
+                }
+            }
+            Opts::ImportTar {
+                archive,
+                chunked,
+                encryption_opts,
+                name_opt,
+                out_dir,
+                quiet,
+                ..
+            } => {
+                //
+                let kd_spec_ext = extract_kd_opt(encryption_opts)?;
+                check_encryption_params(encryption_opts.spread_depth, &kd_spec_ext, encryption_opts.salt_len)?;
+                let (auth_spec, cipher_spec, compressor_spec) = extract_algorithm_specs(encryption_opts)?;
+
+                // the entries are encrypted as if they were under a directory at the root, so that
+                // they decrypt to a directory of its name
+                let name = match (name_opt, archive == Path::new(STDIO_PATH)) {
+                    (Some(name), _) => OsStr::new(name),
+                    (None, true) => OsStr::new(DEFAULT_STREAM_NAME),
+                    (None, false) => archive.file_stem().unwrap_or_else(|| OsStr::new(DEFAULT_STREAM_NAME)),
+                };
+
+                SyncerSpecExt::Encrypt {
+                    auth_spec,
+                    checksum: false,
+                    chunked: *chunked,
+                    cipher_spec,
+                    compressor_spec,
+                    filter_spec: FilterSpec::default(),
+                    index: false,
+                    kd_spec_ext,
+                    spread_depth: encryption_opts.spread_depth,
+                    out_dir: out_dir.to_path_buf(),
+                    roots: Vec::new(),
+                    snapshot: false,
+                    source: Path::new("/").join(name),
+                    source_kind: SourceKind::Tar,
                     verbose: !*quiet,
-                    salt_len: *salt_len,
+                    volume_size_opt: None,
+                    salt_len: encryption_opts.salt_len,
                 }
             }
             Opts::Decrypt {
-                source, out_dir, quiet, ..
+                out_dir, source, quiet, ..
+            } if out_dir == Path::new(STDIO_PATH) => SyncerSpecExt::Cat {
+                path_opt: None,
+                source: source.to_path_buf(),
+                verbose: !*quiet,
+            },
+            Opts::Decrypt {
+                delete,
+                roots,
+                snapshot_opt,
+                source,
+                out_dir,
+                output,
+                quiet,
+                ..
             } => SyncerSpecExt::Decrypt {
+                conflict_spec: extract_conflict_spec(opts)?,
+                delete: *delete,
+                roots: roots.clone(),
+                snapshot_opt: *snapshot_opt,
                 out_dir: out_dir.to_path_buf(),
                 source: source.to_path_buf(),
+                verbose: !*quiet && *output == OutputFormat::Human,
+            },
+            Opts::Cat { source, path, quiet, .. } => SyncerSpecExt::Cat {
+                path_opt: Some(path.to_path_buf()),
+                source: source.to_path_buf(),
                 verbose: !*quiet,
             },
-            Opts::Clean { source, quiet, .. } => {
-                SyncerSpecExt::Clean {
-                    source: source.to_path_buf(),
-                    verbose: !*quiet,
-                };
-                todo!()
-            }
+            Opts::ExportTar {
+                out_opt, source, quiet, ..
+            } => SyncerSpecExt::ExportTar {
+                // `-` is stdout, like it is everywhere else
+                out_opt: out_opt.clone().filter(|out| out != Path::new(STDIO_PATH)),
+                source: source.to_path_buf(),
+                verbose: !*quiet,
+            },
+            Opts::Clean {
+                keep_daily_opt,
+                keep_last_opt,
+                keep_weekly_opt,
+                output,
+                source,
+                quiet,
+                ..
+            } => SyncerSpecExt::Clean {
+                retention_spec: RetentionSpec {
+                    keep_daily: *keep_daily_opt,
+                    keep_last: *keep_last_opt,
+                    keep_weekly: *keep_weekly_opt,
+                },
+                source: source.to_path_buf(),
+                verbose: !*quiet && *output == OutputFormat::Human,
+            },
+            Opts::Daemon { .. } => panic!("`csync daemon` has no spec"),
+        })
+    }
+}
+
+// the name of a stream, an imported archive or a root becomes the only component of its path
+pub(crate) fn check_name(flag: &str, name: &str) -> CsyncResult<()> {
+    match Path::new(name).components().collect::<Vec<_>>()[..] {
+        [Component::Normal(_)] => Ok(()),
+        _ => csync_err!(
+            CommandLineArgumentConflict,
+            format!("`{}` has to be a file name, which {:?} is not", flag, name)
+        ),
+    }
+}
+
+// every root decrypts to a path of its own under `out_dir`, the source included
+pub(crate) fn check_roots(flag: &str, source: &Path, roots: &[SourceRoot]) -> CsyncResult<()> {
+    let mut names: HashSet<&OsStr> = source.file_name().into_iter().collect();
+    for SourceRoot { name, path } in roots {
+        check_name(flag, name)?;
+        if !names.insert(OsStr::new(name)) {
+            csync_err!(
+                CommandLineArgumentConflict,
+                format!(
+                    "`{}` names have to differ from each other and from the source, which {:?} doesn't",
+                    flag, name
+                )
+            )?;
+        }
+        if !path.exists() {
+            csync_err!(SourceDoesNotExist, path.clone())?;
+        }
+    }
+    Ok(())
+}
+
+// anything that would panic while deriving the key or spreading the ciphertexts, and salts too
+// short to be used as nonces
+pub(crate) fn check_encryption_params(spread_depth: u8, kd_spec_ext: &KeyDerivSpecExt, salt_len: u16) -> CsyncResult<()> {
+    if spread_depth > MAX_SPREAD_DEPTH {
+        csync_err!(
+            CommandLineArgumentConflict,
+            format!(
+                "spread depths range from 0 to {}, which {} doesn't",
+                MAX_SPREAD_DEPTH, spread_depth
+            )
+        )?;
+    }
+    let kd_salt_len = match kd_spec_ext {
+        KeyDerivSpecExt::Pbkdf2ByTime { time: 0, .. } | KeyDerivSpecExt::ScryptByTime { time: 0, .. } => csync_err!(
+            CommandLineArgumentConflict,
+            "deriving the key by time has to take at least 1 second".to_string()
+        )?,
+        KeyDerivSpecExt::Pbkdf2ByParams { num_iter: 0, .. } => csync_err!(
+            CommandLineArgumentConflict,
+            "`pbkdf2` has to run at least 1 iteration".to_string()
+        )?,
+        KeyDerivSpecExt::ScryptByTime { output_len, .. } | KeyDerivSpecExt::ScryptByParams { output_len, .. }
+            if *output_len < MIN_KEY_LEN =>
+        {
+            csync_err!(
+                CommandLineArgumentConflict,
+                format!(
+                    "`scrypt` has to output at least {} bytes, which {} isn't",
+                    MIN_KEY_LEN, output_len
+                )
+            )?
+        }
+        KeyDerivSpecExt::Pbkdf2ByTime { salt_len, .. }
+        | KeyDerivSpecExt::Pbkdf2ByParams { salt_len, .. }
+        | KeyDerivSpecExt::ScryptByTime { salt_len, .. }
+        | KeyDerivSpecExt::ScryptByParams { salt_len, .. } => *salt_len,
+    };
+    for salt_len in [salt_len, kd_salt_len].iter() {
+        if *salt_len < MIN_SALT_LEN {
+            csync_err!(
+                CommandLineArgumentConflict,
+                format!(
+                    "salts have to be at least {} bytes long, which {} isn't",
+                    MIN_SALT_LEN, salt_len
+                )
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// whether `out_dir` is empty is left for the syncer, but a file can't be a `csync` dir
+pub(crate) fn check_encryption_out_dir(out_dir: &Path) -> CsyncResult<StorageSpec> {
+    let out_dir_spec = StorageSpec::from_location(out_dir)?;
+    if out_dir_spec.is_dir() && out_dir.exists() && !out_dir.is_dir() {
+        csync_err!(OutdirIsNotDir, out_dir.to_path_buf())?;
+    }
+    Ok(out_dir_spec)
+}
+
+// files already in `out_dir` are only allowed if there is a policy for them
+pub(crate) fn check_decryption_out_dir(out_dir: &Path, conflict_spec: &ConflictSpec) -> CsyncResult<()> {
+    if out_dir.exists() {
+        match out_dir.is_dir() {
+            true => match std::fs::read_dir(out_dir).map(Iterator::count)? {
+                0 => (),
+                _ if *conflict_spec != ConflictSpec::Fail => (),
+                _ => csync_err!(DecryptionOutdirIsNonempty, out_dir.to_path_buf())?,
+            },
+            false => csync_err!(OutdirIsNotDir, out_dir.to_path_buf())?,
+        }
+    }
+    Ok(())
+}
+
+fn extract_roots(roots: &[(String, PathBuf)]) -> Vec<SourceRoot> {
+    roots
+        .iter()
+        .map(|(name, path)| SourceRoot {
+            name: name.clone(),
+            path: path.to_path_buf(),
         })
+        .collect()
+}
+
+// names that no algorithm goes by are only caught here, as `structopt` takes any string
+fn unsupported<T>(flag: &str, name: &str) -> CsyncResult<T> {
+    csync_err!(
+        CommandLineArgumentConflict,
+        format!("`{}` doesn't support {:?}; see `csync encrypt --help`", flag, name)
+    )
+}
+
+fn extract_algorithm_specs(encryption_opts: &EncryptionOpts) -> CsyncResult<(AuthenticatorSpec, CipherSpec, CompressorSpec)> {
+    let EncryptionOpts {
+        auth,
+        cipher,
+        compressor,
+        salt_len,
+        zstd_level,
+        ..
+    } = encryption_opts;
+
+    let auth_spec = match auth.as_str() {
+        "hmac-sha512" => AuthenticatorSpec::HmacSha512,
+        _ => unsupported("--auth", auth)?,
+    };
+    let cipher_spec = match cipher.as_str() {
+        "aes256cbc" => CipherSpec::aes256cbc(*salt_len),
+        "chacha20" => CipherSpec::chacha20(*salt_len),
+        _ => unsupported("--cipher", cipher)?,
+    };
+    let compressor_spec = match compressor.as_str() {
+        "zstd" => CompressorSpec::Zstd { level: *zstd_level },
+        _ => unsupported("--compressor", compressor)?,
+    };
+    Ok((auth_spec, cipher_spec, compressor_spec))
+}
+
+fn extract_kd_opt(encryption_opts: &EncryptionOpts) -> CsyncResult<KeyDerivSpecExt> {
+    let EncryptionOpts {
+        key_deriv_alg,
+        key_deriv_time,
+        key_deriv_by_params,
+        pbkdf2_alg,
+        pbkdf2_num_iter,
+        scrypt_log_n,
+        scrypt_p,
+        scrypt_r,
+        scrypt_output_len,
+        salt_len,
+        ..
+    } = encryption_opts;
+
+    match key_deriv_alg.as_ref() {
+        "scrypt" => Ok(match key_deriv_by_params {
+            true => KeyDerivSpecExt::ScryptByParams {
+                log_n: *scrypt_log_n,
+                r: *scrypt_r,
+                p: *scrypt_p,
+                output_len: *scrypt_output_len,
+                salt_len: *salt_len,
+            },
+            false => KeyDerivSpecExt::ScryptByTime {
+                time: *key_deriv_time,
+                output_len: *scrypt_output_len,
+                salt_len: *salt_len,
+            },
+        }),
+        "pbkdf2" => match key_deriv_by_params {
+            true => Ok(KeyDerivSpecExt::Pbkdf2ByParams {
+                alg_opt: Some(match pbkdf2_alg.as_ref() {
+                    "hmac-sha512" => Pbkdf2Algorithm::HmacSha512,
+                    _ => unsupported("--pbkdf2-alg", pbkdf2_alg)?,
+                }),
+                num_iter: *pbkdf2_num_iter,
+                salt_len: *salt_len,
+            }),
+            false => Ok(KeyDerivSpecExt::Pbkdf2ByTime {
+                alg_opt: Some(match pbkdf2_alg.as_ref() {
+                    "hmac-sha512" => Pbkdf2Algorithm::HmacSha512,
+                    _ => unsupported("--pbkdf2-alg", pbkdf2_alg)?,
+                }),
+                time: *key_deriv_time,
+                salt_len: *salt_len,
+            }),
+        },
+        _ => unsupported("--key-deriv-alg", key_deriv_alg),
     }
 }
 
-fn extract_kd_opt(opts: &Opts) -> CsyncResult<KeyDerivSpecExt> {
+// patterns from `--exclude-from` files come after those from `--exclude`, which doesn't change
+// what they match
+fn extract_filter_spec(opts: &Opts) -> CsyncResult<FilterSpec> {
     match opts {
         Opts::Encrypt {
-            key_deriv_alg,
-            key_deriv_time,
-            key_deriv_by_params,
-            pbkdf2_alg,
-            pbkdf2_num_iter,
-            scrypt_log_n,
-            scrypt_p,
-            scrypt_r,
-            scrypt_output_len,
-            salt_len,
+            excludes,
+            exclude_from_files,
+            gitignore,
+            includes,
+            one_file_system,
+            source,
             ..
-        } => match key_deriv_alg.as_ref() {
-            "scrypt" => Ok(match key_deriv_by_params {
-                true => KeyDerivSpecExt::ScryptByParams {
-                    log_n: *scrypt_log_n,
-                    r: *scrypt_r,
-                    p: *scrypt_p,
-                    output_len: *scrypt_output_len,
-                    salt_len: *salt_len,
-                },
-                false => KeyDerivSpecExt::ScryptByTime {
-                    time: *key_deriv_time,
-                    output_len: *scrypt_output_len,
-                    salt_len: *salt_len,
-                },
+        }
+        | Opts::Watch {
+            excludes,
+            exclude_from_files,
+            gitignore,
+            includes,
+            one_file_system,
+            source,
+            ..
+        } => {
+            let mut excludes = excludes.clone();
+            for exclude_from_file in exclude_from_files {
+                excludes.extend(std::fs::read_to_string(exclude_from_file)?.lines().map(String::from));
+            }
+            let filter_spec = FilterSpec {
+                excludes,
+                git_ignore: *gitignore,
+                includes: includes.clone(),
+                one_file_system: *one_file_system,
+            };
+
+            match filter_spec.matcher(source) {
+                Ok(_) => Ok(filter_spec),
+                Err(err) => csync_err!(
+                    CommandLineArgumentConflict,
+                    format!(
+                        "`--exclude` and `--include` take patterns like those in `.gitignore` files: {}",
+                        err
+                    )
+                ),
+            }
+        }
+        Opts::ImportTar { .. }
+        | Opts::Decrypt { .. }
+        | Opts::Cat { .. }
+        | Opts::ExportTar { .. }
+        | Opts::Clean { .. }
+        | Opts::Daemon { .. } => {
+            panic!()
+        }
+    }
+}
+
+fn extract_conflict_spec(opts: &Opts) -> CsyncResult<ConflictSpec> {
+    match opts {
+        Opts::Decrypt {
+            backup_suffix_opt,
+            incremental,
+            newer_wins,
+            overwrite,
+            skip_existing,
+            ..
+        } => match (*overwrite, *skip_existing, *newer_wins, *incremental, backup_suffix_opt) {
+            (false, false, false, false, None) => Ok(ConflictSpec::Fail),
+            (_, false, false, false, backup_suffix) => Ok(ConflictSpec::Overwrite {
+                backup_suffix: backup_suffix.clone(),
             }),
-            "pbkdf2" => match key_deriv_by_params {
-                true => Ok(KeyDerivSpecExt::Pbkdf2ByParams {
-                    alg_opt: Some(match pbkdf2_alg.as_ref() {
-                        "hmac-sha512" => Pbkdf2Algorithm::HmacSha512,
-                        _ => todo!(),
-                    }),
-                    num_iter: *pbkdf2_num_iter,
-                    salt_len: *salt_len,
-                }),
-                false => Ok(KeyDerivSpecExt::Pbkdf2ByTime {
-                    alg_opt: Some(match pbkdf2_alg.as_ref() {
-                        "hmac-sha512" => Pbkdf2Algorithm::HmacSha512,
-                        _ => todo!(),
-                    }),
-                    time: *key_deriv_time,
-                    salt_len: *salt_len,
-                }),
-            },
-            _ => todo!(),
+            (false, true, false, false, None) => Ok(ConflictSpec::SkipExisting),
+            (false, false, true, false, backup_suffix) => Ok(ConflictSpec::NewerWins {
+                backup_suffix: backup_suffix.clone(),
+            }),
+            (false, false, false, true, backup_suffix) => Ok(ConflictSpec::Incremental {
+                backup_suffix: backup_suffix.clone(),
+            }),
+            (_, _, _, _, Some(_)) if *skip_existing => csync_err!(
+                CommandLineArgumentConflict,
+                "`--backup-suffix` cannot be used with `--skip-existing`".to_string()
+            ),
+            _ => csync_err!(
+                CommandLineArgumentConflict,
+                "only one of `--overwrite`, `--skip-existing`, `--newer-wins`, `--incremental` can be used".to_string()
+            ),
         },
-        Opts::Decrypt { .. } | Opts::Clean { .. } => panic!(),
+        Opts::Encrypt { .. }
+        | Opts::Watch { .. }
+        | Opts::ImportTar { .. }
+        | Opts::Cat { .. }
+        | Opts::ExportTar { .. }
+        | Opts::Clean { .. }
+        | Opts::Daemon { .. } => {
+            panic!()
+        }
     }
 }
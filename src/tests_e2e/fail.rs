@@ -255,6 +255,215 @@ fn decryption_outdir_is_nonempty() {
     );
 }
 
+#[test]
+fn volume_size_without_archive() {
+    //
+    let exit_code = CsyncErr::CommandLineArgumentConflict(String::new()).exit_code();
+
+    //
+    let key_1 = "Ye5nQc1WvK8rTm3ZsB7xLa0pHd4JfU9g";
+    let key_2 = key_1;
+
+    //
+    let source = tmpdir!().unwrap();
+    let out_dir = tmpdir!().unwrap();
+
+    check_encrypt!(
+        exit_code,
+        &source,
+        out_dir.path(),
+        key_1,
+        key_2,
+        path_as_str!(&source),
+        &format!("-o {}", path_as_str!(out_dir.path())),
+        "--volume-size 1M"
+    );
+}
+
+#[test]
+fn conflicting_root_names() {
+    //
+    let exit_code = CsyncErr::CommandLineArgumentConflict(String::new()).exit_code();
+
+    //
+    let key_1 = "Nv6pRt1XcW9kMz4QbL2sYe7HaJ3dFg8u";
+    let key_2 = key_1;
+
+    //
+    let source = tmpdir!().unwrap();
+    let root = tmpdir!().unwrap();
+    let out_dir = tmpdir!().unwrap();
+    let source_name = path_as_str!(source.path().file_name().unwrap()).to_string();
+
+    vec![
+        // a root has to decrypt to a path of its own
+        format!("--root {}={}", source_name, path_as_str!(root.path())),
+        format!("--root Jk5w={0} --root Jk5w={0}", path_as_str!(root.path())),
+        // and its name has to be a file name
+        format!("--root Jk5w/Hx2c={}", path_as_str!(root.path())),
+        format!("--root ..={}", path_as_str!(root.path())),
+    ]
+    .into_iter()
+    .for_each(|arg| {
+        check_encrypt!(
+            exit_code,
+            source.path(),
+            out_dir.path(),
+            key_1,
+            key_2,
+            path_as_str!(source.path()),
+            &format!("-o {}", path_as_str!(out_dir.path())),
+            &arg
+        );
+    });
+}
+
+#[test]
+fn unsupported_algorithms() {
+    //
+    let exit_code = CsyncErr::CommandLineArgumentConflict(String::new()).exit_code();
+
+    //
+    let key_1 = "Tb4mXw8RkQ2zLp6vHn1sYc9dJf3gWe7a";
+    let key_2 = key_1;
+
+    //
+    let source = tmpdir!().unwrap();
+    let out_dir = tmpdir!().unwrap();
+
+    vec![
+        "--auth Vd2k",
+        "--cipher Vd2k",
+        "--compressor Vd2k",
+        "--key-deriv-alg Vd2k",
+        "--key-deriv-alg pbkdf2 --pbkdf2-alg Vd2k",
+    ]
+    .into_iter()
+    .for_each(|arg| {
+        check_encrypt!(
+            exit_code,
+            source.path(),
+            out_dir.path(),
+            key_1,
+            key_2,
+            path_as_str!(source.path()),
+            &format!("-o {}", path_as_str!(out_dir.path())),
+            arg
+        );
+    });
+}
+
+#[test]
+fn conflicting_stream_args() {
+    //
+    let exit_code = CsyncErr::CommandLineArgumentConflict(String::new()).exit_code();
+
+    //
+    let key_1 = "Wd8mKq3XzT6vNc1LbR9sHy4PaE7fJu2o";
+    let key_2 = key_1;
+
+    //
+    let source = tmpdir!().unwrap();
+    let out_dir = tmpdir!().unwrap();
+    let password_file = out_dir.path().join("Fz5rA2gUe8Nw");
+    std::fs::write(&password_file, format!("{}\n", key_1)).unwrap();
+    let password_file_arg = format!("--password-file {}", path_as_str!(&password_file));
+    let source_arg = path_as_str!(&source).to_string();
+
+    vec![
+        // `--name` is only for streams, and only names a file
+        (source_arg.as_str(), "--name Qe4wZ7"),
+        ("-", "--name Qe4wZ7/Tb1n"),
+        ("-", "--name .."),
+        // neither can record a stream
+        ("-", "--index"),
+        ("-", "--snapshot"),
+        // nor can a `csync` dir that holds a stream hold anything else
+        ("-", "--root Qe4wZ7=/"),
+        // there is nothing to filter in a stream
+        ("-", "--exclude Qe4wZ7"),
+        ("-", "--one-file-system"),
+        // there is no terminal to type the password into, as stdin is taken
+        ("-", ""),
+    ]
+    .into_iter()
+    .for_each(|(source_arg, arg)| {
+        let password_arg = match arg {
+            "" => "",
+            _ => &password_file_arg,
+        };
+        check_core!(
+            exit_code,
+            key_1,
+            key_2,
+            "encrypt",
+            source_arg,
+            &format!("-o {}", path_as_str!(out_dir.path())),
+            arg,
+            password_arg,
+            "< /dev/null"
+        );
+    });
+
+    // nothing is written to the filesystem when decrypting to stdout
+    vec!["--delete", "--root Qe4wZ7", "--snapshot 0", "--overwrite"]
+        .into_iter()
+        .for_each(|arg| {
+            check_core!(exit_code, key_1, key_2, "decrypt", path_as_str!(&source), "-o -", arg);
+        });
+}
+
+#[test]
+fn conflicting_decryption_conflict_policies() {
+    //
+    let encryption_exit_code = 0;
+    let decryption_exit_code = CsyncErr::CommandLineArgumentConflict(String::new()).exit_code();
+
+    //
+    let key_1 = "pT7sWq2LmZc9XvNb4HyR0eKjUa6GdF1o";
+    let key_2 = key_1;
+
+    //
+    let source = tmpdir!().unwrap();
+    let out_dir = tmpdir!().unwrap();
+    let out_out_dir = tmpdir!().unwrap();
+
+    // encryption checks
+    check_encrypt!(
+        encryption_exit_code,
+        &source,
+        out_dir.path(),
+        key_1,
+        key_2,
+        path_as_str!(&source),
+        &format!("-o {}", path_as_str!(out_dir.path()))
+    );
+
+    //
+    {
+        fopen_w(out_out_dir.path().join("r8YbKz3QwN0sVx5E")).unwrap();
+    }
+
+    //
+    [
+        vec!["--overwrite", "--skip-existing"],
+        vec!["--overwrite", "--newer-wins"],
+        vec!["--skip-existing", "--backup-suffix .bak"],
+    ]
+    .iter()
+    .for_each(|flags| {
+        check_core!(
+            decryption_exit_code,
+            key_1,
+            key_2,
+            "decrypt",
+            path_as_str!(out_dir.path()),
+            &format!("-o {}", path_as_str!(out_out_dir.path())),
+            &flags.join(" ")
+        );
+    });
+}
+
 /*
 AuthenticationFail,                    // checksum verification failed for this file
 DecryptionOutdirIsNonempty(PathBuf),   // when decrypting, outdir must be empty
@@ -311,6 +520,38 @@ fn outdir_is_not_dir() {
     );
 }
 
+#[test]
+fn storage_failed() {
+    //
+    let exit_code = CsyncErr::StorageFailed(String::new()).exit_code();
+
+    // same keys
+    let key_1 = "uV3mQ8zKc1RdX6nTb9LwY2sPe5HjA0fG";
+    let key_2 = key_1;
+
+    //
+    let source = tmpdir!().unwrap();
+
+    // a location that doesn't name a bucket
+    check_core!(exit_code, key_1, key_2, "encrypt", path_as_str!(&source.path()), "-o s3://");
+    check_core!(exit_code, key_1, key_2, "decrypt", "s3:///prefix", "-o out");
+
+    // a location that doesn't name a host
+    check_core!(exit_code, key_1, key_2, "encrypt", path_as_str!(&source.path()), "-o sftp://");
+    check_core!(exit_code, key_1, key_2, "decrypt", "sftp:///srv/csync", "-o out");
+
+    // a server that isn't there
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "encrypt",
+        path_as_str!(&source.path()),
+        "-o sftp://csync@127.0.0.1:1/csync"
+    );
+    check_core!(exit_code, key_1, key_2, "decrypt", "sftp://csync@127.0.0.1:1/csync", "-o out");
+}
+
 #[test]
 fn source_eq_outdir() {
     //
@@ -360,6 +601,41 @@ fn source_does_not_have_filename() {
     );
 }
 
+#[test]
+fn path_not_found_in_csync_dir() {
+    //
+    let encryption_exit_code = 0;
+    let cat_exit_code = CsyncErr::PathNotFoundInCsyncDir(PathBuf::from("")).exit_code();
+
+    // same keys
+    let key_1 = "Zb3n8WqTj0YcLx5RkVf2HsP9GmDa6EuN";
+    let key_2 = key_1;
+
+    //
+    let source = tmpdir!().unwrap();
+    let out_dir = tmpdir!().unwrap();
+
+    // encryption checks
+    check_encrypt!(
+        encryption_exit_code,
+        &source,
+        out_dir.path(),
+        key_1,
+        key_2,
+        path_as_str!(&source),
+        &format!("-o {}", path_as_str!(out_dir.path()))
+    );
+
+    // neither exists in the source
+    let basename = path_as_string(source.path().file_name().unwrap()).unwrap();
+    [format!("{}/mJ2cV8pXqL", basename), format!("{}/../{}", basename, basename)]
+        .iter()
+        .for_each(|path| {
+            let output = check_core!(cat_exit_code, key_1, key_2, "cat", path_as_str!(out_dir.path()), path);
+            assert!(output.stdout.is_empty());
+        });
+}
+
 mod incremental_encryption_disabled_for_now {
     use super::*;
 
@@ -414,3 +690,270 @@ mod incremental_encryption_disabled_for_now {
         (tmpd.path().to_path_buf(), tmpd)
     });
 }
+
+#[test]
+fn unsupported_archive_entries() {
+    //
+    let exit_code = CsyncErr::UnsupportedArchiveEntry(PathBuf::new()).exit_code();
+
+    //
+    let key_1 = "Xe2rNw8KcT5mQz1VbH7sJd4LyP9fGa3u";
+    let key_2 = key_1;
+
+    //
+    let tmpd = tmpdir!().unwrap();
+    let archive = tmpd.path().join("Cv6tBq1Ws.tar");
+    let password_file = tmpd.path().join("Ny3dK8aZf5Hr");
+    std::fs::write(&password_file, format!("{}\n", key_1)).unwrap();
+
+    // the raw name, as the `tar` crate refuses to write some of them
+    let header = |name: &[u8], entry_type| {
+        let mut header = tar::Header::new_gnu();
+        header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name);
+        header.set_entry_type(entry_type);
+        header.set_mode(0o644);
+        header.set_size(0);
+        header.set_cksum();
+        header
+    };
+    vec![
+        // neither a file, a directory nor a symlink
+        header(b"Mq7e", tar::EntryType::Fifo),
+        header(b"Mq7e", tar::EntryType::Link),
+        // would be decrypted outside of the directory of the archive
+        header(b"../Mq7e", tar::EntryType::Regular),
+        header(b"Mq7e/../../Ur2c", tar::EntryType::Directory),
+        // the top of the archive has to be a directory
+        header(b"./", tar::EntryType::Regular),
+    ]
+    .into_iter()
+    .for_each(|header| {
+        let out_dir = tmpdir!().unwrap();
+        let mut builder = tar::Builder::new(fopen_w(&archive).unwrap());
+        builder.append(&header, std::io::empty()).unwrap();
+        builder.into_inner().unwrap();
+
+        check_core!(
+            exit_code,
+            key_1,
+            key_2,
+            "import-tar",
+            path_as_str!(&archive),
+            &format!("-o {}", path_as_str!(out_dir.path())),
+            &format!("--password-file {}", path_as_str!(&password_file))
+        );
+    });
+}
+
+#[test]
+fn archive_entries_under_symlinks() {
+    //
+    let exit_code = CsyncErr::UnsupportedArchiveEntry(PathBuf::new()).exit_code();
+
+    //
+    let key_1 = "Pf6wLr2NqZ8cXh4TmB1sVk7eJd3yGu9a";
+    let key_2 = key_1;
+
+    //
+    let tmpd = tmpdir!().unwrap();
+    let elsewhere = tmpdir!().unwrap();
+    let archive = tmpd.path().join("Rk4nWs8Dq.tar");
+    let password_file = tmpd.path().join("Jt7cM2xHb9Lp");
+    std::fs::write(&password_file, format!("{}\n", key_1)).unwrap();
+
+    // a symlink to a directory outside of the archive, and a file that would be written through
+    // it, in either order
+    [false, true].iter().for_each(|file_first| {
+        let out_dir = tmpdir!().unwrap();
+        let mut builder = tar::Builder::new(fopen_w(&archive).unwrap());
+        let append_file = |builder: &mut tar::Builder<_>| {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_size(4);
+            builder.append_data(&mut header, "Hq4w/Tn8c", &b"Zs3v"[..]).unwrap();
+        };
+        if *file_first {
+            append_file(&mut builder);
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_mode(0o777);
+        header.set_size(0);
+        builder.append_link(&mut header, "Hq4w", elsewhere.path()).unwrap();
+        if !*file_first {
+            append_file(&mut builder);
+        }
+        builder.into_inner().unwrap();
+
+        check_core!(
+            exit_code,
+            key_1,
+            key_2,
+            "import-tar",
+            path_as_str!(&archive),
+            &format!("-o {}", path_as_str!(out_dir.path())),
+            &format!("--password-file {}", path_as_str!(&password_file))
+        );
+        assert!(dir_is_empty(elsewhere.path()));
+    });
+}
+
+#[test]
+fn json_output_to_stdout() {
+    let source = tmpdir!().unwrap();
+
+    // stdout can't hold both the plaintext and the report
+    let exit_code = CsyncErr::CommandLineArgumentConflict(String::new()).exit_code();
+    ["json", "ndjson"].iter().for_each(|output_format| {
+        check_core!(
+            exit_code,
+            "",
+            "",
+            "decrypt",
+            path_as_str!(source.path()),
+            "-o -",
+            &format!("--output {}", output_format)
+        );
+    });
+}
+
+#[test]
+fn dry_run_of_stdio() {
+    let source = tmpdir!().unwrap();
+    let out_dir = tmpdir!().unwrap();
+
+    // a stream is consumed by reading it, and stdout only ever gets the one file
+    let exit_code = CsyncErr::CommandLineArgumentConflict(String::new()).exit_code();
+    check_core!(
+        exit_code,
+        "",
+        "",
+        "encrypt",
+        "-",
+        &format!("-o {}", path_as_str!(out_dir.path())),
+        "--dry-run"
+    );
+    check_core!(exit_code, "", "", "decrypt", path_as_str!(source.path()), "-o -", "--dry-run");
+}
+
+#[test]
+fn stream_name_mismatch() {
+    let tmpd = tmpdir!().unwrap();
+    let out_dir = tmpdir!().unwrap();
+    let key_1 = "Pc6vLx2NqW8eTk4RbY1sMh9ZgD3fJa7u";
+    let key_2 = key_1;
+    let password_file = tmpd.path().join("Zs3wQ8");
+    std::fs::write(&password_file, format!("{}\n", key_1)).unwrap();
+
+    // a stream only replaces the stream of the same name
+    [
+        (0, "Jn5t"),
+        (CsyncErr::StreamNameMismatch(PathBuf::new()).exit_code(), "Wb2k"),
+    ]
+    .iter()
+    .for_each(|(exit_code, name)| {
+        check_core!(
+            *exit_code,
+            key_1,
+            key_2,
+            "encrypt",
+            "-",
+            &format!("-o {}", path_as_str!(out_dir.path())),
+            &format!("--name {}", name),
+            &format!("--password-file {}", path_as_str!(&password_file)),
+            "< /dev/null"
+        );
+    });
+}
+
+#[test]
+fn config_profile_not_found() {
+    let tmpd = tmpdir!().unwrap();
+    let config = tmpd.path().join("Mf2y.toml");
+    std::fs::write(&config, "[encrypt.Ra8v]\nzstd-level = 9\n").unwrap();
+
+    // only the profiles of the subcommand that is run can be used
+    let exit_code = CsyncErr::ConfigInvalid(String::new()).exit_code();
+    check_core!(
+        exit_code,
+        "",
+        "",
+        "decrypt",
+        &format!("--config {}", path_as_str!(&config)),
+        "--profile Ra8v"
+    );
+    check_core!(
+        exit_code,
+        "",
+        "",
+        "encrypt",
+        &format!("--config {}", path_as_str!(&tmpd.path().join("Pe4c.toml"))),
+        "--profile Ra8v"
+    );
+}
+
+#[test]
+fn watch_needs_a_directory() {
+    //
+    let exit_code = CsyncErr::CommandLineArgumentConflict(String::new()).exit_code();
+
+    //
+    let key_1 = "Gq7wXe2NrB5tKc9ZmH4sVa1LdY8pJf3u";
+    let key_2 = key_1;
+
+    //
+    let tmpd = tmpdir!().unwrap();
+    let source = tmpd.path().join("Dk3x");
+    std::fs::write(&source, "Dk3x").unwrap();
+
+    // a single file
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "watch",
+        path_as_str!(&source),
+        &format!("-o {}", path_as_str!(&tmpd.path().join("Sw6j")))
+    );
+    // nor an archive, which can't be written to again
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "watch",
+        path_as_str!(tmpd.path()),
+        &format!("-o {}", path_as_str!(&tmpd.path().join("Bf0q.csar")))
+    );
+}
+
+#[test]
+fn daemon_not_running() {
+    //
+    let key_1 = "Yt5nWb8qLs2eHd7xRk0cMv4jGa9pZf6u";
+    let key_2 = key_1;
+
+    //
+    let tmpd = tmpdir!().unwrap();
+    let socket = tmpd.path().join("Pe4h.sock");
+
+    // nothing listens on the socket
+    check_core!(
+        CsyncErr::DaemonUnreachable(PathBuf::new()).exit_code(),
+        key_1,
+        key_2,
+        "daemon",
+        &format!("--socket {}", path_as_str!(&socket)),
+        "status"
+    );
+    // which is the same for every request
+    check_core!(
+        CsyncErr::DaemonUnreachable(PathBuf::new()).exit_code(),
+        key_1,
+        key_2,
+        "daemon",
+        &format!("--socket {}", path_as_str!(&socket)),
+        "sync-now",
+        "Cm1r"
+    );
+}
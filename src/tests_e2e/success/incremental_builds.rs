@@ -1 +1,459 @@
+use crate::tests_e2e::util::*;
+use crate::{prelude::*, test_util::*, util::*};
+use filetime::{set_file_mtime, FileTime};
+use itertools::Itertools;
+use std::{io::Write, path::Path};
 
+#[test]
+pub fn incremental_decrypt_with_delete() {
+    let source = tmpdir!().unwrap();
+    let source = source.path();
+    let unchanged = source.join("a9XkR2vLqT0s");
+    let changed = source.join("Pm4wZc7NbE1y");
+    std::fs::write(&unchanged, "unchanged").unwrap();
+    std::fs::write(&changed, "changed").unwrap();
+
+    // pass
+    let exit_code = 0;
+
+    //
+    let out_dir = tmpdir!().unwrap();
+    let out_dir = out_dir.path();
+
+    //
+    let out_out_dir = tmpdir!().unwrap();
+    let out_out_dir = out_out_dir.path();
+
+    // same keys, so it shouldn't fail from mismatch
+    let key_1 = "Ue8sJq3XfWn5RbTy1KcL0oHdV7gMzA2i";
+    let key_2 = key_1;
+
+    // encryption checks
+    check_encrypt!(
+        exit_code,
+        &source,
+        out_dir,
+        key_1,
+        key_2,
+        path_as_str!(&source),
+        &format!("-o {}", path_as_str!(out_dir))
+    );
+
+    // decryption checks
+    check_decrypt!(
+        exit_code,
+        out_dir,
+        out_out_dir,
+        &source,
+        key_1,
+        key_2,
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(out_out_dir))
+    );
+
+    // same size and modification time as the original, so it is assumed to be unchanged
+    let dec_root = out_out_dir.join(source.file_name().unwrap());
+    let dec_unchanged = dec_root.join(unchanged.file_name().unwrap());
+    std::fs::write(&dec_unchanged, "UNCHANGED").unwrap();
+    let mtime = FileTime::from_last_modification_time(&std::fs::metadata(&unchanged).unwrap());
+    set_file_mtime(&dec_unchanged, mtime).unwrap();
+
+    // different size, so it is restored
+    let dec_changed = dec_root.join(changed.file_name().unwrap());
+    std::fs::write(&dec_changed, "damaged").unwrap();
+
+    // not in the `csync` dir, so only the one under the decrypted root is removed
+    let extraneous = dec_root.join("Yd6hG0tQwS3v");
+    std::fs::create_dir(&extraneous).unwrap();
+    std::fs::write(extraneous.join("Bn1fK8rXjC5u"), "extraneous").unwrap();
+    let unrelated = out_out_dir.join("Lz2pV9eHsM4q");
+    std::fs::write(&unrelated, "unrelated").unwrap();
+
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "decrypt",
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(out_out_dir)),
+        "--incremental",
+        "--delete"
+    );
+    assert_eq!(std::fs::read_to_string(&dec_unchanged).unwrap(), "UNCHANGED");
+    assert_eq!(std::fs::read_to_string(&dec_changed).unwrap(), "changed");
+    assert!(!extraneous.exists());
+    assert!(unrelated.exists());
+
+    // nothing has been decrypted to a fresh `out_dir`, so there is nothing to remove from it
+    let fresh_out_dir = tmpdir!().unwrap();
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "decrypt",
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(fresh_out_dir.path())),
+        "--delete"
+    );
+    assert_tree_eq(fresh_out_dir.path().join(source.file_name().unwrap()), source);
+}
+
+#[test]
+pub fn incremental_encrypt_with_checksum() {
+    let source = tmpdir!().unwrap();
+    let source = source.path();
+    let modified = source.join("Hq5tW9mCxR2k");
+    let touched = source.join("Jv0bN7sLpF4e");
+    std::fs::write(&modified, "modified").unwrap();
+    std::fs::write(&touched, "touched").unwrap();
+
+    // pass
+    let exit_code = 0;
+
+    //
+    let out_dir = tmpdir!().unwrap();
+    let out_dir = out_dir.path();
+
+    //
+    let out_out_dir = tmpdir!().unwrap();
+    let out_out_dir = out_out_dir.path();
+
+    // same keys, so it shouldn't fail from mismatch
+    let key_1 = "Rt7yQw2EaZ9xVc4BnM1kLs6DfG0hJp3u";
+    let key_2 = key_1;
+
+    // encryption checks
+    check_encrypt!(
+        exit_code,
+        &source,
+        out_dir,
+        key_1,
+        key_2,
+        path_as_str!(&source),
+        &format!("-o {}", path_as_str!(out_dir))
+    );
+
+    // sugar
+    macro_rules! check_reencrypt {
+        ( $num_synced:expr $( , $arg:expr )* ) => {{
+            let output = check_core!(
+                exit_code,
+                key_1,
+                key_2,
+                "encrypt",
+                path_as_str!(&source),
+                &format!("-o {}", path_as_str!(out_dir))
+                $( , $arg )*
+            );
+            let file_count_line = grep_report_line_with_header(REPORT_HEADER_NUM_FILES, &output);
+            check_report_line(&file_count_line, $num_synced as f64, "files");
+        }};
+    }
+
+    // only the file with new contents is encrypted again
+    std::fs::write(&modified, "modified again").unwrap();
+    check_reencrypt!(1);
+
+    // same contents, but a different modified time
+    set_file_mtime(&touched, FileTime::from_unix_time(1_000_000_000, 0)).unwrap();
+    check_reencrypt!(0, "--checksum");
+    check_reencrypt!(1);
+
+    // decryption checks
+    check_decrypt!(
+        exit_code,
+        out_dir,
+        out_out_dir,
+        &source,
+        key_1,
+        key_2,
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(out_out_dir))
+    );
+}
+
+#[test]
+pub fn incremental_encrypt_with_index() {
+    let source = tmpdir!().unwrap();
+    let source = source.path();
+    let modified = source.join("Wd3kP8vZrN1c");
+    std::fs::write(&modified, "modified").unwrap();
+    std::fs::create_dir(source.join("Gs6mT0yHqB5x")).unwrap();
+    std::fs::write(source.join("Gs6mT0yHqB5x").join("Ka9uE2nXjV7f"), "unchanged").unwrap();
+
+    // pass
+    let exit_code = 0;
+
+    //
+    let out_dir = tmpdir!().unwrap();
+    let out_dir = out_dir.path();
+    let index_path = out_dir.join(INDEX_FILENAME);
+
+    //
+    let out_out_dir = tmpdir!().unwrap();
+    let out_out_dir = out_out_dir.path();
+
+    // same keys, so it shouldn't fail from mismatch
+    let key_1 = "Mf5nC1xQzT8wLb3VhJ0rYk6GsD9pEa2u";
+    let key_2 = key_1;
+
+    // encryption checks
+    check_encrypt!(
+        exit_code,
+        &source,
+        out_dir,
+        key_1,
+        key_2,
+        path_as_str!(&source),
+        &format!("-o {}", path_as_str!(out_dir)),
+        "--index"
+    );
+    assert!(index_path.is_file());
+
+    // sugar
+    macro_rules! check_reencrypt {
+        ( $num_synced:expr $( , $arg:expr )* ) => {{
+            let output = check_core!(
+                exit_code,
+                key_1,
+                key_2,
+                "encrypt",
+                path_as_str!(&source),
+                &format!("-o {}", path_as_str!(out_dir))
+                $( , $arg )*
+            );
+            let file_count_line = grep_report_line_with_header(REPORT_HEADER_NUM_FILES, &output);
+            check_report_line(&file_count_line, $num_synced as f64, "files");
+        }};
+    }
+
+    // the index is kept up to date without `--index`
+    std::fs::write(&modified, "modified again").unwrap();
+    check_reencrypt!(1);
+    check_reencrypt!(0);
+
+    // a lost index is rebuilt from the ciphertexts
+    std::fs::remove_file(&index_path).unwrap();
+    check_reencrypt!(0, "--index");
+    assert!(index_path.is_file());
+
+    // decryption checks
+    check_decrypt!(
+        exit_code,
+        out_dir,
+        out_out_dir,
+        &source,
+        key_1,
+        key_2,
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(out_out_dir))
+    );
+
+    // deletions are decided with the index
+    let extraneous = out_out_dir.join(source.file_name().unwrap()).join("Zr4cY7bMwS0q");
+    std::fs::write(&extraneous, "extraneous").unwrap();
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "decrypt",
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(out_out_dir)),
+        "--incremental",
+        "--delete"
+    );
+    assert!(!extraneous.exists());
+}
+
+#[test]
+pub fn incremental_encrypt_chunked() {
+    let source = tmpdir!().unwrap();
+    let source = source.path();
+    let large = source.join("Qa3sD8fGh1Jk");
+    std::fs::write(&large, rng!(3 << 20).0.unsecure()).unwrap();
+    std::fs::write(source.join("Lz0xC5vBn7Mq"), "small").unwrap();
+
+    // pass
+    let exit_code = 0;
+
+    //
+    let out_dir = tmpdir!().unwrap();
+    let out_dir = out_dir.path();
+
+    //
+    let out_out_dir = tmpdir!().unwrap();
+    let out_out_dir = out_out_dir.path();
+
+    // same keys, so it shouldn't fail from mismatch
+    let key_1 = "Pe6rT1yUi4OpAs9DfGh2JkL7zXc0VbNm";
+    let key_2 = key_1;
+
+    // encryption checks
+    check_encrypt!(
+        exit_code,
+        &source,
+        out_dir,
+        key_1,
+        key_2,
+        path_as_str!(&source),
+        &format!("-o {}", path_as_str!(out_dir)),
+        "--chunked"
+    );
+
+    let num_chunks = || {
+        find(out_dir.join("csync-chunks"))
+            .filter(|p| p.as_ref().unwrap().is_file())
+            .count()
+    };
+    let num_chunks_before = num_chunks();
+
+    // sugar
+    macro_rules! check_reencrypt {
+        () => {
+            check_core!(
+                exit_code,
+                key_1,
+                key_2,
+                "encrypt",
+                path_as_str!(&source),
+                &format!("-o {}", path_as_str!(out_dir))
+            );
+        };
+    }
+
+    // an identical file shares every chunk
+    std::fs::copy(&large, source.join("Wr8tY2uIo5Pa")).unwrap();
+    check_reencrypt!();
+    assert_eq!(num_chunks(), num_chunks_before);
+
+    // appending only changes the last chunk
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(&large)
+        .unwrap()
+        .write_all(b"appended")
+        .unwrap();
+    check_reencrypt!();
+    assert_eq!(num_chunks(), num_chunks_before + 1);
+
+    // decryption checks
+    check_decrypt!(
+        exit_code,
+        out_dir,
+        out_out_dir,
+        &source,
+        key_1,
+        key_2,
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(out_out_dir))
+    );
+
+    let large_rel = Path::new(source.file_name().unwrap()).join(large.file_name().unwrap());
+    let output = check_core!(exit_code, key_1, key_2, "cat", path_as_str!(out_dir), path_as_str!(large_rel));
+    assert_eq!(output.stdout, std::fs::read(&large).unwrap());
+}
+
+#[test]
+pub fn snapshots_with_retention() {
+    let source = tmpdir!().unwrap();
+    let source = source.path();
+    let changed = source.join("Hk5nB2vXc8Rt");
+    let removed = source.join("Jw3qM7sLd0Fy");
+    std::fs::write(&changed, "first version").unwrap();
+    std::fs::write(&removed, "removed").unwrap();
+
+    // pass
+    let exit_code = 0;
+
+    //
+    let out_dir = tmpdir!().unwrap();
+    let out_dir = out_dir.path();
+
+    // same keys, so it shouldn't fail from mismatch
+    let key_1 = "Zr4tG8hJ1kLq6WeRt9YuIo2PaSdFgHjK";
+    let key_2 = key_1;
+
+    // snapshot ids, oldest first
+    let snapshot_ids = || -> Vec<u64> {
+        std::fs::read_dir(out_dir.join("csync-snapshots"))
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                path.file_stem().unwrap().to_str().unwrap().parse().unwrap()
+            })
+            .sorted()
+            .collect()
+    };
+    let num_chunks = || {
+        find(out_dir.join("csync-chunks"))
+            .filter(|p| p.as_ref().unwrap().is_file())
+            .count()
+    };
+
+    // encryption checks
+    check_encrypt!(
+        exit_code,
+        &source,
+        out_dir,
+        key_1,
+        key_2,
+        path_as_str!(&source),
+        &format!("-o {}", path_as_str!(out_dir)),
+        "--snapshot"
+    );
+
+    // snapshots keep being taken without the flag
+    std::fs::write(&changed, "second version").unwrap();
+    std::fs::remove_file(&removed).unwrap();
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "encrypt",
+        path_as_str!(&source),
+        &format!("-o {}", path_as_str!(out_dir))
+    );
+    let ids = snapshot_ids();
+    assert_eq!(ids.len(), 2);
+
+    // sugar
+    macro_rules! check_restore {
+        ( $exit_code:expr, $id:expr ) => {{
+            let out_out_dir = tmpdir!().unwrap();
+            check_core!(
+                $exit_code,
+                key_1,
+                key_2,
+                "decrypt",
+                path_as_str!(out_dir),
+                &format!("-o {}", path_as_str!(out_out_dir.path())),
+                &format!("--snapshot {}", $id)
+            );
+            out_out_dir
+        }};
+    }
+
+    // the first snapshot still has the file as it was, along with the removed file
+    let out_out_dir = check_restore!(exit_code, ids[0]);
+    let dec_root = out_out_dir.path().join(source.file_name().unwrap());
+    assert_eq!(
+        std::fs::read_to_string(dec_root.join(changed.file_name().unwrap())).unwrap(),
+        "first version"
+    );
+    assert!(dec_root.join(removed.file_name().unwrap()).is_file());
+
+    // the second snapshot has the current state of the source
+    let out_out_dir = check_restore!(exit_code, ids[1]);
+    assert_tree_eq(out_out_dir.path().join(source.file_name().unwrap()), source);
+
+    // only the old version of the changed file is pruned along with the first snapshot, as the
+    // ciphertext of the removed file is still in the `csync` dir
+    let num_chunks_before = num_chunks();
+    check_core!(exit_code, key_1, key_2, "clean", path_as_str!(out_dir), "--keep-last 1");
+    assert_eq!(snapshot_ids(), vec![ids[1]]);
+    assert_eq!(num_chunks(), num_chunks_before - 1);
+
+    check_restore!(CsyncErr::SnapshotNotFound(ids[0]).exit_code(), ids[0]);
+    let out_out_dir = check_restore!(exit_code, ids[1]);
+    assert_tree_eq(out_out_dir.path().join(source.file_name().unwrap()), source);
+}
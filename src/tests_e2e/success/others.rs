@@ -1,7 +1,11 @@
 use crate::tests_e2e::util::*;
-use crate::{prelude::*, test_util::*, util::*};
+use crate::{fs_util::*, prelude::*, test_util::*, util::*};
 use itertools::Itertools;
-use std::io::Write;
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
 
 #[test]
 pub fn encrypted_dir_basename_changed() {
@@ -53,3 +57,832 @@ pub fn encrypted_dir_basename_changed() {
         &format!("-o {}", path_as_str!(&out_out_dir))
     );
 }
+
+#[test]
+pub fn cat_single_file() {
+    let source = std::path::PathBuf::from("src");
+
+    // pass
+    let exit_code = 0;
+
+    //
+    let out_dir = tmpdir!().unwrap();
+    let out_dir = out_dir.path();
+
+    // same keys, so it shouldn't fail from mismatch
+    let key_1 = "qXfKu4Mz9AQxK2LhYc7PvRb0nTe5WdSg";
+    let key_2 = key_1;
+
+    // encryption checks
+    check_encrypt!(
+        exit_code,
+        &source,
+        out_dir,
+        key_1,
+        key_2,
+        path_as_str!(&source),
+        &format!("-o {}", path_as_str!(out_dir))
+    );
+
+    //
+    let output = check_core!(exit_code, key_1, key_2, "cat", path_as_str!(out_dir), "src/main.rs");
+    assert_eq!(output.stdout, std::fs::read("src/main.rs").unwrap());
+}
+
+#[test]
+pub fn decrypt_to_nonempty_outdir_with_conflict_policies() {
+    let source = tmpdir!().unwrap();
+    let source = source.path();
+    let original = source.join("Lw6oQyK2eTnB");
+    std::fs::write(&original, "original").unwrap();
+
+    // pass
+    let exit_code = 0;
+
+    //
+    let out_dir = tmpdir!().unwrap();
+    let out_dir = out_dir.path();
+
+    //
+    let out_out_dir = tmpdir!().unwrap();
+    let out_out_dir = out_out_dir.path();
+
+    // same keys, so it shouldn't fail from mismatch
+    let key_1 = "Hc4vTzR8mYq1NpXe0WbJ6uLsKd3GfA9o";
+    let key_2 = key_1;
+
+    // encryption checks
+    check_encrypt!(
+        exit_code,
+        &source,
+        out_dir,
+        key_1,
+        key_2,
+        path_as_str!(&source),
+        &format!("-o {}", path_as_str!(out_dir))
+    );
+
+    // decryption checks
+    check_decrypt!(
+        exit_code,
+        out_dir,
+        out_out_dir,
+        &source,
+        key_1,
+        key_2,
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(out_out_dir))
+    );
+
+    // damage the decrypted copy
+    let decrypted = out_out_dir
+        .join(source.file_name().unwrap())
+        .join(original.file_name().unwrap());
+    std::fs::write(&decrypted, "damaged").unwrap();
+
+    // existing files are left alone
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "decrypt",
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(out_out_dir)),
+        "--skip-existing"
+    );
+    assert_eq!(std::fs::read_to_string(&decrypted).unwrap(), "damaged");
+
+    // existing files are restored, after being backed up
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "decrypt",
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(out_out_dir)),
+        "--overwrite",
+        "--backup-suffix .bak"
+    );
+    assert_eq!(std::fs::read_to_string(&decrypted).unwrap(), "original");
+    let backup = decrypted.with_file_name(format!("{}.bak", path_as_str!(decrypted.file_name().unwrap())));
+    assert_eq!(std::fs::read_to_string(&backup).unwrap(), "damaged");
+}
+
+#[test]
+pub fn archive_with_volumes() {
+    let source = tmpdir!().unwrap();
+    let source = source.path();
+    std::fs::create_dir(source.join("Qp3xN8vLd2Ka")).unwrap();
+    std::fs::write(source.join("Qp3xN8vLd2Ka").join("Wr5mT1cZ"), drng_range(3 << 20, 0, 255)).unwrap();
+    std::fs::write(source.join("Jb7sH4eYf9Uo"), "small").unwrap();
+
+    // pass
+    let exit_code = 0;
+
+    //
+    let tmpd = tmpdir!().unwrap();
+    let archive = tmpd.path().join("Gt2wK6nRv8Xe.csar");
+    let volume = |index: usize| tmpd.path().join(format!("Gt2wK6nRv8Xe.csar.{}", index));
+
+    //
+    let out_out_dir = tmpdir!().unwrap();
+    let out_out_dir = out_out_dir.path();
+
+    // same keys, so it shouldn't fail from mismatch
+    let key_1 = "Zc8pV3mXq6TbLn1wEr4yHs7uKd0aFj2g";
+    let key_2 = key_1;
+
+    // files larger than a volume are split across volumes
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "encrypt",
+        path_as_str!(&source),
+        &format!("-o {}", path_as_str!(&archive)),
+        "--volume-size 1M"
+    );
+    assert!(archive.is_file());
+    assert!(volume(2).is_file());
+
+    // incremental runs append to the last volume
+    std::fs::write(source.join("Jb7sH4eYf9Uo"), "changed").unwrap();
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "encrypt",
+        path_as_str!(&source),
+        &format!("-o {}", path_as_str!(&archive))
+    );
+
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "decrypt",
+        path_as_str!(&archive),
+        &format!("-o {}", path_as_str!(out_out_dir))
+    );
+    assert_tree_eq(out_out_dir.join(source.file_name().unwrap()), source);
+
+    //
+    let output = check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "cat",
+        path_as_str!(&archive),
+        &format!("{}/Jb7sH4eYf9Uo", path_as_str!(source.file_name().unwrap()))
+    );
+    assert_eq!(output.stdout, b"changed");
+}
+
+#[test]
+pub fn stream_to_named_object() {
+    let tmpd = tmpdir!().unwrap();
+    let plaintext = tmpd.path().join("Rk6pW2nXc9Tv");
+    std::fs::write(&plaintext, drng_range(1 << 20, 0, 255)).unwrap();
+
+    // pass
+    let exit_code = 0;
+
+    //
+    let out_dir = tmpdir!().unwrap();
+    let out_dir = out_dir.path();
+    let out_out_dir = tmpdir!().unwrap();
+    let out_out_dir = out_out_dir.path();
+
+    // same keys, so it shouldn't fail from mismatch; stdin is taken by the stream, so the key is
+    // read from a file when encrypting
+    let key_1 = "Hs3kQ9vNe1YbLx6TcW0mRp8ZaJd5UfG2";
+    let key_2 = key_1;
+    let password_file = tmpd.path().join("Mb4tE7yXq1Kc");
+    std::fs::write(&password_file, format!("{}\n", key_1)).unwrap();
+
+    let encrypt_stream = || {
+        check_core!(
+            exit_code,
+            key_1,
+            key_2,
+            "encrypt",
+            "-",
+            &format!("-o {}", path_as_str!(out_dir)),
+            "--name Vn4cR8tLq2Ws.sql",
+            &format!("--password-file {}", path_as_str!(&password_file)),
+            &format!("< {}", path_as_str!(&plaintext))
+        )
+    };
+    encrypt_stream();
+
+    // decrypts back to stdout, or to a file of its name
+    let output = check_core!(exit_code, key_1, key_2, "decrypt", path_as_str!(out_dir), "-o -");
+    assert_eq!(output.stdout, std::fs::read(&plaintext).unwrap());
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "decrypt",
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(out_out_dir))
+    );
+    assert_eq!(
+        std::fs::read(out_out_dir.join("Vn4cR8tLq2Ws.sql")).unwrap(),
+        std::fs::read(&plaintext).unwrap()
+    );
+
+    // a stream of the same name replaces the one before
+    std::fs::write(&plaintext, "replaced").unwrap();
+    encrypt_stream();
+    let output = check_core!(exit_code, key_1, key_2, "decrypt", path_as_str!(out_dir), "-o -");
+    assert_eq!(output.stdout, b"replaced");
+}
+
+// path, type, mode, modified time, link target and contents of an entry in a tar archive
+type TarEntry = (PathBuf, tar::EntryType, u32, u64, Option<PathBuf>, Vec<u8>);
+
+// every entry of the tar archive at `path`, sorted by path
+fn tar_entries(path: &Path) -> Vec<TarEntry> {
+    let mut archive = tar::Archive::new(fopen_r(path).unwrap());
+    archive
+        .entries()
+        .unwrap()
+        .map(Result::unwrap)
+        .map(|mut entry| {
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents).unwrap();
+            let header = entry.header();
+            (
+                entry.path().unwrap().into_owned(),
+                header.entry_type(),
+                header.mode().unwrap(),
+                header.mtime().unwrap(),
+                entry.link_name().unwrap().map(|link| link.into_owned()),
+                contents,
+            )
+        })
+        .sorted_by(|a, b| a.0.cmp(&b.0))
+        .collect()
+}
+
+#[test]
+pub fn tar_round_trip() {
+    let tmpd = tmpdir!().unwrap();
+
+    // every kind of entry that can be imported, in no particular order and without one for the top
+    let archive = tmpd.path().join("Pw7cMz2Hq.tar");
+    {
+        let mut builder = tar::Builder::new(fopen_w(&archive).unwrap());
+        let mut append = |path: &str, entry_type, mode, mtime, contents: &[u8]| {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_mode(mode);
+            header.set_mtime(mtime);
+            header.set_size(0);
+            match entry_type {
+                tar::EntryType::Symlink => builder.append_link(&mut header, path, bytes_to_str(contents)).unwrap(),
+                _ => {
+                    header.set_size(contents.len() as u64);
+                    builder.append_data(&mut header, path, contents).unwrap()
+                }
+            }
+        };
+        append("Gk2", tar::EntryType::Directory, 0o750, 1_000_000_000, b"");
+        append(
+            "Gk2/Tn8x.bin",
+            tar::EntryType::Regular,
+            0o640,
+            1_100_000_000,
+            &drng_range((1 << 20) + 3, 0, 255),
+        );
+        append("Yc5r", tar::EntryType::Regular, 0o600, 1_200_000_000, b"Wq");
+        append("Gk2/Lr4e", tar::EntryType::Symlink, 0o777, 1_300_000_000, b"../Yc5r");
+        append("Bz9d", tar::EntryType::Symlink, 0o777, 1_400_000_000, b"/Xm3kHq8/Rv2p");
+        append("Gk2/Vs1", tar::EntryType::Directory, 0o700, 1_500_000_000, b"");
+        builder.into_inner().unwrap();
+    }
+
+    // pass
+    let exit_code = 0;
+
+    //
+    let out_dir = tmpdir!().unwrap();
+    let out_dir = out_dir.path();
+    let out_out_dir = tmpdir!().unwrap();
+    let out_out_dir = out_out_dir.path();
+
+    // same keys, so it shouldn't fail from mismatch
+    let key_1 = "Tq8nVz3KmW6cXe1RbJ9sLd4PyF7hGu2a";
+    let key_2 = key_1;
+    let password_file = tmpd.path().join("Jr5wC1eYk9Dm");
+    std::fs::write(&password_file, format!("{}\n", key_1)).unwrap();
+    let password_file_arg = format!("--password-file {}", path_as_str!(&password_file));
+
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "import-tar",
+        path_as_str!(&archive),
+        &format!("-o {}", path_as_str!(out_dir)),
+        &password_file_arg
+    );
+
+    // the exported archive has the same entries, whether it's written to a file or to stdout
+    let exported = tmpd.path().join("Hd3xNb6Ls.tar");
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "export-tar",
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(&exported))
+    );
+    assert_eq!(tar_entries(&exported), tar_entries(&archive));
+    let output = check_core!(exit_code, key_1, key_2, "export-tar", path_as_str!(out_dir));
+    assert_eq!(output.stdout, std::fs::read(&exported).unwrap());
+
+    // decrypts to a directory named after the archive
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "decrypt",
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(out_out_dir))
+    );
+    let root = out_out_dir.join("Pw7cMz2Hq");
+    assert_eq!(std::fs::read_link(root.join("Gk2/Lr4e")).unwrap(), PathBuf::from("../Yc5r"));
+    assert_eq!(std::fs::read(root.join("Gk2/Lr4e")).unwrap(), b"Wq");
+    assert_eq!(std::fs::read_link(root.join("Bz9d")).unwrap(), PathBuf::from("/Xm3kHq8/Rv2p"));
+    assert_eq!(perm_bits(&root.join("Gk2/Tn8x.bin")).unwrap() & 0o7777, 0o640);
+    assert_eq!(
+        std::fs::metadata(root.join("Gk2/Tn8x.bin")).unwrap().modified().unwrap(),
+        UNIX_EPOCH + Duration::from_secs(1_100_000_000)
+    );
+
+    // the archive can also be read from stdin, and its files stored as chunks
+    let chunked_out_dir = tmpdir!().unwrap();
+    let chunked_out_dir = chunked_out_dir.path();
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "import-tar",
+        "-",
+        &format!("-o {}", path_as_str!(chunked_out_dir)),
+        "--name Fw4kTs8",
+        "--chunked",
+        &password_file_arg,
+        &format!("< {}", path_as_str!(&archive))
+    );
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "export-tar",
+        path_as_str!(chunked_out_dir),
+        &format!("-o {}", path_as_str!(&exported))
+    );
+    assert_eq!(tar_entries(&exported), tar_entries(&archive));
+}
+
+#[test]
+pub fn encrypt_with_filters() {
+    let source = tmpdir!().unwrap();
+    let source = source.path();
+    for (path, contents) in &[
+        ("Ka4r/Vb7n.rs", "Vb7n"),
+        ("Ka4r/Vb7n.log", "Vb7n"),
+        ("Ka4r/Jt2w.log", "Jt2w"),
+        ("Ka4r/.csyncignore", "Vb7n.rs\n"),
+        ("Mx8q/Sd3f", "Sd3f"),
+        ("Py5e/Hu1c", "Hu1c"),
+        ("Py5e/.gitignore", "Hu1c\n"),
+        ("Zn6g", "Mx8q/\n"),
+    ] {
+        let path = source.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, contents).unwrap();
+    }
+
+    // pass
+    let exit_code = 0;
+
+    //
+    let out_dir = tmpdir!().unwrap();
+    let out_dir = out_dir.path();
+
+    //
+    let out_out_dir = tmpdir!().unwrap();
+    let out_out_dir = out_out_dir.path();
+
+    // same keys, so it shouldn't fail from mismatch
+    let key_1 = "Tg5hWq2mZs8cLp3vXr6nBk9dYf4jNe7u";
+    let key_2 = key_1;
+
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "encrypt",
+        path_as_str!(source),
+        &format!("-o {}", path_as_str!(out_dir)),
+        "--exclude '*.log'",
+        "--include Jt2w.log",
+        &format!("--exclude-from {}", path_as_str!(&source.join("Zn6g"))),
+        "--gitignore"
+    );
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "decrypt",
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(out_out_dir))
+    );
+
+    let root = out_out_dir.join(source.file_name().unwrap());
+    let mut decrypted: Vec<_> = find(&root)
+        .map(|path| path.unwrap().strip_prefix(&root).unwrap().to_path_buf())
+        .filter(|path| !path.as_os_str().is_empty())
+        .collect();
+    decrypted.sort();
+    let expected: Vec<_> = vec![
+        "Ka4r",
+        "Ka4r/.csyncignore",
+        "Ka4r/Jt2w.log",
+        "Py5e",
+        "Py5e/.gitignore",
+        "Zn6g",
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .collect();
+    assert_eq!(decrypted, expected);
+}
+
+#[test]
+pub fn encrypt_named_roots() {
+    let tmpd = tmpdir!().unwrap();
+    let source = tmpd.path().join("Rd5v");
+    let (root_1, root_2) = (tmpd.path().join("Xe2m"), tmpd.path().join("Lc9w"));
+    for (path, contents) in &[
+        (source.join("Ut3s"), "Ut3s"),
+        (root_1.join("Fo7a/Nb4p"), "Nb4p"),
+        (root_2.join("Gy1k"), "Gy1k"),
+    ] {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    // pass
+    let exit_code = 0;
+
+    //
+    let out_dir = tmpdir!().unwrap();
+    let out_dir = out_dir.path();
+
+    // same keys, so it shouldn't fail from mismatch
+    let key_1 = "Hs7dKf2qWm9xBv4nLc6tZp1rJy8gEu3a";
+    let key_2 = key_1;
+
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "encrypt",
+        path_as_str!(&source),
+        &format!("-o {}", path_as_str!(out_dir)),
+        &format!("--root Wq8z={}", path_as_str!(&root_1)),
+        &format!("--root Pm3j={}", path_as_str!(&root_2)),
+        "--index"
+    );
+
+    // every root decrypts under its own name
+    let out_out_dir = tmpdir!().unwrap();
+    let out_out_dir = out_out_dir.path();
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "decrypt",
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(out_out_dir))
+    );
+    assert_eq!(std::fs::read(out_out_dir.join("Rd5v/Ut3s")).unwrap(), b"Ut3s");
+    assert_eq!(std::fs::read(out_out_dir.join("Wq8z/Fo7a/Nb4p")).unwrap(), b"Nb4p");
+    assert_eq!(std::fs::read(out_out_dir.join("Pm3j/Gy1k")).unwrap(), b"Gy1k");
+
+    // or only the roots that are asked for
+    let out_out_dir = tmpdir!().unwrap();
+    let out_out_dir = out_out_dir.path();
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "decrypt",
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(out_out_dir)),
+        "--root Wq8z"
+    );
+    let decrypted: Vec<_> = std::fs::read_dir(out_out_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(decrypted, vec![std::ffi::OsString::from("Wq8z")]);
+    assert_eq!(std::fs::read(out_out_dir.join("Wq8z/Fo7a/Nb4p")).unwrap(), b"Nb4p");
+
+    // a root changed since is encrypted again
+    std::fs::write(root_2.join("Gy1k"), "Gy1k Gy1k").unwrap();
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "encrypt",
+        path_as_str!(&source),
+        &format!("-o {}", path_as_str!(out_dir)),
+        &format!("--root Wq8z={}", path_as_str!(&root_1)),
+        &format!("--root Pm3j={}", path_as_str!(&root_2))
+    );
+    let output = check_core!(exit_code, key_1, key_2, "cat", path_as_str!(out_dir), "Pm3j/Gy1k");
+    assert_eq!(output.stdout, b"Gy1k Gy1k");
+}
+
+#[test]
+pub fn json_output() {
+    let source = tmpdir!().unwrap();
+    let source = source.path();
+    std::fs::write(source.join("Zk4r"), "Zk4r").unwrap();
+
+    // pass
+    let exit_code = 0;
+
+    //
+    let out_dir = tmpdir!().unwrap();
+    let out_dir = out_dir.path();
+
+    // same keys, so it shouldn't fail from mismatch
+    let key_1 = "Vb8qTe3mXn6wRj1cLs9yHd4uGa7kPf2z";
+    let key_2 = key_1;
+
+    // an object per line as it happens, starting with the spec and ending with the totals
+    let output = check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "encrypt",
+        path_as_str!(source),
+        &format!("-o {}", path_as_str!(out_dir)),
+        "--output ndjson"
+    );
+    let lines: Vec<serde_json::Value> = String::from_utf8(output.stdout.clone())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.first().unwrap()["event"], "spec");
+    assert_eq!(lines.first().unwrap()["command"], "encrypt");
+    let synced: Vec<_> = lines
+        .iter()
+        .filter(|line| line["event"] == "file" && line["status"] == "synced")
+        .map(|line| PathBuf::from(line["src"].as_str().unwrap()))
+        .sorted()
+        .collect();
+    assert_eq!(synced, vec![source.to_path_buf(), source.join("Zk4r")]);
+    let finished = lines.last().unwrap();
+    assert_eq!(finished["event"], "finished");
+    assert_eq!(finished["exit_code"], 0);
+    assert_eq!(finished["stats"]["files_synced"], 2);
+
+    // nothing is colored
+    assert!(!output.stdout.contains(&0x1b) && !output.stderr.contains(&0x1b));
+
+    // or a single object once everything is done, which includes what went wrong
+    let exit_code = CsyncErr::DecryptionOutdirIsNonempty(PathBuf::from("")).exit_code();
+    let output = check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "decrypt",
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(source)),
+        "--output json"
+    );
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["exit_code"], exit_code);
+    assert_eq!(report["errors"][0]["kind"], "DecryptionOutdirIsNonempty");
+    assert_eq!(report["errors"][0]["exit_code"], exit_code);
+}
+
+#[test]
+pub fn dry_run() {
+    let source = tmpdir!().unwrap();
+    let source = source.path();
+    std::fs::write(source.join("Hw2p"), "Hw2p").unwrap();
+    std::fs::write(source.join("Rb8e"), "Rb8e").unwrap();
+
+    // pass
+    let exit_code = 0;
+
+    //
+    let tmpd = tmpdir!().unwrap();
+    let out_dir = &tmpd.path().join("Tc5v");
+    let dec_dir = &tmpd.path().join("Ly3n");
+
+    //
+    let key_1 = "Nf6sWq2hYb9tKx4mRe7aJd1pVc8gUz3L";
+    let key_2 = key_1;
+
+    // every path with the kind of change that it would get, and the totals of each kind
+    macro_rules! dry_run {
+        ( $subcommand:literal, $( $arg:expr ),+ ) => {{
+            let output = check_core!(exit_code, key_1, key_2, $subcommand, $( $arg ),+, "--dry-run --output json");
+            let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+            assert_eq!(report["stats"]["dry_run"], true);
+            let changes: Vec<(String, PathBuf)> = report["files"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|file| {
+                    (
+                        file["status"].as_str().unwrap().to_string(),
+                        PathBuf::from(file["path"].as_str().unwrap()),
+                    )
+                })
+                .sorted()
+                .collect();
+            (changes, report["stats"].clone())
+        }};
+    }
+
+    // everything would be created, without creating even `out_dir`
+    let (changes, stats) = dry_run!("encrypt", path_as_str!(source), &format!("-o {}", path_as_str!(out_dir)));
+    assert_eq!(
+        changes,
+        vec![
+            (String::from("create"), source.to_path_buf()),
+            (String::from("create"), source.join("Hw2p")),
+            (String::from("create"), source.join("Rb8e")),
+        ]
+    );
+    assert_eq!(stats["files_to_create"], 3);
+    assert_eq!(stats["bytes_to_create"], 8);
+    assert!(!out_dir.exists());
+
+    //
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "encrypt",
+        path_as_str!(source),
+        &format!("-o {}", path_as_str!(out_dir))
+    );
+    std::fs::write(source.join("Rb8e"), "Rb8e Rb8e").unwrap();
+    std::fs::write(source.join("Gj4x"), "Gj4x").unwrap();
+    let ciphertexts_before = find(out_dir).collect::<Vec<_>>();
+
+    // only what changed would be encrypted again, and nothing in `out_dir` changes
+    let (changes, stats) = dry_run!("encrypt", path_as_str!(source), &format!("-o {}", path_as_str!(out_dir)));
+    assert_eq!(
+        changes,
+        vec![
+            (String::from("create"), source.join("Gj4x")),
+            (String::from("skip"), source.join("Hw2p")),
+            (String::from("update"), source.to_path_buf()),
+            (String::from("update"), source.join("Rb8e")),
+        ]
+    );
+    assert_eq!(stats["bytes_to_update"], 9);
+    assert_eq!(find(out_dir).collect::<Vec<_>>(), ciphertexts_before);
+
+    // neither does `dec_dir` get created
+    let (changes, _) = dry_run!("decrypt", path_as_str!(out_dir), &format!("-o {}", path_as_str!(dec_dir)));
+    let dec_source = dec_dir.join(source.file_name().unwrap());
+    assert_eq!(
+        changes,
+        vec![
+            (String::from("create"), dec_source.clone()),
+            (String::from("create"), dec_source.join("Hw2p")),
+            (String::from("create"), dec_source.join("Rb8e")),
+        ]
+    );
+    assert!(!dec_dir.exists());
+
+    // a password that doesn't authenticate still fails
+    let exit_code = CsyncErr::AuthenticationFail.exit_code();
+    check_core!(
+        exit_code,
+        "Pz7d",
+        "Pz7d",
+        "decrypt",
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(dec_dir)),
+        "--dry-run"
+    );
+}
+
+#[test]
+pub fn log_file_redacts_paths() {
+    let source = tmpdir!().unwrap();
+    let source = source.path();
+    std::fs::write(source.join("Vn3k"), "Vn3k").unwrap();
+
+    // pass
+    let exit_code = 0;
+
+    //
+    let tmpd = tmpdir!().unwrap();
+    let out_dir = &tmpd.path().join("Ew6c");
+    let dec_dir = &tmpd.path().join("Tq1s");
+    let enc_log = &tmpd.path().join("Xa8r.log");
+    let dec_log = &tmpd.path().join("Od5m.log");
+
+    //
+    let key_1 = "Kw5dFv8nRq2xLb7tHs4mZc9gYp3jWe6A";
+    let key_2 = key_1;
+
+    // each file is traced, but not by its name
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "encrypt",
+        path_as_str!(source),
+        &format!("-o {}", path_as_str!(out_dir)),
+        &format!("-vv --log-file {}", path_as_str!(enc_log))
+    );
+    let log = std::fs::read_to_string(enc_log).unwrap();
+    assert_eq!(log.matches("encrypting <redacted>").count(), 2, "{}", log);
+    assert!(!log.contains("Vn3k"), "{}", log);
+
+    // unless paths are asked for
+    check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "decrypt",
+        path_as_str!(out_dir),
+        &format!("-o {}", path_as_str!(dec_dir)),
+        &format!("-vv --log-paths --log-file {}", path_as_str!(dec_log))
+    );
+    let log = std::fs::read_to_string(dec_log).unwrap();
+    assert!(log.contains("Vn3k"), "{}", log);
+    assert!(!log.contains("<redacted>"), "{}", log);
+}
+
+#[test]
+pub fn config_profile() {
+    let source = tmpdir!().unwrap();
+    let source = source.path();
+    std::fs::write(source.join("Bc9w"), "Bc9w").unwrap();
+
+    // pass
+    let exit_code = 0;
+
+    //
+    let tmpd = tmpdir!().unwrap();
+    let out_dir = &tmpd.path().join("Jq2e");
+    let other_out_dir = &tmpd.path().join("Sx5k");
+    let config = &tmpd.path().join("Fo7n.toml");
+    std::fs::write(
+        config,
+        format!(
+            "[encrypt]\nkey-deriv-time = 1\n\n[encrypt.Lg3u]\nsource = {:?}\nout-dir = {:?}\ncipher = \"aes256cbc\"\n",
+            path_as_str!(source),
+            path_as_str!(out_dir)
+        ),
+    )
+    .unwrap();
+
+    //
+    let key_1 = "Dp8vGt1wNs6yKr3eXb9qHm4zLc7fJa2U";
+    let key_2 = key_1;
+
+    // the profile has everything that's needed
+    let output = check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "encrypt",
+        &format!("--config {}", path_as_str!(config)),
+        "--profile Lg3u"
+    );
+    assert!(bytes_to_str(&output.stderr).contains("AES-256-CBC"));
+    assert!(out_dir.exists());
+
+    // but the command line has the last say
+    let output = check_core!(
+        exit_code,
+        key_1,
+        key_2,
+        "encrypt",
+        &format!("--config {}", path_as_str!(config)),
+        "--profile Lg3u",
+        &format!("-o {}", path_as_str!(other_out_dir)),
+        "--cipher chacha20"
+    );
+    assert!(bytes_to_str(&output.stderr).contains("ChaCha20"));
+    assert!(other_out_dir.exists());
+}
@@ -256,7 +256,7 @@ macro_rules! check_decrypt {
 
                 {
                     // check that correct number of files have been decrypted
-                    let encrypted_file_count = find($source).filter(|x| x.as_ref().unwrap().is_file()).count();
+                    let encrypted_file_count = get_all_outdir($source).count();
                     let decrypted_file_count = find(&final_dest).map(|x| x.unwrap()).count();
                     assert_eq!(encrypted_file_count, decrypted_file_count, "check_decrypt");
                 }
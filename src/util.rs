@@ -1,6 +1,7 @@
 use crate::{prelude::*, secure_vec::*};
 use std::{
     convert::Into,
+    io::{self, Read, Write},
     path::{Path, PathBuf},
     time::{Duration, Instant},
 };
@@ -156,17 +157,19 @@ pub fn end_timer(time: &Instant) -> Duration {
 }
 
 ///
+#[doc(hidden)]
+#[macro_export]
 macro_rules! time {
     ( $code:expr ) => {
-        time!(false, "", $code)
+        $crate::time!(false, "", $code)
     };
     ( $verbose:expr, $message:expr, $code:expr ) => {{
         if $verbose {
             eprint!("\n{}...", $message)
         };
-        let start = crate::util::start_timer();
+        let start = $crate::util::start_timer();
         let result = { $code };
-        let elapsed = crate::util::end_timer(&start);
+        let elapsed = $crate::util::end_timer(&start);
 
         if $verbose {
             eprintln!(" took {:?}", elapsed);
@@ -245,6 +248,78 @@ where
     }
 }
 
+/// Reads from `src`, counting the bytes that have been read.
+pub struct CountingReader<R>
+where
+    R: Read,
+{
+    pub count: u64,
+    src: R,
+}
+
+impl<R> CountingReader<R>
+where
+    R: Read,
+{
+    #[inline]
+    pub fn new(src: R) -> Self {
+        Self { count: 0, src }
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &R {
+        &self.src
+    }
+}
+
+impl<R> Read for CountingReader<R>
+where
+    R: Read,
+{
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.src.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
+
+/// Writes to `dest`, counting the bytes that have been written.
+pub struct CountingWriter<W>
+where
+    W: Write,
+{
+    pub count: u64,
+    dest: W,
+}
+
+impl<W> CountingWriter<W>
+where
+    W: Write,
+{
+    #[inline]
+    pub fn new(dest: W) -> Self {
+        Self { count: 0, dest }
+    }
+}
+
+impl<W> Write for CountingWriter<W>
+where
+    W: Write,
+{
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.dest.write(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.dest.flush()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,272 @@
+use crate::{prelude::*, specs::filter_spec::FilterSpec};
+use std::{
+    collections::{BTreeSet, HashMap},
+    ffi::{CString, OsStr},
+    io, mem,
+    os::unix::{ffi::OsStrExt, io::RawFd},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+// what is watched in each directory; changes to the directories themselves are reported by their
+// parents, like changes to any other entry
+const WATCH_MASK: u32 = libc::IN_ATTRIB
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MODIFY
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_ONLYDIR;
+
+/// Paths that changed together, which get synced together.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Batch {
+    pub paths: BTreeSet<PathBuf>, // created, changed, removed, or renamed from or to
+    pub overflowed: bool,         // some changes were dropped, so anything could have changed
+}
+
+/// Watches every directory under a source with `inotify`, and collects what changes in them into
+/// batches.
+///
+/// Directories that are created or moved into the source get watched as soon as they show up, and
+/// directories that the filter spec leaves out are never watched.
+#[derive(Debug)]
+pub struct Watcher {
+    fd: RawFd,
+    filter_spec: FilterSpec,
+    root: PathBuf,
+    // the directory that each watch descriptor watches
+    watched: HashMap<i32, PathBuf>,
+}
+
+impl Watcher {
+    /// # Parameters
+    ///
+    /// 1. `root`: canonical path of the source to watch
+    /// 1. `filter_spec`: which directories under `root` to leave unwatched
+    pub fn new(root: &Path, filter_spec: &FilterSpec) -> CsyncResult<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            Err(io::Error::last_os_error())?;
+        }
+
+        let mut watcher = Self {
+            fd,
+            filter_spec: filter_spec.clone(),
+            root: root.to_path_buf(),
+            watched: HashMap::new(),
+        };
+        watcher.watch_under(root)?;
+        Ok(watcher)
+    }
+
+    /// Wait for something to change, then keep collecting changes until nothing changes for
+    /// `debounce`, so that a file that is being written is synced once it's done. Changes are
+    /// collected for no longer than `WATCH_MAX_DELAY_SECS`, so that files that never stop changing
+    /// get synced too.
+    pub fn next_batch(&mut self, debounce: Duration) -> CsyncResult<Batch> {
+        let mut batch = Batch::default();
+        while !self.wait(None)? {}
+
+        let max_delay = Duration::from_secs(WATCH_MAX_DELAY_SECS);
+        let start = Instant::now();
+        loop {
+            self.read_into(&mut batch)?;
+            match max_delay.checked_sub(start.elapsed()) {
+                Some(left) if self.wait(Some(debounce.min(left)))? => (),
+                _ => break,
+            }
+        }
+        Ok(batch)
+    }
+
+    // watch every directory under `dir` that isn't left out, including `dir`
+    fn watch_under(&mut self, dir: &Path) -> CsyncResult<()> {
+        let within = Arc::new(std::iter::once(dir.to_path_buf()).collect());
+        for entry_res in self.filter_spec.walk_within(&self.root, within)? {
+            let entry = entry_res?;
+            let is_dir = entry.file_type().is_some_and(|file_type| file_type.is_dir());
+            if is_dir && entry.path().starts_with(dir) {
+                self.watch(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    //
+    fn watch(&mut self, dir: &Path) -> CsyncResult<()> {
+        let c_path = CString::new(dir.as_os_str().as_bytes())?;
+        let wd = unsafe { libc::inotify_add_watch(self.fd, c_path.as_ptr(), WATCH_MASK) };
+        if wd < 0 {
+            let err = io::Error::last_os_error();
+            return match err.raw_os_error() {
+                // gone before it could be watched, which gets reported like any other removal
+                Some(libc::ENOENT) | Some(libc::ENOTDIR) => Ok(()),
+                Some(libc::ENOSPC) => csync_err!(
+                    Other,
+                    format!(
+                        "ran out of inotify watches while watching {:?}; raise `fs.inotify.max_user_watches`",
+                        dir
+                    )
+                ),
+                _ => Err(err)?,
+            };
+        }
+        self.watched.insert(wd, dir.to_path_buf());
+        Ok(())
+    }
+
+    // stop watching `dir` and everything under it, which was moved to where its watches would
+    // report the wrong paths
+    fn unwatch_under(&mut self, dir: &Path) {
+        let fd = self.fd;
+        self.watched.retain(|wd, path| match path.starts_with(dir) {
+            true => {
+                unsafe { libc::inotify_rm_watch(fd, *wd) };
+                false
+            }
+            false => true,
+        });
+    }
+
+    // whether there is something to read before `timeout_opt` runs out, waiting for as long as it
+    // takes if there is no `timeout_opt`
+    fn wait(&self, timeout_opt: Option<Duration>) -> CsyncResult<bool> {
+        let mut pollfd = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = match timeout_opt {
+            Some(timeout) => timeout.as_millis().min(i32::MAX as u128) as i32,
+            None => -1,
+        };
+        match unsafe { libc::poll(&mut pollfd, 1, timeout) } {
+            n if n >= 0 => Ok(n > 0),
+            _ => {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::Interrupted => Ok(false),
+                    _ => Err(err)?,
+                }
+            }
+        }
+    }
+
+    // read the events that are ready into `batch`, which only blocks if none are
+    fn read_into(&mut self, batch: &mut Batch) -> CsyncResult<()> {
+        let mut buf = vec![0u8; 64 * 1024];
+        let len = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if len < 0 {
+            let err = io::Error::last_os_error();
+            return match err.kind() {
+                io::ErrorKind::Interrupted => Ok(()),
+                _ => Err(err)?,
+            };
+        }
+
+        let header_len = mem::size_of::<libc::inotify_event>();
+        let mut offset = 0;
+        while offset + header_len <= len as usize {
+            let event = unsafe { std::ptr::read_unaligned(buf.as_ptr().add(offset) as *const libc::inotify_event) };
+            // the name is padded with nuls
+            let name = &buf[offset + header_len..offset + header_len + event.len as usize];
+            let name = &name[..name.iter().position(|byte| *byte == 0).unwrap_or(name.len())];
+            offset += header_len + event.len as usize;
+
+            self.on_event(event.wd, event.mask, OsStr::from_bytes(name), batch)?;
+        }
+        Ok(())
+    }
+
+    //
+    fn on_event(&mut self, wd: i32, mask: u32, name: &OsStr, batch: &mut Batch) -> CsyncResult<()> {
+        if mask & libc::IN_Q_OVERFLOW != 0 {
+            batch.overflowed = true;
+            return Ok(());
+        }
+        if mask & libc::IN_IGNORED != 0 {
+            self.watched.remove(&wd);
+            return Ok(());
+        }
+
+        let path = match self.watched.get(&wd) {
+            Some(dir) => dir.join(name),
+            // an event that was queued before its directory was unwatched
+            None => return Ok(()),
+        };
+        if mask & libc::IN_ISDIR != 0 {
+            if mask & libc::IN_MOVED_FROM != 0 {
+                self.unwatch_under(&path);
+            }
+            if mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+                self.watch_under(&path)?;
+            }
+        }
+        batch.paths.insert(path);
+        Ok(())
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const DEBOUNCE: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn changes_are_batched() {
+        let root = tmpdir!().unwrap();
+        let root = root.path().canonicalize().unwrap();
+        fs::create_dir(root.join("Tg6b")).unwrap();
+        let mut watcher = Watcher::new(&root, &FilterSpec::default()).unwrap();
+
+        fs::write(root.join("Tg6b/Lq1n"), "Lq1n").unwrap();
+        fs::write(root.join("Tg6b/Lq1n"), "Lq1n, again").unwrap();
+        fs::create_dir(root.join("Mz0s")).unwrap();
+        let batch = watcher.next_batch(DEBOUNCE).unwrap();
+        assert!(!batch.overflowed);
+        assert_eq!(
+            batch.paths,
+            [root.join("Tg6b/Lq1n"), root.join("Mz0s")].iter().cloned().collect()
+        );
+
+        // new directories are watched, and renames are reported from both sides
+        fs::write(root.join("Mz0s/Ye3d"), "Ye3d").unwrap();
+        fs::rename(root.join("Tg6b"), root.join("Xk9a")).unwrap();
+        let batch = watcher.next_batch(DEBOUNCE).unwrap();
+        assert_eq!(
+            batch.paths,
+            [root.join("Mz0s/Ye3d"), root.join("Tg6b"), root.join("Xk9a")]
+                .iter()
+                .cloned()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn left_out_dirs_are_not_watched() {
+        let root = tmpdir!().unwrap();
+        let root = root.path().canonicalize().unwrap();
+        fs::create_dir(root.join("Vb2r")).unwrap();
+        let filter_spec = FilterSpec {
+            excludes: vec![String::from("Vb2r/")],
+            ..FilterSpec::default()
+        };
+        let mut watcher = Watcher::new(&root, &filter_spec).unwrap();
+
+        fs::write(root.join("Vb2r/Oe7h"), "Oe7h").unwrap();
+        fs::write(root.join("Nc5u"), "Nc5u").unwrap();
+        let batch = watcher.next_batch(DEBOUNCE).unwrap();
+        assert_eq!(batch.paths, std::iter::once(root.join("Nc5u")).collect());
+    }
+}